@@ -4,523 +4,3339 @@
 #include <dirent.h>
 #include <fcntl.h>
 #include <fnmatch.h>
+#include <glob.h>
 #include <limits.h>
 #include <linux/limits.h>
+#include <regex.h>
 #include <sched.h>
+#include <signal.h>
+#include <spawn.h>
 #include <stdarg.h>
 #include <stdbool.h>
+#include <stddef.h>
 #include <stdint.h>
 #include <stdio.h>
 #include <stdlib.h>
 #include <string.h>
+#include <strings.h>
+#include <sys/ioctl.h>
+#include <sys/mman.h>
+#include <sys/stat.h>
+#include <sys/statfs.h>
+#include <sys/statvfs.h>
 #include <sys/syscall.h>
+#include <time.h>
 #include <unistd.h>
 
 static void dbg(const char *fmt, ...);
-static void trim(char *s);
-
-
-static void trim(char *s) {
-    if (!s) return;
-    size_t n = strlen(s);
-    while (n && (s[n-1] == '\n' || s[n-1] == '\r' || s[n-1] == ' ' || s[n-1] == '\t')) s[--n] = 0;
-    size_t i = 0;
-    while (s[i] == ' ' || s[i] == '\t') i++;
-    if (i) memmove(s, s+i, strlen(s+i)+1);
-}
 
+#include "nvidia-hide-policy.h"
+#include "nvidia-hide-topology.h"
+#include "nvidia-hide-targets.h"
+
+// glibc keeps separate LFS64 entry points (readdir64, stat64, glob64, ...)
+// with their own struct types, and programs built with _FILE_OFFSET_BITS=64
+// call those instead of the plain ones. musl has only the 64-bit ABI: its
+// struct dirent already has the kernel's dirent64 layout, and readdir64 and
+// friends are at most macros for the plain names (no symbols at all since
+// 1.2.4), so defining ours there would redefine readdir. The *64 hooks are
+// therefore glibc-only; on musl the plain hooks see every call.
+#ifndef NH_LFS64
+#ifdef __GLIBC__
+#define NH_LFS64 1
+#else
+#define NH_LFS64 0
+#endif
+#endif
 
 #if __has_include(<linux/openat2.h>)
 #include <linux/openat2.h>
 #else
 struct open_how { uint64_t flags, mode, resolve; };
 #endif
+#ifndef OPEN_HOW_SIZE_VER0
+#define OPEN_HOW_SIZE_VER0 24   // flags, mode, resolve: the first published open_how
+#endif
 
 // --------- config ---------
-// LIBNVIDIAHIDE_DEBUG=1 prints one-time init info
+// LIBNVIDIAHIDE_LOG_LEVEL=error|info|debug|trace; silent by default.
+//   info:  init and policy decisions
+//   debug: plus every blocked open/dlopen/ioctl/mmap
+//   trace: plus every entry hidden from a directory listing
+// LIBNVIDIAHIDE_DEBUG=1 is shorthand for debug.
+enum { NH_LOG_OFF, NH_LOG_ERROR, NH_LOG_INFO, NH_LOG_DEBUG, NH_LOG_TRACE };
+static int g_log_level = NH_LOG_OFF;
+
+static void log_at(int level, const char *fmt, ...) __attribute__((format(printf, 2, 3)));
+#define log_error(...) log_at(NH_LOG_ERROR, __VA_ARGS__)
+#define log_info(...)  log_at(NH_LOG_INFO, __VA_ARGS__)
+#define log_debug(...) log_at(NH_LOG_DEBUG, __VA_ARGS__)
+#define log_trace(...) log_at(NH_LOG_TRACE, __VA_ARGS__)
+
+// LIBNVIDIAHIDE_LOG=/path appends debug lines (with a pid= prefix) to that
+// file instead of stderr, so programs that parse their own stderr aren't hurt
+static int g_log_fd = -1;
+
+// LIBNVIDIAHIDE_LOG_DEDUP=0 logs every repeated block instead of a summary
+static int g_log_dedup = 1;
+
+// LIBNVIDIAHIDE_AUDIT=/path appends one JSON object per block to that file,
+// whatever the log level
+static int g_audit_fd = -1;
+
+// LIBNVIDIAHIDE_ICD_STUB=1 serves an empty ICD manifest instead of ENOENT
+static int g_icd_stub = 0;
+
+// LIBNVIDIAHIDE_FILTER_PROCMODULES=1 serves /proc/modules without the driver's lines
+static int g_filter_procmodules = 0;
+
+// LIBNVIDIAHIDE_FILTER_MAPS=1 serves /proc/<pid>/maps without NVIDIA mappings
+static int g_filter_maps = 0;
+
+// LIBNVIDIAHIDE_BLOCK_NVIDIA_TOOLS=1 makes exec of the vendor's tools
+// (nvidia-smi, ...; LIBNVIDIAHIDE_TOOLS=a,b overrides the list) fail like a blocked open
+static int g_block_tools = 0;
 
-static int g_debug = 0;
+// LIBNVIDIAHIDE_DEVICE_ERRNO / LIBNVIDIAHIDE_FILE_ERRNO pick the errno a blocked
+// /dev path vs any other path fails with (name like EACCES, or a number)
+static int g_device_errno = ENOENT;
+static int g_file_errno = ENOENT;
+
+// LIBNVIDIAHIDE_BLOCK_IOCTL=1 makes ioctl() on NVIDIA device fds fail with ENODEV
+static int g_block_ioctl = 0;
+
+// LIBNVIDIAHIDE_BLOCK_MMAP=1 (also implied by BLOCK_IOCTL) does the same for mmap()
+static int g_block_mmap = 0;
+
+// --------- hide targets (profile + discovered nodes/BDFs) ---------
+// See nvidia-hide-targets.h; the profile follows LIBNVIDIAHIDE_VENDOR.
+static struct nh_targets g_targets = { .profile = &g_profiles[0], .hide_graphics = 1, .hide_compute = 1 };
+
+static struct nh_vendor_sel g_vendor_sel = { .vendor = 0x10de };
+
+// LIBNVIDIAHIDE_CACHE=1 reuses the discovered nodes/BDFs from
+// $XDG_RUNTIME_DIR/nvidia-hide-topo instead of walking sysfs in every process
+static int g_cache = 0;
+
+// LIBNVIDIAHIDE_WATCH=1 installs a SIGHUP handler that re-reads allow/deny
+static int g_watch = 0;
+static volatile int g_reload_requested = 0;
 
 // --------- init guards ---------
+// Startup order: the loader maps and relocates us without calling any hook
+// (ld.so opens libraries with its own syscalls). The first hooked call, or
+// our constructor, whichever comes first, runs nh_init: env knobs, vendor
+// profile, allow/deny policy, then discovery. Calls that land in our hooks
+// from inside nh_init (its fopen of the policy files, NSS or iconv modules
+// dlopen'ed by getpwuid and friends) see a half-built state, so until
+// g_inited is set, the last thing nh_init does, only paths under the fixed
+// device-node prefixes are refused and listings pass unfiltered: the loader
+// and init never open those nodes, while the library and config rules could
+// catch a file init itself is reading (and discovery lists sysfs). Other
+// threads wait for g_inited in nh_init.
 static volatile int g_inited = 0;
 static volatile int g_initializing = 0;
 
-// --------- detected NVIDIA DRM nodes ---------
-// store basenames like "card1", "renderD129"
 // --------- policy (allow/deny) ----------
 // If allowlist is non-empty, the library is active only when /proc/self/exe matches.
 // If denylist matches, the library is disabled for that process.
 static int g_active = 1;
 
-
-static const char *base_name(const char *p) {
-    if (!p) return p;
-    const char *s = strrchr(p, '/');
-    return s ? s+1 : p;
+// How the last policy evaluation came out, for LIBNVIDIAHIDE_DUMP_POLICY.
+// Written by apply_policy_from_exe only, during init and SIGHUP reloads.
+static struct {
+    const char *by;         // override, require_env, only_session, require_sysfs, no_exe or lists
+    struct nh_decision d;   // only filled in for lists
+    char exe[PATH_MAX];     // "" when /proc/self/exe is unreadable
+} g_policy_record;
+
+// The allow/deny lists as compiled by the last apply_policy_from_exe: parsed
+// once at init and again only on a SIGHUP reload.
+static struct nh_policy_sets g_policy_sets;
+
+// LIBNVIDIAHIDE_DRYRUN=1: every verdict is still computed and logged (debug is
+// forced on), but calls are always forwarded. Shows what a program touches
+// that would be hidden without risking breaking it.
+static int g_dryrun = 0;
+
+// Our own link reads go straight to the kernel, never through the readlink
+// hooks below (which would judge, and possibly hide, what we need to see).
+static ssize_t readlink_raw(const char *path, char *buf, size_t sz) {
+    return syscall(SYS_readlinkat, AT_FDCWD, path, buf, sz);
 }
 
 static int read_self_exe(char *out, size_t out_sz) {
     if (!out || out_sz == 0) return -1;
-    ssize_t n = readlink("/proc/self/exe", out, out_sz - 1);
-    if (n < 0) return -1;
+    ssize_t n = readlink_raw("/proc/self/exe", out, out_sz - 1);
+    // A full buffer may be a truncated path; matching a prefix would be wrong.
+    if (n < 0 || (size_t)n == out_sz - 1) return -1;
     out[n] = 0;
     return 0;
 }
 
-// Match a single pattern against either full exe path (if pattern has '/')
-// or basename (if pattern has no '/').
-static int match_pat(const char *pat, const char *exe_full, const char *exe_base) {
-    if (!pat || !*pat) return 0;
-    const char *target = (strchr(pat, '/') != NULL) ? exe_full : exe_base;
-    if (!target) return 0;
-    // FNM_PATHNAME would make '*' not cross '/', but we want typical shell-glob semantics.
-    return fnmatch(pat, target, 0) == 0;
-}
-
-// Env list is colon-separated patterns.
-static int env_list_has_match(const char *envval, const char *exe_full, const char *exe_base) {
-    if (!envval || !*envval) return 0;
-    const char *p = envval;
-    while (*p) {
-        const char *q = strchr(p, ':');
-        size_t len = q ? (size_t)(q - p) : strlen(p);
-        if (len) {
-            char buf[PATH_MAX];
-            if (len >= sizeof(buf)) len = sizeof(buf) - 1;
-            memcpy(buf, p, len);
-            buf[len] = 0;
-            trim(buf);
-            if (match_pat(buf, exe_full, exe_base)) return 1;
-        }
-        if (!q) break;
-        p = q + 1;
+// Raw syscalls: policy is evaluated during init, before our hooks are usable.
+// Returns NULL (cmdline: lines then never match) when unreadable.
+static const char *read_self_cmdline(char *out, size_t out_sz) {
+    int fd = (int)syscall(SYS_openat, AT_FDCWD, "/proc/self/cmdline", O_RDONLY | O_CLOEXEC, 0);
+    if (fd < 0) return NULL;
+    size_t len = 0;
+    while (len < out_sz - 1) {
+        ssize_t n = read(fd, out + len, out_sz - 1 - len);
+        if (n <= 0) break;
+        len += (size_t)n;
     }
-    return 0;
+    close(fd);
+    // NUL-separated arguments -> joined by spaces
+    while (len && out[len-1] == 0) len--;
+    if (len == 0) return NULL;
+    for (size_t i = 0; i < len; i++) if (out[i] == 0) out[i] = ' ';
+    out[len] = 0;
+    return out;
 }
 
-static int file_list_has_match(const char *path, const char *exe_full, const char *exe_base, int *out_had_entries) {
-    if (out_had_entries) *out_had_entries = 0;
-    if (!path || !*path) return 0;
-    FILE *f = fopen(path, "r");
-    if (!f) return 0;
-    char line[PATH_MAX];
-    while (fgets(line, sizeof(line), f)) {
-        trim(line);
-        if (!line[0] || line[0] == '#') continue;
-        if (out_had_entries) *out_had_entries = 1;
-        if (match_pat(line, exe_full, exe_base)) {
-            fclose(f);
-            return 1;
-        }
+static void apply_policy_from_exe(void) {
+    int forced = active_override();
+    if (forced >= 0) {
+        g_active = forced;
+        g_policy_record.by = "override";
+        log_info("policy: LIBNVIDIAHIDE_ACTIVE override, forced %s", forced ? "active" : "inactive");
+        return;
     }
-    fclose(f);
-    return 0;
-}
 
-static void build_xdg_path(char *out, size_t out_sz, const char *leaf) {
-    if (!out || out_sz == 0) return;
-    out[0] = 0;
-    const char *xdg = getenv("XDG_CONFIG_HOME");
-    const char *home = getenv("HOME");
-    if (xdg && *xdg) {
-        snprintf(out, out_sz, "%s/nvidia-hide/%s", xdg, leaf);
-    } else if (home && *home) {
-        snprintf(out, out_sz, "%s/.config/nvidia-hide/%s", home, leaf);
-    } else {
-        snprintf(out, out_sz, "/nonexistent/%s", leaf);
+    const char *gate_var;
+    int gate = require_env_gate(&gate_var);
+    if (gate == 0) {
+        g_active = 0;
+        g_policy_record.by = "require_env";
+        log_info("policy: inactive, %s is not set (LIBNVIDIAHIDE_REQUIRE_ENV)", gate_var);
+        return;
+    }
+    if (gate == 1) log_info("policy: %s is set (LIBNVIDIAHIDE_REQUIRE_ENV)", gate_var);
+
+    const char *session;
+    int in_session = only_session_gate(&session);
+    if (in_session == 0) {
+        g_active = 0;
+        g_policy_record.by = "only_session";
+        log_info("policy: inactive, session type %s is not in LIBNVIDIAHIDE_ONLY_SESSION=%s",
+                 session, getenv("LIBNVIDIAHIDE_ONLY_SESSION"));
+        return;
+    }
+    if (in_session == 1) log_info("policy: session type %s (LIBNVIDIAHIDE_ONLY_SESSION)", session);
+
+    if (!nh_drm_present()) {
+        const char *sysfs_env = getenv("LIBNVIDIAHIDE_REQUIRE_SYSFS");
+        if (sysfs_env && strcmp(sysfs_env, "0") != 0) {
+            g_active = 0;
+            g_policy_record.by = "require_sysfs";
+            log_info("policy: inactive, no local DRM subsystem (LIBNVIDIAHIDE_REQUIRE_SYSFS)");
+            return;
+        }
+        log_debug("policy: no local DRM subsystem (no /sys/class/drm, no /dev/nvidiactl); hiding limited to string rules");
     }
-}
 
-static void apply_policy_from_exe(void) {
+    struct nh_policy_files allow, deny;
+    policy_files(&allow, "allowlist");
+    policy_files(&deny, "denylist");
+    policy_sets_free(&g_policy_sets);
+    policy_sets_load(&g_policy_sets, getenv("LIBNVIDIAHIDE_ALLOWLIST"), getenv("LIBNVIDIAHIDE_DENYLIST"),
+                     allow.list, deny.list);
+
     char exe_full[PATH_MAX];
     if (read_self_exe(exe_full, sizeof(exe_full)) < 0) {
-        // If we cannot read /proc/self/exe, keep active (fail open).
+        // Without /proc/self/exe (hidepid, no /proc) nothing can match, so by
+        // default hiding stays on even for programs an allowlist leaves out.
+        // LIBNVIDIAHIDE_FAIL_CLOSED=1 turns it off instead when an allowlist
+        // exists, for setups that use the allowlist to opt programs in.
+        int active = 1;
+        const char *closed_env = getenv("LIBNVIDIAHIDE_FAIL_CLOSED");
+        if (closed_env && strcmp(closed_env, "0") != 0) {
+            struct nh_subject none = { "", "", NULL, NULL, NULL };
+            struct nh_decision d = decide_active(&none, &g_policy_sets);
+            if (d.has_allow) active = 0;
+        }
+        g_active = active;
+        g_policy_record.by = "no_exe";
+        g_policy_record.exe[0] = 0;
+        log_info("policy: cannot read /proc/self/exe, %s", g_active ? "staying active" : "inactive (LIBNVIDIAHIDE_FAIL_CLOSED)");
         return;
     }
-    const char *exe_base = base_name(exe_full);
 
-    const char *env_allow = getenv("LIBNVIDIAHIDE_ALLOWLIST");
-    const char *env_deny  = getenv("LIBNVIDIAHIDE_DENYLIST");
+    char cmdline[4096];
+    struct nh_ancestors anc = {0};
+    // The running image can't change under us, so its stat and hash are kept
+    // across SIGHUP reloads.
+    static struct nh_exe_id exe_id = { "/proc/self/exe", 0, 0, 0, 0, 0, 0, { 0 } };
+    struct nh_subject subj = { exe_full, base_name(exe_full), read_self_cmdline(cmdline, sizeof(cmdline)), &anc, &exe_id };
+    struct nh_decision d = decide_active(&subj, &g_policy_sets);
+    ancestors_free(&anc);
+
+    // Single store so a concurrent reload never exposes a half-evaluated verdict.
+    g_active = d.active;
+    g_policy_record.by = "lists";
+    g_policy_record.d = d;
+    snprintf(g_policy_record.exe, sizeof(g_policy_record.exe), "%s", exe_full);
+
+    log_info("policy: exe=%s", exe_full);
+    log_info("policy: active=%d (has_allow=%d allow_match=%d deny_match=%d)",
+             g_active, d.has_allow, d.allow_match, d.deny_match);
+}
 
-    char allow_path[PATH_MAX], deny_path[PATH_MAX];
-    build_xdg_path(allow_path, sizeof(allow_path), "allowlist");
-    build_xdg_path(deny_path, sizeof(deny_path), "denylist");
+// Each line goes out in one write() so lines from threads and from processes
+// sharing a LIBNVIDIAHIDE_LOG file never interleave. Long lines are truncated.
+static void vlog_line(const char *fmt, va_list ap) {
+    int saved_errno = errno;
+    int fd = __atomic_load_n(&g_log_fd, __ATOMIC_ACQUIRE);
+
+    char line[1024];
+    int n = fd >= 0 ? snprintf(line, sizeof(line), "[libnvidia-hide] pid=%d ", (int)getpid())
+                    : snprintf(line, sizeof(line), "[libnvidia-hide] ");
+    int m = vsnprintf(line + n, sizeof(line) - (size_t)n, fmt, ap);
+    if (m > 0) n += m;
+    if (n > (int)sizeof(line) - 1) n = (int)sizeof(line) - 1;
+    line[n++] = '\n';
+
+    ssize_t w = write(fd >= 0 ? fd : STDERR_FILENO, line, (size_t)n);
+    (void)w;
+    errno = saved_errno;
+}
 
-    int file_allow_had = 0;
-    int file_deny_had  = 0;
+static void vlog_at(int level, const char *fmt, va_list ap) {
+    if (level <= g_log_level) vlog_line(fmt, ap);
+}
 
-    int allow_match_env = env_list_has_match(env_allow, exe_full, exe_base);
-    int deny_match_env  = env_list_has_match(env_deny,  exe_full, exe_base);
+static void log_at(int level, const char *fmt, ...) {
+    va_list ap; va_start(ap, fmt);
+    vlog_at(level, fmt, ap);
+    va_end(ap);
+}
 
-    int allow_match_file = file_list_has_match(allow_path, exe_full, exe_base, &file_allow_had);
-    int deny_match_file  = file_list_has_match(deny_path,  exe_full, exe_base, &file_deny_had);
+// The shared headers only report init-time config problems; those are info.
+static void dbg(const char *fmt, ...) {
+    va_list ap; va_start(ap, fmt);
+    vlog_at(NH_LOG_INFO, fmt, ap);
+    va_end(ap);
+}
 
-    int has_allow = (env_allow && *env_allow) || file_allow_had;
-    int allow_match = allow_match_env || allow_match_file;
+// ---------- repeated blocks ----------
+// A program retrying /dev/nvidiactl in a loop would log thousands of identical
+// lines. The first block of an (event, path) pair is logged; repeats within
+// DEDUP_WINDOW seconds are only counted, and the next one after that (or
+// eviction, or exit) logs "blocked openat: /dev/nvidiactl (x1000 in last 12s)".
+// A small LRU keyed by hash, no allocation. The lock is only try-locked, as in
+// the DIR* cache: a thread or signal handler that finds it taken logs its line
+// as is. Lines are written after the lock is released.
+#define DEDUP_SLOTS 16
+#define DEDUP_WINDOW 10
+
+struct dedup_slot {
+    uint64_t key;           // 0 = free
+    const char *event;      // a hook's __func__ or a literal
+    char path[256];         // truncated; only shown in summaries
+    unsigned repeats;       // blocks since the last line, not counting it
+    time_t since;           // when that line was logged
+    unsigned long used;     // LRU tick
+};
 
-    // If allowlist exists and we don't match it => disable.
-    if (has_allow && !allow_match) g_active = 0;
+static struct dedup_slot g_dedup[DEDUP_SLOTS];
+static int g_dedup_lock = 0;
+static unsigned long g_dedup_tick = 0;
 
-    // Denylist always wins if matched.
-    if (deny_match_env || deny_match_file) g_active = 0;
+static uint64_t dedup_key(const char *event, const char *path) {
+    uint64_t h = 1469598103934665603ULL;
+    for (const char *s = event; *s; s++) h = (h ^ (unsigned char)*s) * 1099511628211ULL;
+    h = (h ^ 0xff) * 1099511628211ULL;
+    for (const char *s = path; *s; s++) h = (h ^ (unsigned char)*s) * 1099511628211ULL;
+    return h ? h : 1;
+}
 
-    if (g_debug) {
-        dbg("policy: exe=%s", exe_full);
-        dbg("policy: active=%d (has_allow=%d allow_match=%d deny_match=%d)",
-            g_active, has_allow, allow_match, (deny_match_env || deny_match_file));
-    }
+static time_t dedup_now(void) {
+    struct timespec ts;
+    clock_gettime(CLOCK_MONOTONIC, &ts);
+    return ts.tv_sec;
 }
 
-#define MAX_NODES 64
-static char g_nodes[MAX_NODES][NAME_MAX];
-static int  g_nodes_n = 0;
+static void log_summary(const struct dedup_slot *e, time_t now) {
+    long secs = (long)(now - e->since);
+    log_debug("blocked %s: %s (x%u in last %lds)", e->event, e->path, e->repeats, secs > 0 ? secs : 1);
+}
 
-// --------- detected NVIDIA PCI BDFs ---------
-// store "0000:01:00.0" etc (used only to hide by-path entries and optionally sysfs config)
-#define MAX_BDFS 8
-static char g_bdfs[MAX_BDFS][32];
-static int  g_bdfs_n = 0;
+// log_debug("blocked %s: %s", event, path), minus the repeats.
+static void log_blocked(const char *event, const char *path) {
+    if (g_log_level < NH_LOG_DEBUG) return;
+    if (!path) path = "(null)";
+    if (!g_log_dedup || __atomic_test_and_set(&g_dedup_lock, __ATOMIC_ACQUIRE)) {
+        log_debug("blocked %s: %s", event, path);
+        return;
+    }
+    time_t now = dedup_now();
+    uint64_t key = dedup_key(event, path);
+    struct dedup_slot *e = NULL, *lru = &g_dedup[0];
+    for (int i = 0; i < DEDUP_SLOTS; i++) {
+        if (g_dedup[i].key == key) { e = &g_dedup[i]; break; }
+        if (g_dedup[i].used < lru->used) lru = &g_dedup[i];
+    }
+    struct dedup_slot out = { 0 };
+    int log_plain = 1;
+    if (!e) {
+        if (lru->key && lru->repeats) out = *lru;
+        e = lru;
+        e->key = key;
+        e->event = event;
+        snprintf(e->path, sizeof(e->path), "%s", path);
+        e->repeats = 0;
+        e->since = now;
+    } else if (now - e->since < DEDUP_WINDOW) {
+        e->repeats++;
+        log_plain = 0;
+    } else {
+        if (e->repeats) {
+            e->repeats++;
+            out = *e;
+            log_plain = 0;
+        }
+        e->repeats = 0;
+        e->since = now;
+    }
+    e->used = ++g_dedup_tick;
+    __atomic_clear(&g_dedup_lock, __ATOMIC_RELEASE);
 
-// linux_dirent64 for getdents64
-struct linux_dirent64 {
-    uint64_t d_ino;
-    int64_t  d_off;
-    unsigned short d_reclen;
-    unsigned char  d_type;
-    char d_name[];
-};
+    if (out.key) log_summary(&out, now);
+    if (log_plain) log_debug("blocked %s: %s", event, path);
+}
 
-static void dbg(const char *fmt, ...) {
-    if (!g_debug) return;
-    va_list ap; va_start(ap, fmt);
-    fprintf(stderr, "[libnvidia-hide] ");
-    vfprintf(stderr, fmt, ap);
-    fprintf(stderr, "\n");
-    va_end(ap);
+// Counts still pending at exit, so a retry loop doesn't end on its first line.
+__attribute__((destructor)) static void nh_dtor(void) {
+    if (!g_log_dedup || g_log_level < NH_LOG_DEBUG) return;
+    if (__atomic_test_and_set(&g_dedup_lock, __ATOMIC_ACQUIRE)) return;
+    time_t now = dedup_now();
+    for (int i = 0; i < DEDUP_SLOTS; i++) {
+        if (g_dedup[i].key && g_dedup[i].repeats) log_summary(&g_dedup[i], now);
+        g_dedup[i].repeats = 0;
+    }
+    __atomic_clear(&g_dedup_lock, __ATOMIC_RELEASE);
 }
 
+// Names for LIBNVIDIAHIDE_*_ERRNO and the audit trail; others go by number.
+static const struct { const char *name; int err; } g_errno_names[] = {
+    { "ENOENT", ENOENT }, { "EACCES", EACCES }, { "EPERM", EPERM },
+    { "ENODEV", ENODEV }, { "ENXIO", ENXIO },
+};
 
-static int read_file_raw(const char *path, char *buf, size_t bufsz) {
-    int fd = (int)syscall(SYS_openat, AT_FDCWD, path, O_RDONLY | O_CLOEXEC, 0);
-    if (fd < 0) return -1;
-    ssize_t n = read(fd, buf, bufsz - 1);
-    close(fd);
-    if (n <= 0) return -1;
-    buf[n] = 0;
-    trim(buf);
-    return 0;
+static const char *errno_name(int err, char *buf, size_t sz) {
+    snprintf(buf, sz, "%d", err);
+    for (size_t i = 0; i < sizeof(g_errno_names)/sizeof(g_errno_names[0]); i++)
+        if (g_errno_names[i].err == err) snprintf(buf, sz, "%s", g_errno_names[i].name);
+    return buf;
 }
 
-static int parse_hex(const char *s, unsigned *out) {
-    unsigned v = 0;
-    if (sscanf(s, "0x%x", &v) == 1 || sscanf(s, "%x", &v) == 1) { *out = v; return 0; }
-    return -1;
+// ---------- audit trail ----------
+// {"ts":1700000000.123456,"pid":42,"event":"openat","path":"/dev/nvidia0","action":"ENOENT"}
+// event is the hook (or "readdir" for a skipped entry, whose path is dir/name
+// when the directory is known); action is the errno handed back, or "skip".
+// Like log lines, each goes out in one write() on an O_APPEND fd. Bytes >= 0x80
+// are copied as they are, so a path that isn't UTF-8 yields a line that isn't
+// strict JSON.
+
+// Escapes s into out, never splitting an escape; stops early when out is full.
+static size_t json_escape(char *out, size_t cap, const char *s) {
+    size_t n = 0;
+    for (; *s; s++) {
+        unsigned char c = (unsigned char)*s;
+        char esc[8];
+        size_t len = 1;
+        if (c == '"' || c == '\\') { esc[0] = '\\'; esc[1] = (char)c; len = 2; }
+        else if (c < 0x20) len = (size_t)snprintf(esc, sizeof(esc), "\\u%04x", c);
+        else esc[0] = (char)c;
+        if (n + len >= cap) break;
+        memcpy(out + n, esc, len);
+        n += len;
+    }
+    out[n] = 0;
+    return n;
 }
 
-static void add_node(const char *name) {
-    if (!name || !*name) return;
-    for (int i=0;i<g_nodes_n;i++) if (!strcmp(g_nodes[i], name)) return;
-    if (g_nodes_n >= MAX_NODES) return;
-    snprintf(g_nodes[g_nodes_n++], NAME_MAX, "%s", name);
+static void audit_event(const char *event, const char *path, int err) {
+    int fd = __atomic_load_n(&g_audit_fd, __ATOMIC_ACQUIRE);
+    if (fd < 0) return;
+    int saved_errno = errno;
+
+    char action[16] = "skip";
+    if (err) errno_name(err, action, sizeof(action));
+    char esc[2 * PATH_MAX];
+    json_escape(esc, sizeof(esc), path ? path : "");
+    struct timespec ts;
+    clock_gettime(CLOCK_REALTIME, &ts);
+
+    char line[2 * PATH_MAX + 256];
+    int n = snprintf(line, sizeof(line),
+                     "{\"ts\":%lld.%06ld,\"pid\":%d,\"event\":\"%s\",\"path\":\"%s\",\"action\":\"%s\"}\n",
+                     (long long)ts.tv_sec, ts.tv_nsec / 1000, (int)getpid(), event, esc, action);
+    if (n > 0 && (size_t)n < sizeof(line)) {
+        ssize_t w = write(fd, line, (size_t)n);
+        (void)w;
+    }
+    errno = saved_errno;
 }
 
-static int is_node(const char *name) {
-    if (!name) return 0;
-    for (int i=0;i<g_nodes_n;i++) if (!strcmp(g_nodes[i], name)) return 1;
-    return 0;
+static void parse_log_level_env(void) {
+    static const char *const names[] = { "off", "error", "info", "debug", "trace" };
+    const char *env = getenv("LIBNVIDIAHIDE_LOG_LEVEL");
+    if (!env || !*env) return;
+    for (int i = 0; i < (int)(sizeof(names)/sizeof(names[0])); i++) {
+        if (!strcasecmp(env, names[i])) { g_log_level = i; return; }
+    }
 }
 
-static void add_bdf(const char *bdf) {
-    if (!bdf || !*bdf) return;
-    for (int i=0;i<g_bdfs_n;i++) if (!strcmp(g_bdfs[i], bdf)) return;
-    if (g_bdfs_n >= MAX_BDFS) return;
-    snprintf(g_bdfs[g_bdfs_n++], sizeof(g_bdfs[g_bdfs_n-1]), "%s", bdf);
+// Some loaders shrug off EACCES/ENODEV on a device node but log ENOENT loudly.
+static void parse_errno_env(const char *var, int *out) {
+    const char *env = getenv(var);
+    if (!env || !*env) return;
+    for (size_t i = 0; i < sizeof(g_errno_names)/sizeof(g_errno_names[0]); i++) {
+        if (!strcasecmp(env, g_errno_names[i].name)) { *out = g_errno_names[i].err; return; }
+    }
+    char *end;
+    long v = strtol(env, &end, 10);
+    if (*end || v <= 0 || v > 4095) {
+        log_error("init: ignoring %s=%s, expected an errno name or number", var, env);
+        return;
+    }
+    *out = (int)v;
 }
 
-static int drm_entry_is_nvidia(const char *entry) {
-    char vendor_path[PATH_MAX];
-    snprintf(vendor_path, sizeof(vendor_path), "/sys/class/drm/%s/device/vendor", entry);
-    char buf[64];
-    if (read_file_raw(vendor_path, buf, sizeof(buf)) != 0) return 0;
-    unsigned v=0;
-    if (parse_hex(buf, &v) != 0) return 0;
-    return (v == 0x10de);
+static void parse_vendor_env(void) {
+    const char *env = getenv("LIBNVIDIAHIDE_VENDOR");
+    if (vendor_sel_parse(&g_vendor_sel, env) != 0)
+        log_error("vendor: ignoring unparsable LIBNVIDIAHIDE_VENDOR=%s", env);
+    targets_init(&g_targets, &g_vendor_sel);   // profile and the HIDE_GRAPHICS/HIDE_COMPUTE categories
 }
 
-// scan /sys/class/drm via raw getdents64 (so we do NOT depend on libc readdir while initializing)
 static void scan_nodes_raw(void) {
-    int fd = (int)syscall(SYS_openat, AT_FDCWD, "/sys/class/drm", O_RDONLY|O_DIRECTORY|O_CLOEXEC, 0);
-    if (fd < 0) return;
+    static struct nh_drm_node found[MAX_NODES];
+    if (nh_sysroot()[0]) log_debug("discover: reading sysfs under %s (LIBNVIDIAHIDE_SYSROOT)", nh_sysroot());
+    int n = nh_scan_drm(found, MAX_NODES);
+    for (int i=0;i<n;i++) {
+        if (!vendor_sel_matches(&g_vendor_sel, &found[i])) {
+            int j = targets_node_partner(&g_vendor_sel, nh_sysroot(), found, n, i);
+            if (j < 0) continue;
+            log_debug("discover: %s paired with %s", found[i].name, found[j].name);
+        } else if (!targets_bdf_selected(found[i].bdf)) {
+            log_debug("discover: %s (%s) not selected by ONLY_BDF/EXCEPT_BDF", found[i].name, found[i].bdf);
+            continue;
+        } else {
+            log_debug("discover: %s matched by %s", found[i].name, found[i].vendor_from);
+        }
+        targets_add_node(&g_targets, found[i].name);
+        targets_add_bdf(&g_targets, found[i].bdf);
+    }
+}
 
-    char buf[8192];
-    for (;;) {
-        int nread = (int)syscall(SYS_getdents64, fd, buf, (int)sizeof(buf));
-        if (nread <= 0) break;
+// ---------- topology cache ----------
+// Text file: a key line, then "node <name>" / "bdf <bdf>" lines. The key holds
+// the settings that shape discovery (vendor, ONLY/EXCEPT_BDF, SYSROOT) and
+// what the hardware looks like: a hash of the /sys/class/drm entry names,
+// whether /proc/driver/nvidia exists, and the boot_id. Not the directory's
+// mtime, which sysfs doesn't bump when a GPU is hotplugged. Any mismatch is
+// a miss. Raw syscalls throughout so our own hooks never judge the cache file, and it is replaced by rename() so readers in concurrent
+// processes see either the old or the new file, never a torn one.
+static int topo_cache_path(char *out, size_t sz) {
+    const char *rt = getenv("XDG_RUNTIME_DIR");
+    if (!rt || rt[0] != '/') return -1;
+    int m = snprintf(out, sz, "%s/nvidia-hide-topo", rt);
+    return (m < 0 || (size_t)m >= sz) ? -1 : 0;
+}
 
-        int bpos = 0;
-        while (bpos < nread) {
-            struct linux_dirent64 *d = (struct linux_dirent64*)(buf + bpos);
-            const char *n = d->d_name;
-            if (n[0] != '.') {
-                if (!strncmp(n, "card", 4) || !strncmp(n, "renderD", 7)) {
-                    if (drm_entry_is_nvidia(n)) add_node(n);
-                }
+// FNV-1a, for the cache key's parts and the LIBNVIDIAHIDE_TOPO key.
+static uint32_t topo_hash(const char *s) {
+    uint32_t h = 2166136261u;
+    for (const char *p = s; *p; p++) h = (h ^ (unsigned char)*p) * 16777619u;
+    return h;
+}
+
+// Sum of the names' hashes, so the order the kernel lists them in doesn't
+// matter, and their count.
+static int topo_drm_names(const char *drm, uint32_t *sum, int *count) {
+    int fd = (int)syscall(SYS_openat, AT_FDCWD, drm, O_RDONLY|O_DIRECTORY|O_CLOEXEC, 0);
+    if (fd < 0) return -1;
+    char buf[4096];
+    long n;
+    *sum = 0;
+    *count = 0;
+    while ((n = syscall(SYS_getdents64, fd, buf, sizeof(buf))) > 0) {
+        for (long off = 0; off < n; ) {
+            struct linux_dirent64 *e = (struct linux_dirent64 *)(buf + off);
+            if (strcmp(e->d_name, ".") && strcmp(e->d_name, "..")) {
+                *sum += topo_hash(e->d_name);
+                (*count)++;
             }
-            bpos += d->d_reclen;
+            off += e->d_reclen;
         }
     }
     close(fd);
+    return n < 0 ? -1 : 0;
 }
 
-static void discover_bdfs_from_nodes(void) {
-    // resolve /sys/class/drm/<node>/device -> .../<BDF>
-    for (int i=0;i<g_nodes_n;i++) {
-        char linkpath[PATH_MAX];
-        snprintf(linkpath, sizeof(linkpath), "/sys/class/drm/%s/device", g_nodes[i]);
+static int topo_cache_key(char *out, size_t sz) {
+    char path[PATH_MAX], boot_id[64] = "";
+    snprintf(path, sizeof(path), "%s/sys/class/drm", nh_sysroot());
+    uint32_t names;
+    int names_n;
+    if (topo_drm_names(path, &names, &names_n) != 0) return -1;
+    snprintf(path, sizeof(path), "%s/proc/driver/nvidia", nh_sysroot());
+    int nvidia_proc = syscall(SYS_faccessat, AT_FDCWD, path, F_OK, 0) == 0;
+    snprintf(path, sizeof(path), "%s/proc/sys/kernel/random/boot_id", nh_sysroot());
+    if (read_file_raw(path, boot_id, sizeof(boot_id)) != 0) boot_id[0] = 0;
+    boot_id[strcspn(boot_id, "\n")] = 0;
+    const char *vendor_env = getenv("LIBNVIDIAHIDE_VENDOR");
+    const char *only_env = getenv("LIBNVIDIAHIDE_ONLY_BDF");
+    const char *except_env = getenv("LIBNVIDIAHIDE_EXCEPT_BDF");
+    int m = snprintf(out, sz, "nvidia-hide-topo 2 vendor=%s only=%s except=%s sysroot=%s drm=%08x/%d nvidia=%d boot=%s",
+                     vendor_env && *vendor_env ? vendor_env : "default",
+                     only_env ? only_env : "", except_env ? except_env : "", nh_sysroot(),
+                     names, names_n, nvidia_proc, boot_id);
+    return (m < 0 || (size_t)m >= sz || strchr(out, '\n')) ? -1 : 0;
+}
 
-        char target[PATH_MAX];
-        ssize_t n = readlink(linkpath, target, sizeof(target)-1);
-        if (n <= 0) continue;
-        target[n] = 0;
+static int topo_cache_load(const char *path, const char *key) {
+    int fd = (int)syscall(SYS_openat, AT_FDCWD, path, O_RDONLY | O_CLOEXEC, 0);
+    if (fd < 0) return 0;
+    static char buf[8192];
+    ssize_t len = 0, n;
+    while (len < (ssize_t)sizeof(buf) - 1 && (n = read(fd, buf + len, sizeof(buf) - 1 - (size_t)len)) > 0) len += n;
+    close(fd);
+    buf[len] = 0;
+
+    char *next = strchr(buf, '\n');
+    if (!next) return 0;
+    *next++ = 0;
+    if (strcmp(buf, key) != 0) return 0;
+
+    for (char *line = next; *line; line = next) {
+        next = strchr(line, '\n');
+        if (!next) break;   // unterminated: not written by us
+        *next++ = 0;
+        if (!strncmp(line, "node ", 5)) targets_add_node(&g_targets, line + 5);
+        else if (!strncmp(line, "bdf ", 4)) targets_add_bdf(&g_targets, line + 4);
+    }
+    return 1;
+}
 
-        const char *base = strrchr(target, '/');
-        base = base ? base+1 : target;
+static void topo_cache_store(const char *path, const char *key) {
+    char buf[8192], tmp[PATH_MAX];
+    int len = snprintf(buf, sizeof(buf), "%s\n", key);
+    for (int i=0;i<g_targets.nodes_n && len < (int)sizeof(buf);i++)
+        len += snprintf(buf + len, sizeof(buf) - (size_t)len, "node %s\n", g_targets.nodes[i]);
+    for (int i=0;i<g_targets.bdfs_n && len < (int)sizeof(buf);i++)
+        len += snprintf(buf + len, sizeof(buf) - (size_t)len, "bdf %s\n", g_targets.bdfs[i]);
+    if (len >= (int)sizeof(buf)) return;
+
+    int m = snprintf(tmp, sizeof(tmp), "%s.%d.tmp", path, (int)getpid());
+    if (m < 0 || (size_t)m >= sizeof(tmp)) return;
+    int fd = (int)syscall(SYS_openat, AT_FDCWD, tmp, O_WRONLY | O_CREAT | O_TRUNC | O_CLOEXEC, 0600);
+    if (fd < 0) return;
+    int ok = write(fd, buf, (size_t)len) == len;
+    ok = close(fd) == 0 && ok;
+    if (!ok || rename(tmp, path) != 0) unlink(tmp);
+}
 
-        if (strchr(base, ':') && strchr(base, '.')) add_bdf(base);
-    }
+// ---------- topology handed down through the environment ----------
+// After discovering, we hand LIBNVIDIAHIDE_TOPO to exec'd children (which
+// inherit the library through LD_PRELOAD) so they can skip the sysfs walk.
+// Discovery only renders it into g_topo_env. When LD_PRELOAD loaded us,
+// nh_ctor does the setenv(), before main() starts any thread that could be
+// reading environ; otherwise (dlopen()ed into a running program, a reloaded
+// discovery) environ is left alone and spawn_env adds it to the child's
+// environment instead.
+//   v1 key=<hash of the cache key> nodes=card1,renderD129 bdfs=0000:01:00.0
+// The key is the same one the on-disk cache uses, so a child with another
+// vendor/BDF selection, or after a hotplug, walks sysfs again. Anything
+// malformed is ignored the same way.
+static int topo_node_name_valid(const char *s, size_t len) {
+    size_t pre = len > 7 && !strncmp(s, "renderD", 7) ? 7 : len > 4 && !strncmp(s, "card", 4) ? 4 : 0;
+    if (!pre || len >= NAME_MAX) return 0;
+    for (size_t i = pre; i < len; i++) if (s[i] < '0' || s[i] > '9') return 0;
+    return 1;
 }
 
-static void nh_init(void) {
-    if (__atomic_load_n(&g_inited, __ATOMIC_ACQUIRE)) return;
+static int topo_bdf_valid(const char *s, size_t len) {
+    if (len >= sizeof(g_targets.bdfs[0]) || !memchr(s, ':', len)) return 0;
+    for (size_t i = 0; i < len; i++) if (!strchr("0123456789abcdefABCDEF:.", s[i])) return 0;
+    return 1;
+}
 
-    int expected = 0;
-    if (!__atomic_compare_exchange_n(&g_initializing, &expected, 1, 0,
-        __ATOMIC_ACQ_REL, __ATOMIC_RELAXED)) {
-        while (!__atomic_load_n(&g_inited, __ATOMIC_ACQUIRE)) sched_yield();
-        return;
+// Checks the whole value first so a bad entry never leaves a partial set behind.
+static int topo_env_load(const char *val, const char *key) {
+    char want[32];
+    snprintf(want, sizeof(want), "v1 key=%08x ", topo_hash(key));
+    if (!val || strncmp(val, want, strlen(want)) != 0) return 0;
+    const char *nodes = val + strlen(want);
+    if (strncmp(nodes, "nodes=", 6) != 0) return 0;
+    nodes += 6;
+    const char *bdfs = strstr(nodes, " bdfs=");
+    if (!bdfs) return 0;
+    size_t nodes_len = (size_t)(bdfs - nodes);
+    bdfs += 6;
+
+    for (int pass = 0; pass < 2; pass++) {
+        for (int list = 0; list < 2; list++) {
+            const char *p = list ? bdfs : nodes;
+            const char *end = list ? p + strlen(p) : p + nodes_len;
+            while (p < end) {
+                const char *comma = memchr(p, ',', (size_t)(end - p));
+                size_t len = (size_t)((comma ? comma : end) - p);
+                char item[NAME_MAX];
+                if (list ? !topo_bdf_valid(p, len) : !topo_node_name_valid(p, len)) return 0;
+                if (pass) {
+                    snprintf(item, sizeof(item), "%.*s", (int)len, p);
+                    if (list) targets_add_bdf(&g_targets, item);
+                    else targets_add_node(&g_targets, item);
+                }
+                p += len + (comma != NULL);
+            }
         }
+    }
+    return 1;
+}
 
-        const char *dbg_env = getenv("LIBNVIDIAHIDE_DEBUG");
-    if (dbg_env && strcmp(dbg_env, "0") != 0) g_debug = 1;
+static char g_topo_env[4096];
+
+static void topo_env_store(const char *key) {
+    char buf[sizeof(g_topo_env)];
+    int len = snprintf(buf, sizeof(buf), "v1 key=%08x nodes=", topo_hash(key));
+    for (int i=0;i<g_targets.nodes_n && len < (int)sizeof(buf);i++)
+        len += snprintf(buf + len, sizeof(buf) - (size_t)len, "%s%s", i ? "," : "", g_targets.nodes[i]);
+    if (len < (int)sizeof(buf)) len += snprintf(buf + len, sizeof(buf) - (size_t)len, " bdfs=");
+    for (int i=0;i<g_targets.bdfs_n && len < (int)sizeof(buf);i++)
+        len += snprintf(buf + len, sizeof(buf) - (size_t)len, "%s%s", i ? "," : "", g_targets.bdfs[i]);
+    if (len >= (int)sizeof(buf)) return;
+    memcpy(g_topo_env, buf, (size_t)len + 1);
+}
 
-    g_active = 1;
-    apply_policy_from_exe();
+static volatile int g_discovered = 0;
+static unsigned g_dcache_epoch = 1;   // see decision cache below
 
+static void discover_nvidia(void) {
+    if (g_discovered) return;
+    g_discovered = 1;
 
-    if (!g_active) {
-        if (g_debug) dbg("init: inactive for this process; skipping discovery/hooks");
-        return;
+    char cache_path[PATH_MAX], cache_key[512];
+    int have_key = topo_cache_key(cache_key, sizeof(cache_key)) == 0;
+    const char *from = "sysfs";
+    const char *topo_env = getenv("LIBNVIDIAHIDE_TOPO");
+    int use_cache = g_cache && have_key && topo_cache_path(cache_path, sizeof(cache_path)) == 0;
+    if (have_key && topo_env && topo_env_load(topo_env, cache_key)) {
+        from = "LIBNVIDIAHIDE_TOPO";
+    } else {
+        if (topo_env) log_debug("init: ignoring stale or malformed LIBNVIDIAHIDE_TOPO");
+        if (use_cache && topo_cache_load(cache_path, cache_key)) {
+            from = "cache";
+        } else {
+            scan_nodes_raw();
+            if (use_cache) topo_cache_store(cache_path, cache_key);
+        }
+        if (have_key) topo_env_store(cache_key);
     }
-    if (!g_active) {
-        if (g_debug) dbg("init: inactive for this process; skipping discovery");
-        return;
+    log_info("init: topology from %s", from);
+
+    char rules_path[PATH_MAX];
+    build_xdg_path(rules_path, sizeof(rules_path), "hide-targets");
+    targets_load_rules(&g_targets, rules_path);
+    build_xdg_path(rules_path, sizeof(rules_path), "hide-exceptions");
+    targets_load_keep(&g_targets, rules_path);
+    __atomic_add_fetch(&g_dcache_epoch, 1, __ATOMIC_RELEASE);
+
+    log_info("init: vendor=%04x profile=%s device_ids=%d", g_vendor_sel.vendor, g_targets.profile->name, g_vendor_sel.device_ids_n);
+    log_info("init: nvidia_nodes=%d nvidia_bdfs=%d hide_rules=%d keep_rules=%d hide_dev=%d",
+             g_targets.nodes_n, g_targets.bdfs_n, g_targets.rules_n, g_targets.keep_n, g_targets.hide_dev_n);
+    for (int i=0;i<g_targets.nodes_n;i++) log_info("  node: %s", g_targets.nodes[i]);
+    for (int i=0;i<g_targets.bdfs_n;i++) log_info("  bdf:  %s", g_targets.bdfs[i]);
+}
+
+// ---------- policy dump ----------
+// LIBNVIDIAHIDE_DUMP_POLICY=1: at the end of init, write one JSON object
+// describing the verdict and what discovery found, to the log file or stderr
+// and whatever the log level, so it can be pasted into a bug report whole:
+// {"pid":42,"exe":"/usr/bin/foo","basename":"foo","decided_by":"lists",
+//  "allow_match_env":false,...,"active":true,"vendor":"10de","profile":"nvidia",
+//  "nodes":["card1"],"bdfs":["0000:01:00.0"],"config_files":[...]}
+// decided_by names the check that settled it; the match fields are null
+// unless the allow/deny lists were read.
+struct nh_jbuf { char *p; size_t n, cap; };
+
+static void jb_raw(struct nh_jbuf *b, const char *s) {
+    size_t len = strlen(s);
+    if (!b->p) return;
+    if (b->n + len + 1 > b->cap) {
+        size_t cap = (b->n + len + 1) * 2;
+        char *v = (char*)realloc(b->p, cap);
+        if (!v) { free(b->p); b->p = NULL; return; }
+        b->p = v;
+        b->cap = cap;
     }
+    memcpy(b->p + b->n, s, len + 1);
+    b->n += len;
+}
 
+static void jb_field(struct nh_jbuf *b, const char *key, const char *value) {
+    char esc[2 * PATH_MAX];
+    jb_raw(b, ",\"");
+    jb_raw(b, key);
+    jb_raw(b, "\":\"");
+    json_escape(esc, sizeof(esc), value);
+    jb_raw(b, esc);
+    jb_raw(b, "\"");
+}
 
-    scan_nodes_raw();
-    discover_bdfs_from_nodes();
+static void jb_flag(struct nh_jbuf *b, const char *key, int known, int value) {
+    jb_raw(b, ",\"");
+    jb_raw(b, key);
+    jb_raw(b, known ? (value ? "\":true" : "\":false") : "\":null");
+}
 
-    dbg("init: nvidia_nodes=%d nvidia_bdfs=%d", g_nodes_n, g_bdfs_n);
-    for (int i=0;i<g_nodes_n;i++) dbg("  node: %s", g_nodes[i]);
-    for (int i=0;i<g_bdfs_n;i++) dbg("  bdf:  %s", g_bdfs[i]);
+static void jb_array(struct nh_jbuf *b, const char *key, const char *const *items, int n) {
+    char esc[2 * PATH_MAX];
+    jb_raw(b, ",\"");
+    jb_raw(b, key);
+    jb_raw(b, "\":[");
+    for (int i = 0; i < n; i++) {
+        json_escape(esc, sizeof(esc), items[i]);
+        jb_raw(b, i ? ",\"" : "\"");
+        jb_raw(b, esc);
+        jb_raw(b, "\"");
+    }
+    jb_raw(b, "]");
+}
 
-    __atomic_store_n(&g_inited, 1, __ATOMIC_RELEASE);
-    __atomic_store_n(&g_initializing, 0, __ATOMIC_RELEASE);
+static void dump_policy(void) {
+    const char *env = getenv("LIBNVIDIAHIDE_DUMP_POLICY");
+    if (!env || strcmp(env, "0") == 0) return;
+    int saved_errno = errno;
+
+    struct nh_jbuf b = { (char*)malloc(4096), 0, 4096 };
+    if (b.p) b.p[0] = 0;
+    char num[64];
+    snprintf(num, sizeof(num), "{\"pid\":%d", (int)getpid());
+    jb_raw(&b, num);
+
+    // The early verdicts (override, REQUIRE_ENV, ...) never read the exe.
+    char exe_buf[PATH_MAX];
+    const char *exe = g_policy_record.exe;
+    if (!*exe && read_self_exe(exe_buf, sizeof(exe_buf)) >= 0) exe = exe_buf;
+    jb_field(&b, "exe", exe);
+    jb_field(&b, "basename", *exe ? base_name(exe) : "");
+    jb_field(&b, "decided_by", g_policy_record.by ? g_policy_record.by : "");
+    int lists = g_policy_record.by && strcmp(g_policy_record.by, "lists") == 0;
+    const struct nh_decision *d = &g_policy_record.d;
+    jb_flag(&b, "allow_match_env", lists, d->allow_match_env);
+    jb_flag(&b, "allow_match_file", lists, d->allow_match_file);
+    jb_flag(&b, "deny_match_env", lists, d->deny_match_env);
+    jb_flag(&b, "deny_match_file", lists, d->deny_match_file);
+    jb_flag(&b, "has_allow", lists, d->has_allow);
+    jb_flag(&b, "active", 1, g_active);
+
+    snprintf(num, sizeof(num), "%04x", g_vendor_sel.vendor);
+    jb_field(&b, "vendor", num);
+    jb_field(&b, "profile", g_targets.profile ? g_targets.profile->name : "");
+    const char *items[MAX_NODES];
+    for (int i = 0; i < g_targets.nodes_n; i++) items[i] = g_targets.nodes[i];
+    jb_array(&b, "nodes", items, g_targets.nodes_n);
+    for (int i = 0; i < g_targets.bdfs_n; i++) items[i] = g_targets.bdfs[i];
+    jb_array(&b, "bdfs", items, g_targets.bdfs_n);
+
+    // Same paths apply_policy_from_exe and discover_nvidia read; a raw
+    // faccessat so the check itself isn't judged by our hooks.
+    struct nh_policy_files allow, deny;
+    policy_files(&allow, "allowlist");
+    policy_files(&deny, "denylist");
+    char rules[PATH_MAX], keep[PATH_MAX];
+    build_xdg_path(rules, sizeof(rules), "hide-targets");
+    build_xdg_path(keep, sizeof(keep), "hide-exceptions");
+    const char *candidates[] = { allow.system, allow.user, deny.system, deny.user, rules, keep };
+    int found = 0;
+    for (size_t i = 0; i < sizeof(candidates)/sizeof(candidates[0]); i++)
+        if (candidates[i][0] && syscall(SYS_faccessat, AT_FDCWD, candidates[i], F_OK) == 0)
+            items[found++] = candidates[i];
+    jb_array(&b, "config_files", items, found);
+    jb_raw(&b, "}\n");
+
+    if (b.p) {
+        int fd = __atomic_load_n(&g_log_fd, __ATOMIC_ACQUIRE);
+        ssize_t w = write(fd >= 0 ? fd : STDERR_FILENO, b.p, b.n);
+        (void)w;
+        free(b.p);
+    }
+    errno = saved_errno;
 }
 
-static inline void ensure_init(void) { if (!__atomic_load_n(&g_inited, __ATOMIC_ACQUIRE)) nh_init(); }
+// Signal context: only flip the flag. The re-read happens lazily in the next
+// hook call via maybe_reload_policy().
+static struct sigaction g_prev_sighup;
 
-// ---------- deny logic ----------
+static void on_sighup(int sig, siginfo_t *info, void *uctx) {
+    __atomic_store_n(&g_reload_requested, 1, __ATOMIC_RELEASE);
+    if (g_prev_sighup.sa_flags & SA_SIGINFO) {
+        if (g_prev_sighup.sa_sigaction) g_prev_sighup.sa_sigaction(sig, info, uctx);
+    } else if (g_prev_sighup.sa_handler != SIG_DFL && g_prev_sighup.sa_handler != SIG_IGN) {
+        g_prev_sighup.sa_handler(sig);
+    }
+}
 
-static int is_nvidia_path(const char *p) {
-    if (!g_active) return 0;
-    if (!p) return 0;
-    ensure_init();
+static void install_sighup_handler(void) {
+    struct sigaction sa;
+    memset(&sa, 0, sizeof(sa));
+    sa.sa_sigaction = on_sighup;
+    sa.sa_flags = SA_SIGINFO | SA_RESTART;
+    sigemptyset(&sa.sa_mask);
+    if (sigaction(SIGHUP, &sa, &g_prev_sighup) == 0) log_info("init: SIGHUP reloads policy");
+}
 
-    // Device nodes
-    if (!strncmp(p, "/dev/nvidia", 10)) return 1;
+// ---------- metrics ----------
+// Counters for measuring overhead, bumped with relaxed atomics on the hot
+// path. libnvidiahide_metrics() formats them as one JSON object:
+// {"pid":42,"paths_checked":1200,"paths_blocked":3,"entries_hidden":5,
+//  "dlopens_blocked":1,"cache_hits":1100,"cache_misses":90}
+// paths_checked counts the open/stat/access-style checks made while hiding is
+// on; cache_hits and cache_misses are decision-cache lookups, so paths too
+// long to cache are in neither. With LIBNVIDIAHIDE_METRICS=1 (or a file
+// path), SIGUSR1 appends the same line to $XDG_RUNTIME_DIR/nvidia-hide-metrics
+// (or that file).
+static struct nh_metrics {
+    unsigned long paths_checked;
+    unsigned long paths_blocked;    // by deny_path; the self-test watches it too
+    unsigned long entries_hidden;
+    unsigned long dlopens_blocked;
+    unsigned long cache_hits;
+    unsigned long cache_misses;
+} g_metrics;
+
+#define NH_COUNT(field) __atomic_add_fetch(&g_metrics.field, 1, __ATOMIC_RELAXED)
+
+static const struct { const char *name; size_t off; } g_metric_fields[] = {
+    { "paths_checked", offsetof(struct nh_metrics, paths_checked) },
+    { "paths_blocked", offsetof(struct nh_metrics, paths_blocked) },
+    { "entries_hidden", offsetof(struct nh_metrics, entries_hidden) },
+    { "dlopens_blocked", offsetof(struct nh_metrics, dlopens_blocked) },
+    { "cache_hits", offsetof(struct nh_metrics, cache_hits) },
+    { "cache_misses", offsetof(struct nh_metrics, cache_misses) },
+};
 
-    if (!strncmp(p, "/dev/dri/", 9)) {
-        const char *base = p + 9;
-        if (is_node(base)) return 1;
-    }
+// Appends s at *n, as far as it fits; *n keeps counting past the end.
+static void metrics_put(char *buf, size_t len, size_t *n, const char *s) {
+    for (; *s; s++, (*n)++) if (*n + 1 < len) buf[*n] = *s;
+}
+
+static void metrics_put_ulong(char *buf, size_t len, size_t *n, unsigned long v) {
+    char digits[24];
+    int i = (int)sizeof(digits) - 1;
+    digits[i] = 0;
+    do { digits[--i] = (char)('0' + v % 10); v /= 10; } while (v);
+    metrics_put(buf, len, n, digits + i);
+}
 
-    // NVIDIA GBM/GL/Vulkan assets
-    if (strstr(p, "nvidia-drm_gbm.so")) return 1;
-    if (strstr(p, "libGLX_nvidia.so")) return 1;
-    if (strstr(p, "/usr/share/vulkan/implicit_layer.d/nvidia")) return 1;
-    if (strstr(p, "/usr/share/vulkan/icd.d/nvidia")) return 1;
+// No stdio, so the SIGUSR1 handler can use it. Returns the full length, like
+// snprintf; the line is cut short (and still terminated) when len is too small.
+static size_t metrics_format(char *buf, size_t len) {
+    size_t n = 0;
+    metrics_put(buf, len, &n, "{\"pid\":");
+    metrics_put_ulong(buf, len, &n, (unsigned long)getpid());
+    for (size_t i = 0; i < sizeof(g_metric_fields)/sizeof(g_metric_fields[0]); i++) {
+        const unsigned long *v = (const unsigned long *)((const char *)&g_metrics + g_metric_fields[i].off);
+        metrics_put(buf, len, &n, ",\"");
+        metrics_put(buf, len, &n, g_metric_fields[i].name);
+        metrics_put(buf, len, &n, "\":");
+        metrics_put_ulong(buf, len, &n, __atomic_load_n(v, __ATOMIC_RELAXED));
+    }
+    metrics_put(buf, len, &n, "}");
+    if (len) buf[n < len ? n : len - 1] = 0;
+    return n;
+}
 
-    // Extra: block libnvidia-* opens (still only via open/openat, no dlopen dependency)
-    if (strstr(p, "/usr/lib/libnvidia-")) return 1;
+__attribute__((visibility("default"))) int libnvidiahide_metrics(char *buf, size_t len) {
+    return (int)metrics_format(buf, len);
+}
 
-    // Block PCI config reads through ANY sysfs path (bus or devices)
-    // matches ".../<BDF>/config" anywhere under /sys/
-    if (strstr(p, "/sys/") && strstr(p, "/config")) {
-        for (int i=0;i<g_bdfs_n;i++) {
-            char needle[64];
-            snprintf(needle, sizeof(needle), "/%s/config", g_bdfs[i]);
-            if (strstr(p, needle)) return 1;
-        }
+static char g_metrics_path[PATH_MAX];
+static struct sigaction g_prev_sigusr1;
+
+// Raw syscalls only, like the rest of the signal path.
+static void on_sigusr1(int sig, siginfo_t *info, void *uctx) {
+    int saved_errno = errno;
+    char line[512];
+    size_t n = metrics_format(line, sizeof(line) - 1);
+    if (n > sizeof(line) - 2) n = sizeof(line) - 2;
+    line[n++] = '\n';
+    int fd = (int)syscall(SYS_openat, AT_FDCWD, g_metrics_path, O_WRONLY | O_APPEND | O_CREAT | O_CLOEXEC, 0600);
+    if (fd >= 0) {
+        ssize_t w = write(fd, line, n);
+        (void)w;
+        close(fd);
     }
+    errno = saved_errno;
 
-    return 0;
+    if (g_prev_sigusr1.sa_flags & SA_SIGINFO) {
+        if (g_prev_sigusr1.sa_sigaction) g_prev_sigusr1.sa_sigaction(sig, info, uctx);
+    } else if (g_prev_sigusr1.sa_handler != SIG_DFL && g_prev_sigusr1.sa_handler != SIG_IGN) {
+        g_prev_sigusr1.sa_handler(sig);
+    }
 }
 
-static int is_nvidia_dirent(DIR *dirp, const char *name) {
-    if (!g_active) return 0;
-    if (!name) return 0;
-    ensure_init();
+static void install_metrics_handler(void) {
+    const char *env = getenv("LIBNVIDIAHIDE_METRICS");
+    if (!env || !*env || strcmp(env, "0") == 0) return;
+    const char *run = getenv("XDG_RUNTIME_DIR");
+    if (strchr(env, '/')) snprintf(g_metrics_path, sizeof(g_metrics_path), "%s", env);
+    else snprintf(g_metrics_path, sizeof(g_metrics_path), "%s/nvidia-hide-metrics", run && *run ? run : "/tmp");
+
+    struct sigaction sa;
+    memset(&sa, 0, sizeof(sa));
+    sa.sa_sigaction = on_sigusr1;
+    sa.sa_flags = SA_SIGINFO | SA_RESTART;
+    sigemptyset(&sa.sa_mask);
+    if (sigaction(SIGUSR1, &sa, &g_prev_sigusr1) == 0) log_info("init: SIGUSR1 appends metrics to %s", g_metrics_path);
+}
 
-    // If it scans /dev, hide /dev/nvidia* names
-    if (!strncmp(name, "nvidia", 5)) return 1;
+static void spawn_init(void);     // with the exec hooks
 
-    // Hide discovered DRM nodes (cardX/renderD*)
-    if (is_node(name)) return 1;
+// Policy is read-mostly: a hook racing a reload simply sees the old or the new
+// g_active, both of which are complete verdicts.
+static void maybe_reload_policy(void) {
+    if (!__atomic_load_n(&g_reload_requested, __ATOMIC_ACQUIRE)) return;
+    if (!__atomic_exchange_n(&g_reload_requested, 0, __ATOMIC_ACQ_REL)) return;
 
-    // If scanning /dev/dri/by-path, often includes BDF in symlink name
-    // hide if matches
-    for (int i=0;i<g_bdfs_n;i++) {
-        if (strstr(name, g_bdfs[i])) return 1;
-        // also hide without domain "01:00.0" style
-        const char *colon = strchr(g_bdfs[i], ':');
-        if (colon && strstr(name, colon+1)) return 1;
+    apply_policy_from_exe();
+    if (g_active) {
+        discover_nvidia();
+        spawn_init();
     }
+}
 
-    return 0;
+// ---------- kill switch ----------
+// While $XDG_RUNTIME_DIR/nvidia-hide-disable (or LIBNVIDIAHIDE_KILLSWITCH)
+// exists, every process behaves as if inactive; removing it turns hiding back
+// on. Hooks look at most every NH_KILLSWITCH_MS, one raw faccessat: the first
+// thread past the deadline checks, the others use the last answer.
+#define NH_KILLSWITCH_MS 100
+
+static char g_killswitch_path[PATH_MAX];    // "" when there is nowhere to look
+static long g_killswitch_checked = -NH_KILLSWITCH_MS;  // CLOCK_MONOTONIC ms
+static int g_killswitch_on = 0;
+
+static void killswitch_init(void) {
+    const char *env = getenv("LIBNVIDIAHIDE_KILLSWITCH");
+    const char *run = getenv("XDG_RUNTIME_DIR");
+    if (env) snprintf(g_killswitch_path, sizeof(g_killswitch_path), "%s", env);
+    else if (run && *run) snprintf(g_killswitch_path, sizeof(g_killswitch_path), "%s/nvidia-hide-disable", run);
 }
 
-static int deny_ret(void) { errno = ENOENT; return -1; }
+static int killswitch_engaged(void) {
+    if (!g_killswitch_path[0]) return 0;
+    struct timespec ts;
+    clock_gettime(CLOCK_MONOTONIC_COARSE, &ts);
+    long now = (long)ts.tv_sec * 1000 + ts.tv_nsec / 1000000;
+    long last = __atomic_load_n(&g_killswitch_checked, __ATOMIC_ACQUIRE);
+    if (now - last < NH_KILLSWITCH_MS ||
+        !__atomic_compare_exchange_n(&g_killswitch_checked, &last, now, 0, __ATOMIC_ACQ_REL, __ATOMIC_RELAXED))
+        return __atomic_load_n(&g_killswitch_on, __ATOMIC_ACQUIRE);
+
+    int saved_errno = errno;
+    int on = syscall(SYS_faccessat, AT_FDCWD, g_killswitch_path, F_OK) == 0;
+    errno = saved_errno;
+    if (__atomic_exchange_n(&g_killswitch_on, on, __ATOMIC_ACQ_REL) != on)
+        log_info("killswitch: %s %s, hiding %s", g_killswitch_path, on ? "exists" : "is gone", on ? "off" : "back on");
+    return on;
+}
 
-// ---------- hooks ----------
+// g_active, unless the kill switch is engaged. What the hooks test before
+// hiding anything.
+static int hiding_now(void) {
+    return g_active && !killswitch_engaged();
+}
 
-typedef int (*openat_f)(int, const char*, int, ...);
-typedef int (*open_f)(const char*, int, ...);
+// --------- environment scrub ---------
+// Offload/ICD variables that steer loaders toward the NVIDIA stack even when
+// its devices are hidden. LIBNVIDIAHIDE_SCRUB_ENV=VAR,VAR,... replaces the set.
+// "__NV_*" variables are always dropped; others only when their value mentions
+// nvidia, and path lists (VK_ICD_FILENAMES etc.) lose just the nvidia entries.
+static const char *g_scrub_default =
+    "__NV_PRIME_RENDER_OFFLOAD,__NV_PRIME_RENDER_OFFLOAD_PROVIDER,"
+    "__GLX_VENDOR_LIBRARY_NAME,__VK_LAYER_NV_optimus,"
+    "VK_ICD_FILENAMES,VK_DRIVER_FILES,__EGL_VENDOR_LIBRARY_FILENAMES";
+
+static int is_path_list_var(const char *name) {
+    return !strcmp(name, "VK_ICD_FILENAMES") || !strcmp(name, "VK_DRIVER_FILES") ||
+           !strcmp(name, "VK_ADD_DRIVER_FILES") || !strcmp(name, "__EGL_VENDOR_LIBRARY_FILENAMES");
+}
 
-int openat(int dirfd, const char *pathname, int flags, ...) {
-    static openat_f real_openat = NULL;
-    if (!real_openat) real_openat = (openat_f)dlsym(RTLD_NEXT, "openat");
+static void scrub_var(const char *name) {
+    const char *val = getenv(name);
+    if (!val) return;
+
+    if (strncmp(name, "__NV_", 5) != 0 && !strcasestr(val, "nvidia")) return;
+
+    if (is_path_list_var(name)) {
+        char kept[PATH_MAX];
+        size_t kn = 0;
+        kept[0] = 0;
+        const char *p = val;
+        while (*p) {
+            const char *q = strchr(p, ':');
+            size_t len = q ? (size_t)(q - p) : strlen(p);
+            char entry[PATH_MAX];
+            if (len && len < sizeof(entry)) {
+                memcpy(entry, p, len);
+                entry[len] = 0;
+                if (!strcasestr(entry, "nvidia") && kn + len + 2 < sizeof(kept)) {
+                    kn += (size_t)snprintf(kept + kn, sizeof(kept) - kn, "%s%s", kn ? ":" : "", entry);
+                }
+            }
+            if (!q) break;
+            p = q + 1;
+        }
+        if (kept[0]) {
+            setenv(name, kept, 1);
+            log_info("env: rewrote %s=%s", name, kept);
+            return;
+        }
+    }
 
-    if (is_nvidia_path(pathname)) return deny_ret();
+    unsetenv(name);
+    log_info("env: unset %s", name);
+}
 
-    va_list ap;
-    va_start(ap, flags);
-    int fd;
-    if (flags & O_CREAT) {
-        mode_t mode = va_arg(ap, mode_t);
-        fd = real_openat(dirfd, pathname, flags, mode);
-    } else {
-        fd = real_openat(dirfd, pathname, flags);
+// Runs from nh_init, which normally happens in our .init_array constructor
+// before main(). Libraries initialised earlier may already have cached
+// environ/getenv results, and a lazy init in a threaded program races other
+// getenv callers; both are accepted caveats.
+static void scrub_env(void) {
+    const char *list = getenv("LIBNVIDIAHIDE_SCRUB_ENV");
+    if (!list) list = g_scrub_default;
+
+    char buf[1024];
+    snprintf(buf, sizeof(buf), "%s", list);
+    char *save = NULL;
+    for (char *tok = strtok_r(buf, ",", &save); tok; tok = strtok_r(NULL, ",", &save)) {
+        trim(tok);
+        if (*tok) scrub_var(tok);
     }
-    va_end(ap);
-    return fd;
 }
 
-// Also hook open/open64 for completeness (some paths use these)
-int open(const char *pathname, int flags, ...) {
-    static open_f real_open = NULL;
-    if (!real_open) real_open = (open_f)dlsym(RTLD_NEXT, "open");
+// Set while this thread runs nh_init. Init reads its own config through
+// fopen, which lands back in our hooks; those calls must see the partial
+// state instead of waiting on an init that can't finish without them.
+static __thread int t_in_init = 0;
 
-    if (is_nvidia_path(pathname)) return deny_ret();
+static void nh_init(void) {
+    if (__atomic_load_n(&g_inited, __ATOMIC_ACQUIRE)) return;
+    if (t_in_init) return;
 
-    va_list ap;
-    va_start(ap, flags);
-    int fd;
-    if (flags & O_CREAT) {
-        mode_t mode = va_arg(ap, mode_t);
-        fd = real_open(pathname, flags, mode);
-    } else {
-        fd = real_open(pathname, flags);
+    int expected = 0;
+    if (!__atomic_compare_exchange_n(&g_initializing, &expected, 1, 0,
+        __ATOMIC_ACQ_REL, __ATOMIC_RELAXED)) {
+        while (!__atomic_load_n(&g_inited, __ATOMIC_ACQUIRE)) sched_yield();
+        return;
+        }
+    t_in_init = 1;
+
+    const char *log_env = getenv("LIBNVIDIAHIDE_LOG");
+    if (log_env && *log_env) {
+        // Raw syscall: the log file must never be judged by our own open hook.
+        int fd = (int)syscall(SYS_openat, AT_FDCWD, log_env, O_WRONLY | O_APPEND | O_CREAT | O_CLOEXEC, 0644);
+        if (fd >= 0) __atomic_store_n(&g_log_fd, fd, __ATOMIC_RELEASE);
     }
-    va_end(ap);
-    return fd;
-}
 
-int open64(const char *pathname, int flags, ...) {
-    static open_f real_open64 = NULL;
-    if (!real_open64) real_open64 = (open_f)dlsym(RTLD_NEXT, "open64");
+    const char *audit_env = getenv("LIBNVIDIAHIDE_AUDIT");
+    if (audit_env && *audit_env) {
+        // Raw syscall, like the log file.
+        int fd = (int)syscall(SYS_openat, AT_FDCWD, audit_env, O_WRONLY | O_APPEND | O_CREAT | O_CLOEXEC, 0644);
+        if (fd >= 0) __atomic_store_n(&g_audit_fd, fd, __ATOMIC_RELEASE);
+    }
 
-    if (is_nvidia_path(pathname)) return deny_ret();
+    const char *dbg_env = getenv("LIBNVIDIAHIDE_DEBUG");
+    if (dbg_env && strcmp(dbg_env, "0") != 0) g_log_level = NH_LOG_DEBUG;
+    parse_log_level_env();
 
-    va_list ap;
-    va_start(ap, flags);
-    int fd;
-    if (flags & O_CREAT) {
-        mode_t mode = va_arg(ap, mode_t);
-        fd = real_open64(pathname, flags, mode);
-    } else {
-        fd = real_open64(pathname, flags);
+    const char *dedup_env = getenv("LIBNVIDIAHIDE_LOG_DEDUP");
+    if (dedup_env && strcmp(dedup_env, "0") == 0) g_log_dedup = 0;
+
+    // Dry-run output is the debug-level would-block lines.
+    const char *dryrun_env = getenv("LIBNVIDIAHIDE_DRYRUN");
+    if (dryrun_env && strcmp(dryrun_env, "0") != 0) {
+        g_dryrun = 1;
+        if (g_log_level < NH_LOG_DEBUG) g_log_level = NH_LOG_DEBUG;
     }
-    va_end(ap);
-    return fd;
-}
 
-// Hook openat2 if present
-typedef int (*openat2_f)(int, const char*, const struct open_how*, size_t);
-int openat2(int dirfd, const char *pathname, const struct open_how *how, size_t size) {
-    static openat2_f real_openat2 = NULL;
-    if (!real_openat2) real_openat2 = (openat2_f)dlsym(RTLD_NEXT, "openat2");
+    const char *tools_env = getenv("LIBNVIDIAHIDE_BLOCK_NVIDIA_TOOLS");
+    if (tools_env && strcmp(tools_env, "0") != 0) g_block_tools = 1;
 
-    if (is_nvidia_path(pathname)) return deny_ret();
+    const char *ioctl_env = getenv("LIBNVIDIAHIDE_BLOCK_IOCTL");
+    if (ioctl_env && strcmp(ioctl_env, "0") != 0) g_block_ioctl = 1;
 
-    if (real_openat2) return real_openat2(dirfd, pathname, how, size);
-    #ifdef SYS_openat2
-    return (int)syscall(SYS_openat2, dirfd, pathname, how, size);
-    #else
-    errno = ENOSYS;
-    return -1;
-    #endif
-}
+    const char *mmap_env = getenv("LIBNVIDIAHIDE_BLOCK_MMAP");
+    if (g_block_ioctl || (mmap_env && strcmp(mmap_env, "0") != 0)) g_block_mmap = 1;
 
-/* ---- Block dlopen of NVIDIA libs ---- */
-typedef void* (*dlopen_f)(const char*, int);
+    parse_errno_env("LIBNVIDIAHIDE_DEVICE_ERRNO", &g_device_errno);
+    parse_errno_env("LIBNVIDIAHIDE_FILE_ERRNO", &g_file_errno);
 
-void *dlopen(const char *filename, int flags) {
-    static dlopen_f real_dlopen = NULL;
-    static __thread int in_hook = 0;
+    const char *stub_env = getenv("LIBNVIDIAHIDE_ICD_STUB");
+    if (stub_env && strcmp(stub_env, "0") != 0) g_icd_stub = 1;
 
-    if (!real_dlopen) {
-        if (in_hook) { errno = ENOENT; return NULL; }
-        in_hook = 1;
-        real_dlopen = (dlopen_f)dlsym(RTLD_NEXT, "dlopen");
-        in_hook = 0;
-    }
+    const char *modules_env = getenv("LIBNVIDIAHIDE_FILTER_PROCMODULES");
+    if (modules_env && strcmp(modules_env, "0") != 0) g_filter_procmodules = 1;
 
-    if (filename && (
-        strstr(filename, "nvidia") ||
-        strstr(filename, "libGLX_nvidia") ||
-        strstr(filename, "nvidia-drm_gbm.so") ||
-        strstr(filename, "libnvidia-")
-    )) {
-        errno = ENOENT;
-        return NULL;
+    const char *maps_env = getenv("LIBNVIDIAHIDE_FILTER_MAPS");
+    if (maps_env && strcmp(maps_env, "0") != 0) {
+        g_filter_maps = 1;
+        log_info("init: LIBNVIDIAHIDE_FILTER_MAPS=1: /proc/*/maps loses NVIDIA mappings, "
+                 "which can confuse tools that compare it with what they mapped");
     }
 
-    return real_dlopen ? real_dlopen(filename, flags) : NULL;
-}
+    const char *cache_env = getenv("LIBNVIDIAHIDE_CACHE");
+    if (cache_env && strcmp(cache_env, "0") != 0) g_cache = 1;
 
-/* ---- Hide NVIDIA entries from directory enumeration ---- */
-typedef struct dirent *(*readdir_f)(DIR*);
-typedef struct dirent64 *(*readdir64_f)(DIR*);
+    const char *watch_env = getenv("LIBNVIDIAHIDE_WATCH");
+    if (watch_env && strcmp(watch_env, "0") != 0) g_watch = 1;
 
-struct dirent *readdir(DIR *dirp) {
-    static readdir_f real_readdir = NULL;
-    if (!real_readdir) real_readdir = (readdir_f)dlsym(RTLD_NEXT, "readdir");
+    parse_vendor_env();
+    killswitch_init();
 
-    struct dirent *ent;
-    while ((ent = real_readdir(dirp)) != NULL) {
-        if (!is_nvidia_dirent(dirp, ent->d_name)) return ent;
-    }
-    return NULL;
-}
+    // Installed even when inactive, so a reload can turn hiding on later.
+    if (g_watch) install_sighup_handler();
+    install_metrics_handler();
 
-struct dirent64 *readdir64(DIR *dirp) {
-    static readdir64_f real_readdir64 = NULL;
-    if (!real_readdir64) real_readdir64 = (readdir64_f)dlsym(RTLD_NEXT, "readdir64");
+    g_active = 1;
+    apply_policy_from_exe();
 
-    struct dirent64 *ent;
-    while ((ent = real_readdir64(dirp)) != NULL) {
-        if (!is_nvidia_dirent(dirp, ent->d_name)) return ent;
+    if (!g_active) {
+        log_info("init: inactive for this process; skipping discovery/hooks");
+    } else {
+        discover_nvidia();
+        if (g_targets.hide_all_dri)
+            log_info("init: LIBNVIDIAHIDE_HIDE_ALL_DRI=1, hiding every DRM node of every vendor");
+        scrub_env();
+        spawn_init();
+    }
+    dump_policy();
+
+    __atomic_store_n(&g_inited, 1, __ATOMIC_RELEASE);
+    __atomic_store_n(&g_initializing, 0, __ATOMIC_RELEASE);
+    t_in_init = 0;
+}
+
+// ---------- self-test ----------
+// libnvidiahide_probe() lets a wrapper or test ask whether the library is in
+// the process and what it decided:
+//   bit 0     active for this program
+//   bit 1     logging at debug or above
+//   bits 8-15 DRM nodes discovered, capped at 255
+// It runs init if nothing has yet, and touches nothing else.
+enum { NH_PROBE_ACTIVE = 1 << 0, NH_PROBE_DEBUG = 1 << 1, NH_PROBE_NODES_SHIFT = 8 };
+
+// LIBNVIDIAHIDE_SELFTEST=1: once init is done, open a path the library hides
+// through whatever "open" the program itself would call, and print one line
+// (whatever the log level) saying whether our hook refused it.
+static void selftest_line(const char *fmt, ...) __attribute__((format(printf, 1, 2)));
+static void selftest_line(const char *fmt, ...) {
+    va_list ap; va_start(ap, fmt);
+    vlog_line(fmt, ap);
+    va_end(ap);
+}
+
+static void selftest(void) {
+    const char *env = getenv("LIBNVIDIAHIDE_SELFTEST");
+    if (!env || strcmp(env, "0") == 0) return;
+    if (!g_active) { selftest_line("selftest: SKIP, inactive for this program (see nvidia-hide check)"); return; }
+    if (g_dryrun) { selftest_line("selftest: SKIP, dry run hides nothing"); return; }
+
+    char path[PATH_MAX];
+    if (g_targets.nodes_n) snprintf(path, sizeof(path), "/dev/dri/%s", g_targets.nodes[0]);
+    else if (g_targets.profile->dev_prefixes[0]) snprintf(path, sizeof(path), "%sctl", g_targets.profile->dev_prefixes[0]);
+    else { selftest_line("selftest: SKIP, no GPU node to try"); return; }
+
+    typedef int (*open_f)(const char*, int, ...);
+    open_f seen = (open_f)dlsym(RTLD_DEFAULT, "open");
+    if (seen != (open_f)open) {
+        selftest_line("selftest: FAIL, open() resolves to another library; is this one in LD_PRELOAD?");
+        return;
+    }
+    // g_metrics.paths_blocked tells an open refused by the hook from one that
+    // fails on its own.
+    unsigned long before = __atomic_load_n(&g_metrics.paths_blocked, __ATOMIC_RELAXED);
+    int fd = seen(path, O_RDONLY | O_CLOEXEC);
+    char err[16];
+    errno_name(errno, err, sizeof(err));
+    if (fd >= 0) {
+        close(fd);
+        selftest_line("selftest: FAIL, %s opened", path);
+    } else if (__atomic_load_n(&g_metrics.paths_blocked, __ATOMIC_RELAXED) == before) {
+        selftest_line("selftest: FAIL, %s failed with %s, but not in the hook", path, err);
+    } else {
+        selftest_line("selftest: PASS, open(%s) refused by the hook with %s", path, err);
+    }
+}
+
+// Every hook runs this before reading any config global (g_active, g_log_level,
+// the targets), so a hook that fires before our constructor -- another
+// library's constructor, or a dlopen()ed copy of us -- still sees a finished
+// init. The acquire load pairs with the release store at the end of nh_init.
+static inline void ensure_init(void) { if (!__atomic_load_n(&g_inited, __ATOMIC_ACQUIRE)) nh_init(); }
+
+// g_active for hooks that test it themselves, e.g. to skip resolving a
+// directory; never the initial value a cold call would otherwise read.
+static inline int active_after_init(void) { ensure_init(); return g_active; }
+
+// Front-load init from .init_array so policy, discovery and the env scrub
+// happen before main() rather than at the first hooked call.
+static int preloaded_at_start(void);     // with the exec hooks
+
+__attribute__((constructor)) static void nh_ctor(void) {
+    ensure_init();
+    if (g_topo_env[0] && preloaded_at_start()) setenv("LIBNVIDIAHIDE_TOPO", g_topo_env, 1);
+    selftest();
+}
+
+__attribute__((visibility("default"))) int libnvidiahide_probe(void) {
+    ensure_init();
+    int nodes = g_targets.nodes_n < 255 ? g_targets.nodes_n : 255;
+    return (g_active ? NH_PROBE_ACTIVE : 0) | (g_log_level >= NH_LOG_DEBUG ? NH_PROBE_DEBUG : 0) |
+           nodes << NH_PROBE_NODES_SHIFT;
+}
+
+// ---------- deny logic ----------
+
+// ---------- decision cache ----------
+// targets_match_path() is a pure function of the string and the discovered
+// topology, so its verdict can be memoised. Direct-mapped table, one seqlock
+// per slot: readers never block, a writer that loses the slot race simply
+// doesn't cache. Entries are stamped with g_dcache_epoch, which discovery bumps.
+// Long paths are not cached. The realpath step in is_nvidia_path is not cached
+// because symlink targets can change.
+// Measured on a 5-path desktop mix, 1e7 calls: ~150 ns -> ~110 ns per lookup,
+// more once BDFs are discovered (each adds a snprintf+strstr to the miss path).
+#define DCACHE_SLOTS 512
+#define DCACHE_PATH  128
+
+struct dcache_slot {
+    unsigned seq;       // odd while being written
+    unsigned epoch;
+    uint32_t hash;
+    unsigned char verdict;
+    char path[DCACHE_PATH];
+};
+
+static struct dcache_slot g_dcache[DCACHE_SLOTS];
+
+static uint32_t path_hash(const char *p, size_t *len) {
+    uint32_t h = 2166136261u;
+    size_t n = 0;
+    for (; p[n]; n++) { h ^= (unsigned char)p[n]; h *= 16777619u; }
+    *len = n;
+    return h;
+}
+
+static int dcache_lookup(const char *p, size_t len, uint32_t h, unsigned epoch, int *verdict) {
+    struct dcache_slot *e = &g_dcache[h % DCACHE_SLOTS];
+    unsigned seq = __atomic_load_n(&e->seq, __ATOMIC_ACQUIRE);
+    if (seq & 1) return 0;
+
+    char copy[DCACHE_PATH];
+    unsigned e_epoch = e->epoch;
+    uint32_t e_hash = e->hash;
+    int v = e->verdict;
+    memcpy(copy, e->path, len + 1);
+
+    __atomic_thread_fence(__ATOMIC_ACQUIRE);
+    if (__atomic_load_n(&e->seq, __ATOMIC_RELAXED) != seq) return 0;
+
+    if (e_epoch != epoch || e_hash != h || memcmp(copy, p, len + 1) != 0) return 0;
+    *verdict = v;
+    return 1;
+}
+
+static void dcache_store(const char *p, size_t len, uint32_t h, unsigned epoch, int verdict) {
+    struct dcache_slot *e = &g_dcache[h % DCACHE_SLOTS];
+    unsigned seq = __atomic_load_n(&e->seq, __ATOMIC_RELAXED);
+    if (seq & 1) return;
+    if (!__atomic_compare_exchange_n(&e->seq, &seq, seq + 1, 0,
+        __ATOMIC_ACQUIRE, __ATOMIC_RELAXED)) return;
+
+    e->epoch = epoch;
+    e->hash = h;
+    e->verdict = (unsigned char)verdict;
+    memcpy(e->path, p, len + 1);
+
+    __atomic_store_n(&e->seq, seq + 2, __ATOMIC_RELEASE);
+}
+
+static int path_matches_cached(const char *p) {
+    size_t len;
+    uint32_t h = path_hash(p, &len);
+    if (len >= DCACHE_PATH) return targets_match_path(&g_targets, p);
+
+    unsigned epoch = __atomic_load_n(&g_dcache_epoch, __ATOMIC_ACQUIRE);
+    int v;
+    if (dcache_lookup(p, len, h, epoch, &v)) {
+        NH_COUNT(cache_hits);
+        return v;
+    }
+    NH_COUNT(cache_misses);
+
+    v = targets_match_path(&g_targets, p);
+    dcache_store(p, len, h, epoch, v);
+    return v;
+}
+
+// Best-effort realpath so symlinks like /dev/dri/by-path/pci-...-card and
+// /dev/dri/by-id/... are judged by what they point at. Only done under /dev/
+// to keep normal opens cheap; a failing realpath (nonexistent path) just means no canonical form.
+static const char *canonicalize_for_match(const char *p, char *out) {
+    if (strncmp(p, "/dev/", 5) != 0) return NULL;
+    int saved = errno;
+    const char *r = realpath(p, out);
+    errno = saved;
+    if (!r || !strcmp(out, p)) return NULL;
+    return out;
+}
+
+// In dry-run, report a would-be block and tell the caller to let it through.
+static int dry_run_pass(const char *what, const char *subject) {
+    if (!g_dryrun) return 0;
+    log_debug("[dry-run would-block] %s: %s", what, subject);
+    return 1;
+}
+
+// Set while this thread is inside one of our own decisions. What runs there
+// (nh_init, a policy reload reading the allow/deny files through fopen,
+// realpath, log output) can land back in our hooks; those calls are ours, so
+// they pass unjudged instead of recursing or tripping over a hide rule that
+// happens to cover the config dir.
+static __thread int t_in_hook = 0;
+
+static int judge_path(const char *p) {
+    ensure_init();
+    maybe_reload_policy();
+    if (!hiding_now()) return 0;
+    if (!p) return 0;
+    NH_COUNT(paths_checked);
+
+    // A kept path stays visible even when what it links to is hidden.
+    int hit = path_matches_cached(p);
+    if (!hit && !(g_targets.keep_n && targets_kept(&g_targets, p))) {
+        char canon[PATH_MAX];
+        const char *c = canonicalize_for_match(p, canon);
+        hit = c && path_matches_cached(c);
+    }
+
+    return hit && !dry_run_pass("path", p);
+}
+
+// Before g_inited (see init guards): /dev/nvidia* and the like by prefix, and
+// LIBNVIDIAHIDE_HIDE_DEV; no discovered nodes, no library or config rules.
+static int judge_path_pre_ready(const char *p) {
+    const struct nh_targets *t = &g_targets;
+    if (!g_active || !p || !t->profile) return 0;
+    if (t->keep_n && targets_kept(t, p)) return 0;
+    int hit = targets_hide_dev_path(t, p) ||
+              (has_prefix_in(p, t->profile->compute_dev_prefixes)
+               ? t->hide_compute
+               : targets_core_hidden(t) && has_prefix_in(p, t->profile->dev_prefixes));
+    return hit && !dry_run_pass("path", p);
+}
+
+static int is_nvidia_path(const char *p) {
+    if (t_in_hook) return 0;
+    if (t_in_init) return judge_path_pre_ready(p);
+    t_in_hook = 1;
+    int hit = judge_path(p);
+    t_in_hook = 0;
+    return hit;
+}
+
+// PCI enumeration lists devices by BDF in /sys/bus/pci/devices,
+// /sys/bus/pci/drivers/<driver> and /proc/bus/pci/<bus>. Only exact names are
+// hidden there, so the iGPU and the dGPU's other functions stay listed.
+static int is_pci_bdf_entry(const char *dir, const char *name) {
+    int sys = !strcmp(dir, "/sys/bus/pci/devices") || !strncmp(dir, "/sys/bus/pci/drivers/", 21);
+    int proc = !strncmp(dir, "/proc/bus/pci/", 14);
+    if (!sys && !proc) return 0;
+
+    for (int i=0;i<g_targets.bdfs_n;i++) {
+        if (sys) {
+            if (!strcmp(name, g_targets.bdfs[i])) return 1;
+        } else {
+            char pdir[64];
+            const char *devfn;
+            if (bdf_proc_parts(g_targets.bdfs[i], pdir, sizeof(pdir), &devfn) == 0 &&
+                !strcmp(dir, pdir) && !strcmp(name, devfn)) return 1;
+        }
+    }
+    return 0;
+}
+
+// dir is the directory being enumerated when known (may be NULL).
+// name is the raw d_name; every check below is a plain byte comparison, so
+// entries that aren't valid UTF-8 pass through untouched.
+static int judge_dirent(const char *dir, const char *name) {
+    ensure_init();
+    maybe_reload_policy();
+    if (!hiding_now()) return 0;
+    if (!name) return 0;
+
+    if (g_targets.keep_n && dir) {
+        char full[PATH_MAX];
+        int m = snprintf(full, sizeof(full), "%s/%s", dir, name);
+        if (m > 0 && (size_t)m < sizeof(full) && targets_kept(&g_targets, full)) return 0;
+    }
+
+    // LIBNVIDIAHIDE_HIDE_DEV: /dev/kfd leaves /dev's listing, whatever the categories
+    if (dir && targets_hide_dev_entry(&g_targets, dir, name)) return 1;
+
+    // Compute nodes the user chose to keep must stay enumerable too.
+    if (!g_targets.hide_compute) {
+        char devpath[PATH_MAX];
+        snprintf(devpath, sizeof(devpath), "/dev/%s", name);
+        if (has_prefix_in(devpath, g_targets.profile->compute_dev_prefixes)) return 0;
+    }
+
+    if (g_targets.hide_graphics && dir && targets_config_entry(&g_targets, dir, strlen(dir), name)) return 1;
+
+    // Everything below is core: shared by both stacks.
+    if (!targets_core_hidden(&g_targets)) return 0;
+
+    // If it scans /dev, hide /dev/nvidia* names. The same prefix also drops
+    // the driver's entries from /sys/module and /proc/driver.
+    if (has_prefix_in(name, g_targets.profile->dirent_prefixes)) return 1;
+
+    if (dir && is_pci_bdf_entry(dir, name)) return 1;
+
+    // LIBNVIDIAHIDE_HIDE_ALL_DRI: no node and no link left in /dev/dri
+    if (g_targets.hide_all_dri && dir) {
+        if (!strcmp(dir, "/dev/dri") ? drm_node_name(name)
+                                     : name[0] != '.' && targets_dri_link_dir(dir, strlen(dir))) return 1;
+    }
+
+    // Hide discovered DRM nodes (cardX/renderD*)
+    if (targets_has_node(&g_targets, name)) return 1;
+
+    // Compositors that walk /sys/class/drm instead of /dev/dri: the nodes
+    // above, plus the card's connectors
+    if (dir && !strcmp(dir, "/sys/class/drm") && targets_sysfs_drm_entry(&g_targets, name, strlen(name))) return 1;
+
+    // /dev/dri/by-id names don't always carry the BDF, so judge the link by
+    // the node it points at.
+    if (dir && (!strcmp(dir, "/dev/dri/by-id") || !strcmp(dir, "/dev/dri/by-path"))) {
+        char link[PATH_MAX], target[PATH_MAX];
+        int m = snprintf(link, sizeof(link), "%s/%s", dir, name);
+        if (m > 0 && (size_t)m < sizeof(link)) {
+            int saved = errno;   // readdir callers check errno after NULL
+            ssize_t n = readlink_raw(link, target, sizeof(target) - 1);
+            errno = saved;
+            if (n > 0) {
+                target[n] = 0;
+                if (targets_has_node(&g_targets, base_name(target))) return 1;
+            }
+        }
+    }
+
+    // If scanning /dev/dri/by-path, often includes BDF in symlink name
+    // hide if matches; the domain-less "01:00.0" style only there
+    if (targets_name_has_bdf(&g_targets, name, dir && targets_dri_link_dir(dir, strlen(dir)))) return 1;
+
+    return 0;
+}
+
+static int dirent_matches_nvidia(const char *dir, const char *name) {
+    if (t_in_hook || t_in_init) return 0;
+    t_in_hook = 1;
+    int hit = judge_dirent(dir, name);
+    t_in_hook = 0;
+    return hit;
+}
+
+static int is_nvidia_dirent(const char *dir, const char *name) {
+    if (!dirent_matches_nvidia(dir, name) || dry_run_pass("dirent", name)) return 0;
+    NH_COUNT(entries_hidden);
+    log_trace("hidden dirent: %s/%s", dir ? dir : "?", name);
+    if (g_audit_fd >= 0) {
+        char full[PATH_MAX];
+        int m = dir ? snprintf(full, sizeof(full), "%s/%s", dir, name) : -1;
+        audit_event("readdir", m > 0 && (size_t)m < sizeof(full) ? full : name, 0);
+    }
+    return 1;
+}
+
+static int deny_ret(void) { errno = ENOENT; return -1; }
+
+// Logging can itself clobber errno, so errno is set last, right before -1.
+static int deny_path(const char *hook, const char *p) {
+    int err = p && !strncmp(p, "/dev/", 5) ? g_device_errno : g_file_errno;
+    NH_COUNT(paths_blocked);
+    log_blocked(hook, p);
+    audit_event(hook, p, err);
+    errno = err;
+    return -1;
+}
+
+// dlsym(RTLD_NEXT) for a hook's real function. NULL when no later object
+// defines it (readdir64 on musl, say); the hook then fails with ENOSYS, or
+// ends a listing, instead of calling through a null pointer.
+static void *resolve_next(const char *name) {
+    void *p = dlsym(RTLD_NEXT, name);
+    if (!p) log_debug("hook: %s not found in any later library", name);
+    return p;
+}
+
+// Each hook caches its next definition in a function-local void * slot. The
+// first calls from several threads may race on it: each runs dlsym and stores
+// the same address, and the atomic accesses keep that race well-defined (a
+// plain pointer written by one thread while another reads it is not). The
+// slot holds the dlsym result as is; callers convert it to their function
+// type, which POSIX guarantees for dlsym's return value.
+static void *resolve_real(void **slot, const char *name) {
+    void *p = __atomic_load_n(slot, __ATOMIC_ACQUIRE);
+    if (!p) {
+        p = resolve_next(name);
+        __atomic_store_n(slot, p, __ATOMIC_RELEASE);
+    }
+    return p;
+}
+
+static int missing_real(void) { errno = ENOSYS; return -1; }
+
+// Path an fd refers to, via /proc/self/fd. Leaves errno untouched.
+static int fd_path(int fd, char *out, size_t out_sz) {
+    char linkpath[64];
+    snprintf(linkpath, sizeof(linkpath), "/proc/self/fd/%d", fd);
+
+    int saved = errno;
+    ssize_t n = readlink_raw(linkpath, out, out_sz - 1);
+    errno = saved;
+    if (n <= 0) return -1;
+    out[n] = 0;
+    return 0;
+}
+
+// Turn an *at() call's (dirfd, pathname, flags) into something is_nvidia_path
+// can match. With AT_EMPTY_PATH and "" the call is about dirfd itself (the
+// cwd for AT_FDCWD), read back from /proc; readlinkat takes "" that way
+// without the flag, so it passes AT_EMPTY_PATH. Otherwise absolute paths and
+// AT_FDCWD are returned as-is, and a relative path is joined to the directory
+// from /proc/self/fd/<dirfd>. Fails open to pathname.
+// AT_SYMLINK_NOFOLLOW changes nothing here: a link is judged by its own name
+// and, under /dev, by what it points at (as in listings), so lstat of a
+// by-id link doesn't reveal the hidden node behind it.
+static const char *at_target_path(int dirfd, const char *pathname, int flags, char *out, size_t out_sz) {
+    if (pathname && !pathname[0] && (flags & AT_EMPTY_PATH)) {
+        if (dirfd != AT_FDCWD) return fd_path(dirfd, out, out_sz) == 0 ? out : pathname;
+        int saved = errno;
+        ssize_t n = readlink_raw("/proc/self/cwd", out, out_sz - 1);
+        errno = saved;
+        if (n <= 0) return pathname;
+        out[n] = 0;
+        return out;
+    }
+    if (!pathname || pathname[0] == '/' || dirfd == AT_FDCWD) return pathname;
+
+    char dir[PATH_MAX];
+    if (fd_path(dirfd, dir, sizeof(dir)) != 0) return pathname;
+
+    int m = snprintf(out, out_sz, "%s/%s", dir, pathname);
+    if (m < 0 || (size_t)m >= out_sz) return pathname;
+    return out;
+}
+
+// ---------- DIR* -> directory path cache ----------
+// readdir needs the directory being enumerated; resolve it once per DIR*
+// instead of hitting /proc/self/fd for every entry. Entries are dropped in
+// closedir. Nothing here waits: a signal handler may re-enter readdir or
+// closedir at any point, including on the same thread halfway through an
+// update, so each slot is a small seqlock. seq is odd while the slot's owner
+// writes it; readers that see it odd or changed resolve uncached. Forgetting
+// is a CAS of dirp to NULL, which is also what frees the slot, and bumps
+// g_dir_cache_gen so an insert that raced it takes its entry back.
+#define MAX_DIR_CACHE 64
+struct dir_cache_entry {
+    DIR *dirp;          // NULL: free
+    unsigned seq;
+    char path[PATH_MAX];
+};
+static struct dir_cache_entry g_dir_cache[MAX_DIR_CACHE];
+static unsigned g_dir_cache_gen = 0;
+
+static int dir_cache_lookup(DIR *dirp, char *out, size_t out_sz) {
+    for (int i=0;i<MAX_DIR_CACHE;i++) {
+        struct dir_cache_entry *e = &g_dir_cache[i];
+        unsigned seq = __atomic_load_n(&e->seq, __ATOMIC_ACQUIRE);
+        if ((seq & 1) || __atomic_load_n(&e->dirp, __ATOMIC_ACQUIRE) != dirp) continue;
+        snprintf(out, out_sz, "%.*s", PATH_MAX - 1, e->path);
+        __atomic_thread_fence(__ATOMIC_ACQUIRE);
+        if (__atomic_load_n(&e->seq, __ATOMIC_RELAXED) == seq &&
+            __atomic_load_n(&e->dirp, __ATOMIC_ACQUIRE) == dirp) return 1;
+    }
+    return 0;
+}
+
+// gen: g_dir_cache_gen from before path was resolved.
+static void dir_cache_insert(DIR *dirp, const char *path, unsigned gen) {
+    for (int i=0;i<MAX_DIR_CACHE;i++) {
+        struct dir_cache_entry *e = &g_dir_cache[i];
+        unsigned seq = __atomic_load_n(&e->seq, __ATOMIC_ACQUIRE);
+        if ((seq & 1) || __atomic_load_n(&e->dirp, __ATOMIC_ACQUIRE)) continue;
+        if (!__atomic_compare_exchange_n(&e->seq, &seq, seq + 1, 0, __ATOMIC_ACQ_REL, __ATOMIC_RELAXED)) continue;
+        // Ours to write; another writer may have filled it between the checks.
+        DIR *none = NULL;
+        if (__atomic_compare_exchange_n(&e->dirp, &none, dirp, 0, __ATOMIC_SEQ_CST, __ATOMIC_RELAXED)) {
+            snprintf(e->path, sizeof(e->path), "%s", path);
+            __atomic_store_n(&e->seq, seq + 2, __ATOMIC_RELEASE);
+            // A closedir since we resolved may have scanned before dirp was
+            // set; it can't have meant a later DIR* at the same address yet.
+            if (__atomic_load_n(&g_dir_cache_gen, __ATOMIC_SEQ_CST) != gen)
+                __atomic_compare_exchange_n(&e->dirp, &dirp, NULL, 0, __ATOMIC_SEQ_CST, __ATOMIC_RELAXED);
+            return;
+        }
+        __atomic_store_n(&e->seq, seq + 2, __ATOMIC_RELEASE);
+    }
+}
+
+static const char *dir_path(DIR *dirp, char *out, size_t out_sz) {
+    if (!dirp) return NULL;
+    if (dir_cache_lookup(dirp, out, out_sz)) return out;
+
+    unsigned gen = __atomic_load_n(&g_dir_cache_gen, __ATOMIC_SEQ_CST);
+    if (fd_path(dirfd(dirp), out, out_sz) != 0) return NULL;
+    dir_cache_insert(dirp, out, gen);
+    return out;
+}
+
+// Must not be skipped, or a recycled DIR* would inherit a stale path.
+static void dir_cache_forget(DIR *dirp) {
+    __atomic_add_fetch(&g_dir_cache_gen, 1, __ATOMIC_SEQ_CST);
+    for (int i=0;i<MAX_DIR_CACHE;i++) {
+        DIR *cur = dirp;
+        __atomic_compare_exchange_n(&g_dir_cache[i].dirp, &cur, NULL, 0, __ATOMIC_SEQ_CST, __ATOMIC_RELAXED);
+    }
+}
+
+// ---------- stubs: files served from a memfd instead of the real file ----------
+// Vulkan ICD (LIBNVIDIAHIDE_ICD_STUB): some loaders abort when a manifest
+// listed in VK_ICD_FILENAMES is missing. Instead of ENOENT, hand them a valid
+// manifest whose library doesn't exist.
+// /proc/modules (LIBNVIDIAHIDE_FILTER_PROCMODULES): a flat file, so unlike
+// /sys/module it can't be filtered by readdir. Serve a snapshot without the
+// driver's lines; a memfd keeps read/pread/lseek/mmap all consistent.
+// /proc/<pid>/maps (LIBNVIDIAHIDE_FILTER_MAPS): the same for mappings of the
+// vendor's libraries and device nodes, loaded before us or by another process.
+// Tools that compare maps with what they mapped themselves can be confused.
+enum nh_stub { NH_STUB_NONE, NH_STUB_ICD, NH_STUB_PROC_MODULES, NH_STUB_PROC_MAPS };
+
+static const char g_icd_stub_json[] =
+    "{\"file_format_version\":\"1.0.0\",\"ICD\":{\"library_path\":\"/nonexistent\",\"api_version\":\"1.0.0\"}}\n";
+
+static int kept_path(const char *p) { return g_targets.keep_n && targets_kept(&g_targets, p); }
+
+// /proc/<pid>/maps, with self or thread-self for the pid, and
+// /proc/<pid>/task/<tid>/maps.
+static int proc_maps_path(const char *p) {
+    if (strncmp(p, "/proc/", 6) != 0) return 0;
+    p += 6;
+    size_t n = strspn(p, "0123456789");
+    if (!n && !strncmp(p, "self/", 5)) n = 4;
+    else if (!n && !strncmp(p, "thread-self/", 12)) n = 11;
+    if (!n || p[n] != '/') return 0;
+    p += n + 1;
+    if (!strncmp(p, "task/", 5) && (n = strspn(p + 5, "0123456789")) && p[5 + n] == '/') p += 6 + n;
+    return !strcmp(p, "maps");
+}
+
+static enum nh_stub stub_for(const char *p) {
+    if (!p) return NH_STUB_NONE;
+    if (g_filter_procmodules && !strcmp(p, "/proc/modules")) {
+        ensure_init();
+        return hiding_now() && targets_core_hidden(&g_targets) ? NH_STUB_PROC_MODULES : NH_STUB_NONE;
+    }
+    if (g_filter_maps && proc_maps_path(p)) {
+        ensure_init();
+        return hiding_now() ? NH_STUB_PROC_MAPS : NH_STUB_NONE;
+    }
+    if (!g_icd_stub) return NH_STUB_NONE;
+    if (!strstr(p, "/vulkan/icd.d/")) return NH_STUB_NONE;
+    return is_nvidia_path(p) ? NH_STUB_ICD : NH_STUB_NONE;
+}
+
+// "nvidia_drm 69632 4 - Live 0x..." -> is the first field a hidden module?
+static int proc_modules_line_hidden(const char *line, size_t len) {
+    char name[64];
+    size_t n = 0;
+    while (n < len && n < sizeof(name) - 1 && line[n] != ' ') { name[n] = line[n]; n++; }
+    name[n] = 0;
+    return n && has_prefix_in(name, g_targets.profile->dirent_prefixes);
+}
+
+// "7f0e1c000000-7f0e1c200000 r-xp 00000000 103:02 1234   /usr/lib/libnvidia-glcore.so.550"
+// -> is the mapped file a hidden library or device node? The path is the
+// sixth field, padded with spaces; anonymous mappings have none.
+static int proc_maps_line_hidden(const char *line, size_t len) {
+    size_t i = 0;
+    for (int field = 0; field < 5; field++) {
+        while (i < len && line[i] != ' ') i++;
+        while (i < len && line[i] == ' ') i++;
+    }
+    char path[PATH_MAX];
+    size_t n = len - i;
+    if (n && line[len - 1] == '\n') n--;
+    if (!n || line[i] != '/' || n >= sizeof(path)) return 0;
+    memcpy(path, line + i, n);
+    path[n] = 0;
+    return !kept_path(path) && (targets_library_hidden(&g_targets, path) || targets_dev_node(&g_targets, path));
+}
+
+// Copies the file at src to out without the lines hidden() picks.
+static int write_filtered(const char *src, int (*hidden)(const char *, size_t), int out) {
+    int in = (int)syscall(SYS_openat, AT_FDCWD, src, O_RDONLY | O_CLOEXEC, 0);
+    if (in < 0) return -1;
+
+    // Lines are copied as they complete; a partial line carries over to the
+    // next read. Longer lines than the buffer are passed through unfiltered.
+    char buf[8192];
+    size_t have = 0;
+    int rc = 0;
+    for (;;) {
+        ssize_t n = read(in, buf + have, sizeof(buf) - have);
+        if (n < 0) { rc = -1; break; }
+        have += (size_t)n;
+        size_t start = 0;
+        for (size_t i = 0; i < have; i++) {
+            if (buf[i] != '\n') continue;
+            size_t len = i + 1 - start;
+            if (!hidden(buf + start, len) && write(out, buf + start, len) != (ssize_t)len) rc = -1;
+            start = i + 1;
+        }
+        if (n == 0 || (start == 0 && have == sizeof(buf))) {
+            if (have - start && write(out, buf + start, have - start) != (ssize_t)(have - start)) rc = -1;
+            start = have;
+        }
+        memmove(buf, buf + start, have - start);
+        have -= start;
+        if (n == 0 || rc) break;
+    }
+    close(in);
+    return rc;
+}
+
+// path is the file stub_for picked kind for; maps are read from it.
+static int open_stub(enum nh_stub kind, const char *path, int flags) {
+    int fd = memfd_create("nvidia-hide-stub", (flags & O_CLOEXEC) ? MFD_CLOEXEC : 0);
+    if (fd < 0) return deny_ret();
+    int rc;
+    if (kind == NH_STUB_PROC_MODULES) {
+        rc = write_filtered("/proc/modules", proc_modules_line_hidden, fd);
+    } else if (kind == NH_STUB_PROC_MAPS) {
+        rc = write_filtered(path, proc_maps_line_hidden, fd);
+    } else {
+        size_t len = strlen(g_icd_stub_json);
+        rc = write(fd, g_icd_stub_json, len) == (ssize_t)len ? 0 : -1;
+    }
+    if (rc != 0 || lseek(fd, 0, SEEK_SET) != 0) {
+        close(fd);
+        return deny_ret();
+    }
+    return fd;
+}
+
+// ---------- NVIDIA fd tracking ----------
+// fds that refer to NVIDIA device nodes, either inherited (found by scanning
+// /proc/self/fd on first use) or opened while not blocked. Every fd-keyed
+// feature goes through one nh_fd_table, whose entries follow the fd's life:
+// close/close_range/fclose drop them and dup* copy them, so a recycled fd
+// number never inherits a verdict. Slots hold fd+1 so zero means empty;
+// updates are lock-free CAS so concurrent opens are safe.
+#define MAX_NV_FDS 64
+
+struct nh_fd_table {
+    int slots[MAX_NV_FDS];
+    int used;       // ever inserted; lets close() skip the scan in most programs
+};
+
+static struct nh_fd_table g_nv_fds;
+static volatile int g_nv_fds_scanned = 0;
+
+static int fdtab_contains(struct nh_fd_table *t, int fd) {
+    if (fd < 0 || !__atomic_load_n(&t->used, __ATOMIC_ACQUIRE)) return 0;
+    for (int i=0;i<MAX_NV_FDS;i++) if (__atomic_load_n(&t->slots[i], __ATOMIC_ACQUIRE) == fd+1) return 1;
+    return 0;
+}
+
+static void fdtab_insert(struct nh_fd_table *t, int fd) {
+    if (fd < 0 || fdtab_contains(t, fd)) return;
+    __atomic_store_n(&t->used, 1, __ATOMIC_RELEASE);
+    for (int i=0;i<MAX_NV_FDS;i++) {
+        int expected = 0;
+        if (__atomic_compare_exchange_n(&t->slots[i], &expected, fd+1, 0,
+            __ATOMIC_ACQ_REL, __ATOMIC_RELAXED)) return;
+    }
+}
+
+// Removes every fd in [lo, hi].
+static void fdtab_remove_range(struct nh_fd_table *t, unsigned lo, unsigned hi) {
+    if (!__atomic_load_n(&t->used, __ATOMIC_ACQUIRE)) return;
+    for (int i=0;i<MAX_NV_FDS;i++) {
+        int v = __atomic_load_n(&t->slots[i], __ATOMIC_ACQUIRE);
+        if (v && (unsigned)(v-1) >= lo && (unsigned)(v-1) <= hi)
+            __atomic_compare_exchange_n(&t->slots[i], &v, 0, 0, __ATOMIC_ACQ_REL, __ATOMIC_RELAXED);
+    }
+}
+
+static void fdtab_remove(struct nh_fd_table *t, int fd) {
+    if (fd >= 0) fdtab_remove_range(t, (unsigned)fd, (unsigned)fd);
+}
+
+static void track_nv_fd(int fd) { fdtab_insert(&g_nv_fds, fd); }
+static int is_nv_fd(int fd) { return fdtab_contains(&g_nv_fds, fd); }
+
+// Same raw getdents64 walk as scan_nodes_raw, over our own fd table.
+static void scan_self_fds_raw(void) {
+    int expected = 0;
+    if (!__atomic_compare_exchange_n(&g_nv_fds_scanned, &expected, 1, 0,
+        __ATOMIC_ACQ_REL, __ATOMIC_RELAXED)) return;
+
+    int fd = (int)syscall(SYS_openat, AT_FDCWD, "/proc/self/fd", O_RDONLY|O_DIRECTORY|O_CLOEXEC, 0);
+    if (fd < 0) return;
+
+    char buf[4096];
+    for (;;) {
+        int nread = (int)syscall(SYS_getdents64, fd, buf, (int)sizeof(buf));
+        if (nread <= 0) break;
+
+        int bpos = 0;
+        while (bpos < nread) {
+            struct linux_dirent64 *d = (struct linux_dirent64*)(buf + bpos);
+            bpos += d->d_reclen;
+            if (d->d_name[0] == '.') continue;
+
+            char linkpath[64], target[PATH_MAX];
+            snprintf(linkpath, sizeof(linkpath), "/proc/self/fd/%s", d->d_name);
+            ssize_t n = readlink_raw(linkpath, target, sizeof(target)-1);
+            if (n <= 0) continue;
+            target[n] = 0;
+            if (targets_dev_node(&g_targets, target)) {
+                log_debug("fd: tracking inherited fd %s -> %s", d->d_name, target);
+                track_nv_fd(atoi(d->d_name));
+            }
+        }
+    }
+    close(fd);
+}
+
+// Called with the result of every forwarded open.
+static int note_open(int fd, const char *path) {
+    if (fd >= 0 && (g_block_ioctl || g_block_mmap) && path && targets_dev_node(&g_targets, path)) track_nv_fd(fd);
+    return fd;
+}
+
+// ---------- hooks ----------
+// A new hook also adds a call to tests/preload-probe.c's `unrelated` mode that
+// it must leave alone (an fd it never tracked, a path no rule covers), so the
+// growing hook surface can't start refusing calls it has no business with.
+
+// The mode argument is only there (and must only be read) when the call can
+// create a file: O_CREAT, or O_TMPFILE, which includes O_DIRECTORY and so
+// needs a full-mask test. Same rule as glibc's __OPEN_NEEDS_MODE.
+static int open_needs_mode(int flags) {
+#ifdef O_TMPFILE
+    if ((flags & O_TMPFILE) == O_TMPFILE) return 1;
+#endif
+    return (flags & O_CREAT) != 0;
+}
+
+// The caller's mode reaches us default-promoted, as an unsigned int; read it
+// as one. Same bits as va_arg(ap, mode_t) while mode_t is unsigned int, as on
+// every Linux ABI, but it doesn't depend on that. tests/preload-probe.c
+// checks the created files' modes, on aarch64 too with `make check-aarch64`.
+#define VA_MODE(ap) ((mode_t)va_arg(ap, unsigned int))
+
+typedef int (*openat_f)(int, const char*, int, ...);
+typedef int (*open_f)(const char*, int, ...);
+
+int openat(int dirfd, const char *pathname, int flags, ...) {
+    static void *next_openat;
+    openat_f real_openat = (openat_f)resolve_real(&next_openat, "openat");
+    if (!real_openat) return missing_real();
+
+    char abs[PATH_MAX];
+    const char *resolved = at_target_path(dirfd, pathname, 0, abs, sizeof(abs));
+    enum nh_stub stub = stub_for(resolved);
+    if (stub) return open_stub(stub, resolved, flags);
+    if (is_nvidia_path(resolved)) return deny_path(__func__, resolved);
+
+    va_list ap;
+    va_start(ap, flags);
+    int fd;
+    if (open_needs_mode(flags)) {
+        mode_t mode = VA_MODE(ap);
+        fd = real_openat(dirfd, pathname, flags, mode);
+    } else {
+        fd = real_openat(dirfd, pathname, flags);
+    }
+    va_end(ap);
+    return note_open(fd, resolved);
+}
+
+// Shared by open and its aliases; real is NULL when this libc lacks the symbol,
+// and then the kernel is asked directly (what every one of them boils down to).
+static int open_common(open_f real, const char *hook, const char *pathname, int flags, va_list ap) {
+    enum nh_stub stub = stub_for(pathname);
+    if (stub) return open_stub(stub, pathname, flags);
+    if (is_nvidia_path(pathname)) return deny_path(hook, pathname);
+
+    mode_t mode = open_needs_mode(flags) ? VA_MODE(ap) : 0;
+    int fd;
+    if (!real) fd = (int)syscall(SYS_openat, AT_FDCWD, pathname, flags, mode);
+    else if (open_needs_mode(flags)) fd = real(pathname, flags, mode);
+    else fd = real(pathname, flags);
+    return note_open(fd, pathname);
+}
+
+// Also hook open/open64 for completeness (some paths use these)
+int open(const char *pathname, int flags, ...) {
+    static void *next_open;
+    open_f real_open = (open_f)resolve_real(&next_open, "open");
+
+    va_list ap;
+    va_start(ap, flags);
+    int fd = open_common(real_open, __func__, pathname, flags, ap);
+    va_end(ap);
+    return fd;
+}
+
+#if NH_LFS64
+int open64(const char *pathname, int flags, ...) {
+    static void *next_open64;
+    open_f real_open64 = (open_f)resolve_real(&next_open64, "open64");
+
+    va_list ap;
+    va_start(ap, flags);
+    int fd = open_common(real_open64, __func__, pathname, flags, ap);
+    va_end(ap);
+    return fd;
+}
+#endif
+
+// Other exported spellings of open. __open/__open64 are public aliases some
+// older binaries and wrappers bind to. __open_nocancel/__open64_nocancel are
+// GLIBC_PRIVATE, used by other glibc libraries (libnss_*, ...). Calls made
+// inside libc.so itself use hidden aliases no preload can reach, which is why
+// opendir and fopen are hooked directly.
+int __open(const char *pathname, int flags, ...) {
+    static void *next___open;
+    open_f real___open = (open_f)resolve_real(&next___open, "__open");
+
+    va_list ap;
+    va_start(ap, flags);
+    int fd = open_common(real___open, __func__, pathname, flags, ap);
+    va_end(ap);
+    return fd;
+}
+
+int __open64(const char *pathname, int flags, ...) {
+    static void *next___open64;
+    open_f real___open64 = (open_f)resolve_real(&next___open64, "__open64");
+
+    va_list ap;
+    va_start(ap, flags);
+    int fd = open_common(real___open64, __func__, pathname, flags, ap);
+    va_end(ap);
+    return fd;
+}
+
+int __open_nocancel(const char *pathname, int flags, ...) {
+    static void *next_open_nocancel;
+    open_f real_open_nocancel = (open_f)resolve_real(&next_open_nocancel, "__open_nocancel");
+
+    va_list ap;
+    va_start(ap, flags);
+    int fd = open_common(real_open_nocancel, __func__, pathname, flags, ap);
+    va_end(ap);
+    return fd;
+}
+
+int __open64_nocancel(const char *pathname, int flags, ...) {
+    static void *next_open64_nocancel;
+    open_f real_open64_nocancel = (open_f)resolve_real(&next_open64_nocancel, "__open64_nocancel");
+
+    va_list ap;
+    va_start(ap, flags);
+    int fd = open_common(real_open64_nocancel, __func__, pathname, flags, ap);
+    va_end(ap);
+    return fd;
+}
+
+// _FORTIFY_SOURCE builds call these instead of open/openat when the flags
+// aren't a compile-time constant (they check O_CREAT has a mode)
+typedef int (*open_2_f)(const char*, int);
+typedef int (*openat_2_f)(int, const char*, int);
+
+int __open_2(const char *pathname, int flags) {
+    static void *next_open_2;
+    open_2_f real_open_2 = (open_2_f)resolve_real(&next_open_2, "__open_2");
+    if (!real_open_2) return missing_real();
+
+    enum nh_stub stub = stub_for(pathname);
+    if (stub) return open_stub(stub, pathname, flags);
+    if (is_nvidia_path(pathname)) return deny_path(__func__, pathname);
+    return note_open(real_open_2(pathname, flags), pathname);
+}
+
+int __open64_2(const char *pathname, int flags) {
+    static void *next_open64_2;
+    open_2_f real_open64_2 = (open_2_f)resolve_real(&next_open64_2, "__open64_2");
+    if (!real_open64_2) return missing_real();
+
+    enum nh_stub stub = stub_for(pathname);
+    if (stub) return open_stub(stub, pathname, flags);
+    if (is_nvidia_path(pathname)) return deny_path(__func__, pathname);
+    return note_open(real_open64_2(pathname, flags), pathname);
+}
+
+int __openat_2(int dirfd, const char *pathname, int flags) {
+    static void *next_openat_2;
+    openat_2_f real_openat_2 = (openat_2_f)resolve_real(&next_openat_2, "__openat_2");
+    if (!real_openat_2) return missing_real();
+
+    char abs[PATH_MAX];
+    const char *resolved = at_target_path(dirfd, pathname, 0, abs, sizeof(abs));
+    enum nh_stub stub = stub_for(resolved);
+    if (stub) return open_stub(stub, resolved, flags);
+    if (is_nvidia_path(resolved)) return deny_path(__func__, resolved);
+    return note_open(real_openat_2(dirfd, pathname, flags), resolved);
+}
+
+int __openat64_2(int dirfd, const char *pathname, int flags) {
+    static void *next_openat64_2;
+    openat_2_f real_openat64_2 = (openat_2_f)resolve_real(&next_openat64_2, "__openat64_2");
+    if (!real_openat64_2) return missing_real();
+
+    char abs[PATH_MAX];
+    const char *resolved = at_target_path(dirfd, pathname, 0, abs, sizeof(abs));
+    enum nh_stub stub = stub_for(resolved);
+    if (stub) return open_stub(stub, resolved, flags);
+    if (is_nvidia_path(resolved)) return deny_path(__func__, resolved);
+    return note_open(real_openat64_2(dirfd, pathname, flags), resolved);
+}
+
+// Hook openat2 if present
+typedef int (*openat2_f)(int, const char*, const struct open_how*, size_t);
+int openat2(int dirfd, const char *pathname, const struct open_how *how, size_t size) {
+    static void *next_openat2;
+    openat2_f real_openat2 = (openat2_f)resolve_real(&next_openat2, "openat2");
+
+    // A how the kernel would reject (NULL, or shorter than the first open_how)
+    // goes through untouched, so the caller gets the native EFAULT/EINVAL.
+    // Otherwise how and size are forwarded as given, including any newer
+    // fields past our struct and the resolve flags.
+    int valid = how && size >= OPEN_HOW_SIZE_VER0;
+
+    char abs[PATH_MAX];
+    const char *resolved = at_target_path(dirfd, pathname, 0, abs, sizeof(abs));
+    enum nh_stub stub = valid ? stub_for(resolved) : NH_STUB_NONE;
+    if (stub) return open_stub(stub, resolved, (int)how->flags);
+    if (valid && is_nvidia_path(resolved)) return deny_path(__func__, resolved);
+
+    if (real_openat2) return note_open(real_openat2(dirfd, pathname, how, size), resolved);
+    #ifdef SYS_openat2
+    return note_open((int)syscall(SYS_openat2, dirfd, pathname, how, size), resolved);
+    #else
+    errno = ENOSYS;
+    return -1;
+    #endif
+}
+
+/* ---- stdio opens ---- */
+// glibc's fopen reaches the kernel through its internal __open, which never
+// goes through our open() above, so stdio readers need their own hooks.
+typedef FILE *(*fopen_f)(const char*, const char*);
+typedef FILE *(*freopen_f)(const char*, const char*, FILE*);
+
+static FILE *fopen_stub(enum nh_stub kind, const char *path, const char *mode) {
+    int fd = open_stub(kind, path, strchr(mode, 'e') ? O_CLOEXEC : 0);
+    return fd < 0 ? NULL : fdopen(fd, mode);
+}
+
+static FILE *note_fopen(FILE *f, const char *path) {
+    if (f) note_open(fileno(f), path);
+    return f;
+}
+
+FILE *fopen(const char *pathname, const char *mode) {
+    static void *next_fopen;
+    fopen_f real_fopen = (fopen_f)resolve_real(&next_fopen, "fopen");
+    if (!real_fopen) { errno = ENOSYS; return NULL; }
+
+    enum nh_stub stub = stub_for(pathname);
+    if (stub) return fopen_stub(stub, pathname, mode);
+    if (is_nvidia_path(pathname)) { deny_path(__func__, pathname); return NULL; }
+    return note_fopen(real_fopen(pathname, mode), pathname);
+}
+
+#if NH_LFS64
+FILE *fopen64(const char *pathname, const char *mode) {
+    static void *next_fopen64;
+    fopen_f real_fopen64 = (fopen_f)resolve_real(&next_fopen64, "fopen64");
+    if (!real_fopen64) { errno = ENOSYS; return NULL; }
+
+    enum nh_stub stub = stub_for(pathname);
+    if (stub) return fopen_stub(stub, pathname, mode);
+    if (is_nvidia_path(pathname)) { deny_path(__func__, pathname); return NULL; }
+    return note_fopen(real_fopen64(pathname, mode), pathname);
+}
+#endif
+
+// A NULL pathname only changes the mode of the already-open stream.
+FILE *freopen(const char *pathname, const char *mode, FILE *stream) {
+    static void *next_freopen;
+    freopen_f real_freopen = (freopen_f)resolve_real(&next_freopen, "freopen");
+    if (!real_freopen) { errno = ENOSYS; return NULL; }
+
+    if (pathname && is_nvidia_path(pathname)) { deny_path(__func__, pathname); return NULL; }
+    return note_fopen(real_freopen(pathname, mode, stream), pathname);
+}
+
+/* ---- Block ioctl on NVIDIA fds (opt-in) ---- */
+typedef int (*ioctl_f)(int, unsigned long, ...);
+
+int ioctl(int fd, unsigned long request, ...) {
+    static void *next_ioctl;
+    ioctl_f real_ioctl = (ioctl_f)resolve_real(&next_ioctl, "ioctl");
+    if (!real_ioctl) return missing_real();
+
+    // Every ioctl request takes at most one pointer-sized argument.
+    va_list ap;
+    va_start(ap, request);
+    void *arg = va_arg(ap, void*);
+    va_end(ap);
+
+    ensure_init();
+    if (g_block_ioctl && hiding_now()) {
+        scan_self_fds_raw();
+        if (is_nv_fd(fd)) {
+            char fdstr[16];
+            snprintf(fdstr, sizeof(fdstr), "fd %d", fd);
+            if (!dry_run_pass("ioctl", fdstr)) {
+                log_blocked("ioctl", fdstr);
+                errno = ENODEV;
+                return -1;
+            }
+        }
+    }
+    return real_ioctl(fd, request, arg);
+}
+
+/* ---- Block mmap of NVIDIA fds (opt-in) ---- */
+// Mapping the device gives direct register access, so it gets the same
+// treatment as ioctl. Anonymous maps are forwarded before anything else: the
+// allocator calls mmap from inside dlsym and init, where the real pointer may
+// not be resolved yet, so those go straight to the syscall.
+typedef void *(*mmap_f)(void*, size_t, int, int, int, off_t);
+#if NH_LFS64
+typedef void *(*mmap64_f)(void*, size_t, int, int, int, off64_t);
+#endif
+
+static int mmap_blocked(int fd) {
+    ensure_init();
+    if (!g_block_mmap || !hiding_now()) return 0;
+    scan_self_fds_raw();
+    if (!is_nv_fd(fd)) return 0;
+    char fdstr[16];
+    snprintf(fdstr, sizeof(fdstr), "fd %d", fd);
+    if (dry_run_pass("mmap", fdstr)) return 0;
+    log_blocked("mmap", fdstr);
+    return 1;
+}
+
+// NULL while another call is inside dlsym; the caller then uses the syscall.
+static void *resolve_mmap(void **real, const char *sym) {
+    static int resolving = 0;
+    void *p = __atomic_load_n(real, __ATOMIC_ACQUIRE);
+    if (!p && !__atomic_exchange_n(&resolving, 1, __ATOMIC_ACQ_REL)) {
+        p = dlsym(RTLD_NEXT, sym);
+        __atomic_store_n(real, p, __ATOMIC_RELEASE);
+        __atomic_store_n(&resolving, 0, __ATOMIC_RELEASE);
+    }
+    return p;
+}
+
+void *mmap(void *addr, size_t length, int prot, int flags, int fd, off_t offset) {
+    static void *real_mmap = NULL;
+    if (fd >= 0 && !(flags & MAP_ANONYMOUS) && mmap_blocked(fd)) { errno = ENODEV; return MAP_FAILED; }
+    mmap_f real = (mmap_f)resolve_mmap(&real_mmap, "mmap");
+    if (real) return real(addr, length, prot, flags, fd, offset);
+    return (void*)syscall(SYS_mmap, addr, length, prot, flags, fd, offset);
+}
+
+#if NH_LFS64
+void *mmap64(void *addr, size_t length, int prot, int flags, int fd, off64_t offset) {
+    static void *real_mmap64 = NULL;
+    if (fd >= 0 && !(flags & MAP_ANONYMOUS) && mmap_blocked(fd)) { errno = ENODEV; return MAP_FAILED; }
+    mmap64_f real = (mmap64_f)resolve_mmap(&real_mmap64, "mmap64");
+    if (real) return real(addr, length, prot, flags, fd, offset);
+    return (void*)syscall(SYS_mmap, addr, length, prot, flags, fd, offset);
+}
+#endif
+
+/* ---- Keep the fd table in step with close/dup ---- */
+typedef int (*close_f)(int);
+typedef int (*close_range_f)(unsigned, unsigned, int);
+typedef int (*dup_f)(int);
+typedef int (*dup2_f)(int, int);
+typedef int (*dup3_f)(int, int, int);
+typedef int (*fclose_f)(FILE*);
+
+int close(int fd) {
+    static void *next_close;
+    close_f real_close = (close_f)resolve_real(&next_close, "close");
+    if (!real_close) return missing_real();
+
+    fdtab_remove(&g_nv_fds, fd);
+    return real_close(fd);
+}
+
+int close_range(unsigned first, unsigned last, int flags) {
+    static void *next_close_range;
+    close_range_f real_close_range = (close_range_f)resolve_real(&next_close_range, "close_range");
+    if (!real_close_range) { errno = ENOSYS; return -1; }
+
+    int rc = real_close_range(first, last, flags);
+    // CLOSE_RANGE_CLOEXEC only marks them; they stay open until exec
+#ifdef CLOSE_RANGE_CLOEXEC
+    if (flags & CLOSE_RANGE_CLOEXEC) return rc;
+#endif
+    if (rc == 0) fdtab_remove_range(&g_nv_fds, first, last);
+    return rc;
+}
+
+int fclose(FILE *stream) {
+    static void *next_fclose;
+    fclose_f real_fclose = (fclose_f)resolve_real(&next_fclose, "fclose");
+    if (!real_fclose) return missing_real();
+
+    if (stream) fdtab_remove(&g_nv_fds, fileno(stream));
+    return real_fclose(stream);
+}
+
+// The copy is tracked under its new number if the original was.
+static int note_dup(int oldfd, int newfd) {
+    if (newfd >= 0 && is_nv_fd(oldfd)) track_nv_fd(newfd);
+    return newfd;
+}
+
+int dup(int oldfd) {
+    static void *next_dup;
+    dup_f real_dup = (dup_f)resolve_real(&next_dup, "dup");
+    if (!real_dup) return missing_real();
+    return note_dup(oldfd, real_dup(oldfd));
+}
+
+int dup2(int oldfd, int newfd) {
+    static void *next_dup2;
+    dup2_f real_dup2 = (dup2_f)resolve_real(&next_dup2, "dup2");
+    if (!real_dup2) return missing_real();
+
+    int rc = real_dup2(oldfd, newfd);
+    // newfd was closed first (unless it is oldfd itself)
+    if (rc >= 0 && oldfd != newfd) fdtab_remove(&g_nv_fds, newfd);
+    return note_dup(oldfd, rc);
+}
+
+int dup3(int oldfd, int newfd, int flags) {
+    static void *next_dup3;
+    dup3_f real_dup3 = (dup3_f)resolve_real(&next_dup3, "dup3");
+    if (!real_dup3) return missing_real();
+
+    int rc = real_dup3(oldfd, newfd, flags);
+    if (rc >= 0) fdtab_remove(&g_nv_fds, newfd);
+    return note_dup(oldfd, rc);
+}
+
+/* ---- Hide NVIDIA paths from stat-family probes ---- */
+typedef int (*stat_f)(const char*, struct stat*);
+typedef int (*fstatat_f)(int, const char*, struct stat*, int);
+typedef int (*xstat_f)(int, const char*, struct stat*);
+typedef int (*fxstatat_f)(int, int, const char*, struct stat*, int);
+#if NH_LFS64
+typedef int (*stat64_f)(const char*, struct stat64*);
+typedef int (*fstatat64_f)(int, const char*, struct stat64*, int);
+typedef int (*xstat64_f)(int, const char*, struct stat64*);
+typedef int (*fxstatat64_f)(int, int, const char*, struct stat64*, int);
+#endif
+// musl before 1.2.5 has no statx wrapper, and no struct statx to declare one.
+#ifdef STATX_BASIC_STATS
+typedef int (*statx_f)(int, const char*, int, unsigned int, struct statx*);
+#endif
+
+int stat(const char *pathname, struct stat *buf) {
+    static void *next_stat;
+    stat_f real_stat = (stat_f)resolve_real(&next_stat, "stat");
+    if (!real_stat) return missing_real();
+
+    if (is_nvidia_path(pathname)) return deny_path(__func__, pathname);
+    return real_stat(pathname, buf);
+}
+
+int lstat(const char *pathname, struct stat *buf) {
+    static void *next_lstat;
+    stat_f real_lstat = (stat_f)resolve_real(&next_lstat, "lstat");
+    if (!real_lstat) return missing_real();
+
+    if (is_nvidia_path(pathname)) return deny_path(__func__, pathname);
+    return real_lstat(pathname, buf);
+}
+
+#if NH_LFS64
+int stat64(const char *pathname, struct stat64 *buf) {
+    static void *next_stat64;
+    stat64_f real_stat64 = (stat64_f)resolve_real(&next_stat64, "stat64");
+    if (!real_stat64) return missing_real();
+
+    if (is_nvidia_path(pathname)) return deny_path(__func__, pathname);
+    return real_stat64(pathname, buf);
+}
+
+int lstat64(const char *pathname, struct stat64 *buf) {
+    static void *next_lstat64;
+    stat64_f real_lstat64 = (stat64_f)resolve_real(&next_lstat64, "lstat64");
+    if (!real_lstat64) return missing_real();
+
+    if (is_nvidia_path(pathname)) return deny_path(__func__, pathname);
+    return real_lstat64(pathname, buf);
+}
+#endif
+
+int fstatat(int dirfd, const char *pathname, struct stat *buf, int flags) {
+    static void *next_fstatat;
+    fstatat_f real_fstatat = (fstatat_f)resolve_real(&next_fstatat, "fstatat");
+    if (!real_fstatat) return missing_real();
+
+    char abs[PATH_MAX];
+    const char *resolved = at_target_path(dirfd, pathname, flags, abs, sizeof(abs));
+    if (is_nvidia_path(resolved)) return deny_path(__func__, resolved);
+    return real_fstatat(dirfd, pathname, buf, flags);
+}
+
+#if NH_LFS64
+int fstatat64(int dirfd, const char *pathname, struct stat64 *buf, int flags) {
+    static void *next_fstatat64;
+    fstatat64_f real_fstatat64 = (fstatat64_f)resolve_real(&next_fstatat64, "fstatat64");
+    if (!real_fstatat64) return missing_real();
+
+    char abs[PATH_MAX];
+    const char *resolved = at_target_path(dirfd, pathname, flags, abs, sizeof(abs));
+    if (is_nvidia_path(resolved)) return deny_path(__func__, resolved);
+    return real_fstatat64(dirfd, pathname, buf, flags);
+}
+#endif
+
+// Pre-2.33 glibc routes stat() through these versioned entry points.
+int __xstat(int ver, const char *pathname, struct stat *buf) {
+    static void *next_xstat;
+    xstat_f real_xstat = (xstat_f)resolve_real(&next_xstat, "__xstat");
+    if (!real_xstat) return missing_real();
+
+    if (is_nvidia_path(pathname)) return deny_path(__func__, pathname);
+    return real_xstat(ver, pathname, buf);
+}
+
+int __lxstat(int ver, const char *pathname, struct stat *buf) {
+    static void *next_lxstat;
+    xstat_f real_lxstat = (xstat_f)resolve_real(&next_lxstat, "__lxstat");
+    if (!real_lxstat) return missing_real();
+
+    if (is_nvidia_path(pathname)) return deny_path(__func__, pathname);
+    return real_lxstat(ver, pathname, buf);
+}
+
+#if NH_LFS64
+int __xstat64(int ver, const char *pathname, struct stat64 *buf) {
+    static void *next_xstat64;
+    xstat64_f real_xstat64 = (xstat64_f)resolve_real(&next_xstat64, "__xstat64");
+    if (!real_xstat64) return missing_real();
+
+    if (is_nvidia_path(pathname)) return deny_path(__func__, pathname);
+    return real_xstat64(ver, pathname, buf);
+}
+
+int __lxstat64(int ver, const char *pathname, struct stat64 *buf) {
+    static void *next_lxstat64;
+    xstat64_f real_lxstat64 = (xstat64_f)resolve_real(&next_lxstat64, "__lxstat64");
+    if (!real_lxstat64) return missing_real();
+
+    if (is_nvidia_path(pathname)) return deny_path(__func__, pathname);
+    return real_lxstat64(ver, pathname, buf);
+}
+#endif
+
+int __fxstatat(int ver, int dirfd, const char *pathname, struct stat *buf, int flags) {
+    static void *next_fxstatat;
+    fxstatat_f real_fxstatat = (fxstatat_f)resolve_real(&next_fxstatat, "__fxstatat");
+    if (!real_fxstatat) return missing_real();
+
+    char abs[PATH_MAX];
+    const char *resolved = at_target_path(dirfd, pathname, flags, abs, sizeof(abs));
+    if (is_nvidia_path(resolved)) return deny_path(__func__, resolved);
+    return real_fxstatat(ver, dirfd, pathname, buf, flags);
+}
+
+#if NH_LFS64
+int __fxstatat64(int ver, int dirfd, const char *pathname, struct stat64 *buf, int flags) {
+    static void *next_fxstatat64;
+    fxstatat64_f real_fxstatat64 = (fxstatat64_f)resolve_real(&next_fxstatat64, "__fxstatat64");
+    if (!real_fxstatat64) return missing_real();
+
+    char abs[PATH_MAX];
+    const char *resolved = at_target_path(dirfd, pathname, flags, abs, sizeof(abs));
+    if (is_nvidia_path(resolved)) return deny_path(__func__, resolved);
+    return real_fxstatat64(ver, dirfd, pathname, buf, flags);
+}
+#endif
+
+#ifdef STATX_BASIC_STATS
+int statx(int dirfd, const char *pathname, int flags, unsigned int mask, struct statx *buf) {
+    static void *next_statx;
+    statx_f real_statx = (statx_f)resolve_real(&next_statx, "statx");
+    if (!real_statx) return missing_real();
+
+    char abs[PATH_MAX];
+    const char *resolved = at_target_path(dirfd, pathname, flags, abs, sizeof(abs));
+    if (is_nvidia_path(resolved)) return deny_path(__func__, resolved);
+    return real_statx(dirfd, pathname, flags, mask, buf);
+}
+#endif
+
+/* ---- Hide NVIDIA paths from statfs/statvfs probes ---- */
+// glibc's statvfs calls its internal __statfs, not the exported statfs, so
+// both layers need their own hook.
+typedef int (*statfs_f)(const char*, struct statfs*);
+typedef int (*statvfs_f)(const char*, struct statvfs*);
+#if NH_LFS64
+typedef int (*statfs64_f)(const char*, struct statfs64*);
+typedef int (*statvfs64_f)(const char*, struct statvfs64*);
+#endif
+
+int statfs(const char *pathname, struct statfs *buf) {
+    static void *next_statfs;
+    statfs_f real_statfs = (statfs_f)resolve_real(&next_statfs, "statfs");
+    if (!real_statfs) return missing_real();
+
+    if (is_nvidia_path(pathname)) return deny_path(__func__, pathname);
+    return real_statfs(pathname, buf);
+}
+
+#if NH_LFS64
+int statfs64(const char *pathname, struct statfs64 *buf) {
+    static void *next_statfs64;
+    statfs64_f real_statfs64 = (statfs64_f)resolve_real(&next_statfs64, "statfs64");
+    if (!real_statfs64) return missing_real();
+
+    if (is_nvidia_path(pathname)) return deny_path(__func__, pathname);
+    return real_statfs64(pathname, buf);
+}
+#endif
+
+int statvfs(const char *pathname, struct statvfs *buf) {
+    static void *next_statvfs;
+    statvfs_f real_statvfs = (statvfs_f)resolve_real(&next_statvfs, "statvfs");
+    if (!real_statvfs) return missing_real();
+
+    if (is_nvidia_path(pathname)) return deny_path(__func__, pathname);
+    return real_statvfs(pathname, buf);
+}
+
+#if NH_LFS64
+int statvfs64(const char *pathname, struct statvfs64 *buf) {
+    static void *next_statvfs64;
+    statvfs64_f real_statvfs64 = (statvfs64_f)resolve_real(&next_statvfs64, "statvfs64");
+    if (!real_statvfs64) return missing_real();
+
+    if (is_nvidia_path(pathname)) return deny_path(__func__, pathname);
+    return real_statvfs64(pathname, buf);
+}
+#endif
+
+/* ---- Hide NVIDIA paths from access() probes ---- */
+typedef int (*access_f)(const char*, int);
+typedef int (*faccessat_f)(int, const char*, int, int);
+
+int access(const char *pathname, int mode) {
+    static void *next_access;
+    access_f real_access = (access_f)resolve_real(&next_access, "access");
+    if (!real_access) return missing_real();
+
+    if (is_nvidia_path(pathname)) return deny_path(__func__, pathname);
+    return real_access(pathname, mode);
+}
+
+// Forward through libc rather than the raw syscall: glibc emulates AT_EACCESS
+// in userspace on kernels without faccessat2, and we must keep that behaviour.
+int faccessat(int dirfd, const char *pathname, int mode, int flags) {
+    static void *next_faccessat;
+    faccessat_f real_faccessat = (faccessat_f)resolve_real(&next_faccessat, "faccessat");
+    if (!real_faccessat) return missing_real();
+
+    char abs[PATH_MAX];
+    const char *resolved = at_target_path(dirfd, pathname, flags, abs, sizeof(abs));
+    if (is_nvidia_path(resolved)) return deny_path(__func__, resolved);
+    return real_faccessat(dirfd, pathname, mode, flags);
+}
+
+// Hook faccessat2 if present
+int faccessat2(int dirfd, const char *pathname, int mode, int flags) {
+    static void *next_faccessat2;
+    faccessat_f real_faccessat2 = (faccessat_f)resolve_real(&next_faccessat2, "faccessat2");
+
+    char abs[PATH_MAX];
+    const char *resolved = at_target_path(dirfd, pathname, flags, abs, sizeof(abs));
+    if (is_nvidia_path(resolved)) return deny_path(__func__, resolved);
+
+    if (real_faccessat2) return real_faccessat2(dirfd, pathname, mode, flags);
+    #ifdef SYS_faccessat2
+    return (int)syscall(SYS_faccessat2, dirfd, pathname, mode, flags);
+    #else
+    errno = ENOSYS;
+    return -1;
+    #endif
+}
+
+/* ---- Hide NVIDIA link targets from readlink ---- */
+// A link that resolves to a hidden node (/dev/dri/by-path/...-card,
+// /proc/self/fd/N, ...) would otherwise name it. Only the target is judged;
+// every other link is returned untouched. The target is read whole into a
+// private buffer, so a short caller buffer can't truncate it into something
+// that looks harmless, and a hidden one never reaches the caller's.
+typedef ssize_t (*readlink_f)(const char*, char*, size_t);
+typedef ssize_t (*readlinkat_f)(int, const char*, char*, size_t);
+typedef ssize_t (*readlink_chk_f)(const char*, char*, size_t, size_t);
+typedef ssize_t (*readlinkat_chk_f)(int, const char*, char*, size_t, size_t);
+
+// link is the (absolute when known) path of the link, buf/n what it holds.
+static int link_target_hidden(const char *link, const char *buf, ssize_t n) {
+    if (n <= 0 || !link) return 0;
+    char target[PATH_MAX];
+    const char *slash = strrchr(link, '/');
+    int m;
+    if (buf[0] == '/' || !slash) m = snprintf(target, sizeof(target), "%.*s", (int)n, buf);
+    else m = snprintf(target, sizeof(target), "%.*s/%.*s", (int)(slash - link), link, (int)n, buf);
+    if (m < 0 || (size_t)m >= sizeof(target)) return 0;
+    return is_nvidia_path(target);
+}
+
+// full/n: the whole target as read. Hands the caller what readlink would
+// have put in buf, or refuses without touching buf.
+static ssize_t link_result(const char *hook, const char *link, const char *full, ssize_t n,
+                           char *buf, size_t bufsiz) {
+    if (link_target_hidden(link, full, n)) return deny_path(hook, link);
+    if (n > 0 && (size_t)n > bufsiz) n = (ssize_t)bufsiz;
+    if (n > 0) memcpy(buf, full, (size_t)n);
+    return n;
+}
+
+ssize_t readlink(const char *pathname, char *buf, size_t bufsiz) {
+    static void *next_readlink;
+    readlink_f real_readlink = (readlink_f)resolve_real(&next_readlink, "readlink");
+    if (!real_readlink) return missing_real();
+    if (!bufsiz) return real_readlink(pathname, buf, bufsiz);   // EINVAL as without us
+
+    char full[PATH_MAX];
+    ssize_t n = real_readlink(pathname, full, sizeof(full));
+    return link_result(__func__, pathname, full, n, buf, bufsiz);
+}
+
+ssize_t readlinkat(int dirfd, const char *pathname, char *buf, size_t bufsiz) {
+    static void *next_readlinkat;
+    readlinkat_f real_readlinkat = (readlinkat_f)resolve_real(&next_readlinkat, "readlinkat");
+    if (!real_readlinkat) return missing_real();
+    if (!bufsiz) return real_readlinkat(dirfd, pathname, buf, bufsiz);
+
+    char full[PATH_MAX], abs[PATH_MAX];
+    ssize_t n = real_readlinkat(dirfd, pathname, full, sizeof(full));
+    const char *resolved = at_target_path(dirfd, pathname, AT_EMPTY_PATH, abs, sizeof(abs));
+    return link_result(__func__, resolved, full, n, buf, bufsiz);
+}
+
+// _FORTIFY_SOURCE builds call these when the buffer size is known. A bufsiz
+// past buflen goes to the real one as is, for its overflow abort.
+ssize_t __readlink_chk(const char *pathname, char *buf, size_t bufsiz, size_t buflen) {
+    static void *next_readlink_chk;
+    readlink_chk_f real_readlink_chk = (readlink_chk_f)resolve_real(&next_readlink_chk, "__readlink_chk");
+    if (!real_readlink_chk) return missing_real();
+    if (!bufsiz || bufsiz > buflen) return real_readlink_chk(pathname, buf, bufsiz, buflen);
+
+    char full[PATH_MAX];
+    ssize_t n = real_readlink_chk(pathname, full, sizeof(full), sizeof(full));
+    return link_result(__func__, pathname, full, n, buf, bufsiz);
+}
+
+ssize_t __readlinkat_chk(int dirfd, const char *pathname, char *buf, size_t bufsiz, size_t buflen) {
+    static void *next_readlinkat_chk;
+    readlinkat_chk_f real_readlinkat_chk = (readlinkat_chk_f)resolve_real(&next_readlinkat_chk, "__readlinkat_chk");
+    if (!real_readlinkat_chk) return missing_real();
+    if (!bufsiz || bufsiz > buflen) return real_readlinkat_chk(dirfd, pathname, buf, bufsiz, buflen);
+
+    char full[PATH_MAX], abs[PATH_MAX];
+    ssize_t n = real_readlinkat_chk(dirfd, pathname, full, sizeof(full), sizeof(full));
+    const char *resolved = at_target_path(dirfd, pathname, AT_EMPTY_PATH, abs, sizeof(abs));
+    return link_result(__func__, resolved, full, n, buf, bufsiz);
+}
+
+/* ---- Refuse to exec the vendor's tools (opt-in) ---- */
+// nvidia-smi and friends open /dev/nvidiactl themselves and wake the dGPU, so
+// a program that shells out to them is stopped at exec. The program is judged
+// by its basename, and by the basename of what it resolves to so a renamed
+// symlink doesn't get through. execl* and the p variants are hooked too:
+// glibc implements them on its internal execve, which we can't see.
+typedef int (*execve_f)(const char*, char *const[], char *const[]);
+typedef int (*execvpe_f)(const char*, char *const[], char *const[]);
+typedef int (*posix_spawn_f)(pid_t*, const char*, const posix_spawn_file_actions_t*,
+                             const posix_spawnattr_t*, char *const[], char *const[]);
+
+static int tool_name_listed(const char *name) {
+    const char *env = getenv("LIBNVIDIAHIDE_TOOLS");
+    if (!env) {
+        for (int i = 0; g_targets.profile->tool_names[i]; i++)
+            if (!strcmp(name, g_targets.profile->tool_names[i])) return 1;
+        return 0;
+    }
+    size_t n = strlen(name);
+    for (const char *p = env; p && *p; ) {
+        const char *end = strchr(p, ',');
+        size_t len = end ? (size_t)(end - p) : strlen(p);
+        if (len == n && !strncmp(p, name, n)) return 1;
+        p = end ? end + 1 : NULL;
+    }
+    return 0;
+}
+
+// file as given to exec; search: look it up in $PATH like execvp when it has
+// no '/'. Returns file itself when the search finds nothing.
+static const char *exec_lookup(const char *file, int search, char found[PATH_MAX]) {
+    if (!search || strchr(file, '/')) return file;
+    const char *dirs = getenv("PATH");
+    if (!dirs) dirs = "/bin:/usr/bin";
+    for (const char *d = dirs; d; ) {
+        const char *end = strchr(d, ':');
+        int len = end ? (int)(end - d) : (int)strlen(d);
+        int m = snprintf(found, PATH_MAX, "%.*s/%s", len ? len : 1, len ? d : ".", file);
+        if (m > 0 && m < PATH_MAX && access(found, X_OK) == 0) return found;
+        d = end ? end + 1 : NULL;
+    }
+    return file;
+}
+
+// No ensure_init() here or in spawn_env(): see "Carry the preload" below.
+static int exec_blocked(const char *file, int search) {
+    if (!__atomic_load_n(&g_inited, __ATOMIC_ACQUIRE)) return 0;
+    if (!g_block_tools || !file || !*file || !hiding_now()) return 0;
+
+    char found[PATH_MAX], canon[PATH_MAX];
+    int saved = errno;
+    const char *path = exec_lookup(file, search, found);
+    int hit = tool_name_listed(base_name(path)) ||
+              (realpath(path, canon) && tool_name_listed(base_name(canon)));
+    errno = saved;
+    return hit && !dry_run_pass("exec", path);
+}
+
+/* ---- Carry the preload across exec and posix_spawn ---- */
+// Build tools and launchers that hand execve or posix_spawn an environment
+// built from scratch drop LD_PRELOAD, and the new image sees the GPU again.
+// While active we put our .so back into its LD_PRELOAD, or take it out when
+// the program matches LIBNVIDIAHIDE_NO_PROPAGATE (allowlist syntax). That list
+// sees the program's resolved path, argv and file identity; ancestor: lines
+// never match it.
+
+// The exec hooks can run in a vfork child, or in the fork child of a
+// threaded program, where taking a lock or allocating can deadlock. So our
+// path and the NO_PROPAGATE list are settled by spawn_init() from nh_init,
+// and the child's environment is built in a struct nh_spawn_env the caller
+// keeps on its stack. re: lines still go through regexec(), which may
+// allocate; prefer globs on that list for programs that vfork.
+#define NH_SPAWN_ENV_MAX 1024
+
+struct nh_spawn_env {
+    char *env[NH_SPAWN_ENV_MAX];
+    char preload[sizeof("LD_PRELOAD=") + 2 * PATH_MAX];
+    char topo[sizeof("LIBNVIDIAHIDE_TOPO=") + sizeof(g_topo_env)];
+};
+
+static char g_self_so[PATH_MAX];            // "": not found, nothing to propagate
+static struct nh_matchers g_no_propagate;
+
+// Our own path, from the /proc/self/maps line that covers this function, and
+// LIBNVIDIAHIDE_NO_PROPAGATE compiled. Once, on the first init or reload
+// that finds us active.
+static void spawn_init(void) {
+    static int done = 0;
+    if (done) return;
+    done = 1;
+    const char *stop = getenv("LIBNVIDIAHIDE_NO_PROPAGATE");
+    if (stop && *stop) matchers_from_env(&g_no_propagate, stop);
+
+    int fd = (int)syscall(SYS_openat, AT_FDCWD, "/proc/self/maps", O_RDONLY | O_CLOEXEC, 0);
+    FILE *f = fd < 0 ? NULL : fdopen(fd, "r");
+    if (!f) {
+        if (fd >= 0) close(fd);
+        log_debug("spawn: can't read /proc/self/maps, not propagating");
+        return;
+    }
+    uintptr_t here = (uintptr_t)&spawn_init;
+    char line[PATH_MAX + 128];
+    while (fgets(line, sizeof(line), f)) {
+        unsigned long lo, hi;
+        int off = 0;
+        if (sscanf(line, "%lx-%lx %*s %*s %*s %*s %n", &lo, &hi, &off) < 2 || !off) continue;
+        if (here < lo || here >= hi || line[off] != '/') continue;
+        line[strcspn(line, "\n")] = 0;
+        snprintf(g_self_so, sizeof(g_self_so), "%s", line + off);
+        break;
+    }
+    fclose(f);
+    if (!g_self_so[0]) log_debug("spawn: our .so isn't in /proc/self/maps, not propagating");
+}
+
+// An LD_PRELOAD entry that is us: the same path, or another path to a file
+// of the same name (the loader also accepts bare names).
+static int preload_entry_is_self(const char *e, size_t len, const char *self) {
+    const char *base = base_name(self);
+    size_t blen = strlen(base);
+    if (len == strlen(self) && !strncmp(e, self, len)) return 1;
+    return len >= blen && !strncmp(e + len - blen, base, blen) &&
+           (len == blen || e[len - blen - 1] == '/');
+}
+
+// Whether LD_PRELOAD names us, so that our constructor runs as the process
+// starts rather than from a dlopen() in a program that may have threads.
+static int preloaded_at_start(void) {
+    const char *cur = getenv("LD_PRELOAD");
+    if (!g_self_so[0]) return 0;
+    for (const char *p = cur; p && *p; ) {
+        size_t n = strcspn(p, ": ");
+        if (n && preload_entry_is_self(p, n, g_self_so)) return 1;
+        p += n;
+        if (*p) p++;
+    }
+    return 0;
+}
+
+// Writes "LD_PRELOAD=<value>" for the child into out: cur with our entries
+// dropped, or with ours appended when keep is set and it has none. Returns
+// the value's length, 0 to leave the variable out, -1 when out is too small.
+static int preload_entry_for_child(const char *cur, const char *self, int keep, char *out, size_t cap) {
+    const size_t pre = sizeof("LD_PRELOAD=") - 1;
+    size_t len = pre;
+    int seen = 0;
+    memcpy(out, "LD_PRELOAD=", pre);
+    for (const char *p = cur; p && *p; ) {
+        size_t n = strcspn(p, ": ");
+        int is_self = n && preload_entry_is_self(p, n, self);
+        seen |= is_self;
+        if (n && (keep || !is_self)) {
+            if (len + 1 + n >= cap) return -1;
+            if (len > pre) out[len++] = ':';
+            memcpy(out + len, p, n);
+            len += n;
+        }
+        p += n;
+        if (*p) p++;
+    }
+    if (keep && !seen) {
+        size_t n = strlen(self);
+        if (len + 1 + n >= cap) return -1;
+        if (len > pre) out[len++] = ':';
+        memcpy(out + len, self, n);
+        len += n;
+    }
+    out[len] = 0;
+    return (int)(len - pre);
+}
+
+// The environment the child should get: envp itself, or b->env with
+// LD_PRELOAD rewritten and, when the child keeps the library, our
+// LIBNVIDIAHIDE_TOPO in place of any other. A NULL envp is the empty
+// environment, as the kernel takes it. Before init, or when envp doesn't fit
+// in b, envp goes through as it is.
+static char *const *spawn_env(const char *file, int search, char *const argv[], char *const envp[],
+                              struct nh_spawn_env *b) {
+    if (!__atomic_load_n(&g_inited, __ATOMIC_ACQUIRE) || !g_active || !file || !*file || !g_self_so[0])
+        return envp;
+
+    int saved = errno;
+    int keep = 1;
+    if (g_no_propagate.n) {
+        char found[PATH_MAX], canon[PATH_MAX], cmdline[4096];
+        const char *path = exec_lookup(file, search, found);
+        if (realpath(path, canon)) path = canon;
+        size_t len = 0;
+        cmdline[0] = 0;
+        for (int i = 0; argv && argv[i] && len + 1 < sizeof(cmdline); i++) {
+            int n = snprintf(cmdline + len, sizeof(cmdline) - len, "%s%s", i ? " " : "", argv[i]);
+            if (n < 0) break;
+            len += (size_t)n;
+        }
+        struct nh_exe_id id = { path, 0, 0, 0, 0, 0, 0, { 0 } };
+        struct nh_subject subj = { path, base_name(path), cmdline, NULL, &id };
+        keep = !matchers_match(&g_no_propagate, &subj);
+    }
+
+    const char *cur = NULL, *topo_cur = NULL;
+    int n = 0, at = -1, topo_at = -1;
+    for (; envp && envp[n]; n++) {
+        if (!strncmp(envp[n], "LD_PRELOAD=", 11)) {
+            at = n;
+            cur = envp[n] + 11;
+        } else if (!strncmp(envp[n], "LIBNVIDIAHIDE_TOPO=", 19)) {
+            topo_at = n;
+            topo_cur = envp[n] + 19;
+        }
+    }
+    int topo = keep && g_topo_env[0] && (!topo_cur || strcmp(topo_cur, g_topo_env) != 0);
+    int vlen = n + 3 > NH_SPAWN_ENV_MAX ? -1 :
+               preload_entry_for_child(cur, g_self_so, keep, b->preload, sizeof(b->preload));
+    int same = (vlen && cur && !strcmp(b->preload + 11, cur)) || (!vlen && !cur);
+    if (vlen < 0 || (same && !topo)) {
+        if (vlen < 0) log_debug("spawn: %s: environment too large to rewrite, passed as is", file);
+        errno = saved;
+        return envp;
+    }
+    int k = 0;
+    for (int i = 0; i < n; i++) if (i != at && !(topo && i == topo_at)) b->env[k++] = envp[i];
+    if (vlen) b->env[k++] = b->preload;
+    if (topo) {
+        size_t len = strlen(g_topo_env);
+        memcpy(b->topo, "LIBNVIDIAHIDE_TOPO=", 19);
+        memcpy(b->topo + 19, g_topo_env, len + 1);
+        b->env[k++] = b->topo;
+    }
+    b->env[k] = NULL;
+    log_debug("spawn: %s %s, LD_PRELOAD=%s", file, keep ? "keeps the preload" : "is on LIBNVIDIAHIDE_NO_PROPAGATE",
+              vlen ? b->preload + 11 : "(unset)");
+    errno = saved;
+    return b->env;
+}
+
+int execve(const char *pathname, char *const argv[], char *const envp[]) {
+    static void *next_execve;
+    execve_f real_execve = (execve_f)resolve_real(&next_execve, "execve");
+    if (!real_execve) return missing_real();
+
+    if (exec_blocked(pathname, 0)) return deny_path(__func__, pathname);
+    struct nh_spawn_env b;
+    char *const *env = spawn_env(pathname, 0, argv, envp, &b);
+    return real_execve(pathname, argv, env);
+}
+
+// execveat and fexecve can name the program by fd; it is judged by the path the
+// fd refers to. glibc's fexecve goes through its internal execveat or execve.
+typedef int (*execveat_f)(int, const char*, char *const[], char *const[], int);
+typedef int (*fexecve_f)(int, char *const[], char *const[]);
+
+int execveat(int dirfd, const char *pathname, char *const argv[], char *const envp[], int flags) {
+    static void *next_execveat;
+    execveat_f real_execveat = (execveat_f)resolve_real(&next_execveat, "execveat");
+    if (!real_execveat) return missing_real();
+
+    char abs[PATH_MAX];
+    const char *resolved = at_target_path(dirfd, pathname, flags, abs, sizeof(abs));
+    if (exec_blocked(resolved, 0)) return deny_path(__func__, resolved);
+    struct nh_spawn_env b;
+    char *const *env = spawn_env(resolved, 0, argv, envp, &b);
+    return real_execveat(dirfd, pathname, argv, env, flags);
+}
+
+int fexecve(int fd, char *const argv[], char *const envp[]) {
+    static void *next_fexecve;
+    fexecve_f real_fexecve = (fexecve_f)resolve_real(&next_fexecve, "fexecve");
+    if (!real_fexecve) return missing_real();
+
+    char path[PATH_MAX];
+    const char *resolved = fd_path(fd, path, sizeof(path)) == 0 ? path : NULL;
+    if (resolved && exec_blocked(resolved, 0)) return deny_path(__func__, resolved);
+    struct nh_spawn_env b;
+    char *const *env = resolved ? spawn_env(resolved, 0, argv, envp, &b) : envp;
+    return real_fexecve(fd, argv, env);
+}
+
+int execv(const char *pathname, char *const argv[]) {
+    return execve(pathname, argv, environ);
+}
+
+int execvpe(const char *file, char *const argv[], char *const envp[]) {
+    static void *next_execvpe;
+    execvpe_f real_execvpe = (execvpe_f)resolve_real(&next_execvpe, "execvpe");
+    if (!real_execvpe) return missing_real();
+
+    if (exec_blocked(file, 1)) return deny_path(__func__, file);
+    struct nh_spawn_env b;
+    char *const *env = spawn_env(file, 1, argv, envp, &b);
+    return real_execvpe(file, argv, env);
+}
+
+int execvp(const char *file, char *const argv[]) {
+    return execvpe(file, argv, environ);
+}
+
+// execl* take the argv as varargs; collect them and go through the v variants.
+#define COLLECT_EXECL_ARGS(arg, argv, after)                        \
+    size_t argc_ = 1;                                               \
+    va_list ap_;                                                    \
+    va_start(ap_, arg);                                             \
+    while (va_arg(ap_, const char*)) argc_++;                       \
+    va_end(ap_);                                                    \
+    char *argv[argc_ + 1];                                          \
+    argv[0] = (char*)(arg);                                         \
+    va_start(ap_, arg);                                             \
+    for (size_t i_ = 1; i_ <= argc_; i_++) argv[i_] = va_arg(ap_, char*); \
+    after;                                                          \
+    va_end(ap_)
+
+int execl(const char *pathname, const char *arg, ...) {
+    COLLECT_EXECL_ARGS(arg, argv, (void)0);
+    return execve(pathname, argv, environ);
+}
+
+int execlp(const char *file, const char *arg, ...) {
+    COLLECT_EXECL_ARGS(arg, argv, (void)0);
+    return execvpe(file, argv, environ);
+}
+
+int execle(const char *pathname, const char *arg, ...) {
+    char *const *envp;
+    COLLECT_EXECL_ARGS(arg, argv, envp = va_arg(ap_, char *const *));
+    return execve(pathname, argv, envp);
+}
+
+int posix_spawn(pid_t *pid, const char *path, const posix_spawn_file_actions_t *fa,
+                const posix_spawnattr_t *attr, char *const argv[], char *const envp[]) {
+    static void *next_posix_spawn;
+    posix_spawn_f real_posix_spawn = (posix_spawn_f)resolve_real(&next_posix_spawn, "posix_spawn");
+    if (!real_posix_spawn) return ENOSYS;
+
+    if (exec_blocked(path, 0)) { deny_path(__func__, path); return errno; }
+    struct nh_spawn_env b;
+    char *const *env = spawn_env(path, 0, argv, envp, &b);
+    return real_posix_spawn(pid, path, fa, attr, argv, env);
+}
+
+int posix_spawnp(pid_t *pid, const char *file, const posix_spawn_file_actions_t *fa,
+                 const posix_spawnattr_t *attr, char *const argv[], char *const envp[]) {
+    static void *next_posix_spawnp;
+    posix_spawn_f real_posix_spawnp = (posix_spawn_f)resolve_real(&next_posix_spawnp, "posix_spawnp");
+    if (!real_posix_spawnp) return ENOSYS;
+
+    if (exec_blocked(file, 1)) { deny_path(__func__, file); return errno; }
+    struct nh_spawn_env b;
+    char *const *env = spawn_env(file, 1, argv, envp, &b);
+    return real_posix_spawnp(pid, file, fa, attr, argv, env);
+}
+
+/* ---- Block dlopen of NVIDIA libs ---- */
+typedef void* (*dlopen_f)(const char*, int);
+
+void *dlopen(const char *filename, int flags) {
+    static void *next_dlopen;
+    static __thread int in_hook = 0;
+
+    dlopen_f real_dlopen = (dlopen_f)__atomic_load_n(&next_dlopen, __ATOMIC_ACQUIRE);
+    if (!real_dlopen) {
+        if (in_hook) { errno = ENOENT; return NULL; }
+        in_hook = 1;
+        real_dlopen = (dlopen_f)resolve_real(&next_dlopen, "dlopen");
+        in_hook = 0;
+    }
+
+    ensure_init();
+    // Not before g_inited: nh_init's own lookups may dlopen NSS modules.
+    int ready = __atomic_load_n(&g_inited, __ATOMIC_ACQUIRE);
+    // Keep rules see the name as passed, e.g. "libcuda.so.1" or a glob over it.
+    if (filename && ready && hiding_now() && !kept_path(filename) && targets_library_hidden(&g_targets, filename) && !dry_run_pass("dlopen", filename)) {
+        NH_COUNT(dlopens_blocked);
+        log_blocked("dlopen", filename);
+        audit_event("dlopen", filename, ENOENT);
+        errno = ENOENT;
+        return NULL;
+    }
+
+    if (!real_dlopen) { errno = ENOSYS; return NULL; }
+    return real_dlopen(filename, flags);
+}
+
+/* ---- Hide NVIDIA entries from directory enumeration ---- */
+typedef struct dirent *(*readdir_f)(DIR*);
+#if NH_LFS64
+typedef struct dirent64 *(*readdir64_f)(DIR*);
+#endif
+
+// Hidden entries are skipped in place and the entry handed back is the one
+// the real readdir just returned, still in the DIR's own buffer. So the DIR's
+// position is always just past a visible entry: telldir() records only
+// visible entries, and a seekdir() into a run of skipped ones resumes at the
+// next visible entry. Offsets are never translated.
+struct dirent *readdir(DIR *dirp) {
+    static void *next_readdir;
+    readdir_f real_readdir = (readdir_f)resolve_real(&next_readdir, "readdir");
+    if (!real_readdir) return NULL;    // end of listing
+
+    char dbuf[PATH_MAX];
+    const char *dir = active_after_init() ? dir_path(dirp, dbuf, sizeof(dbuf)) : NULL;
+
+    struct dirent *ent;
+    while ((ent = real_readdir(dirp)) != NULL) {
+        if (!is_nvidia_dirent(dir, ent->d_name)) return ent;
     }
     return NULL;
 }
+
+#if NH_LFS64
+struct dirent64 *readdir64(DIR *dirp) {
+    static void *next_readdir64;
+    readdir64_f real_readdir64 = (readdir64_f)resolve_real(&next_readdir64, "readdir64");
+    if (!real_readdir64) return NULL;    // end of listing
+
+    char dbuf[PATH_MAX];
+    const char *dir = active_after_init() ? dir_path(dirp, dbuf, sizeof(dbuf)) : NULL;
+
+    struct dirent64 *ent;
+    while ((ent = real_readdir64(dirp)) != NULL) {
+        if (!is_nvidia_dirent(dir, ent->d_name)) return ent;
+    }
+    return NULL;
+}
+#endif
+
+typedef DIR *(*opendir_f)(const char*);
+
+// glibc's opendir opens through its internal __open_nocancel, not open().
+DIR *opendir(const char *name) {
+    static void *next_opendir;
+    opendir_f real_opendir = (opendir_f)resolve_real(&next_opendir, "opendir");
+    if (!real_opendir) { errno = ENOSYS; return NULL; }
+
+    if (is_nvidia_path(name)) { deny_path(__func__, name); return NULL; }
+
+    DIR *d = real_opendir(name);
+    // Resolve the path now so readdir finds it cached.
+    if (d && active_after_init()) {
+        char dbuf[PATH_MAX];
+        dir_path(d, dbuf, sizeof(dbuf));
+    }
+    return d;
+}
+
+typedef int (*closedir_f)(DIR*);
+
+int closedir(DIR *dirp) {
+    static void *next_closedir;
+    closedir_f real_closedir = (closedir_f)resolve_real(&next_closedir, "closedir");
+    if (!real_closedir) return missing_real();
+
+    dir_cache_forget(dirp);
+    return real_closedir(dirp);
+}
+
+/* ---- Filter scandir() results the same way readdir does ---- */
+typedef int (*scandir_filter_f)(const struct dirent*);
+typedef int (*scandir_compar_f)(const struct dirent**, const struct dirent**);
+typedef int (*scandir_f)(const char*, struct dirent***, scandir_filter_f, scandir_compar_f);
+#if NH_LFS64
+typedef int (*scandir64_filter_f)(const struct dirent64*);
+typedef int (*scandir64_compar_f)(const struct dirent64**, const struct dirent64**);
+typedef int (*scandir64_f)(const char*, struct dirent64***, scandir64_filter_f, scandir64_compar_f);
+#endif
+
+// The caller's filter/compar already ran inside the real scandir; we only drop
+// hidden entries afterwards and compact the (still sorted) array in place.
+
+// The directory as the rules key it, like dir_path() gives readdir: dirp may
+// be relative, end in a slash or go through a symlink. Falls back to dirp.
+static const char *scandir_dir(const char *dirp, char *out) {
+    ensure_init();
+    if (!g_active) return dirp;
+    int saved = errno;
+    const char *r = realpath(dirp, out);
+    errno = saved;
+    return r ? r : dirp;
+}
+int scandir(const char *dirp, struct dirent ***namelist,
+            scandir_filter_f filter, scandir_compar_f compar) {
+    static void *next_scandir;
+    scandir_f real_scandir = (scandir_f)resolve_real(&next_scandir, "scandir");
+    if (!real_scandir) return missing_real();
+
+    int n = real_scandir(dirp, namelist, filter, compar);
+    if (n <= 0 || !*namelist) return n;
+
+    char dbuf[PATH_MAX];
+    const char *dir = scandir_dir(dirp, dbuf);
+    struct dirent **list = *namelist;
+    int kept = 0;
+    for (int i = 0; i < n; i++) {
+        if (is_nvidia_dirent(dir, list[i]->d_name)) {
+            free(list[i]);
+            continue;
+        }
+        list[kept++] = list[i];
+    }
+    return kept;
+}
+
+#if NH_LFS64
+int scandir64(const char *dirp, struct dirent64 ***namelist,
+              scandir64_filter_f filter, scandir64_compar_f compar) {
+    static void *next_scandir64;
+    scandir64_f real_scandir64 = (scandir64_f)resolve_real(&next_scandir64, "scandir64");
+    if (!real_scandir64) return missing_real();
+
+    int n = real_scandir64(dirp, namelist, filter, compar);
+    if (n <= 0 || !*namelist) return n;
+
+    char dbuf[PATH_MAX];
+    const char *dir = scandir_dir(dirp, dbuf);
+    struct dirent64 **list = *namelist;
+    int kept = 0;
+    for (int i = 0; i < n; i++) {
+        if (is_nvidia_dirent(dir, list[i]->d_name)) {
+            free(list[i]);
+            continue;
+        }
+        list[kept++] = list[i];
+    }
+    return kept;
+}
+#endif
+
+/* ---- Filter glob() results ---- */
+// glibc's glob scans directories through its internal opendir/readdir, which
+// our hooks never see. Matches are judged as paths, like open() would judge
+// them, and dropped from gl_pathv in place: each is freed and the vector
+// compacted, so the real globfree() still frees exactly what is left.
+// Entries from an earlier call (GLOB_APPEND) were filtered by that call.
+typedef int (*glob_errfunc_f)(const char*, int);
+typedef int (*glob_f)(const char*, int, glob_errfunc_f, glob_t*);
+#if NH_LFS64
+typedef int (*glob64_f)(const char*, int, glob_errfunc_f, glob64_t*);
+#endif
+
+// Returns the new value for the real glob's return code. With GLOB_NOCHECK
+// the pattern itself is a result and is never hidden: it is returned whether
+// or not anything matched, so it gives nothing away.
+static int glob_filter(const char *pattern, int flags, int rc,
+                       char **pathv, size_t *pathc, size_t offs, size_t prev) {
+    if (rc != 0 || !pathv || !active_after_init()) return rc;
+
+    size_t kept = prev;
+    for (size_t i = prev; i < *pathc; i++) {
+        char *path = pathv[offs + i];
+        if (path && !((flags & GLOB_NOCHECK) && !strcmp(path, pattern)) && is_nvidia_path(path)) {
+            log_trace("hidden glob match: %s", path);
+            audit_event("glob", path, 0);
+            free(path);
+            continue;
+        }
+        pathv[offs + kept++] = path;
+    }
+    if (kept == *pathc) return rc;
+    pathv[offs + kept] = NULL;
+    *pathc = kept;
+    if (kept > prev) return rc;
+
+    // Everything new was hidden: what glob does when nothing matches.
+    if (!(flags & GLOB_NOCHECK)) return GLOB_NOMATCH;
+    char *lit = strdup(pattern);
+    if (!lit) return GLOB_NOSPACE;
+    pathv[offs + kept] = lit;
+    pathv[offs + kept + 1] = NULL;
+    *pathc = kept + 1;
+    return rc;
+}
+
+int glob(const char *pattern, int flags, glob_errfunc_f errfunc, glob_t *pglob) {
+    static void *next_glob;
+    glob_f real_glob = (glob_f)resolve_real(&next_glob, "glob");
+    if (!real_glob) { errno = ENOSYS; return GLOB_ABORTED; }
+
+    size_t prev = (flags & GLOB_APPEND) ? pglob->gl_pathc : 0;
+    int rc = real_glob(pattern, flags, errfunc, pglob);
+    size_t offs = (flags & GLOB_DOOFFS) ? pglob->gl_offs : 0;
+    return glob_filter(pattern, flags, rc, pglob->gl_pathv, &pglob->gl_pathc, offs, prev);
+}
+
+#if NH_LFS64
+int glob64(const char *pattern, int flags, glob_errfunc_f errfunc, glob64_t *pglob) {
+    static void *next_glob64;
+    glob64_f real_glob64 = (glob64_f)resolve_real(&next_glob64, "glob64");
+    if (!real_glob64) { errno = ENOSYS; return GLOB_ABORTED; }
+
+    size_t prev = (flags & GLOB_APPEND) ? pglob->gl_pathc : 0;
+    int rc = real_glob64(pattern, flags, errfunc, pglob);
+    size_t offs = (flags & GLOB_DOOFFS) ? pglob->gl_offs : 0;
+    return glob_filter(pattern, flags, rc, pglob->gl_pathv, &pglob->gl_pathc, offs, prev);
+}
+#endif
+
+/* ---- Filter raw getdents64() callers ---- */
+// Programs that call getdents64 themselves (glibc >= 2.30 exports a wrapper)
+// never go through readdir. Truly static binaries ignore LD_PRELOAD entirely,
+// so this only covers dynamically linked callers of the wrapper. musl's
+// wrapper is getdents, filling the same kernel dirent64 records.
+
+// Drops hidden records from the nread bytes at dirp; returns the bytes left.
+// That can be 0 for a batch of nothing but hidden entries, which callers would
+// take for the end of the directory, so the hooks read again until something
+// is left or the real call returns 0 or -1.
+static ssize_t filter_dirents(int fd, void *dirp, ssize_t nread) {
+    if (nread <= 0) return nread;
+
+    char dbuf[PATH_MAX];
+    const char *dir = (active_after_init() && fd_path(fd, dbuf, sizeof(dbuf)) == 0) ? dbuf : NULL;
+
+    char *buf = (char*)dirp;
+    ssize_t bpos = 0;
+    while (bpos < nread) {
+        struct linux_dirent64 *d = (struct linux_dirent64*)(buf + bpos);
+        unsigned short reclen = d->d_reclen;
+        if (is_nvidia_dirent(dir, d->d_name)) {
+            memmove(buf + bpos, buf + bpos + reclen, (size_t)(nread - bpos - reclen));
+            nread -= reclen;
+            continue;
+        }
+        bpos += reclen;
+    }
+    return nread;
+}
+
+#if NH_LFS64
+typedef ssize_t (*getdents64_f)(int, void*, size_t);
+
+ssize_t getdents64(int fd, void *dirp, size_t count) {
+    static void *next_getdents64;
+    getdents64_f real_getdents64 = (getdents64_f)resolve_real(&next_getdents64, "getdents64");
+
+    for (;;) {
+        ssize_t nread = real_getdents64
+            ? real_getdents64(fd, dirp, count)
+            : (ssize_t)syscall(SYS_getdents64, fd, dirp, count);
+        ssize_t left = filter_dirents(fd, dirp, nread);
+        if (nread <= 0 || left > 0) return left;
+    }
+}
+#else
+typedef int (*getdents_f)(int, struct dirent*, size_t);
+
+int getdents(int fd, struct dirent *buf, size_t len) {
+    static void *next_getdents;
+    getdents_f real_getdents = (getdents_f)resolve_real(&next_getdents, "getdents");
+
+    for (;;) {
+        ssize_t nread = real_getdents
+            ? real_getdents(fd, buf, len)
+            : (ssize_t)syscall(SYS_getdents64, fd, buf, len);
+        ssize_t left = filter_dirents(fd, buf, nread);
+        if (nread <= 0 || left > 0) return (int)left;
+    }
+}
+#endif
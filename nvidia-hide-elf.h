@@ -0,0 +1,113 @@
+// ELF header inspection for the nvidia-hide CLI: which ABI a library or
+// program is built for, and whether the dynamic loader (and so LD_PRELOAD)
+// is involved at all when it runs.
+//
+// Header-only like the others; stdio is fine here, nothing in it runs inside
+// the preloaded library.
+#ifndef NVIDIA_HIDE_ELF_H
+#define NVIDIA_HIDE_ELF_H
+
+#include <elf.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/stat.h>
+#include <sys/statvfs.h>
+#include <sys/types.h>
+#include <sys/xattr.h>
+
+// 1 for ELFCLASS32, 2 for ELFCLASS64, -1 if p isn't a readable ELF file.
+static int elf_class(const char *p) {
+    unsigned char ident[5];
+    FILE *f = fopen(p, "rb");
+    if (!f) return -1;
+    size_t n = fread(ident, 1, sizeof(ident), f);
+    fclose(f);
+    if (n != sizeof(ident) || memcmp(ident, "\177ELF", 4) != 0) return -1;
+    return ident[4];
+}
+
+struct nh_elf {
+    int elf_class;      // ELFCLASS32 or ELFCLASS64
+    unsigned machine;   // e_machine, e.g. EM_X86_64
+    int interp;         // has a PT_INTERP segment: started by ld.so, which reads LD_PRELOAD
+};
+
+// Static and static-pie programs have no PT_INTERP, and LD_PRELOAD never
+// reaches them. Only files in this host's byte order are read; anything
+// else, truncated headers included, returns -1.
+static int elf_inspect(const char *path, struct nh_elf *out) {
+    memset(out, 0, sizeof(*out));
+    FILE *f = fopen(path, "rb");
+    if (!f) return -1;
+    union { Elf32_Ehdr h32; Elf64_Ehdr h64; unsigned char ident[EI_NIDENT]; } eh;
+    int rc = -1;
+    size_t n = fread(&eh, 1, sizeof(eh), f);
+    const unsigned char *id = eh.ident;
+#if __BYTE_ORDER__ == __ORDER_LITTLE_ENDIAN__
+    const int host_data = ELFDATA2LSB;
+#else
+    const int host_data = ELFDATA2MSB;
+#endif
+    if (n < EI_NIDENT || memcmp(id, ELFMAG, SELFMAG) != 0 || id[EI_DATA] != host_data) goto out;
+
+    unsigned long long phoff;
+    unsigned phnum, phentsize;
+    if (id[EI_CLASS] == ELFCLASS64 && n >= sizeof(Elf64_Ehdr)) {
+        out->machine = eh.h64.e_machine;
+        phoff = eh.h64.e_phoff;
+        phnum = eh.h64.e_phnum;
+        phentsize = eh.h64.e_phentsize;
+        if (phnum && phentsize < sizeof(Elf64_Phdr)) goto out;
+    } else if (id[EI_CLASS] == ELFCLASS32 && n >= sizeof(Elf32_Ehdr)) {
+        out->machine = eh.h32.e_machine;
+        phoff = eh.h32.e_phoff;
+        phnum = eh.h32.e_phnum;
+        phentsize = eh.h32.e_phentsize;
+        if (phnum && phentsize < sizeof(Elf32_Phdr)) goto out;
+    } else {
+        goto out;
+    }
+    out->elf_class = id[EI_CLASS];
+
+    for (unsigned i = 0; i < phnum; i++) {
+        union { Elf32_Phdr p32; Elf64_Phdr p64; } ph;
+        size_t want = out->elf_class == ELFCLASS64 ? sizeof(ph.p64) : sizeof(ph.p32);
+        if (fseeko(f, (off_t)(phoff + (unsigned long long)i * phentsize), SEEK_SET) != 0 ||
+            fread(&ph, 1, want, f) != want) goto out;
+        unsigned type = out->elf_class == ELFCLASS64 ? ph.p64.p_type : ph.p32.p_type;
+        if (type == PT_INTERP) out->interp = 1;
+    }
+    rc = 0;
+out:
+    fclose(f);
+    return rc;
+}
+
+// 1 if running path as uid/gid would be AT_SECURE, so the loader drops
+// LD_PRELOAD: setuid to someone else, setgid to another group, or file
+// capabilities. The bits don't count on a nosuid mount. 0 if not, -1 if path
+// can't be stat'ed.
+static int exec_drops_preload(const char *path, uid_t uid, gid_t gid) {
+    struct stat st;
+    struct statvfs vfs;
+    if (stat(path, &st) != 0) return -1;
+    if (statvfs(path, &vfs) == 0 && (vfs.f_flag & ST_NOSUID)) return 0;
+    if ((st.st_mode & S_ISUID) && st.st_uid != uid) return 1;
+    if ((st.st_mode & S_ISGID) && st.st_gid != gid) return 1;
+    return getxattr(path, "security.capability", NULL, 0) > 0;
+}
+
+static const char *elf_machine_name(unsigned machine) {
+    switch (machine) {
+    case EM_X86_64: return "x86_64";
+    case EM_386: return "i386";
+    case EM_AARCH64: return "aarch64";
+    case EM_ARM: return "arm";
+    case EM_RISCV: return "riscv";
+    case EM_PPC64: return "ppc64";
+    case EM_S390: return "s390";
+    default: return "unknown machine";
+    }
+}
+
+#endif
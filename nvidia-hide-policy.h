@@ -0,0 +1,713 @@
+// Allow/deny policy evaluation shared by libnvidia-hide.so and the nvidia-hide
+// launcher, so `nvidia-hide check` reports exactly what the library decides.
+//
+// Header-only on purpose: both binaries are single translation units. The
+// includer must define _GNU_SOURCE and provide dbg().
+#ifndef NVIDIA_HIDE_POLICY_H
+#define NVIDIA_HIDE_POLICY_H
+
+#include <ctype.h>
+#include <fcntl.h>
+#include <fnmatch.h>
+#include <limits.h>
+#include <regex.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <strings.h>
+#include <sys/stat.h>
+#include <sys/syscall.h>
+#include <unistd.h>
+
+static void dbg(const char *fmt, ...);
+
+static void trim(char *s) {
+    if (!s) return;
+    size_t n = strlen(s);
+    while (n && (s[n-1] == '\n' || s[n-1] == '\r' || s[n-1] == ' ' || s[n-1] == '\t')) s[--n] = 0;
+    size_t i = 0;
+    while (s[i] == ' ' || s[i] == '\t') i++;
+    if (i) memmove(s, s+i, strlen(s+i)+1);
+}
+
+// One list entry as written, an env token or a file line, cleaned the same
+// way for both: a '#' at its start or after a space or tab begins a comment,
+// then surrounding whitespace goes. A '#' inside a pattern ("a#b") stays.
+static void list_entry_clean(char *s) {
+    for (char *h = strchr(s, '#'); h; h = strchr(h + 1, '#')) {
+        if (h == s || h[-1] == ' ' || h[-1] == '\t') { *h = 0; break; }
+    }
+    trim(s);
+}
+
+static const char *base_name(const char *p) {
+    if (!p) return p;
+    const char *s = strrchr(p, '/');
+    return s ? s+1 : p;
+}
+
+// --------- compiled matchers ---------
+// Each allow/deny line is parsed once into a matcher, so matching itself does
+// no allocation or regex compilation. Globs keep exact fnmatch(pat, target, 0)
+// semantics; a glob without metacharacters is compared with strcmp, which is
+// all fnmatch would do for it.
+enum nh_match_kind { NH_MATCH_LITERAL, NH_MATCH_GLOB, NH_MATCH_REGEX, NH_MATCH_SHA256, NH_MATCH_INODE };
+
+struct nh_matcher {
+    enum nh_match_kind kind;
+    int on_full_path;   // pattern has '/': match exe_full, else exe_base
+    int on_cmdline;     // "cmdline:" line: match the whole command line
+    int on_ancestor;    // "ancestor:" line: match any parent process, same '/' rule
+    int negate;         // "!" line: an exception to the lines above it
+    char *pat;
+    regex_t rx;         // NH_MATCH_REGEX only
+    unsigned char sha256[32];               // NH_MATCH_SHA256 only
+    unsigned long long dev, ino;            // NH_MATCH_INODE only
+};
+
+struct nh_matchers {
+    struct nh_matcher *v;
+    int n, cap;
+    int had_entries;    // any non-comment line, even one that failed to compile
+    int bad;            // lines dropped as unparsable, and includes that failed
+};
+
+// "re:<ERE>" lines use POSIX extended regex with the same '/' rule.
+// A pattern that fails to compile is dropped and never matches.
+// "cmdline:<glob>" lines match the command line (arguments joined by spaces)
+// instead, to tell apart programs that share one binary.
+// "ancestor:<glob>" lines match each process up the parent chain, so everything
+// started under e.g. steam's reaper can be covered by one line.
+// "sha256:<hex>" and "inode:<dev>:<ino>" lines match the executable itself by
+// content or by file identity (`stat -c %d:%i`), wherever it was started from.
+// A leading '!' (before any prefix) makes the line an exception; see matchers_match.
+static int parse_sha256_hex(const char *s, unsigned char out[32]) {
+    if (strlen(s) != 64) return -1;
+    for (int i = 0; i < 32; i++) {
+        unsigned v;
+        if (!isxdigit((unsigned char)s[2*i]) || !isxdigit((unsigned char)s[2*i+1]) ||
+            sscanf(s + 2*i, "%2x", &v) != 1) return -1;
+        out[i] = (unsigned char)v;
+    }
+    return 0;
+}
+
+static void matchers_add(struct nh_matchers *m, const char *line) {
+    if (!line || !*line) return;
+    int negate = line[0] == '!';
+    if (negate && !*++line) return;     // a bare '!' is no entry at all
+    m->had_entries = 1;
+
+    if (m->n == m->cap) {
+        int cap = m->cap ? m->cap * 2 : 8;
+        struct nh_matcher *v = (struct nh_matcher*)realloc(m->v, (size_t)cap * sizeof(*v));
+        if (!v) return;
+        m->v = v;
+        m->cap = cap;
+    }
+
+    struct nh_matcher *e = &m->v[m->n];
+    memset(e, 0, sizeof(*e));
+    e->negate = negate;
+    if (!strncmp(line, "cmdline:", 8)) {
+        line += 8;
+        e->on_cmdline = 1;
+        e->kind = strpbrk(line, "*?[\\") ? NH_MATCH_GLOB : NH_MATCH_LITERAL;
+    } else if (!strncmp(line, "ancestor:", 9)) {
+        line += 9;
+        e->on_ancestor = 1;
+        e->kind = strpbrk(line, "*?[\\") ? NH_MATCH_GLOB : NH_MATCH_LITERAL;
+        e->on_full_path = strchr(line, '/') != NULL;
+    } else if (!strncmp(line, "sha256:", 7)) {
+        line += 7;
+        if (parse_sha256_hex(line, e->sha256) != 0) {
+            dbg("policy: bad sha256 '%s', expected 64 hex digits", line);
+            m->bad++;
+            return;
+        }
+        e->kind = NH_MATCH_SHA256;
+    } else if (!strncmp(line, "inode:", 6)) {
+        line += 6;
+        int end = 0;
+        if (sscanf(line, "%llu:%llu%n", &e->dev, &e->ino, &end) != 2 || line[end]) {
+            dbg("policy: bad inode '%s', expected <dev>:<ino> as from stat -c %%d:%%i", line);
+            m->bad++;
+            return;
+        }
+        e->kind = NH_MATCH_INODE;
+    } else if (!strncmp(line, "re:", 3)) {
+        const char *re = line + 3;
+        int rc = regcomp(&e->rx, re, REG_EXTENDED | REG_NOSUB);
+        if (rc != 0) {
+            char err[128];
+            regerror(rc, &e->rx, err, sizeof(err));
+            dbg("policy: bad regex '%s': %s", re, err);
+            m->bad++;
+            return;
+        }
+        e->kind = NH_MATCH_REGEX;
+        e->on_full_path = strchr(re, '/') != NULL;
+    } else {
+        e->kind = strpbrk(line, "*?[\\") ? NH_MATCH_GLOB : NH_MATCH_LITERAL;
+        e->on_full_path = strchr(line, '/') != NULL;
+    }
+
+    e->pat = strdup(line);
+    if (!e->pat) {
+        if (e->kind == NH_MATCH_REGEX) regfree(&e->rx);
+        return;
+    }
+    m->n++;
+}
+
+// Env list is colon-separated patterns. A "re:", "cmdline:" or "ancestor:"
+// prefix is kept with its pattern, so those patterns just can't contain ':' themselves.
+static size_t matcher_prefix_len(const char *p) {
+    size_t bang = p[0] == '!';
+    p += bang;
+    if (!strncmp(p, "re:", 3)) return bang + 3;
+    if (!strncmp(p, "cmdline:", 8)) return bang + 8;
+    if (!strncmp(p, "ancestor:", 9)) return bang + 9;
+    if (!strncmp(p, "sha256:", 7)) return bang + 7;
+    if (!strncmp(p, "inode:", 6)) {
+        // the device number is followed by a ':' of its own
+        size_t n = 6 + strspn(p + 6, "0123456789");
+        return bang + n + (p[n] == ':');
+    }
+    return bang;
+}
+
+static void matchers_from_env(struct nh_matchers *m, const char *envval) {
+    if (!envval || !*envval) return;
+    const char *p = envval;
+    while (*p) {
+        p += strspn(p, " \t");
+        const char *q = strchr(p + matcher_prefix_len(p), ':');
+        size_t len = q ? (size_t)(q - p) : strlen(p);
+        if (len) {
+            char *buf = strndup(p, len);
+            if (buf) {
+                list_entry_clean(buf);
+                matchers_add(m, buf);
+                free(buf);
+            }
+        }
+        if (!q) break;
+        p = q + 1;
+    }
+}
+
+// "include <path>" lines pull in another list. Relative paths are taken from
+// the including file's directory; a leading "~/" or "$XDG_CONFIG_HOME/" is
+// expanded. Missing files, cycles and nesting deeper than
+// NH_MAX_INCLUDE_DEPTH contribute nothing.
+#define NH_MAX_INCLUDE_DEPTH 16
+
+static void expand_include_path(char *out, size_t out_sz, const char *from_file, const char *p) {
+    const char *home = getenv("HOME");
+    const char *xdg = getenv("XDG_CONFIG_HOME");
+    if (!strncmp(p, "~/", 2) && home && *home) {
+        snprintf(out, out_sz, "%s/%s", home, p + 2);
+    } else if (!strncmp(p, "$XDG_CONFIG_HOME/", 17)) {
+        if (xdg && *xdg) snprintf(out, out_sz, "%s/%s", xdg, p + 17);
+        else snprintf(out, out_sz, "%s/.config/%s", home ? home : "", p + 17);
+    } else if (p[0] == '/') {
+        snprintf(out, out_sz, "%s", p);
+    } else {
+        const char *slash = strrchr(from_file, '/');
+        int dir_len = slash ? (int)(slash - from_file) : 1;
+        snprintf(out, out_sz, "%.*s/%s", dir_len, slash ? from_file : ".", p);
+    }
+}
+
+// stack holds the canonical paths of the files currently being read.
+static void matchers_from_file_nested(struct nh_matchers *m, const char *path,
+                                      char (*stack)[PATH_MAX], int depth) {
+    if (depth >= NH_MAX_INCLUDE_DEPTH) {
+        dbg("policy: include depth limit reached at %s", path);
+        m->bad++;
+        return;
+    }
+    if (!realpath(path, stack[depth])) {
+        if (depth) {
+            dbg("policy: missing include %s", path);
+            m->bad++;
+        }
+        return;
+    }
+    for (int i = 0; i < depth; i++) {
+        if (!strcmp(stack[i], stack[depth])) {
+            dbg("policy: include cycle at %s", path);
+            m->bad++;
+            return;
+        }
+    }
+
+    FILE *f = fopen(path, "r");
+    if (!f) return;
+    // getline: no length limit, and the length shows NUL bytes that would
+    // otherwise silently cut the line short
+    char *line = NULL;
+    size_t cap = 0;
+    ssize_t len;
+    for (int lineno = 1; (len = getline(&line, &cap, f)) != -1; lineno++) {
+        if (memchr(line, 0, (size_t)len)) {
+            dbg("policy: %s:%d: line contains a NUL byte, ignored", path, lineno);
+            m->bad++;
+            continue;
+        }
+        list_entry_clean(line);
+        if (!line[0]) continue;
+        if (!strncmp(line, "include", 7) && (line[7] == ' ' || line[7] == '\t')) {
+            char *inc = line + 8;
+            trim(inc);
+            char inc_path[PATH_MAX];
+            expand_include_path(inc_path, sizeof(inc_path), stack[depth], inc);
+            matchers_from_file_nested(m, inc_path, stack, depth + 1);
+            continue;
+        }
+        matchers_add(m, line);
+    }
+    free(line);
+    fclose(f);
+}
+
+static void matchers_from_file(struct nh_matchers *m, const char *path) {
+    if (!path || !*path) return;
+    char (*stack)[PATH_MAX] = (char (*)[PATH_MAX])malloc(NH_MAX_INCLUDE_DEPTH * PATH_MAX);
+    if (!stack) return;
+    matchers_from_file_nested(m, path, stack, 0);
+    free(stack);
+}
+
+// --------- parent chain ---------
+// Reads /proc/<pid>/<what>: "stat" and "comm" as text, "exe" as a link target.
+// Returns the length read or -1. Swappable so the walk can run on a fake tree.
+typedef ssize_t (*nh_proc_reader)(int pid, const char *what, char *buf, size_t sz);
+
+// Raw syscalls: the library walks the chain during init, under its own hooks.
+static ssize_t nh_proc_read(int pid, const char *what, char *buf, size_t sz) {
+    char path[64];
+    snprintf(path, sizeof(path), "/proc/%d/%s", pid, what);
+    if (!strcmp(what, "exe")) return syscall(SYS_readlinkat, AT_FDCWD, path, buf, sz);
+    int fd = (int)syscall(SYS_openat, AT_FDCWD, path, O_RDONLY | O_CLOEXEC, 0);
+    if (fd < 0) return -1;
+    ssize_t n = read(fd, buf, sz);
+    close(fd);
+    return n;
+}
+
+#define NH_MAX_ANCESTORS 32
+
+// Walked on first use by an "ancestor:" line, from our parent up to (not
+// including) init. Each entry is the exe path, or comm where exe can't be read
+// (another user's process). The walk just stops at anything unreadable.
+struct nh_ancestors {
+    nh_proc_reader read;    // NULL: nh_proc_read
+    int pid;                // whose ancestors; 0: ours
+    int walked;
+    int n;
+    char *names[NH_MAX_ANCESTORS];
+};
+
+static int proc_ppid(nh_proc_reader rd, int pid) {
+    char buf[512];
+    ssize_t n = rd(pid, "stat", buf, sizeof(buf) - 1);
+    if (n <= 0) return -1;
+    buf[n] = 0;
+    // comm may itself contain ") ", so the state field follows the last ')'
+    const char *p = strrchr(buf, ')');
+    int ppid;
+    char state;
+    if (!p || sscanf(p + 1, " %c %d", &state, &ppid) != 2) return -1;
+    return ppid;
+}
+
+static void ancestors_walk(struct nh_ancestors *a) {
+    if (a->walked) return;
+    a->walked = 1;
+    nh_proc_reader rd = a->read ? a->read : nh_proc_read;
+
+    int pid = a->pid ? a->pid : (int)getpid();
+    while (a->n < NH_MAX_ANCESTORS) {
+        pid = proc_ppid(rd, pid);
+        if (pid <= 1) break;
+
+        char name[PATH_MAX];
+        ssize_t n = rd(pid, "exe", name, sizeof(name) - 1);
+        if (n <= 0) {
+            n = rd(pid, "comm", name, sizeof(name) - 1);
+            while (n > 0 && name[n-1] == '\n') n--;
+        }
+        if (n <= 0) break;
+        name[n] = 0;
+        if (!(a->names[a->n] = strdup(name))) break;
+        a->n++;
+    }
+}
+
+static void ancestors_free(struct nh_ancestors *a) {
+    for (int i = 0; i < a->n; i++) free(a->names[i]);
+    a->n = 0;
+    a->walked = 0;
+}
+
+// --------- executable identity ---------
+// For sha256:/inode: lines. Filled on first use by such a line: the stat is one
+// fstat, the hash reads the whole file, so neither happens for policies that
+// don't ask. Raw openat like nh_proc_read, so the library can use it from init.
+struct nh_exe_id {
+    const char *path;       // "/proc/self/exe" in the library, the resolved exe in the CLI
+    int statted, stat_ok;
+    int hashed, hash_ok;
+    unsigned long long dev, ino;
+    unsigned char sha256[32];
+};
+
+struct nh_sha256 {
+    uint32_t h[8];
+    uint64_t len;
+    unsigned char buf[64];
+    size_t n;
+};
+
+static const uint32_t g_sha256_k[64] = {
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+};
+
+#define NH_ROR32(x, r) (((x) >> (r)) | ((x) << (32 - (r))))
+
+static void sha256_block(struct nh_sha256 *c, const unsigned char *p) {
+    uint32_t w[64], v[8];
+    for (int i = 0; i < 16; i++)
+        w[i] = (uint32_t)p[4*i] << 24 | (uint32_t)p[4*i+1] << 16 | (uint32_t)p[4*i+2] << 8 | p[4*i+3];
+    for (int i = 16; i < 64; i++) {
+        uint32_t s0 = NH_ROR32(w[i-15], 7) ^ NH_ROR32(w[i-15], 18) ^ (w[i-15] >> 3);
+        uint32_t s1 = NH_ROR32(w[i-2], 17) ^ NH_ROR32(w[i-2], 19) ^ (w[i-2] >> 10);
+        w[i] = w[i-16] + s0 + w[i-7] + s1;
+    }
+    memcpy(v, c->h, sizeof(v));
+    for (int i = 0; i < 64; i++) {
+        uint32_t t1 = v[7] + (NH_ROR32(v[4], 6) ^ NH_ROR32(v[4], 11) ^ NH_ROR32(v[4], 25))
+                    + ((v[4] & v[5]) ^ (~v[4] & v[6])) + g_sha256_k[i] + w[i];
+        uint32_t t2 = (NH_ROR32(v[0], 2) ^ NH_ROR32(v[0], 13) ^ NH_ROR32(v[0], 22))
+                    + ((v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]));
+        memmove(v + 1, v, 7 * sizeof(v[0]));
+        v[4] += t1;
+        v[0] = t1 + t2;
+    }
+    for (int i = 0; i < 8; i++) c->h[i] += v[i];
+}
+
+static void sha256_init(struct nh_sha256 *c) {
+    static const uint32_t iv[8] = {
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+    };
+    memset(c, 0, sizeof(*c));
+    memcpy(c->h, iv, sizeof(iv));
+}
+
+static void sha256_update(struct nh_sha256 *c, const unsigned char *p, size_t len) {
+    c->len += len;
+    while (len) {
+        size_t take = 64 - c->n < len ? 64 - c->n : len;
+        memcpy(c->buf + c->n, p, take);
+        c->n += take;
+        p += take;
+        len -= take;
+        if (c->n == 64) {
+            sha256_block(c, c->buf);
+            c->n = 0;
+        }
+    }
+}
+
+static void sha256_final(struct nh_sha256 *c, unsigned char out[32]) {
+    uint64_t bits = c->len * 8;
+    unsigned char pad[72] = { 0x80 };
+    size_t padlen = (c->n < 56 ? 56 : 120) - c->n;
+    for (int i = 0; i < 8; i++) pad[padlen + i] = (unsigned char)(bits >> (56 - 8*i));
+    sha256_update(c, pad, padlen + 8);
+    for (int i = 0; i < 8; i++) {
+        out[4*i] = (unsigned char)(c->h[i] >> 24);
+        out[4*i+1] = (unsigned char)(c->h[i] >> 16);
+        out[4*i+2] = (unsigned char)(c->h[i] >> 8);
+        out[4*i+3] = (unsigned char)c->h[i];
+    }
+}
+
+static void exe_id_fill(struct nh_exe_id *id, int want_hash) {
+    if (id->statted && (id->hashed || !want_hash)) return;
+    int fd = (int)syscall(SYS_openat, AT_FDCWD, id->path, O_RDONLY | O_CLOEXEC, 0);
+    if (!id->statted) {
+        struct stat st;
+        id->statted = 1;
+        if (fd >= 0 && fstat(fd, &st) == 0) {
+            id->dev = (unsigned long long)st.st_dev;
+            id->ino = (unsigned long long)st.st_ino;
+            id->stat_ok = 1;
+        }
+    }
+    if (want_hash && !id->hashed) {
+        id->hashed = 1;
+        struct nh_sha256 c;
+        sha256_init(&c);
+        unsigned char buf[16384];
+        ssize_t n = 0;
+        while (fd >= 0 && (n = read(fd, buf, sizeof(buf))) > 0) sha256_update(&c, buf, (size_t)n);
+        if (fd >= 0 && n == 0) {
+            sha256_final(&c, id->sha256);
+            id->hash_ok = 1;
+        } else {
+            dbg("policy: cannot hash %s for sha256: lines", id->path);
+        }
+    }
+    if (fd >= 0) close(fd);
+}
+
+static int exe_id_matches(const struct nh_matcher *e, struct nh_exe_id *id) {
+    exe_id_fill(id, e->kind == NH_MATCH_SHA256);
+    if (e->kind == NH_MATCH_SHA256) return id->hash_ok && !memcmp(id->sha256, e->sha256, 32);
+    return id->stat_ok && id->dev == e->dev && id->ino == e->ino;
+}
+
+// What allow/deny lines are matched against.
+struct nh_subject {
+    const char *exe_full;
+    const char *exe_base;
+    const char *cmdline;    // NULL when unknown: cmdline lines never match
+    struct nh_ancestors *ancestors;     // NULL: ancestor lines never match
+    struct nh_exe_id *exe_id;           // NULL: sha256/inode lines never match
+};
+
+static int matcher_hit(const struct nh_matcher *e, const char *target) {
+    switch (e->kind) {
+    case NH_MATCH_LITERAL:
+        return !strcmp(e->pat, target);
+    case NH_MATCH_GLOB:
+        // FNM_PATHNAME would make '*' not cross '/', but we want typical shell-glob semantics.
+        return fnmatch(e->pat, target, 0) == 0;
+    case NH_MATCH_REGEX:
+        return regexec(&e->rx, target, 0, NULL, 0) == 0;
+    case NH_MATCH_SHA256:
+    case NH_MATCH_INODE:
+        break;      // not about names; see exe_id_matches
+    }
+    return 0;
+}
+
+static int matcher_matches(const struct nh_matcher *e, const struct nh_subject *s) {
+    if (e->kind == NH_MATCH_SHA256 || e->kind == NH_MATCH_INODE) return s->exe_id && exe_id_matches(e, s->exe_id);
+    if (e->on_ancestor) {
+        if (!s->ancestors) return 0;
+        ancestors_walk(s->ancestors);
+        for (int k = 0; k < s->ancestors->n; k++) {
+            const char *name = s->ancestors->names[k];
+            if (matcher_hit(e, e->on_full_path ? name : base_name(name))) return 1;
+        }
+        return 0;
+    }
+    const char *target = e->on_cmdline ? s->cmdline : e->on_full_path ? s->exe_full : s->exe_base;
+    return target && matcher_hit(e, target);
+}
+
+// The last matching line decides: the list matches unless that line is a '!'
+// exception, so "*" followed by "!mpv" covers everything but mpv. Without '!'
+// lines this is just "any line matches".
+static int matchers_match(const struct nh_matchers *m, const struct nh_subject *s) {
+    for (int i = m->n - 1; i >= 0; i--) {
+        if (matcher_matches(&m->v[i], s)) return !m->v[i].negate;
+    }
+    return 0;
+}
+
+static void matchers_free(struct nh_matchers *m) {
+    for (int i = 0; i < m->n; i++) {
+        if (m->v[i].kind == NH_MATCH_REGEX) regfree(&m->v[i].rx);
+        free(m->v[i].pat);
+    }
+    free(m->v);
+    memset(m, 0, sizeof(*m));
+}
+
+static void build_xdg_path(char *out, size_t out_sz, const char *leaf) {
+    if (!out || out_sz == 0) return;
+    out[0] = 0;
+    const char *xdg = getenv("XDG_CONFIG_HOME");
+    const char *home = getenv("HOME");
+    if (xdg && *xdg) {
+        snprintf(out, out_sz, "%s/nvidia-hide/%s", xdg, leaf);
+    } else if (home && *home) {
+        snprintf(out, out_sz, "%s/.config/nvidia-hide/%s", home, leaf);
+    } else {
+        snprintf(out, out_sz, "/nonexistent/%s", leaf);
+    }
+}
+
+// Each list is read from /etc/nvidia-hide (admin baseline) and then from the
+// user's XDG dir, and the two are merged by union: a deny match in either
+// disables, an allowlist with entries in either makes the process allow-only,
+// and a match in either satisfies it. A user file can therefore add to the
+// system lists but never re-enable what /etc denies.
+#define NH_SYSTEM_CONFIG_DIR "/etc/nvidia-hide"
+
+#define NH_POLICY_FILES 2
+
+struct nh_policy_files {
+    char system[PATH_MAX];
+    char user[PATH_MAX];
+    const char *list[NH_POLICY_FILES + 1];    // { system, user, NULL }, for policy_sets_load()
+};
+
+static void policy_files(struct nh_policy_files *f, const char *leaf) {
+    snprintf(f->system, sizeof(f->system), NH_SYSTEM_CONFIG_DIR "/%s", leaf);
+    build_xdg_path(f->user, sizeof(f->user), leaf);
+    f->list[0] = f->system;
+    f->list[1] = f->user;
+    f->list[2] = NULL;
+}
+
+// The allow/deny lists compiled once per policy load, so deciding matches
+// against them without parsing a line. Each file stays a set of its own: a
+// '!' line in the user's file must not cancel a match in /etc's.
+struct nh_policy_sets {
+    struct nh_matchers allow_env, deny_env;
+    struct nh_matchers allow_files[NH_POLICY_FILES], deny_files[NH_POLICY_FILES];
+    int allow_env_set;      // LIBNVIDIAHIDE_ALLOWLIST non-empty, even if all comments
+};
+
+// Missing files are just empty sets; lists past NH_POLICY_FILES are ignored.
+static void policy_sets_load(struct nh_policy_sets *p, const char *env_allow, const char *env_deny,
+                             const char *const *allow_files, const char *const *deny_files) {
+    memset(p, 0, sizeof(*p));
+    matchers_from_env(&p->allow_env, env_allow);
+    matchers_from_env(&p->deny_env, env_deny);
+    p->allow_env_set = env_allow && *env_allow;
+    for (int i = 0; i < NH_POLICY_FILES && allow_files && allow_files[i]; i++)
+        matchers_from_file(&p->allow_files[i], allow_files[i]);
+    for (int i = 0; i < NH_POLICY_FILES && deny_files && deny_files[i]; i++)
+        matchers_from_file(&p->deny_files[i], deny_files[i]);
+}
+
+static void policy_sets_free(struct nh_policy_sets *p) {
+    matchers_free(&p->allow_env);
+    matchers_free(&p->deny_env);
+    for (int i = 0; i < NH_POLICY_FILES; i++) {
+        matchers_free(&p->allow_files[i]);
+        matchers_free(&p->deny_files[i]);
+    }
+}
+
+// Matches and "had entries" are unioned over the files.
+static int file_sets_match(const struct nh_matchers *sets, const struct nh_subject *s, int *out_had_entries) {
+    int r = 0, had = 0;
+    for (int i = 0; i < NH_POLICY_FILES; i++) {
+        if (matchers_match(&sets[i], s)) r = 1;
+        if (sets[i].had_entries) had = 1;
+    }
+    if (out_had_entries) *out_had_entries = had;
+    return r;
+}
+
+struct nh_decision {
+    int allow_match_env;
+    int allow_match_file;
+    int deny_match_env;
+    int deny_match_file;
+    int has_allow;      // any allowlist entries at all (env or file)
+    int allow_match;
+    int deny_match;
+    int active;         // final verdict
+};
+
+// LIBNVIDIAHIDE_ACTIVE=0/1 forces the verdict without reading allow/deny.
+// Returns -1 when unset or any other value.
+static int active_override(void) {
+    const char *env = getenv("LIBNVIDIAHIDE_ACTIVE");
+    if (!env) return -1;
+    if (!strcmp(env, "0")) return 0;
+    if (!strcmp(env, "1")) return 1;
+    return -1;
+}
+
+// LIBNVIDIAHIDE_REQUIRE_ENV=VAR keeps hiding on only while VAR is set to
+// something other than "" or "0", e.g. in shells that export it. Composes with
+// allow/deny: both have to agree. Returns -1 when no gate is configured,
+// otherwise 0/1 with *var set to the variable's name.
+static int require_env_gate(const char **var) {
+    const char *name = getenv("LIBNVIDIAHIDE_REQUIRE_ENV");
+    if (!name || !*name) return -1;
+    *var = name;
+    const char *v = getenv(name);
+    return v && *v && strcmp(v, "0") != 0;
+}
+
+// The kind of session this process runs in: XDG_SESSION_TYPE when set,
+// otherwise guessed from the display variables. WAYLAND_DISPLAY wins over
+// DISPLAY, which Xwayland sets as well; with neither it is "tty".
+static const char *session_type(void) {
+    const char *t = getenv("XDG_SESSION_TYPE");
+    if (t && *t) return t;
+    const char *w = getenv("WAYLAND_DISPLAY");
+    if (w && *w) return "wayland";
+    const char *x = getenv("DISPLAY");
+    if (x && *x) return "x11";
+    return "tty";
+}
+
+// LIBNVIDIAHIDE_ONLY_SESSION=wayland[,x11,...] keeps hiding on only in
+// sessions of the listed types (case-insensitive), e.g. to hide the dGPU under
+// Wayland but leave it to PRIME offload under X11. Composes with allow/deny
+// like REQUIRE_ENV. Returns -1 when unset, otherwise 0/1 with *type set to
+// the session's type.
+static int only_session_gate(const char **type) {
+    const char *list = getenv("LIBNVIDIAHIDE_ONLY_SESSION");
+    if (!list || !*list) return -1;
+    const char *t = session_type();
+    *type = t;
+    size_t tl = strlen(t);
+    for (const char *p = list; *p; ) {
+        size_t n = strcspn(p, ",");
+        if (n == tl && !strncasecmp(p, t, n)) return 1;
+        p += n;
+        if (*p) p++;
+    }
+    return 0;
+}
+
+// Pure decision: no globals, no /proc, no parsing.
+static struct nh_decision decide_active(const struct nh_subject *s, const struct nh_policy_sets *p) {
+    struct nh_decision d;
+    memset(&d, 0, sizeof(d));
+
+    int file_allow_had = 0;
+
+    d.allow_match_env = matchers_match(&p->allow_env, s);
+    d.deny_match_env  = matchers_match(&p->deny_env,  s);
+
+    d.allow_match_file = file_sets_match(p->allow_files, s, &file_allow_had);
+    d.deny_match_file  = file_sets_match(p->deny_files,  s, NULL);
+
+    d.has_allow = p->allow_env_set || file_allow_had;
+    d.allow_match = d.allow_match_env || d.allow_match_file;
+    d.deny_match = d.deny_match_env || d.deny_match_file;
+    d.active = 1;
+
+    // If allowlist exists and we don't match it => disable.
+    if (d.has_allow && !d.allow_match) d.active = 0;
+
+    // Denylist always wins if matched.
+    if (d.deny_match) d.active = 0;
+
+    return d;
+}
+
+#endif
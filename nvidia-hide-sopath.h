@@ -0,0 +1,66 @@
+// Where the nvidia-hide CLI looks for libnvidia-hide.so once LIBNVIDIAHIDE_SO,
+// --so-dir and its own prefix have come up empty: the fixed places a package
+// may have put it.
+//
+// Header-only like the others, so tests/sopath-probe.c can run the search
+// over a fake tree; nothing in it runs inside the preloaded library.
+#ifndef NVIDIA_HIDE_SOPATH_H
+#define NVIDIA_HIDE_SOPATH_H
+
+#include <limits.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/stat.h>
+
+static const char *default_so_name(void) {
+    return "libnvidia-hide.so";
+}
+
+// own_dir is where our own libc was loaded from, /usr stripped ("lib64",
+// "lib/x86_64-linux-gnu"; NULL if unknown), which adds the multiarch triplet
+// dir; each $XDG_DATA_DIRS entry (default /usr/local/share:/usr/share, like
+// the spec) contributes <dir>/nvidia-hide/, which is where Nix and Guix
+// profiles end up. Returns how many were written.
+#define MAX_SO_CANDIDATES 32
+
+static int so_candidates(char (*out)[PATH_MAX], int max, const char *own_dir, const char *xdg_data_dirs) {
+    static const char *const dirs[] = {
+        "/usr/lib", "/usr/lib64", "/usr/local/lib", "/usr/local/lib64", "/lib", "/lib64", NULL
+    };
+    int n = 0;
+    for (int i = 0; dirs[i] && n < max; i++) {
+        snprintf(out[n++], PATH_MAX, "%s/%s", dirs[i], default_so_name());
+        // e.g. /usr/lib/x86_64-linux-gnu, right after the /usr/lib it lives in
+        if (i == 0 && own_dir && !strncmp(own_dir, "lib/", 4) && n < max)
+            snprintf(out[n++], PATH_MAX, "/usr/%.*s/%s", PATH_MAX - 64, own_dir, default_so_name());
+    }
+
+    if (!xdg_data_dirs || !*xdg_data_dirs) xdg_data_dirs = "/usr/local/share:/usr/share";
+    for (const char *p = xdg_data_dirs; *p && n < max; ) {
+        const char *end = strchr(p, ':');
+        int len = end ? (int)(end - p) : (int)strlen(p);
+        if (len && p[0] == '/') snprintf(out[n++], PATH_MAX, "%.*s/nvidia-hide/%s", len, p, default_so_name());
+        if (!end) break;
+        p = end + 1;
+    }
+    return n;
+}
+
+// The first candidate that is a regular file, looked up under root ("" for
+// the real filesystem) and written to out with root in front. 0 or -1.
+static int so_find_candidate(char *out, size_t out_sz, const char *root, const char *own_dir, const char *xdg_data_dirs) {
+    static char cands[MAX_SO_CANDIDATES][PATH_MAX];
+    int n = so_candidates(cands, MAX_SO_CANDIDATES, own_dir, xdg_data_dirs);
+    for (int i = 0; i < n; i++) {
+        char p[PATH_MAX];
+        struct stat st;
+        if (snprintf(p, sizeof(p), "%s%s", root, cands[i]) >= (int)sizeof(p)) continue;
+        if (stat(p, &st) == 0 && S_ISREG(st.st_mode)) {
+            snprintf(out, out_sz, "%s", p);
+            return 0;
+        }
+    }
+    return -1;
+}
+
+#endif
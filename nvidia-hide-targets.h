@@ -0,0 +1,610 @@
+// What gets hidden: the vendor profiles, the discovered DRM nodes and BDFs,
+// and the path predicate over them. Shared by the library's hooks and the
+// launcher's seccomp supervisor so both refuse exactly the same paths.
+//
+// Nothing here interposes anything, so a launcher of your own can embed the
+// same decisions without LD_PRELOAD:
+//
+//   struct nh_vendor_sel sel;
+//   vendor_sel_parse(&sel, getenv("LIBNVIDIAHIDE_VENDOR"));
+//   struct nh_targets t;
+//   targets_init(&t, &sel);
+//   targets_discover(&t, &sel);
+//   targets_match_path(&t, "/dev/nvidia0");             -> 1
+//   targets_match_path(&t, "/usr/lib/libEGL_mesa.so.0");  -> 0
+//
+// Header-only like nvidia-hide-policy.h. The includer must define _GNU_SOURCE
+// and provide dbg().
+#ifndef NVIDIA_HIDE_TARGETS_H
+#define NVIDIA_HIDE_TARGETS_H
+
+#include <ctype.h>
+#include <limits.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <strings.h>
+
+#include "nvidia-hide-policy.h"   // trim, build_xdg_path
+#include "nvidia-hide-topology.h" // nh_scan_drm
+
+static void dbg(const char *fmt, ...);
+
+// --------- vendor profile ---------
+// LIBNVIDIAHIDE_VENDOR=<hex>[:<dev>,<dev>...] picks the PCI vendor to hide
+// (default 10de) and optionally restricts discovery to specific device IDs.
+// The string rules for /dev nodes, enumeration and userspace stacks come from
+// the matching profile; an unknown vendor gets DRM-node hiding only.
+#define MAX_PROFILE_RULES 16
+struct vendor_profile {
+    unsigned vendor;
+    const char *name;
+    const char *dev_prefixes[MAX_PROFILE_RULES];      // device nodes, full path prefix
+    const char *dirent_prefixes[MAX_PROFILE_RULES];   // names hidden from enumeration
+    const char *path_substrings[MAX_PROFILE_RULES];   // GBM/GL/Vulkan assets
+    const char *driver_substrings[MAX_PROFILE_RULES]; // driver procfs/sysfs
+    const char *dlopen_prefixes[MAX_PROFILE_RULES];   // library base names refused by dlopen
+    // Loader selection dirs (GLVND, EGL external platforms) that are globbed
+    // and read file by file: entries whose name contains one of
+    // config_names are hidden from listings and refused on open.
+    const char *config_dirs[MAX_PROFILE_RULES];
+    const char *config_names[MAX_PROFILE_RULES];
+    // Compute stack (CUDA/ROCm): checked first so LIBNVIDIAHIDE_HIDE_COMPUTE=0
+    // can let these through even where a broader rule above would match.
+    const char *compute_dev_prefixes[MAX_PROFILE_RULES];
+    const char *compute_substrings[MAX_PROFILE_RULES];  // open and dlopen
+    // Vendor tools that open the device themselves (LIBNVIDIAHIDE_BLOCK_NVIDIA_TOOLS)
+    const char *tool_names[MAX_PROFILE_RULES];
+};
+
+static const struct vendor_profile g_profiles[] = {
+    { 0x10de, "nvidia",
+      { "/dev/nvidia", NULL },
+      { "nvidia", NULL },
+      { "nvidia-drm_gbm.so", "libGLX_nvidia.so",
+        "/usr/share/vulkan/implicit_layer.d/nvidia", "/usr/share/vulkan/icd.d/nvidia",
+        "/usr/lib/libnvidia-", NULL },
+      // nvidia, nvidia_uvm, nvidia_drm, nvidia_modeset
+      { "/proc/driver/nvidia", "/sys/module/nvidia", NULL },
+      { "nvidia", "libGLX_nvidia", "libEGL_nvidia", "libGLESv2_nvidia", "libGLESv1_CM_nvidia",
+        "libvdpau_nvidia", "libnvidia-", NULL },
+      // 10_nvidia.json, 10_nvidia_wayland.json, 15_nvidia_gbm.json, ...
+      { "/usr/share/glvnd/egl_vendor.d", "/etc/glvnd/egl_vendor.d",
+        "/usr/share/egl/egl_external_platform.d", "/etc/egl/egl_external_platform.d", NULL },
+      { "nvidia", NULL },
+      { "/dev/nvidia-uvm", "/dev/nvidia-caps", NULL },  // also covers nvidia-uvm-tools
+      { "libcuda", "libcudart", "libnvidia-ml", "libnvcuvid",
+        "libnvidia-ptxjitcompiler", "libnvidia-nvvm", NULL },
+      { "nvidia-smi", "nvidia-settings", "nvidia-persistenced", NULL } },
+    { 0x1002, "amdgpu",
+      { NULL },
+      { "kfd", NULL },
+      { "libvulkan_radeon.so", "amdvlk", "/usr/share/vulkan/icd.d/radeon_icd",
+        "/usr/share/vulkan/icd.d/amd_icd", NULL },
+      { NULL },
+      { "libvulkan_radeon", "amdvlk", "radeonsi", NULL },
+      { NULL },
+      { NULL },
+      { "/dev/kfd", NULL },
+      { "libamdhip64", "libhsa-runtime64", NULL },
+      { "rocm-smi", "amd-smi", NULL } },
+};
+
+static const struct vendor_profile g_profile_generic = { 0, "generic", { NULL }, { NULL }, { NULL }, { NULL }, { NULL }, { NULL }, { NULL }, { NULL }, { NULL }, { NULL } };
+
+static const struct vendor_profile *profile_for_vendor(unsigned vendor) {
+    for (size_t i=0;i<sizeof(g_profiles)/sizeof(g_profiles[0]);i++) {
+        if (g_profiles[i].vendor == vendor) return &g_profiles[i];
+    }
+    return &g_profile_generic;
+}
+
+// Parsed LIBNVIDIAHIDE_VENDOR.
+#define MAX_DEVICE_IDS 16
+struct nh_vendor_sel {
+    unsigned vendor;
+    unsigned device_ids[MAX_DEVICE_IDS];   // empty: every device of the vendor
+    int device_ids_n;
+};
+
+// NULL or empty env selects NVIDIA. Returns -1 for an unparsable vendor, and
+// sel is then still the default.
+static int vendor_sel_parse(struct nh_vendor_sel *sel, const char *env) {
+    memset(sel, 0, sizeof(*sel));
+    sel->vendor = 0x10de;
+    if (!env || !*env) return 0;
+    if (parse_hex(env, &sel->vendor) != 0) {
+        sel->vendor = 0x10de;
+        return -1;
+    }
+    const char *ids = strchr(env, ':');
+    while (ids && *ids && sel->device_ids_n < MAX_DEVICE_IDS) {
+        ids++;
+        unsigned d = 0;
+        if (parse_hex(ids, &d) == 0) sel->device_ids[sel->device_ids_n++] = d;
+        ids = strchr(ids, ',');
+    }
+    return 0;
+}
+
+static int vendor_sel_matches(const struct nh_vendor_sel *sel, const struct nh_drm_node *n) {
+    if (!n->vendor_from || n->vendor != sel->vendor) return 0;
+    if (sel->device_ids_n == 0) return 1;
+    for (int i=0;i<sel->device_ids_n;i++) if (sel->device_ids[i] == n->device) return 1;
+    return 0;
+}
+
+static int has_prefix_in(const char *s, const char *const *list) {
+    for (int i=0;i<MAX_PROFILE_RULES && list[i];i++) {
+        if (!strncmp(s, list[i], strlen(list[i]))) return 1;
+    }
+    return 0;
+}
+
+static int has_substring_in(const char *s, const char *const *list) {
+    for (int i=0;i<MAX_PROFILE_RULES && list[i];i++) {
+        if (strstr(s, list[i])) return 1;
+    }
+    return 0;
+}
+
+// --------- user hide rules ---------
+// $XDG_CONFIG_HOME/nvidia-hide/hide-targets, one rule per line, checked before
+// the built-ins:
+//   prefix:/usr/lib/foo      path starts with it
+//   contains:bar.so          path contains it
+//   exact:/dev/whatever      whole path equals it
+enum nh_rule_kind { NH_RULE_PREFIX, NH_RULE_CONTAINS, NH_RULE_EXACT };
+
+struct nh_hide_rule {
+    enum nh_rule_kind kind;
+    char *s;
+};
+
+// --------- keep rules ---------
+// Paths that stay visible whatever else matches, built-ins and hide-targets
+// rules included: LIBNVIDIAHIDE_KEEP (':'-separated) and
+// $XDG_CONFIG_HOME/nvidia-hide/hide-exceptions (one per line). Each is an exact
+// path or a shell glob ('*' crosses '/'), e.g. /dev/nvidia-caps/*.
+
+// --------- extra device trees ---------
+// LIBNVIDIAHIDE_HIDE_DEV (':'-separated) hides other vendors' or subsystems'
+// nodes too, e.g. /dev/kfd for ROCm: any path starting with one of the
+// prefixes, and the listing entries that such a path names. Prefixes must be
+// under /dev/; anything else is ignored. Added to the profile's rules, and
+// hidden whatever the categories say.
+
+// --------- discovered targets ---------
+// nodes: basenames like "card1", "renderD129"
+// bdfs: "0000:01:00.0" etc (used only to hide by-path entries and optionally sysfs config)
+// bdfs_short: the same without the domain ("01:00.0"), which tools and by-path
+// names use for domain 0000 only; empty for any other domain.
+#define MAX_NODES 64
+#define MAX_BDFS 8
+struct nh_targets {
+    const struct vendor_profile *profile;
+    // Rule categories. Device nodes, driver procfs/sysfs and PCI entries serve
+    // both stacks ("core") and stay hidden unless both categories are off.
+    int hide_graphics;  // LIBNVIDIAHIDE_HIDE_GRAPHICS: GL/EGL/GBM/Vulkan assets
+    int hide_compute;   // LIBNVIDIAHIDE_HIDE_COMPUTE (or BLOCK_CUDA): CUDA/ROCm
+    int hide_all_dri;   // LIBNVIDIAHIDE_HIDE_ALL_DRI: every DRM node, any vendor
+    char nodes[MAX_NODES][NAME_MAX];
+    int  nodes_n;
+    char bdfs[MAX_BDFS][32];
+    char bdfs_short[MAX_BDFS][32];
+    int  bdfs_n;
+    struct nh_hide_rule *rules;
+    int  rules_n;
+    char **keep;
+    int  keep_n;
+    char **dlopen_keep; // LIBNVIDIAHIDE_DLOPEN_KEEP: substrings of library names never refused
+    int  dlopen_keep_n;
+    char **hide_dev;    // LIBNVIDIAHIDE_HIDE_DEV: prefixes of extra device paths
+    int  hide_dev_n;
+};
+
+static void targets_add_rule(struct nh_targets *t, const char *line) {
+    static const struct { const char *tag; enum nh_rule_kind kind; } tags[] = {
+        { "prefix:", NH_RULE_PREFIX }, { "contains:", NH_RULE_CONTAINS }, { "exact:", NH_RULE_EXACT },
+    };
+    for (size_t i=0;i<sizeof(tags)/sizeof(tags[0]);i++) {
+        size_t n = strlen(tags[i].tag);
+        if (strncmp(line, tags[i].tag, n) != 0) continue;
+        if (!line[n]) break;
+        struct nh_hide_rule *v = (struct nh_hide_rule*)realloc(t->rules, (size_t)(t->rules_n + 1) * sizeof(*v));
+        if (!v) return;
+        t->rules = v;
+        t->rules[t->rules_n].kind = tags[i].kind;
+        t->rules[t->rules_n].s = strdup(line + n);
+        if (t->rules[t->rules_n].s) t->rules_n++;
+        return;
+    }
+    dbg("hide-targets: ignoring bad rule '%s'", line);
+}
+
+// Missing file: built-ins only.
+static void targets_load_rules(struct nh_targets *t, const char *path) {
+    FILE *f = fopen(path, "r");
+    if (!f) return;
+    char line[PATH_MAX];
+    while (fgets(line, sizeof(line), f)) {
+        trim(line);
+        if (!line[0] || line[0] == '#') continue;
+        targets_add_rule(t, line);
+    }
+    fclose(f);
+}
+
+static void targets_add_string(char ***list, int *n, const char *s, size_t len) {
+    if (!len) return;
+    char **v = (char**)realloc(*list, (size_t)(*n + 1) * sizeof(*v));
+    if (!v) return;
+    *list = v;
+    v[*n] = strndup(s, len);
+    if (v[*n]) (*n)++;
+}
+
+static void targets_add_keep(struct nh_targets *t, const char *pat, size_t len) {
+    targets_add_string(&t->keep, &t->keep_n, pat, len);
+}
+
+// Missing file: no exceptions from it.
+static void targets_load_keep(struct nh_targets *t, const char *path) {
+    FILE *f = fopen(path, "r");
+    if (!f) return;
+    char line[PATH_MAX];
+    while (fgets(line, sizeof(line), f)) {
+        trim(line);
+        if (!line[0] || line[0] == '#') continue;
+        targets_add_keep(t, line, strlen(line));
+    }
+    fclose(f);
+}
+
+static int targets_kept(const struct nh_targets *t, const char *p) {
+    for (int i=0;i<t->keep_n;i++)
+        if (!strcmp(p, t->keep[i]) || fnmatch(t->keep[i], p, 0) == 0) return 1;
+    return 0;
+}
+
+static int targets_hide_dev_path(const struct nh_targets *t, const char *p) {
+    for (int i=0;i<t->hide_dev_n;i++)
+        if (!strncmp(p, t->hide_dev[i], strlen(t->hide_dev[i]))) return 1;
+    return 0;
+}
+
+// A listing entry of dir whose full path falls under a HIDE_DEV prefix.
+__attribute__((unused))
+static int targets_hide_dev_entry(const struct nh_targets *t, const char *dir, const char *name) {
+    if (!t->hide_dev_n || strncmp(dir, "/dev", 4) != 0) return 0;
+    char full[PATH_MAX];
+    int m = snprintf(full, sizeof(full), "%s/%s", dir, name);
+    return m > 0 && (size_t)m < sizeof(full) && targets_hide_dev_path(t, full);
+}
+
+static int targets_rule_match(const struct nh_targets *t, const char *p) {
+    for (int i=0;i<t->rules_n;i++) {
+        const char *s = t->rules[i].s;
+        switch (t->rules[i].kind) {
+        case NH_RULE_PREFIX:   if (!strncmp(p, s, strlen(s))) return 1; break;
+        case NH_RULE_CONTAINS: if (strstr(p, s)) return 1; break;
+        case NH_RULE_EXACT:    if (!strcmp(p, s)) return 1; break;
+        }
+    }
+    return 0;
+}
+
+static void targets_add_node(struct nh_targets *t, const char *name) {
+    if (!name || !*name) return;
+    for (int i=0;i<t->nodes_n;i++) if (!strcmp(t->nodes[i], name)) return;
+    if (t->nodes_n >= MAX_NODES) return;
+    snprintf(t->nodes[t->nodes_n++], NAME_MAX, "%.*s", NAME_MAX - 1, name);
+}
+
+static int targets_has_node(const struct nh_targets *t, const char *name) {
+    if (!name) return 0;
+    for (int i=0;i<t->nodes_n;i++) if (!strcmp(t->nodes[i], name)) return 1;
+    return 0;
+}
+
+static void targets_add_bdf(struct nh_targets *t, const char *bdf) {
+    if (!bdf || !*bdf) return;
+    for (int i=0;i<t->bdfs_n;i++) if (!strcmp(t->bdfs[i], bdf)) return;
+    if (t->bdfs_n >= MAX_BDFS) return;
+    t->bdfs_short[t->bdfs_n][0] = 0;
+    if (!strncmp(bdf, "0000:", 5)) snprintf(t->bdfs_short[t->bdfs_n], sizeof(t->bdfs_short[0]), "%s", bdf + 5);
+    snprintf(t->bdfs[t->bdfs_n++], sizeof(t->bdfs[0]), "%s", bdf);
+}
+
+// s occurs in name as a whole BDF: not the tail of a longer domain or bus
+// ("0000:01:00.0" inside "10000:01:00.0") nor followed by more digits.
+static int bdf_occurs_in(const char *name, const char *s) {
+    size_t n = strlen(s);
+    if (!n) return 0;
+    for (const char *hit = strstr(name, s); hit; hit = strstr(hit + 1, s)) {
+        int before = hit > name && (isxdigit((unsigned char)hit[-1]) || hit[-1] == ':');
+        if (!before && !isxdigit((unsigned char)hit[n])) return 1;
+    }
+    return 0;
+}
+
+// Does name mention a hidden GPU's BDF? The short form collides too easily to
+// be tried everywhere, so only with short set: for names known to be BDF-keyed
+// (/dev/dri/by-path and by-id links).
+static int targets_name_has_bdf(const struct nh_targets *t, const char *name, int short_form) {
+    for (int i=0;i<t->bdfs_n;i++) {
+        if (bdf_occurs_in(name, t->bdfs[i])) return 1;
+        if (short_form && bdf_occurs_in(name, t->bdfs_short[i])) return 1;
+    }
+    return 0;
+}
+
+static int targets_dri_link_dir(const char *dir, size_t len) {
+    return (len == 14 && !strncmp(dir, "/dev/dri/by-id", 14)) ||
+           (len == 16 && !strncmp(dir, "/dev/dri/by-path", 16));
+}
+
+// LIBNVIDIAHIDE_ONLY_BDF / LIBNVIDIAHIDE_EXCEPT_BDF narrow discovery to some of
+// the vendor's GPUs. Lists are comma-separated since BDFs contain colons. A
+// node without a PCI parent has no BDF and is never "only"-selected.
+static int bdf_in_list(const char *list, const char *bdf) {
+    size_t n = strlen(bdf);
+    for (const char *p = list; p && *p; ) {
+        const char *end = strchr(p, ',');
+        size_t len = end ? (size_t)(end - p) : strlen(p);
+        if (n && len == n && !strncasecmp(p, bdf, n)) return 1;
+        p = end ? end + 1 : NULL;
+    }
+    return 0;
+}
+
+static int targets_bdf_selected(const char *bdf) {
+    const char *only = getenv("LIBNVIDIAHIDE_ONLY_BDF");
+    const char *except = getenv("LIBNVIDIAHIDE_EXCEPT_BDF");
+    if (only && *only && !bdf_in_list(only, bdf)) return 0;
+    if (except && *except && bdf_in_list(except, bdf)) return 0;
+    return 1;
+}
+
+static int targets_node_wanted(const struct nh_vendor_sel *sel, const struct nh_drm_node *n) {
+    return vendor_sel_matches(sel, n) && targets_bdf_selected(n->bdf);
+}
+
+// For a node the selection doesn't pick, the index of a picked node it pairs
+// with, or -1. The card and render node of one GPU are hidden together: if
+// the vendor read failed for one of them (a hotplug, a transient sysfs
+// error), it would otherwise stay visible beside its hidden twin.
+static int targets_node_partner(const struct nh_vendor_sel *sel, const char *root,
+                                const struct nh_drm_node *found, int n, int i) {
+    for (int j=0;j<n;j++) {
+        if (j != i && targets_node_wanted(sel, &found[j]) && nh_drm_paired_at(root, &found[j], &found[i])) return j;
+    }
+    return -1;
+}
+
+static int env_is_zero(const char *name) {
+    const char *env = getenv(name);
+    return env && strcmp(env, "0") == 0;
+}
+
+// Empty targets for the selected vendor. Both categories default to hidden;
+// LIBNVIDIAHIDE_BLOCK_CUDA=0 is the older spelling of HIDE_COMPUTE=0, used
+// only when HIDE_COMPUTE is unset.
+static void targets_init(struct nh_targets *t, const struct nh_vendor_sel *sel) {
+    memset(t, 0, sizeof(*t));
+    t->profile = profile_for_vendor(sel->vendor);
+    t->hide_graphics = !env_is_zero("LIBNVIDIAHIDE_HIDE_GRAPHICS");
+    t->hide_compute = getenv("LIBNVIDIAHIDE_HIDE_COMPUTE")
+                      ? !env_is_zero("LIBNVIDIAHIDE_HIDE_COMPUTE")
+                      : !env_is_zero("LIBNVIDIAHIDE_BLOCK_CUDA");
+    const char *all_dri = getenv("LIBNVIDIAHIDE_HIDE_ALL_DRI");
+    t->hide_all_dri = all_dri && strcmp(all_dri, "0") != 0;
+    for (const char *p = getenv("LIBNVIDIAHIDE_KEEP"); p && *p; ) {
+        size_t n = strcspn(p, ":");
+        targets_add_keep(t, p, n);
+        p += n;
+        if (*p) p++;
+    }
+    for (const char *p = getenv("LIBNVIDIAHIDE_DLOPEN_KEEP"); p && *p; ) {
+        size_t n = strcspn(p, ":");
+        targets_add_string(&t->dlopen_keep, &t->dlopen_keep_n, p, n);
+        p += n;
+        if (*p) p++;
+    }
+    for (const char *p = getenv("LIBNVIDIAHIDE_HIDE_DEV"); p && *p; ) {
+        size_t n = strcspn(p, ":");
+        if (n > 5 && !strncmp(p, "/dev/", 5)) targets_add_string(&t->hide_dev, &t->hide_dev_n, p, n);
+        else if (n) dbg("LIBNVIDIAHIDE_HIDE_DEV: ignoring '%.*s', not a path under /dev/", (int)n, p);
+        p += n;
+        if (*p) p++;
+    }
+}
+
+// A library as dlopen gets it ("libcuda.so.1", "/usr/lib/libnvidia-glcore.so.550")
+// or a mapped file's path: is it one of the vendor's, in a hidden category?
+// The base name has to start with one of the profile's names; containing one
+// isn't enough ("mylibnvidia-tool.so"), and this library never counts.
+__attribute__((unused))
+static int targets_library_hidden(const struct nh_targets *t, const char *name) {
+    const char *base = base_name(name);
+    if (!strncmp(base, "libnvidia-hide", 14)) return 0;
+    for (int i=0;i<t->dlopen_keep_n;i++) if (strstr(name, t->dlopen_keep[i])) return 0;
+    if (has_prefix_in(base, t->profile->compute_substrings)) return t->hide_compute;
+    return t->hide_graphics && has_prefix_in(base, t->profile->dlopen_prefixes);
+}
+
+static int targets_core_hidden(const struct nh_targets *t) {
+    return t->hide_graphics || t->hide_compute;
+}
+
+// Adds the wanted DRM nodes and their BDFs from <root>/sys/class/drm, and the
+// user's hide-targets rules and hide-exceptions. Returns the number of nodes added. (The library walks
+// sysfs itself, to log each node and to use its topology cache.)
+__attribute__((unused))
+static int targets_discover_at(struct nh_targets *t, const struct nh_vendor_sel *sel, const char *root) {
+    static struct nh_drm_node found[MAX_NODES];
+    int n = nh_scan_drm_at(root, found, MAX_NODES), added = 0;
+    for (int i=0;i<n;i++) {
+        if (!targets_node_wanted(sel, &found[i]) && targets_node_partner(sel, root, found, n, i) < 0) continue;
+        targets_add_node(t, found[i].name);
+        targets_add_bdf(t, found[i].bdf);
+        added++;
+    }
+
+    char rules_path[PATH_MAX];
+    build_xdg_path(rules_path, sizeof(rules_path), "hide-targets");
+    targets_load_rules(t, rules_path);
+    build_xdg_path(rules_path, sizeof(rules_path), "hide-exceptions");
+    targets_load_keep(t, rules_path);
+    return added;
+}
+
+__attribute__((unused))
+static int targets_discover(struct nh_targets *t, const struct nh_vendor_sel *sel) {
+    return targets_discover_at(t, sel, nh_sysroot());
+}
+
+// LIBNVIDIAHIDE_HIDE_ALL_DRI: base is relative to /dev/dri. Every node and
+// every by-path/by-id link, so the program finds no GPU and falls back to
+// software rendering.
+static int targets_dri_any(const char *base) {
+    return drm_node_name(base) || !strncmp(base, "by-path/", 8) || !strncmp(base, "by-id/", 6);
+}
+
+static int targets_dev_node(const struct nh_targets *t, const char *p) {
+    if (has_prefix_in(p, t->profile->dev_prefixes)) return 1;
+
+    if (!strncmp(p, "/dev/dri/", 9)) {
+        const char *base = p + 9;
+        if (targets_has_node(t, base)) return 1;
+        if (t->hide_all_dri && targets_dri_any(base)) return 1;
+        // by-path/by-id links by name, for callers that can't resolve them
+        const char *slash = strrchr(p, '/');
+        if (targets_dri_link_dir(p, (size_t)(slash - p)) && targets_name_has_bdf(t, slash + 1, 1)) return 1;
+    }
+    return 0;
+}
+
+// A /sys/class/drm entry of a hidden GPU: its card and render nodes, and the
+// card's connectors ("card1-DP-1"). With HIDE_ALL_DRI, every one of them.
+static int targets_sysfs_drm_entry(const struct nh_targets *t, const char *name, size_t len) {
+    char node[NAME_MAX];
+    if (len >= sizeof(node)) return 0;
+    memcpy(node, name, len);
+    node[len] = 0;
+    char *dash = strncmp(node, "card", 4) ? NULL : strchr(node, '-');
+    if (dash) *dash = 0;
+    return drm_node_name(node) && (t->hide_all_dri || targets_has_node(t, node));
+}
+
+// /sys/class/drm/<entry>[/...] for such an entry. Discovery reads these with
+// raw syscalls, so hiding them doesn't blind it.
+static int targets_sysfs_drm_path(const struct nh_targets *t, const char *p) {
+    if (strncmp(p, "/sys/class/drm/", 15) != 0) return 0;
+    const char *name = p + 15;
+    return targets_sysfs_drm_entry(t, name, strcspn(name, "/"));
+}
+
+static int targets_compute_path(const struct nh_targets *t, const char *p) {
+    return has_prefix_in(p, t->profile->compute_dev_prefixes) ||
+           has_substring_in(p, t->profile->compute_substrings);
+}
+
+// name is an entry of dir (no trailing slash), as listed by readdir.
+static int targets_config_entry(const struct nh_targets *t, const char *dir, size_t dir_len, const char *name) {
+    const char *const *dirs = t->profile->config_dirs;
+    for (int i=0;i<MAX_PROFILE_RULES && dirs[i];i++) {
+        if (strlen(dirs[i]) == dir_len && !strncmp(dir, dirs[i], dir_len))
+            return has_substring_in(name, t->profile->config_names);
+    }
+    return 0;
+}
+
+static int targets_config_path(const struct nh_targets *t, const char *p) {
+    const char *slash = strrchr(p, '/');
+    return slash && targets_config_entry(t, p, (size_t)(slash - p), slash + 1);
+}
+
+// /proc/bus/pci names a device "<bus>/<dev>.<fn>", with "<domain>:" in front
+// of the bus only outside domain 0000.
+static int bdf_proc_parts(const char *bdf, char *dir, size_t dir_sz, const char **devfn) {
+    const char *c1 = strchr(bdf, ':');
+    const char *c2 = c1 ? strchr(c1 + 1, ':') : NULL;
+    if (!c2) return -1;
+    int whole_bus = strncmp(bdf, "0000:", 5) != 0;
+    const char *bus = whole_bus ? bdf : c1 + 1;
+    snprintf(dir, dir_sz, "/proc/bus/pci/%.*s", (int)(c2 - bus), bus);
+    *devfn = c2 + 1;
+    return 0;
+}
+
+// The whole sysfs subtree of a hidden device: /sys/bus/pci/devices/<bdf>/...
+// and its real path under /sys/devices, where <bdf> is one path component.
+// Plus its /proc/bus/pci config file. Other functions of the same card (e.g.
+// its HDMI audio) and the iGPU have different BDFs and stay visible.
+static int targets_pci_path(const struct nh_targets *t, const char *p) {
+    int sys = !strncmp(p, "/sys/", 5);
+    int proc = !strncmp(p, "/proc/bus/pci/", 14);
+    if (!sys && !proc) return 0;
+
+    for (int i=0;i<t->bdfs_n;i++) {
+        const char *bdf = t->bdfs[i];
+        size_t n = strlen(bdf);
+        if (sys) {
+            for (const char *hit = strstr(p, bdf); hit; hit = strstr(hit + 1, bdf)) {
+                if (hit[-1] == '/' && (hit[n] == '/' || hit[n] == 0)) return 1;
+            }
+        } else {
+            char dir[64];
+            const char *devfn;
+            if (bdf_proc_parts(bdf, dir, sizeof(dir), &devfn) != 0) continue;
+            size_t dn = strlen(dir);
+            if (!strncmp(p, dir, dn) && p[dn] == '/' && !strcmp(p + dn + 1, devfn)) return 1;
+        }
+    }
+    return 0;
+}
+
+// Every prefix-anchored built-in (device nodes, PCI sysfs/procfs, the loader
+// config dirs) lives under one of these, so a path outside them only needs the
+// substring rules, which match anywhere by design (libraries in /opt, /app,
+// /nix/store...). tests/targets-probe.c asserts the profiles keep to this.
+static const char *const g_anchored_roots[] = { "/dev/", "/sys/", "/proc/", "/usr/", "/etc/", NULL };
+
+static int targets_anchored_root(const char *p) {
+    return has_prefix_in(p, g_anchored_roots);
+}
+
+// Pure function of the string and the targets; no canonicalization here.
+static int targets_match_path(const struct nh_targets *t, const char *p) {
+    if (t->keep_n && targets_kept(t, p)) return 0;
+    if (targets_rule_match(t, p)) return 1;
+    if (targets_hide_dev_path(t, p)) return 1;
+
+    int rooted = targets_anchored_root(p);
+    if (rooted ? targets_compute_path(t, p) : has_substring_in(p, t->profile->compute_substrings))
+        return t->hide_compute;
+
+    if (targets_core_hidden(t)) {
+        // Device nodes
+        if (rooted && targets_dev_node(t, p)) return 1;
+
+        if (has_substring_in(p, t->profile->driver_substrings)) return 1;
+
+        if (rooted && targets_pci_path(t, p)) return 1;
+
+        if (rooted && targets_sysfs_drm_path(t, p)) return 1;
+    }
+
+    if (t->hide_graphics) {
+        // GBM/GL/Vulkan assets and vendor libs (libnvidia-* for the default profile)
+        if (has_substring_in(p, t->profile->path_substrings)) return 1;
+
+        // GLVND/EGL vendor selection JSONs
+        if (rooted && targets_config_path(t, p)) return 1;
+    }
+
+    return 0;
+}
+
+#endif
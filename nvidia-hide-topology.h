@@ -0,0 +1,193 @@
+// DRM/PCI topology discovery shared by libnvidia-hide.so and `nvidia-hide list`.
+//
+// Header-only like nvidia-hide-policy.h. Everything here goes through raw
+// syscalls so the library can run it from its constructor without re-entering
+// its own open/readdir hooks. The includer must define _GNU_SOURCE.
+#ifndef NVIDIA_HIDE_TOPOLOGY_H
+#define NVIDIA_HIDE_TOPOLOGY_H
+
+#include <fcntl.h>
+#include <limits.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/syscall.h>
+#include <unistd.h>
+
+// linux_dirent64 for getdents64
+struct linux_dirent64 {
+    uint64_t d_ino;
+    int64_t  d_off;
+    unsigned short d_reclen;
+    unsigned char  d_type;
+    char d_name[];
+};
+
+static int read_file_raw(const char *path, char *buf, size_t bufsz) {
+    int fd = (int)syscall(SYS_openat, AT_FDCWD, path, O_RDONLY | O_CLOEXEC, 0);
+    if (fd < 0) return -1;
+    ssize_t n = read(fd, buf, bufsz - 1);
+    close(fd);
+    if (n <= 0) return -1;
+    while (n && (buf[n-1] == '\n' || buf[n-1] == ' ' || buf[n-1] == '\t')) n--;
+    buf[n] = 0;
+    return 0;
+}
+
+// LIBNVIDIAHIDE_SYSROOT: a directory standing in for / while discovering, so
+// a fake sys/class/drm tree can be scanned in tests or from inside a sandbox
+// that bind-mounts the host's sysfs elsewhere. Read once; "" (the default)
+// means the real root. Only discovery reads go through it: the paths a
+// program opens are still judged as that program sees them.
+static const char *nh_sysroot(void) {
+    static const char *root;
+    if (!root) {
+        const char *env = getenv("LIBNVIDIAHIDE_SYSROOT");
+        root = env && env[0] == '/' && strlen(env) < PATH_MAX / 2 ? env : "";
+    }
+    return root;
+}
+
+static int parse_hex(const char *s, unsigned *out) {
+    unsigned v = 0;
+    if (sscanf(s, "0x%x", &v) == 1 || sscanf(s, "%x", &v) == 1) { *out = v; return 0; }
+    return -1;
+}
+
+// One /sys/class/drm card* or renderD* entry. Connector entries such as
+// card1-DP-2 are skipped. vendor/device stay 0 and bdf empty when the entry
+// has no PCI parent (virtual or platform devices).
+struct nh_drm_node {
+    char name[NAME_MAX];
+    char bdf[32];
+    unsigned vendor;
+    unsigned device;
+    const char *vendor_from;    // which file gave the vendor; NULL if none did
+};
+
+// Kernel drivers whose name alone pins the vendor, for the DRIVER= fallback.
+static const struct { const char *driver; unsigned vendor; } g_drm_drivers[] = {
+    { "nvidia", 0x10de }, { "nouveau", 0x10de },
+    { "amdgpu", 0x1002 }, { "radeon", 0x1002 },
+    { "i915", 0x8086 }, { "xe", 0x8086 },
+};
+
+// device/vendor can be briefly missing while a GPU is hotplugged (eGPU) even
+// though device/uevent is already there. Prefer PCI_ID=VVVV:DDDD, then the
+// driver name. PCI_SLOT_NAME also gives the BDF when the device link is gone.
+static void drm_node_fill_uevent(const char *root, struct nh_drm_node *n, const char *entry) {
+    char path[PATH_MAX], buf[1024];
+    snprintf(path, sizeof(path), "%s/sys/class/drm/%s/device/uevent", root, entry);
+    if (read_file_raw(path, buf, sizeof(buf)) != 0) return;
+
+    unsigned driver_vendor = 0;
+    for (char *line = buf, *next; line && *line; line = next) {
+        next = strchr(line, '\n');
+        if (next) *next++ = 0;
+        unsigned v, d;
+        if (sscanf(line, "PCI_ID=%x:%x", &v, &d) == 2) {
+            n->vendor = v;
+            n->device = d;
+            n->vendor_from = "uevent PCI_ID";
+        } else if (!strncmp(line, "DRIVER=", 7)) {
+            for (size_t i=0;i<sizeof(g_drm_drivers)/sizeof(g_drm_drivers[0]);i++)
+                if (!strcmp(line + 7, g_drm_drivers[i].driver)) driver_vendor = g_drm_drivers[i].vendor;
+        } else if (!strncmp(line, "PCI_SLOT_NAME=", 14) && !n->bdf[0]) {
+            snprintf(n->bdf, sizeof(n->bdf), "%.31s", line + 14);
+        }
+    }
+    if (!n->vendor && driver_vendor) {
+        n->vendor = driver_vendor;
+        n->vendor_from = "uevent DRIVER";
+    }
+}
+
+static void drm_node_fill(const char *root, struct nh_drm_node *n, const char *entry) {
+    memset(n, 0, sizeof(*n));
+    snprintf(n->name, sizeof(n->name), "%s", entry);
+
+    char path[PATH_MAX], buf[64];
+    snprintf(path, sizeof(path), "%s/sys/class/drm/%s/device/vendor", root, entry);
+    if (read_file_raw(path, buf, sizeof(buf)) == 0 && parse_hex(buf, &n->vendor) == 0) n->vendor_from = "vendor";
+    snprintf(path, sizeof(path), "%s/sys/class/drm/%s/device/device", root, entry);
+    if (read_file_raw(path, buf, sizeof(buf)) == 0) parse_hex(buf, &n->device);
+
+    // resolve /sys/class/drm/<node>/device -> .../<BDF>
+    char target[PATH_MAX];
+    snprintf(path, sizeof(path), "%s/sys/class/drm/%s/device", root, entry);
+    ssize_t len = syscall(SYS_readlinkat, AT_FDCWD, path, target, sizeof(target)-1);
+    if (len > 0) {
+        target[len] = 0;
+        const char *base = strrchr(target, '/');
+        base = base ? base+1 : target;
+        if (strchr(base, ':') && strchr(base, '.')) snprintf(n->bdf, sizeof(n->bdf), "%.31s", base);
+    }
+
+    if (!n->vendor_from) drm_node_fill_uevent(root, n, entry);
+}
+
+// Whether there's a local GPU subsystem at all: /sys/class/drm, or the NVIDIA
+// control node for drivers loaded without DRM. Minimal containers may have
+// neither; there are no nodes to discover then, and only the string rules
+// (libraries, ICD manifests) would still hide anything.
+static int nh_drm_present_at(const char *root) {
+    char sys[PATH_MAX], ctl[PATH_MAX];
+    snprintf(sys, sizeof(sys), "%s/sys/class/drm", root);
+    snprintf(ctl, sizeof(ctl), "%s/dev/nvidiactl", root);
+    return syscall(SYS_faccessat, AT_FDCWD, sys, F_OK, 0) == 0 ||
+           syscall(SYS_faccessat, AT_FDCWD, ctl, F_OK, 0) == 0;
+}
+
+// card1 or renderD129; not connector entries such as card1-DP-2.
+static int drm_node_name(const char *n) {
+    return (!strncmp(n, "card", 4) || !strncmp(n, "renderD", 7)) && !strchr(n, '-');
+}
+
+// Whether a and b are nodes of one GPU, its card and render node: the same
+// BDF, or one listed under the other's device/drm/. The second catches a node
+// whose own device link couldn't be read.
+static int nh_drm_paired_at(const char *root, const struct nh_drm_node *a, const struct nh_drm_node *b) {
+    if (!strcmp(a->name, b->name)) return 0;
+    if (a->bdf[0] && !strcmp(a->bdf, b->bdf)) return 1;
+    char path[PATH_MAX];
+    snprintf(path, sizeof(path), "%s/sys/class/drm/%s/device/drm/%s", root, a->name, b->name);
+    if (syscall(SYS_faccessat, AT_FDCWD, path, F_OK, 0) == 0) return 1;
+    snprintf(path, sizeof(path), "%s/sys/class/drm/%s/device/drm/%s", root, b->name, a->name);
+    return syscall(SYS_faccessat, AT_FDCWD, path, F_OK, 0) == 0;
+}
+
+// scan /sys/class/drm via raw getdents64 (so we do NOT depend on libc readdir while initializing)
+// Fills at most max entries, in directory order, and returns how many.
+static int nh_scan_drm_at(const char *root, struct nh_drm_node *out, int max) {
+    char dir[PATH_MAX];
+    snprintf(dir, sizeof(dir), "%s/sys/class/drm", root);
+    int fd = (int)syscall(SYS_openat, AT_FDCWD, dir, O_RDONLY|O_DIRECTORY|O_CLOEXEC, 0);
+    if (fd < 0) return 0;
+
+    int count = 0;
+    char buf[8192];
+    while (count < max) {
+        int nread = (int)syscall(SYS_getdents64, fd, buf, (int)sizeof(buf));
+        if (nread <= 0) break;
+
+        int bpos = 0;
+        while (bpos < nread && count < max) {
+            struct linux_dirent64 *d = (struct linux_dirent64*)(buf + bpos);
+            if (drm_node_name(d->d_name)) drm_node_fill(root, &out[count++], d->d_name);
+            bpos += d->d_reclen;
+        }
+    }
+    close(fd);
+    return count;
+}
+
+static int nh_scan_drm(struct nh_drm_node *out, int max) {
+    return nh_scan_drm_at(nh_sysroot(), out, max);
+}
+
+static int nh_drm_present(void) {
+    return nh_drm_present_at(nh_sysroot());
+}
+
+#endif
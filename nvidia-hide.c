@@ -5,7 +5,29 @@
 #include <stdlib.h>
 #include <string.h>
 #include <unistd.h>
+#include <stdarg.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <fcntl.h>
+#include <poll.h>
+#include <signal.h>
+#include <sys/ioctl.h>
+#include <sys/prctl.h>
+#include <sys/socket.h>
 #include <sys/stat.h>
+#include <sys/syscall.h>
+#include <sys/wait.h>
+#include <linux/audit.h>
+#include <linux/filter.h>
+#include <linux/seccomp.h>
+
+static void dbg(const char *fmt, ...);
+
+#include "nvidia-hide-policy.h"
+#include "nvidia-hide-topology.h"
+#include "nvidia-hide-targets.h"
+#include "nvidia-hide-elf.h"
+#include "nvidia-hide-sopath.h"
 
 static int file_exists(const char *p) {
     struct stat st;
@@ -40,11 +62,58 @@ static int dirname_of_argv0(char *out, size_t out_sz, const char *argv0) {
     return 0;
 }
 
-static const char *default_so_name(void) {
-    return "libnvidia-hide.so";
+// glibc expands the token $LIB in LD_PRELOAD separately for every process, to
+// the library directory of that process's ABI ("lib64" vs "lib" on Fedora,
+// "lib" vs "lib32" on Arch, the multiarch triplets on Debian). When a 64-bit
+// and a 32-bit build sit side by side under one prefix, preloading
+// "<prefix>/$LIB/libnvidia-hide.so" follows a 64-bit launcher into the 32-bit
+// helpers it execs (Steam's bootstrap does this), where an absolute path would
+// just fail to load with "wrong ELF class".
+//
+// Which spelling this glibc uses is read off where our own libc was loaded
+// from, so the template is only offered when it expands to the 64-bit build.
+static int own_lib_dir(char *out, size_t out_sz) {
+    FILE *f = fopen("/proc/self/maps", "r");
+    if (!f) return -1;
+    char line[PATH_MAX + 128];
+    int rc = -1;
+    while (rc != 0 && fgets(line, sizeof(line), f)) {
+        char *p = strchr(line, '/');
+        char *leaf = p ? strstr(p, "/libc.so.6") : NULL;
+        if (!leaf) continue;
+        *leaf = 0;
+        if (!strncmp(p, "/usr/", 5)) p += 4;
+        snprintf(out, out_sz, "%s", p + 1);
+        rc = 0;
+    }
+    fclose(f);
+    return rc;
 }
 
-static int resolve_so_path(char *out, size_t out_sz, const char *argv0) {
+static int resolve_lib_template(char *out, size_t out_sz, const char *prefix) {
+    static const char *const pairs[][2] = {
+        { "lib64", "lib" },
+        { "lib", "lib32" },
+        { "lib/x86_64-linux-gnu", "lib/i386-linux-gnu" },
+    };
+    char own[PATH_MAX];
+    if (own_lib_dir(own, sizeof(own)) != 0) return -1;
+
+    for (size_t i = 0; i < sizeof(pairs)/sizeof(pairs[0]); i++) {
+        if (strcmp(own, pairs[i][0]) != 0) continue;
+        char p64[PATH_MAX], p32[PATH_MAX];
+        snprintf(p64, sizeof(p64), "%s/%s/%s", prefix, pairs[i][0], default_so_name());
+        snprintf(p32, sizeof(p32), "%s/%s/%s", prefix, pairs[i][1], default_so_name());
+        if (elf_class(p64) == 2 && elf_class(p32) == 1) {
+            snprintf(out, out_sz, "%s/$LIB/%s", prefix, default_so_name());
+            return 0;
+        }
+    }
+    return -1;
+}
+
+// so_dir is run's --so-dir, or NULL.
+static int resolve_so_path(char *out, size_t out_sz, const char *argv0, const char *so_dir) {
     // 1) env override
     const char *env = getenv("LIBNVIDIAHIDE_SO");
     if (env && *env && file_exists(env)) {
@@ -52,8 +121,21 @@ static int resolve_so_path(char *out, size_t out_sz, const char *argv0) {
         return 0;
     }
 
-    // 2) next to this binary (common for local installs)
+    // 2) the directory we were told to use
     char d[PATH_MAX], p[PATH_MAX];
+    if (so_dir && build_path(p, sizeof(p), so_dir, default_so_name()) == 0 && file_exists(p)) {
+        snprintf(out, out_sz, "%s", p);
+        return 0;
+    }
+
+    // 3) both arches installed under our own prefix or a system one: use $LIB
+    if (dirname_of_argv0(d, sizeof(d), argv0) == 0) {
+        if (build_path(p, sizeof(p), d, "..") == 0 && resolve_lib_template(out, out_sz, p) == 0) return 0;
+    }
+    if (resolve_lib_template(out, out_sz, "/usr/local") == 0) return 0;
+    if (resolve_lib_template(out, out_sz, "/usr") == 0) return 0;
+
+    // 4) next to this binary (common for local installs)
     if (dirname_of_argv0(d, sizeof(d), argv0) == 0) {
         if (build_path(p, sizeof(p), d, default_so_name()) == 0 && file_exists(p)) {
             snprintf(out, out_sz, "%s", p);
@@ -69,21 +151,917 @@ static int resolve_so_path(char *out, size_t out_sz, const char *argv0) {
         }
     }
 
-    // 3) typical system paths
-    const char *cands[] = {
-        "/usr/lib/libnvidia-hide.so",
-        "/usr/local/lib/libnvidia-hide.so",
-        "/lib/libnvidia-hide.so",
-        NULL
-    };
-    for (int i = 0; cands[i]; i++) {
-        if (file_exists(cands[i])) {
-            snprintf(out, out_sz, "%s", cands[i]);
+    // 5) typical system paths
+    char own[PATH_MAX];
+    return so_find_candidate(out, out_sz, "", own_lib_dir(own, sizeof(own)) == 0 ? own : NULL,
+                             getenv("XDG_DATA_DIRS"));
+}
+
+static void dbg(const char *fmt, ...) {
+    va_list ap; va_start(ap, fmt);
+    fprintf(stderr, "nvidia-hide: ");
+    vfprintf(stderr, fmt, ap);
+    fprintf(stderr, "\n");
+    va_end(ap);
+}
+
+// exe as given when it has a slash, else the first executable match in $PATH.
+static int find_in_path(char out[PATH_MAX], const char *exe) {
+    if (strchr(exe, '/')) {
+        snprintf(out, PATH_MAX, "%s", exe);
+        return 0;
+    }
+    const char *path = getenv("PATH");
+    while (path && *path) {
+        const char *q = strchr(path, ':');
+        size_t len = q ? (size_t)(q - path) : strlen(path);
+        char d[PATH_MAX];
+        if (len && len < sizeof(d)) {
+            memcpy(d, path, len);
+            d[len] = 0;
+            if (build_path(out, PATH_MAX, d, exe) == 0 && access(out, X_OK) == 0) return 0;
+        }
+        path = q ? q + 1 : NULL;
+    }
+    return -1;
+}
+
+// The library sees the canonical /proc/self/exe, so resolve the same way:
+// bare names through $PATH, then realpath.
+static int resolve_exe(char out[PATH_MAX], const char *exe) {
+    char cand[PATH_MAX];
+    if (find_in_path(cand, exe) != 0) return -1;
+    if (!realpath(cand, out)) return -1;
+    return 0;
+}
+
+// argv joined by spaces, the way the library sees /proc/self/cmdline.
+static void join_args(char *out, size_t sz, char *const *args) {
+    size_t len = 0;
+    out[0] = 0;
+    for (int i = 0; args[i] && len + 1 < sz; i++) {
+        int n = snprintf(out + len, sz - len, "%s%s", i ? " " : "", args[i]);
+        if (n < 0) break;
+        len += (size_t)n;
+    }
+}
+
+static int cmd_check(char *const *args) {
+    const char *exe = args[0];
+    char exe_full[PATH_MAX];
+    if (resolve_exe(exe_full, exe) != 0) {
+        fprintf(stderr, "nvidia-hide: cannot resolve executable '%s'\n", exe);
+        return 1;
+    }
+    const char *exe_base = base_name(exe_full);
+
+    const char *env_allow = getenv("LIBNVIDIAHIDE_ALLOWLIST");
+    const char *env_deny  = getenv("LIBNVIDIAHIDE_DENYLIST");
+
+    struct nh_policy_files allow, deny;
+    policy_files(&allow, "allowlist");
+    policy_files(&deny, "denylist");
+
+    char cmdline[4096];
+    join_args(cmdline, sizeof(cmdline), args);
+    // The program would run as our sibling or child, under the same parents.
+    struct nh_ancestors anc = {0};
+    struct nh_exe_id exe_id = { exe_full, 0, 0, 0, 0, 0, 0, { 0 } };
+    struct nh_subject subj = { exe_full, exe_base, cmdline, &anc, &exe_id };
+    struct nh_policy_sets sets;
+    policy_sets_load(&sets, env_allow, env_deny, allow.list, deny.list);
+    struct nh_decision d = decide_active(&subj, &sets);
+
+    printf("exe:              %s\n", exe_full);
+    printf("basename:         %s\n", exe_base);
+    printf("cmdline:          %s\n", cmdline);
+    if (anc.walked) {
+        printf("ancestors:       ");
+        for (int i = 0; i < anc.n; i++) printf(" %s", anc.names[i]);
+        printf("%s\n", anc.n ? "" : " (none readable)");
+    }
+    if (exe_id.statted) {
+        if (exe_id.stat_ok) printf("inode:            %llu:%llu\n", exe_id.dev, exe_id.ino);
+        else printf("inode:            (cannot stat)\n");
+    }
+    if (exe_id.hashed) {
+        printf("sha256:           ");
+        if (exe_id.hash_ok) for (int i = 0; i < 32; i++) printf("%02x", exe_id.sha256[i]);
+        else printf("(cannot read)");
+        printf("\n");
+    }
+    printf("allowlist (env):  %s -> match=%d\n", env_allow ? env_allow : "(unset)", d.allow_match_env);
+    for (int i = 0; allow.list[i]; i++) {
+        const char *p = allow.list[i];
+        printf("allowlist (file): %s%s -> match=%d\n", p, file_exists(p) ? "" : " (missing)", matchers_match(&sets.allow_files[i], &subj));
+    }
+    printf("has_allow:        %d\n", d.has_allow);
+    printf("denylist (env):   %s -> match=%d\n", env_deny ? env_deny : "(unset)", d.deny_match_env);
+    for (int i = 0; deny.list[i]; i++) {
+        const char *p = deny.list[i];
+        printf("denylist (file):  %s%s -> match=%d\n", p, file_exists(p) ? "" : " (missing)", matchers_match(&sets.deny_files[i], &subj));
+    }
+    policy_sets_free(&sets);
+    const char *gate_var;
+    int gate = require_env_gate(&gate_var);
+    if (gate >= 0) {
+        printf("require env:      %s -> %s\n", gate_var, gate ? "set" : "not set (inactive)");
+        if (!gate) d.active = 0;
+    }
+    const char *session;
+    int in_session = only_session_gate(&session);
+    if (in_session >= 0) {
+        printf("only session:     %s -> %s\n", session, in_session ? "listed" : "not listed (inactive)");
+        if (!in_session) d.active = 0;
+    }
+    int forced = active_override();
+    if (forced >= 0) {
+        printf("override:         LIBNVIDIAHIDE_ACTIVE=%d (allow/deny ignored)\n", forced);
+        d.active = forced;
+    }
+    printf("active:           %d (%s)\n", d.active,
+           d.active ? "NVIDIA will be hidden" : "library is a no-op for this program");
+    ancestors_free(&anc);
+    return 0;
+}
+
+#define MAX_LIST_NODES 64
+
+// One row per card* node, paired with the renderD* node on the same BDF.
+// A render node without a card node gets a row of its own.
+static int cmd_list(int json) {
+    static struct nh_drm_node all[MAX_LIST_NODES];
+    int n = nh_scan_drm(all, MAX_LIST_NODES);
+
+    // Same selection as the library's discovery.
+    struct nh_vendor_sel sel;
+    vendor_sel_parse(&sel, getenv("LIBNVIDIAHIDE_VENDOR"));
+    const struct nh_drm_node *nodes[MAX_LIST_NODES];
+    int nodes_n = 0;
+    for (int i = 0; i < n; i++) {
+        if (targets_node_wanted(&sel, &all[i]) || targets_node_partner(&sel, nh_sysroot(), all, n, i) >= 0)
+            nodes[nodes_n++] = &all[i];
+    }
+
+    if (json) printf("[");
+    else printf("%-10s %-12s %-14s %-8s %s\n", "NODE", "RENDER", "BDF", "VENDOR", "DEVICE");
+
+    int rows = 0;
+    for (int i = 0; i < nodes_n; i++) {
+        const struct nh_drm_node *e = nodes[i];
+        const char *render = NULL;
+        int is_card = !strncmp(e->name, "card", 4);
+        if (is_card) {
+            for (int j = 0; j < nodes_n && e->bdf[0]; j++) {
+                if (!strncmp(nodes[j]->name, "renderD", 7) && !strcmp(nodes[j]->bdf, e->bdf)) render = nodes[j]->name;
+            }
+        } else {
+            int paired = 0;
+            for (int j = 0; j < nodes_n && e->bdf[0]; j++) {
+                if (!strncmp(nodes[j]->name, "card", 4) && !strcmp(nodes[j]->bdf, e->bdf)) paired = 1;
+            }
+            if (paired) continue;
+        }
+
+        if (json) {
+            printf("%s\n  {\"node\": \"%s\", ", rows ? "," : "", e->name);
+            if (render) printf("\"render_node\": \"%s\", ", render);
+            else printf("\"render_node\": null, ");
+            if (e->bdf[0]) printf("\"bdf\": \"%s\", ", e->bdf);
+            else printf("\"bdf\": null, ");
+            printf("\"vendor\": \"0x%04x\", \"device\": \"0x%04x\"}", e->vendor, e->device);
+        } else {
+            printf("%-10s %-12s %-14s 0x%04x   0x%04x\n", e->name, render ? render : "-",
+                   e->bdf[0] ? e->bdf : "-", e->vendor, e->device);
+        }
+        rows++;
+    }
+
+    if (json) printf("%s]\n", rows ? "\n" : "");
+    return 0;
+}
+
+// ---------- run --seccomp ----------
+// Static executables ignore LD_PRELOAD and setuid ones drop it, so the library
+// never gets to see their probing. With --seccomp the child installs a
+// SECCOMP_RET_USER_NOTIF filter on the path-taking open/stat/access syscalls
+// right before exec and hands the listener fd back over a socketpair. The
+// launcher stays around as the supervisor: it reads each trapped path out of
+// the child, fails NVIDIA paths with ENOENT and lets everything else continue.
+//
+// Requirements: Linux 5.5+ (SECCOMP_USER_NOTIF_FLAG_CONTINUE), and ptrace
+// access to the child to read its memory (fine for a parent under the default
+// Yama scope 1; otherwise CAP_SYS_PTRACE). Installing the filter needs
+// no_new_privs, so setuid programs run without their extra privileges.
+//
+// CONTINUE re-reads the path in the kernel after we've checked it, so this
+// hides the GPU from ordinary probing, not from a program racing us on purpose.
+#if defined(__x86_64__)
+#define NH_AUDIT_ARCH AUDIT_ARCH_X86_64
+#elif defined(__aarch64__)
+#define NH_AUDIT_ARCH AUDIT_ARCH_AARCH64
+#endif
+
+struct trapped_syscall {
+    long nr;
+    int path_arg;
+    int dirfd_arg;      // -1: path is relative to the cwd
+};
+
+static const struct trapped_syscall g_trapped[] = {
+#ifdef SYS_open
+    { SYS_open, 0, -1 },
+#endif
+#ifdef SYS_stat
+    { SYS_stat, 0, -1 },
+    { SYS_lstat, 0, -1 },
+#endif
+#ifdef SYS_access
+    { SYS_access, 0, -1 },
+#endif
+    { SYS_openat, 1, 0 },
+#ifdef SYS_openat2
+    { SYS_openat2, 1, 0 },
+#endif
+    { SYS_newfstatat, 1, 0 },
+#ifdef SYS_statx
+    { SYS_statx, 1, 0 },
+#endif
+    { SYS_faccessat, 1, 0 },
+#ifdef SYS_faccessat2
+    { SYS_faccessat2, 1, 0 },
+#endif
+};
+#define N_TRAPPED ((int)(sizeof(g_trapped)/sizeof(g_trapped[0])))
+
+static const struct trapped_syscall *trapped_lookup(int nr) {
+    for (int i = 0; i < N_TRAPPED; i++) if (g_trapped[i].nr == nr) return &g_trapped[i];
+    return NULL;
+}
+
+// Same targets the library builds in discover_nvidia(), from the same env.
+static void build_targets(struct nh_targets *t) {
+    struct nh_vendor_sel sel;
+    vendor_sel_parse(&sel, getenv("LIBNVIDIAHIDE_VENDOR"));
+    targets_init(t, &sel);
+    targets_discover(t, &sel);
+}
+
+// The allow/deny policy still decides, as it would inside the library.
+static int policy_applies(char *const *cmd) {
+    int forced = active_override();
+    if (forced >= 0) return forced;
+    const char *gate_var;
+    if (require_env_gate(&gate_var) == 0) return 0;
+    const char *session;
+    if (only_session_gate(&session) == 0) return 0;
+    const char *sysfs_env = getenv("LIBNVIDIAHIDE_REQUIRE_SYSFS");
+    if (sysfs_env && strcmp(sysfs_env, "0") != 0 && !nh_drm_present()) return 0;
+
+    char exe_full[PATH_MAX];
+    if (resolve_exe(exe_full, cmd[0]) != 0) return 1;   // let execvp report it
+
+    struct nh_policy_files allow, deny;
+    policy_files(&allow, "allowlist");
+    policy_files(&deny, "denylist");
+    char cmdline[4096];
+    join_args(cmdline, sizeof(cmdline), cmd);
+    struct nh_ancestors anc = {0};
+    struct nh_exe_id exe_id = { exe_full, 0, 0, 0, 0, 0, 0, { 0 } };
+    struct nh_subject subj = { exe_full, base_name(exe_full), cmdline, &anc, &exe_id };
+    struct nh_policy_sets sets;
+    policy_sets_load(&sets, getenv("LIBNVIDIAHIDE_ALLOWLIST"), getenv("LIBNVIDIAHIDE_DENYLIST"),
+                     allow.list, deny.list);
+    struct nh_decision d = decide_active(&subj, &sets);
+    policy_sets_free(&sets);
+    ancestors_free(&anc);
+    return d.active;
+}
+
+#ifdef NH_AUDIT_ARCH
+// Runs in the child after fork: returns the listener fd or -1.
+static int install_notify_filter(void) {
+    // arch check, load nr, one JEQ per trapped syscall, ALLOW, USER_NOTIF
+    struct sock_filter f[3 + N_TRAPPED + 2];
+    int k = 0;
+    f[k++] = (struct sock_filter)BPF_STMT(BPF_LD | BPF_W | BPF_ABS, offsetof(struct seccomp_data, arch));
+    f[k++] = (struct sock_filter)BPF_JUMP(BPF_JMP | BPF_JEQ | BPF_K, NH_AUDIT_ARCH, 0, N_TRAPPED + 1);
+    f[k++] = (struct sock_filter)BPF_STMT(BPF_LD | BPF_W | BPF_ABS, offsetof(struct seccomp_data, nr));
+    for (int i = 0; i < N_TRAPPED; i++) {
+        f[k++] = (struct sock_filter)BPF_JUMP(BPF_JMP | BPF_JEQ | BPF_K, (unsigned)g_trapped[i].nr, (unsigned char)(N_TRAPPED - i), 0);
+    }
+    f[k++] = (struct sock_filter)BPF_STMT(BPF_RET | BPF_K, SECCOMP_RET_ALLOW);
+    f[k++] = (struct sock_filter)BPF_STMT(BPF_RET | BPF_K, SECCOMP_RET_USER_NOTIF);
+
+    struct sock_fprog prog = { .len = (unsigned short)k, .filter = f };
+    if (prctl(PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0) return -1;
+    return (int)syscall(SYS_seccomp, SECCOMP_SET_MODE_FILTER, SECCOMP_FILTER_FLAG_NEW_LISTENER, &prog);
+}
+
+static int send_fd(int sock, int fd) {
+    char byte = 0;
+    struct iovec iov = { .iov_base = &byte, .iov_len = 1 };
+    union { struct cmsghdr h; char buf[CMSG_SPACE(sizeof(int))]; } u;
+    memset(&u, 0, sizeof(u));
+    struct msghdr msg = { .msg_iov = &iov, .msg_iovlen = 1, .msg_control = u.buf, .msg_controllen = sizeof(u.buf) };
+    struct cmsghdr *c = CMSG_FIRSTHDR(&msg);
+    c->cmsg_level = SOL_SOCKET;
+    c->cmsg_type = SCM_RIGHTS;
+    c->cmsg_len = CMSG_LEN(sizeof(int));
+    memcpy(CMSG_DATA(c), &fd, sizeof(int));
+    return sendmsg(sock, &msg, 0) == 1 ? 0 : -1;
+}
+
+static int recv_fd(int sock) {
+    char byte;
+    struct iovec iov = { .iov_base = &byte, .iov_len = 1 };
+    union { struct cmsghdr h; char buf[CMSG_SPACE(sizeof(int))]; } u;
+    struct msghdr msg = { .msg_iov = &iov, .msg_iovlen = 1, .msg_control = u.buf, .msg_controllen = sizeof(u.buf) };
+    if (recvmsg(sock, &msg, MSG_CMSG_CLOEXEC) != 1) return -1;
+    struct cmsghdr *c = CMSG_FIRSTHDR(&msg);
+    if (!c || c->cmsg_type != SCM_RIGHTS) return -1;
+    int fd;
+    memcpy(&fd, CMSG_DATA(c), sizeof(int));
+    return fd;
+}
+
+// /proc/<pid>/mem returns a short read at the end of a mapping, so a path
+// near a page boundary still comes through.
+static int read_child_string(pid_t pid, uint64_t addr, char *out, size_t out_sz) {
+    char mem[64];
+    snprintf(mem, sizeof(mem), "/proc/%d/mem", (int)pid);
+    int fd = open(mem, O_RDONLY | O_CLOEXEC);
+    if (fd < 0) return -1;
+    ssize_t n = pread(fd, out, out_sz - 1, (off_t)addr);
+    close(fd);
+    if (n <= 0) return -1;
+    out[n] = 0;
+    return memchr(out, 0, (size_t)n) ? 0 : -1;
+}
+
+static int child_abs_path(pid_t pid, int dirfd, const char *p, char *out, size_t out_sz) {
+    if (p[0] == '/') {
+        snprintf(out, out_sz, "%s", p);
+        return 0;
+    }
+    char link[64], base[PATH_MAX];
+    if (dirfd == AT_FDCWD) snprintf(link, sizeof(link), "/proc/%d/cwd", (int)pid);
+    else snprintf(link, sizeof(link), "/proc/%d/fd/%d", (int)pid, dirfd);
+    ssize_t n = readlink(link, base, sizeof(base) - 1);
+    if (n <= 0) return -1;
+    base[n] = 0;
+    int w = snprintf(out, out_sz, "%s/%s", base, p);
+    return (w > 0 && (size_t)w < out_sz) ? 0 : -1;
+}
+
+// Mirrors is_nvidia_path(): literal path, then the canonical form for /dev/.
+static int notif_should_block(const struct nh_targets *t, int listener, const struct seccomp_notif *req) {
+    const struct trapped_syscall *sc = trapped_lookup(req->data.nr);
+    if (!sc) return 0;
+
+    char p[PATH_MAX];
+    if (read_child_string((pid_t)req->pid, req->data.args[sc->path_arg], p, sizeof(p)) != 0) return 0;
+    // The pid could have been recycled while we read its memory.
+    if (ioctl(listener, SECCOMP_IOCTL_NOTIF_ID_VALID, &req->id) != 0) return 0;
+    if (!p[0]) return 0;    // AT_EMPTY_PATH: acts on the fd itself
+
+    int dirfd = sc->dirfd_arg < 0 ? AT_FDCWD : (int)req->data.args[sc->dirfd_arg];
+    char abs[PATH_MAX];
+    if (child_abs_path((pid_t)req->pid, dirfd, p, abs, sizeof(abs)) != 0) return 0;
+    if (targets_match_path(t, abs)) return 1;
+
+    char canon[PATH_MAX];
+    return !strncmp(abs, "/dev/", 5) && realpath(abs, canon) && targets_match_path(t, canon);
+}
+
+// Serves notifications until no task uses the filter any more (POLLHUP), or,
+// on kernels that don't report that, until the direct child has exited.
+static int supervise(int listener, const struct nh_targets *t, pid_t child) {
+    struct seccomp_notif_sizes sizes;
+    if (syscall(SYS_seccomp, SECCOMP_GET_NOTIF_SIZES, 0, &sizes) != 0) return -1;
+    struct seccomp_notif *req = (struct seccomp_notif*)calloc(1, sizes.seccomp_notif);
+    struct seccomp_notif_resp *resp = (struct seccomp_notif_resp*)calloc(1, sizes.seccomp_notif_resp);
+    if (!req || !resp) { free(req); free(resp); return -1; }
+
+    int status = -1;
+    for (;;) {
+        struct pollfd pfd = { .fd = listener, .events = POLLIN };
+        int pr = poll(&pfd, 1, 1000);
+        if (pr < 0 && errno != EINTR) break;
+        if (pr == 0) {
+            if (status == -1 && waitpid(child, &status, WNOHANG) == child) break;
+            continue;
+        }
+        if (pr < 0) continue;
+        if (pfd.revents & (POLLHUP | POLLERR | POLLNVAL)) break;
+
+        memset(req, 0, sizes.seccomp_notif);
+        if (ioctl(listener, SECCOMP_IOCTL_NOTIF_RECV, req) != 0) continue;   // target died meanwhile
+
+        memset(resp, 0, sizes.seccomp_notif_resp);
+        resp->id = req->id;
+        if (notif_should_block(t, listener, req)) {
+            resp->error = -ENOENT;
+        } else {
+            resp->flags = SECCOMP_USER_NOTIF_FLAG_CONTINUE;
+        }
+        ioctl(listener, SECCOMP_IOCTL_NOTIF_SEND, resp);   // ENOENT if it died meanwhile
+    }
+
+    free(req);
+    free(resp);
+    if (status == -1 && waitpid(child, &status, 0) != child) return -1;
+    return status;
+}
+
+static int run_seccomp(char **cmd) {
+    struct nh_targets t;
+    build_targets(&t);
+
+    int sv[2];
+    if (socketpair(AF_UNIX, SOCK_STREAM | SOCK_CLOEXEC, 0, sv) != 0) {
+        fprintf(stderr, "nvidia-hide: socketpair failed: %s\n", strerror(errno));
+        return 1;
+    }
+
+    pid_t pid = fork();
+    if (pid < 0) {
+        fprintf(stderr, "nvidia-hide: fork failed: %s\n", strerror(errno));
+        return 1;
+    }
+    if (pid == 0) {
+        close(sv[0]);
+        int listener = install_notify_filter();
+        if (listener < 0) {
+            fprintf(stderr, "nvidia-hide: cannot install seccomp filter: %s\n", strerror(errno));
+            _exit(127);
+        }
+        if (send_fd(sv[1], listener) != 0) _exit(127);
+        close(listener);
+        close(sv[1]);
+        execvp(cmd[0], cmd);
+        fprintf(stderr, "nvidia-hide: execvp(%s) failed: %s\n", cmd[0], strerror(errno));
+        _exit(127);
+    }
+
+    // Like system(): the terminal's ^C belongs to the child; we must outlive it.
+    signal(SIGINT, SIG_IGN);
+    signal(SIGQUIT, SIG_IGN);
+
+    close(sv[1]);
+    int listener = recv_fd(sv[0]);
+    close(sv[0]);
+
+    int status;
+    if (listener < 0) {
+        if (waitpid(pid, &status, 0) != pid) return 1;
+    } else {
+        status = supervise(listener, &t, pid);
+        close(listener);
+        if (status == -1) {
+            fprintf(stderr, "nvidia-hide: seccomp supervisor failed: %s\n", strerror(errno));
+            return 1;
+        }
+    }
+    if (WIFEXITED(status)) return WEXITSTATUS(status);
+    if (WIFSIGNALED(status)) return 128 + WTERMSIG(status);
+    return 1;
+}
+#else
+static int run_seccomp(char **cmd) {
+    (void)cmd;
+    fprintf(stderr, "nvidia-hide: --seccomp is not supported on this architecture\n");
+    return 1;
+}
+#endif
+
+// ---------- wrap ----------
+// Writes a /bin/sh script that preloads the library and execs the real
+// program, for putting ahead of it on $PATH.
+
+static void put_sh_quoted(FILE *f, const char *s) {
+    fputc('\'', f);
+    for (; *s; s++) {
+        if (*s == '\'') fputs("'\\''", f);
+        else fputc(*s, f);
+    }
+    fputc('\'', f);
+}
+
+static int mkdir_parents(const char *path) {
+    char tmp[PATH_MAX];
+    snprintf(tmp, sizeof(tmp), "%s", path);
+    for (char *p = tmp + 1; (p = strchr(p, '/')); p++) {
+        *p = 0;
+        if (mkdir(tmp, 0755) != 0 && errno != EEXIST) return -1;
+        *p = '/';
+    }
+    return 0;
+}
+
+static int cmd_wrap(const char *exe, const char *out, int force, const char *argv0) {
+    char exe_full[PATH_MAX];
+    if (resolve_exe(exe_full, exe) != 0) {
+        fprintf(stderr, "nvidia-hide: cannot resolve executable '%s'\n", exe);
+        return 1;
+    }
+    // A wrapper named like its program and already on $PATH would resolve to itself.
+    char out_full[PATH_MAX];
+    if (realpath(out, out_full) && !strcmp(out_full, exe_full)) {
+        fprintf(stderr, "nvidia-hide: refusing to replace '%s' with a wrapper for itself\n", exe_full);
+        return 1;
+    }
+
+    char so_path[PATH_MAX], so_abs[PATH_MAX];
+    if (resolve_so_path(so_path, sizeof(so_path), argv0, NULL) != 0) {
+        fprintf(stderr, "nvidia-hide: could not find libnvidia-hide.so.\n");
+        fprintf(stderr, "  Set LIBNVIDIAHIDE_SO=/full/path/to/libnvidia-hide.so\n");
+        return 1;
+    }
+    if (so_path[0] != '/' && realpath(so_path, so_abs)) snprintf(so_path, sizeof(so_path), "%s", so_abs);
+
+    if (strchr(exe_full, '\n') || strchr(so_path, '\n')) {
+        fprintf(stderr, "nvidia-hide: paths with newlines are not supported\n");
+        return 1;
+    }
+    if (mkdir_parents(out) != 0) {
+        fprintf(stderr, "nvidia-hide: cannot create the directory for '%s': %s\n", out, strerror(errno));
+        return 1;
+    }
+    int fd = open(out, O_WRONLY | O_CREAT | O_CLOEXEC | (force ? O_TRUNC : O_EXCL), 0755);
+    if (fd < 0) {
+        if (errno == EEXIST) fprintf(stderr, "nvidia-hide: '%s' exists (use --force to overwrite)\n", out);
+        else fprintf(stderr, "nvidia-hide: cannot write '%s': %s\n", out, strerror(errno));
+        return 1;
+    }
+    FILE *f = fdopen(fd, "w");
+    if (!f) { close(fd); return 1; }
+
+    // The .so path is single-quoted so a $LIB template reaches ld.so verbatim.
+    fprintf(f, "#!/bin/sh\n");
+    fprintf(f, "# Generated by 'nvidia-hide wrap'; regenerate with --force rather than editing.\n");
+    fprintf(f, "# Runs %s with libnvidia-hide preloaded.\n", exe_full);
+    fprintf(f, "so=");
+    put_sh_quoted(f, so_path);
+    fprintf(f, "\n"
+               "case \" $LD_PRELOAD \" in\n"
+               "  *\" $so \"*) ;;\n"
+               "  *) LD_PRELOAD=\"${LD_PRELOAD:+$LD_PRELOAD }$so\" ;;\n"
+               "esac\n"
+               "export LD_PRELOAD\n"
+               "exec ");
+    put_sh_quoted(f, exe_full);
+    fprintf(f, " \"$@\"\n");
+
+    int ok = fchmod(fd, 0755) == 0;
+    ok = fclose(f) == 0 && ok;
+    if (!ok) {
+        fprintf(stderr, "nvidia-hide: failed to write '%s'\n", out);
+        return 1;
+    }
+    printf("%s -> %s (preload %s)\n", out, exe_full, so_path);
+    return 0;
+}
+
+// ---------- install-session ----------
+// A systemd user environment.d drop-in that preloads the library into the
+// whole graphical session. Blanket preloading is only safe with an allowlist,
+// so the drop-in carries a snapshot of the configured one and installing is
+// refused without it.
+
+#define SESSION_DROPIN "environment.d/60-nvidia-hide.conf"
+#define SESSION_MARKER "# Generated by 'nvidia-hide install-session'"
+
+static int session_dropin_path(char *out, size_t out_sz) {
+    const char *xdg = getenv("XDG_CONFIG_HOME");
+    const char *home = getenv("HOME");
+    int n;
+    if (xdg && *xdg) n = snprintf(out, out_sz, "%s/" SESSION_DROPIN, xdg);
+    else if (home && *home) n = snprintf(out, out_sz, "%s/.config/" SESSION_DROPIN, home);
+    else return -1;
+    return (n > 0 && (size_t)n < out_sz) ? 0 : -1;
+}
+
+// environment.d expands $VAR and strips quotes and backslashes, and ld.so
+// splits LD_PRELOAD on spaces and colons; values using any of those are
+// refused rather than escaped.
+static int session_value_ok(const char *s, const char *extra) {
+    return !strpbrk(s, "\n$\\\"'") && !(extra && strpbrk(s, extra));
+}
+
+// Appends the matchers of m to the colon-separated env list in out. Patterns
+// that would need a ':' of their own can't be expressed there and are skipped
+// (the library still reads them from the file).
+static void session_allow_append(char *out, size_t out_sz, const struct nh_matchers *m) {
+    for (int i = 0; i < m->n; i++) {
+        const struct nh_matcher *e = &m->v[i];
+        const char *prefix = e->on_cmdline ? "cmdline:" : e->on_ancestor ? "ancestor:" :
+                             e->kind == NH_MATCH_SHA256 ? "sha256:" : e->kind == NH_MATCH_INODE ? "inode:" : "";
+        const char *pat = e->kind == NH_MATCH_REGEX ? e->pat + 3 : e->pat;
+        // inode:<dev>:<ino> is the one pattern whose ':' the env list expects
+        if ((e->kind != NH_MATCH_INODE && strchr(pat, ':')) || !session_value_ok(pat, NULL)) {
+            dbg("install-session: allowlist entry '%s' can't go in the drop-in, skipped", e->pat);
+            continue;
+        }
+        size_t len = strlen(out);
+        snprintf(out + len, out_sz - len, "%s%s%s%s%s", len ? ":" : "", e->negate ? "!" : "",
+                 prefix, e->kind == NH_MATCH_REGEX ? "re:" : "", pat);
+    }
+}
+
+// The allowlist as configured now: LIBNVIDIAHIDE_ALLOWLIST, then the system
+// and user files (includes followed), as one env list. Empty if none.
+static void session_allowlist(char *out, size_t out_sz) {
+    out[0] = 0;
+    struct nh_matchers m = {0};
+    matchers_from_env(&m, getenv("LIBNVIDIAHIDE_ALLOWLIST"));
+    struct nh_policy_files files;
+    policy_files(&files, "allowlist");
+    for (int i = 0; files.list[i]; i++) matchers_from_file(&m, files.list[i]);
+    session_allow_append(out, out_sz, &m);
+    matchers_free(&m);
+}
+
+static int render_session_dropin(FILE *f, const char *so_path, const char *allow) {
+    fprintf(f, SESSION_MARKER "; remove it with\n"
+               "# 'nvidia-hide uninstall-session' rather than editing.\n"
+               "#\n"
+               "# Preloads libnvidia-hide into every program of the systemd user session\n"
+               "# (from the next login). Hiding applies only to the programs in\n"
+               "# LIBNVIDIAHIDE_ALLOWLIST, copied from your allowlist when this was written;\n"
+               "# everything else runs unchanged. Entries added to the allowlist files later\n"
+               "# apply too, but removed ones stay listed here until you run\n"
+               "# 'nvidia-hide install-session --force' again.\n");
+    fprintf(f, "LD_PRELOAD=${LD_PRELOAD:+$LD_PRELOAD:}%s\n", so_path);
+    fprintf(f, "LIBNVIDIAHIDE_ALLOWLIST=%s\n", allow);
+    return ferror(f) ? -1 : 0;
+}
+
+static int cmd_install_session(int force, int print, const char *argv0) {
+    char so_path[PATH_MAX], so_abs[PATH_MAX];
+    if (resolve_so_path(so_path, sizeof(so_path), argv0, NULL) != 0) {
+        fprintf(stderr, "nvidia-hide: could not find libnvidia-hide.so.\n");
+        fprintf(stderr, "  Set LIBNVIDIAHIDE_SO=/full/path/to/libnvidia-hide.so\n");
+        return 1;
+    }
+    if (so_path[0] != '/' && realpath(so_path, so_abs)) snprintf(so_path, sizeof(so_path), "%s", so_abs);
+    if (!session_value_ok(so_path, " \t:")) {
+        fprintf(stderr, "nvidia-hide: can't put '%s' in an environment.d file\n", so_path);
+        fprintf(stderr, "  Set LIBNVIDIAHIDE_SO to a plain path (no $LIB template, spaces or quotes)\n");
+        return 1;
+    }
+
+    static char allow[16384];
+    session_allowlist(allow, sizeof(allow));
+    if (!allow[0]) {
+        char user[PATH_MAX];
+        build_xdg_path(user, sizeof(user), "allowlist");
+        fprintf(stderr, "nvidia-hide: refusing to preload the whole session without an allowlist.\n");
+        fprintf(stderr, "  List the programs to hide the dGPU from in %s first\n", user);
+        return 1;
+    }
+
+    if (print) return render_session_dropin(stdout, so_path, allow) == 0 ? 0 : 1;
+
+    char out[PATH_MAX];
+    if (session_dropin_path(out, sizeof(out)) != 0) {
+        fprintf(stderr, "nvidia-hide: neither XDG_CONFIG_HOME nor HOME is set\n");
+        return 1;
+    }
+    if (mkdir_parents(out) != 0) {
+        fprintf(stderr, "nvidia-hide: cannot create the directory for '%s': %s\n", out, strerror(errno));
+        return 1;
+    }
+    int fd = open(out, O_WRONLY | O_CREAT | O_CLOEXEC | (force ? O_TRUNC : O_EXCL), 0644);
+    if (fd < 0) {
+        if (errno == EEXIST) fprintf(stderr, "nvidia-hide: '%s' exists (use --force to overwrite)\n", out);
+        else fprintf(stderr, "nvidia-hide: cannot write '%s': %s\n", out, strerror(errno));
+        return 1;
+    }
+    FILE *f = fdopen(fd, "w");
+    if (!f) { close(fd); return 1; }
+    int ok = render_session_dropin(f, so_path, allow) == 0;
+    ok = fclose(f) == 0 && ok;
+    if (!ok) {
+        fprintf(stderr, "nvidia-hide: failed to write '%s'\n", out);
+        return 1;
+    }
+    printf("%s: preload %s for %s (log in again to apply)\n", out, so_path, allow);
+    return 0;
+}
+
+// Only removes a file install-session wrote.
+static int cmd_uninstall_session(void) {
+    char path[PATH_MAX], first[256] = "";
+    if (session_dropin_path(path, sizeof(path)) != 0) {
+        fprintf(stderr, "nvidia-hide: neither XDG_CONFIG_HOME nor HOME is set\n");
+        return 1;
+    }
+    FILE *f = fopen(path, "r");
+    if (!f) {
+        if (errno == ENOENT) {
+            printf("%s: not installed\n", path);
             return 0;
         }
+        fprintf(stderr, "nvidia-hide: cannot read '%s': %s\n", path, strerror(errno));
+        return 1;
+    }
+    if (!fgets(first, sizeof(first), f)) first[0] = 0;
+    fclose(f);
+    if (strncmp(first, SESSION_MARKER, strlen(SESSION_MARKER)) != 0) {
+        fprintf(stderr, "nvidia-hide: '%s' was not written by install-session, leaving it alone\n", path);
+        return 1;
+    }
+    if (unlink(path) != 0) {
+        fprintf(stderr, "nvidia-hide: cannot remove '%s': %s\n", path, strerror(errno));
+        return 1;
     }
+    printf("%s: removed (log in again to apply)\n", path);
+    return 0;
+}
 
-    return -1;
+// ---------- doctor ----------
+// The usual reasons hiding "does nothing", checked on the live system: no
+// library to preload, or one built for another ABI; no GPU to hide; a program
+// LD_PRELOAD can't reach (Flatpak, Snap, static, setuid); allow/deny lines
+// that don't parse. One PASS/WARN/FAIL line each, with a hint for the fix.
+static int g_doctor_failed = 0;
+
+__attribute__((format(printf, 3, 4)))
+static void doctor_report(const char *status, const char *hint, const char *fmt, ...) {
+    va_list ap;
+    va_start(ap, fmt);
+    printf("%-5s ", status);
+    vprintf(fmt, ap);
+    printf("\n");
+    va_end(ap);
+    if (hint) printf("      hint: %s\n", hint);
+    fflush(stdout);     // keeps the parser's stderr notes next to their line
+    if (!strcmp(status, "FAIL")) g_doctor_failed = 1;
+}
+
+// Fills *so (elf_class 0 when unknown or a $LIB template covering both ABIs).
+static void doctor_library(const char *argv0, struct nh_elf *so) {
+    memset(so, 0, sizeof(*so));
+    char path[PATH_MAX];
+    if (resolve_so_path(path, sizeof(path), argv0, NULL) != 0) {
+        doctor_report("FAIL", "install it, or set LIBNVIDIAHIDE_SO=/path/to/libnvidia-hide.so",
+                      "library: libnvidia-hide.so not found");
+        return;
+    }
+    if (strstr(path, "$LIB")) {
+        doctor_report("PASS", NULL, "library: %s (64- and 32-bit builds, the loader picks per program)", path);
+        return;
+    }
+    struct nh_elf host;
+    if (elf_inspect(path, so) != 0) {
+        doctor_report("FAIL", "rebuild it with `make`", "library: %s is not an ELF file for this system", path);
+        return;
+    }
+    int bits = so->elf_class == ELFCLASS64 ? 64 : 32;
+    if (elf_inspect("/proc/self/exe", &host) == 0 && (host.machine != so->machine || host.elf_class != so->elf_class)) {
+        doctor_report("WARN", "programs of this system's own ABI can't load it; build one with `make`",
+                      "library: %s is %s %d-bit, this system is %s %d-bit", path, elf_machine_name(so->machine), bits,
+                      elf_machine_name(host.machine), host.elf_class == ELFCLASS64 ? 64 : 32);
+        return;
+    }
+    doctor_report("PASS", NULL, "library: %s (%s, %d-bit)", path, elf_machine_name(so->machine), bits);
+}
+
+static void doctor_gpu(void) {
+    if (!nh_drm_present()) {
+        doctor_report("WARN", "in a container without the host's /sys and /dev, only library and ICD names can be hidden",
+                      "GPU: no /sys/class/drm and no /dev/nvidiactl");
+        return;
+    }
+    static struct nh_drm_node all[MAX_LIST_NODES];
+    int n = nh_scan_drm(all, MAX_LIST_NODES);
+    struct nh_vendor_sel sel;
+    vendor_sel_parse(&sel, getenv("LIBNVIDIAHIDE_VENDOR"));
+    char names[256] = "";
+    size_t len = 0;
+    int found = 0;
+    for (int i = 0; i < n; i++) {
+        if (!targets_node_wanted(&sel, &all[i]) && targets_node_partner(&sel, nh_sysroot(), all, n, i) < 0) continue;
+        found++;
+        int m = snprintf(names + len, sizeof(names) - len, " %s", all[i].name);
+        if (m > 0 && (size_t)m < sizeof(names) - len) len += (size_t)m;
+    }
+    if (!found)
+        doctor_report("WARN", "no dGPU is powered or bound to a driver, or LIBNVIDIAHIDE_VENDOR/ONLY_BDF select another",
+                      "GPU: no DRM node of vendor 0x%04x found", sel.vendor);
+    else
+        doctor_report("PASS", NULL, "GPU: %d node(s) to hide:%s", found, names);
+}
+
+static void doctor_preload(void) {
+    const char *pre = getenv("LD_PRELOAD");
+    if (!pre || !*pre)
+        doctor_report("PASS", NULL, "LD_PRELOAD: not set here (`nvidia-hide run` sets it per program)");
+    else if (strstr(pre, default_so_name()))
+        doctor_report("PASS", NULL, "LD_PRELOAD: already names the library, for everything started from here");
+    else
+        doctor_report("WARN", "`nvidia-hide run` adds to it; --replace-preload drops these if one breaks the program",
+                      "LD_PRELOAD: already set to %s", pre);
+}
+
+static void doctor_program(char *const *args, const struct nh_elf *so) {
+    char exe_full[PATH_MAX];
+    if (resolve_exe(exe_full, args[0]) != 0) {
+        doctor_report("FAIL", "pass a path, or a name found in $PATH", "program: cannot find %s", args[0]);
+        return;
+    }
+    if (!strncmp(exe_full, "/app/", 5) || strstr(exe_full, "/flatpak/")) {
+        doctor_report("FAIL", "LD_PRELOAD does not reach into the Flatpak sandbox; use a native build of the program",
+                      "program: %s is a Flatpak app", exe_full);
+        return;
+    }
+    if (!strncmp(exe_full, "/snap/", 6)) {
+        doctor_report("FAIL", "LD_PRELOAD does not reach into the Snap sandbox; use a native build of the program",
+                      "program: %s is a Snap", exe_full);
+        return;
+    }
+    if (exec_drops_preload(exe_full, getuid(), getgid()) == 1) {
+        doctor_report("FAIL", "the loader ignores LD_PRELOAD for it; use `nvidia-hide run --seccomp`",
+                      "program: %s is setuid/setgid", exe_full);
+        return;
+    }
+
+    // A script's interpreter is what gets preloaded.
+    char interp[PATH_MAX] = "", target[PATH_MAX];
+    snprintf(target, sizeof(target), "%s", exe_full);
+    struct nh_elf e;
+    if (elf_inspect(target, &e) != 0) {
+        FILE *f = fopen(exe_full, "r");
+        char line[PATH_MAX];
+        int script = f && fgets(line, sizeof(line), f) && !strncmp(line, "#!", 2);
+        if (f) fclose(f);
+        if (script) {
+            char *p = line + 2;
+            p += strspn(p, " \t");
+            p[strcspn(p, " \t\n")] = 0;
+            snprintf(interp, sizeof(interp), "%s", p);
+            snprintf(target, sizeof(target), "%s", p);
+        }
+        if (!script || elf_inspect(target, &e) != 0) {
+            doctor_report("WARN", NULL, "program: %s is neither an ELF program nor a script", exe_full);
+            return;
+        }
+    }
+    const char *via = interp[0] ? ", script run by " : "";
+    int bits = e.elf_class == ELFCLASS64 ? 64 : 32;
+    if (!e.interp) {
+        doctor_report("FAIL", "LD_PRELOAD never reaches a static program; use `nvidia-hide run --seccomp`",
+                      "program: %s%s%s is statically linked", exe_full, via, interp);
+    } else if (so->elf_class && (e.elf_class != so->elf_class || e.machine != so->machine)) {
+        doctor_report("FAIL", "install a build for its ABI next to this one and preload <prefix>/$LIB/libnvidia-hide.so",
+                      "program: %s%s%s is %s %d-bit, the library is %s %d-bit", exe_full, via, interp,
+                      elf_machine_name(e.machine), bits,
+                      elf_machine_name(so->machine), so->elf_class == ELFCLASS64 ? 64 : 32);
+    } else {
+        doctor_report("PASS", NULL, "program: %s%s%s (%s, %d-bit, dynamically linked)", exe_full, via, interp,
+                      elf_machine_name(e.machine), bits);
+    }
+
+    if (policy_applies(args))
+        doctor_report("PASS", NULL, "policy: active for %s", exe_full);
+    else
+        doctor_report("WARN", "`nvidia-hide check` shows which rule decided", "policy: inactive for %s", exe_full);
+}
+
+// Unparsable lines are reported on stderr by the parser as it goes.
+static void doctor_list(const char *what, const char *file, const char *env) {
+    struct nh_matchers m = {0};
+    if (file) {
+        if (!file_exists(file)) return;
+        matchers_from_file(&m, file);
+    } else {
+        if (!env || !*env) return;
+        matchers_from_env(&m, env);
+    }
+    if (m.bad)
+        doctor_report("FAIL", "entries that don't parse never match; fix or remove them", "%s: %d entr%s ignored (details on stderr)",
+                      file ? file : what, m.bad, m.bad == 1 ? "y" : "ies");
+    else
+        doctor_report("PASS", NULL, "%s: %d entr%s", file ? file : what, m.n, m.n == 1 ? "y" : "ies");
+    matchers_free(&m);
+}
+
+static int cmd_doctor(const char *argv0, char *const *for_args) {
+    struct nh_elf so;
+    doctor_library(argv0, &so);
+    doctor_gpu();
+    doctor_preload();
+    if (for_args) doctor_program(for_args, &so);
+
+    struct nh_policy_files allow, deny;
+    policy_files(&allow, "allowlist");
+    policy_files(&deny, "denylist");
+    for (int i = 0; allow.list[i]; i++) doctor_list("allowlist", allow.list[i], NULL);
+    for (int i = 0; deny.list[i]; i++) doctor_list("denylist", deny.list[i], NULL);
+    doctor_list("LIBNVIDIAHIDE_ALLOWLIST", NULL, getenv("LIBNVIDIAHIDE_ALLOWLIST"));
+    doctor_list("LIBNVIDIAHIDE_DENYLIST", NULL, getenv("LIBNVIDIAHIDE_DENYLIST"));
+    return g_doctor_failed;
 }
 
 static void usage(FILE *f) {
@@ -91,13 +1069,34 @@ static void usage(FILE *f) {
         "Usage:\n"
         "  nvidia-hide run -- <command> [args...]\n"
         "  nvidia-hide run <command> [args...]\n"
+        "  nvidia-hide run --dry-run -- <command> [args...]   log what would be hidden, hide nothing\n"
+        "  nvidia-hide run --selftest -- <command> [args...]   print whether the hooks hide a GPU node\n"
+        "  nvidia-hide run --seccomp -- <command> [args...]   also covers static/setuid programs (see README)\n"
+        "  nvidia-hide run --lib-template '/opt/x/$LIB/libnvidia-hide.so' -- <command> [args...]\n"
+        "  nvidia-hide run --so-dir <dir> -- <command> [args...]   look for libnvidia-hide.so in <dir> first\n"
+        "  nvidia-hide run --replace-preload -- <command> [args...]   drop any existing LD_PRELOAD entries\n"
+        "  nvidia-hide unhide -- <command> [args...]   run one command with the GPU visible,\n"
+        "                                 without the library in its LD_PRELOAD\n"
+        "  nvidia-hide check <exe> [args...]  explain the allow/deny decision for <exe>\n"
+        "  nvidia-hide list [--json]      show the NVIDIA DRM nodes that would be hidden\n"
+        "  nvidia-hide doctor [--for <exe> [args...]]   check the setup for common problems\n"
+        "  nvidia-hide wrap [--force] <exe> <wrapper>   write a script that runs <exe> preloaded\n"
+        "  nvidia-hide install-session [--force] [--print]   preload into the systemd user session,\n"
+        "                                 for the programs on the allowlist only\n"
+        "  nvidia-hide uninstall-session  remove what install-session wrote\n"
         "\n"
         "Environment:\n"
         "  LIBNVIDIAHIDE_SO=/path/to/libnvidia-hide.so\n"
+        "    (default: <prefix>/$LIB/libnvidia-hide.so when 32- and 64-bit builds are both installed,\n"
+        "     else next to this binary, the usual lib dirs, then $XDG_DATA_DIRS/nvidia-hide/)\n"
         "  LIBNVIDIAHIDE_ALLOWLIST=pat1:pat2:...   (optional; evaluated inside the .so)\n"
         "  LIBNVIDIAHIDE_DENYLIST=pat1:pat2:...    (optional; evaluated inside the .so)\n"
+        "  LIBNVIDIAHIDE_ACTIVE=0|1                (optional; forces the verdict, ignoring both lists)\n"
+        "  LIBNVIDIAHIDE_REQUIRE_ENV=VAR           (optional; inactive unless VAR is set)\n"
+        "  LIBNVIDIAHIDE_ONLY_SESSION=wayland,...  (optional; inactive in other session types)\n"
         "\n"
         "Config files (optional; evaluated inside the .so):\n"
+        "  /etc/nvidia-hide/allowlist, /etc/nvidia-hide/denylist (system-wide, merged with the user's)\n"
         "  $XDG_CONFIG_HOME/nvidia-hide/allowlist (or ~/.config/nvidia-hide/allowlist)\n"
         "  $XDG_CONFIG_HOME/nvidia-hide/denylist  (or ~/.config/nvidia-hide/denylist)\n"
         "\n"
@@ -107,24 +1106,108 @@ static void usage(FILE *f) {
     );
 }
 
-static int set_preload(const char *so_path) {
+// Adds so_path to LD_PRELOAD, or with replace makes it the only entry (for
+// environments whose existing preload is itself what breaks the program).
+// ld.so splits the list on spaces and colons alike; the merged value is
+// rewritten space-separated so appending never leaves a mixed list.
+static int set_preload(const char *so_path, int replace) {
     const char *prev = getenv("LD_PRELOAD");
-    if (!prev || !*prev) {
+    if (replace || !prev || !*prev) {
         return setenv("LD_PRELOAD", so_path, 1);
     }
-    // Avoid duplicating
-    if (strstr(prev, so_path)) return 0;
 
-    // glibc reliably supports space-separated entries.
     size_t need = strlen(prev) + 1 + strlen(so_path) + 1;
     char *buf = (char*)malloc(need);
     if (!buf) return -1;
-    snprintf(buf, need, "%s %s", prev, so_path);
+    size_t len = 0;
+    int have = 0;
+    for (const char *p = prev; *p; ) {
+        size_t n = strcspn(p, " :");
+        if (n) {
+            // Avoid duplicating
+            if (n == strlen(so_path) && !strncmp(p, so_path, n)) have = 1;
+            len += (size_t)snprintf(buf + len, need - len, "%s%.*s", len ? " " : "", (int)n, p);
+        }
+        p += n;
+        if (*p) p++;
+    }
+    if (!have) snprintf(buf + len, need - len, "%s%s", len ? " " : "", so_path);
     int rc = setenv("LD_PRELOAD", buf, 1);
     free(buf);
     return rc;
 }
 
+// An LD_PRELOAD entry that is the library: one named libnvidia-hide.so (bare,
+// or under a $LIB template, which only ld.so expands), or any other path to
+// the same file as ours.
+static int preload_entry_is_ours(const char *e, size_t len, const char *ours) {
+    char entry[PATH_MAX], real[PATH_MAX];
+    if (len >= sizeof(entry)) return 0;
+    memcpy(entry, e, len);
+    entry[len] = 0;
+    const char *slash = strrchr(entry, '/');
+    if (!strcmp(slash ? slash + 1 : entry, default_so_name())) return 1;
+    return ours && realpath(entry, real) && !strcmp(real, ours);
+}
+
+// cur without the library's entries, the others kept in order and written
+// space-separated as set_preload does. NULL when nothing is left.
+static char *preload_without_ours(const char *cur, const char *ours) {
+    char *out = (char*)malloc(strlen(cur) + 1);
+    if (!out) return NULL;
+    size_t len = 0;
+    for (const char *p = cur; *p; ) {
+        size_t n = strcspn(p, " :");
+        if (n && !preload_entry_is_ours(p, n, ours)) {
+            if (len) out[len++] = ' ';
+            memcpy(out + len, p, n);
+            len += n;
+        }
+        p += n;
+        if (*p) p++;
+    }
+    out[len] = 0;
+    if (!len) {
+        free(out);
+        return NULL;
+    }
+    return out;
+}
+
+// One command with the GPU visible, from inside a wrapped tree: the library
+// leaves LD_PRELOAD, so not even its string rules apply, and
+// LIBNVIDIAHIDE_ACTIVE=0 keeps it off should something further down preload it
+// again. The exec is a raw system call because this process may itself have
+// the library loaded, whose exec hooks would put it back into the child's
+// LD_PRELOAD (or refuse nvidia-smi under LIBNVIDIAHIDE_BLOCK_NVIDIA_TOOLS).
+static int cmd_unhide(const char *argv0, char **cmd) {
+    char so[PATH_MAX], ours[PATH_MAX];
+    int have = resolve_so_path(so, sizeof(so), argv0, NULL) == 0 && realpath(so, ours);
+    const char *cur = getenv("LD_PRELOAD");
+    if (cur) {
+        char *rest = preload_without_ours(cur, have ? ours : NULL);
+        int rc = rest ? setenv("LD_PRELOAD", rest, 1) : unsetenv("LD_PRELOAD");
+        free(rest);
+        if (rc != 0) {
+            fprintf(stderr, "nvidia-hide: failed to set LD_PRELOAD: %s\n", strerror(errno));
+            return 1;
+        }
+    }
+    if (setenv("LIBNVIDIAHIDE_ACTIVE", "0", 1) != 0) {
+        fprintf(stderr, "nvidia-hide: failed to set LIBNVIDIAHIDE_ACTIVE: %s\n", strerror(errno));
+        return 1;
+    }
+
+    char path[PATH_MAX];
+    if (find_in_path(path, cmd[0]) != 0) {
+        fprintf(stderr, "nvidia-hide: %s: command not found\n", cmd[0]);
+        return 127;
+    }
+    syscall(SYS_execve, path, cmd, environ);
+    fprintf(stderr, "nvidia-hide: execve(%s) failed: %s\n", path, strerror(errno));
+    return 127;
+}
+
 int main(int argc, char **argv) {
     if (argc < 2) {
         usage(stderr);
@@ -137,6 +1220,78 @@ int main(int argc, char **argv) {
         return 0;
     }
 
+    if (strcmp(sub, "check") == 0) {
+        if (argc < 3) {
+            fprintf(stderr, "nvidia-hide: check needs an executable\n\n");
+            usage(stderr);
+            return 2;
+        }
+        return cmd_check(&argv[2]);
+    }
+
+    if (strcmp(sub, "list") == 0) {
+        int json = argc == 3 && strcmp(argv[2], "--json") == 0;
+        if (argc > 3 || (argc == 3 && !json)) {
+            fprintf(stderr, "nvidia-hide: list only accepts --json\n\n");
+            usage(stderr);
+            return 2;
+        }
+        return cmd_list(json);
+    }
+
+    if (strcmp(sub, "doctor") == 0) {
+        int has_for = argc > 2 && strcmp(argv[2], "--for") == 0;
+        if ((argc > 2 && !has_for) || (has_for && argc < 4)) {
+            fprintf(stderr, "nvidia-hide: doctor only accepts --for <exe> [args...]\n\n");
+            usage(stderr);
+            return 2;
+        }
+        return cmd_doctor(argv[0], has_for ? &argv[3] : NULL);
+    }
+
+    if (strcmp(sub, "wrap") == 0) {
+        int force = argc > 2 && strcmp(argv[2], "--force") == 0;
+        if (argc != 4 + force) {
+            fprintf(stderr, "nvidia-hide: wrap takes an executable and an output path\n\n");
+            usage(stderr);
+            return 2;
+        }
+        return cmd_wrap(argv[2 + force], argv[3 + force], force, argv[0]);
+    }
+
+    if (strcmp(sub, "install-session") == 0) {
+        int force = 0, print = 0;
+        for (int i = 2; i < argc; i++) {
+            if (strcmp(argv[i], "--force") == 0) force = 1;
+            else if (strcmp(argv[i], "--print") == 0) print = 1;
+            else {
+                fprintf(stderr, "nvidia-hide: bad install-session option '%s'\n\n", argv[i]);
+                usage(stderr);
+                return 2;
+            }
+        }
+        return cmd_install_session(force, print, argv[0]);
+    }
+
+    if (strcmp(sub, "uninstall-session") == 0) {
+        if (argc != 2) {
+            fprintf(stderr, "nvidia-hide: uninstall-session takes no arguments\n\n");
+            usage(stderr);
+            return 2;
+        }
+        return cmd_uninstall_session();
+    }
+
+    if (strcmp(sub, "unhide") == 0) {
+        int cmd_i = argc > 2 && strcmp(argv[2], "--") == 0 ? 3 : 2;
+        if (cmd_i >= argc) {
+            fprintf(stderr, "nvidia-hide: unhide needs a command\n\n");
+            usage(stderr);
+            return 2;
+        }
+        return cmd_unhide(argv[0], &argv[cmd_i]);
+    }
+
     if (strcmp(sub, "run") != 0) {
         fprintf(stderr, "nvidia-hide: unknown subcommand '%s'\n\n", sub);
         usage(stderr);
@@ -144,6 +1299,41 @@ int main(int argc, char **argv) {
     }
 
     int cmd_i = 2;
+    const char *lib_template = NULL;
+    const char *so_dir = NULL;
+    int use_seccomp = 0;
+    int replace_preload = 0;
+    while (cmd_i < argc && argv[cmd_i][0] == '-' && strcmp(argv[cmd_i], "--") != 0) {
+        if (strcmp(argv[cmd_i], "--dry-run") == 0) {
+            if (setenv("LIBNVIDIAHIDE_DRYRUN", "1", 1) != 0) {
+                fprintf(stderr, "nvidia-hide: failed to set LIBNVIDIAHIDE_DRYRUN: %s\n", strerror(errno));
+                return 1;
+            }
+            cmd_i++;
+        } else if (strcmp(argv[cmd_i], "--selftest") == 0) {
+            if (setenv("LIBNVIDIAHIDE_SELFTEST", "1", 1) != 0) {
+                fprintf(stderr, "nvidia-hide: failed to set LIBNVIDIAHIDE_SELFTEST: %s\n", strerror(errno));
+                return 1;
+            }
+            cmd_i++;
+        } else if (strcmp(argv[cmd_i], "--seccomp") == 0) {
+            use_seccomp = 1;
+            cmd_i++;
+        } else if (strcmp(argv[cmd_i], "--replace-preload") == 0 || strcmp(argv[cmd_i], "--no-preload-merge") == 0) {
+            replace_preload = 1;
+            cmd_i++;
+        } else if (strcmp(argv[cmd_i], "--lib-template") == 0 && cmd_i + 1 < argc && argv[cmd_i+1][0]) {
+            lib_template = argv[cmd_i+1];
+            cmd_i += 2;
+        } else if (strcmp(argv[cmd_i], "--so-dir") == 0 && cmd_i + 1 < argc && argv[cmd_i+1][0]) {
+            so_dir = argv[cmd_i+1];
+            cmd_i += 2;
+        } else {
+            fprintf(stderr, "nvidia-hide: bad run option '%s'\n\n", argv[cmd_i]);
+            usage(stderr);
+            return 2;
+        }
+    }
     if (cmd_i < argc && strcmp(argv[cmd_i], "--") == 0) cmd_i++;
 
     if (cmd_i >= argc) {
@@ -152,18 +1342,37 @@ int main(int argc, char **argv) {
         return 2;
     }
 
-    // Set per-process LD_PRELOAD and exec
+    // Set per-process LD_PRELOAD and exec. Under --seccomp the preload only
+    // adds directory filtering for dynamic programs, so it may be missing.
     char so_path[PATH_MAX];
-    if (resolve_so_path(so_path, sizeof(so_path), argv[0]) != 0) {
-        fprintf(stderr, "nvidia-hide: could not find libnvidia-hide.so.\n");
-        fprintf(stderr, "  Set LIBNVIDIAHIDE_SO=/full/path/to/libnvidia-hide.so\n");
-        return 1;
+    int have_so = 1;
+    if (lib_template) {
+        snprintf(so_path, sizeof(so_path), "%s", lib_template);
+    } else if (resolve_so_path(so_path, sizeof(so_path), argv[0], so_dir) != 0) {
+        if (!use_seccomp) {
+            fprintf(stderr, "nvidia-hide: could not find libnvidia-hide.so.\n");
+            fprintf(stderr, "  Set LIBNVIDIAHIDE_SO=/full/path/to/libnvidia-hide.so or pass --so-dir <dir>\n");
+            return 1;
+        }
+        have_so = 0;
     }
-    if (set_preload(so_path) != 0) {
+    if (have_so && set_preload(so_path, replace_preload) != 0) {
         fprintf(stderr, "nvidia-hide: failed to set LD_PRELOAD: %s\n", strerror(errno));
         return 1;
     }
 
+    if (use_seccomp && policy_applies(&argv[cmd_i])) return run_seccomp(&argv[cmd_i]);
+
+    // AT_SECURE makes the loader drop LD_PRELOAD; say so rather than let the
+    // program run unhidden without a word. Not worked around, only reported.
+    char exe_full[PATH_MAX];
+    if (!use_seccomp && resolve_exe(exe_full, argv[cmd_i]) == 0 &&
+        exec_drops_preload(exe_full, getuid(), getgid()) == 1) {
+        fprintf(stderr, "nvidia-hide: warning: %s is setuid/setgid (or has file capabilities), so the loader\n"
+                        "  ignores LD_PRELOAD and nothing will be hidden from it. Use\n"
+                        "  `nvidia-hide run --seccomp -- %s ...` instead.\n", exe_full, argv[cmd_i]);
+    }
+
     execvp(argv[cmd_i], &argv[cmd_i]);
     fprintf(stderr, "nvidia-hide: execvp(%s) failed: %s\n", argv[cmd_i], strerror(errno));
     return 127;
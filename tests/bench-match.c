@@ -0,0 +1,94 @@
+// Times targets_match_path() over a typical mix of paths, the per-open cost
+// the library pays before its decision cache warms up (and every time for
+// paths too long to cache). Not part of `make check`; run `make bench`, and
+// compare against another build by running it there too.
+//
+// The mix is modeled on `strace -f -e trace=openat,statx` of a desktop app
+// starting up: mostly $HOME, /tmp and shared libraries, some /proc and /sys,
+// a few device nodes.
+#define _GNU_SOURCE
+#include <stdarg.h>
+#include <stdio.h>
+#include <time.h>
+
+static void dbg(const char *fmt, ...);
+
+#include "../nvidia-hide-targets.h"
+
+static void dbg(const char *fmt, ...) {
+    va_list ap; va_start(ap, fmt);
+    vfprintf(stderr, fmt, ap);
+    va_end(ap);
+    fputc('\n', stderr);
+}
+
+static const char *const g_paths[] = {
+    "/etc/ld.so.cache",
+    "/lib/x86_64-linux-gnu/libc.so.6",
+    "/lib/x86_64-linux-gnu/libm.so.6",
+    "/lib/x86_64-linux-gnu/libglib-2.0.so.0",
+    "/lib/x86_64-linux-gnu/libgtk-3.so.0",
+    "/lib/x86_64-linux-gnu/libEGL_mesa.so.0",
+    "/usr/lib/x86_64-linux-gnu/libGLX_mesa.so.0",
+    "/usr/lib/x86_64-linux-gnu/libvulkan.so.1",
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/icons/Adwaita/index.theme",
+    "/usr/share/glvnd/egl_vendor.d/50_mesa.json",
+    "/usr/share/vulkan/icd.d/intel_icd.x86_64.json",
+    "/etc/fonts/fonts.conf",
+    "/etc/localtime",
+    "/etc/passwd",
+    "/proc/self/maps",
+    "/proc/self/status",
+    "/proc/meminfo",
+    "/sys/devices/system/cpu/online",
+    "/sys/class/drm/renderD128/device/vendor",
+    "/dev/dri/renderD128",
+    "/dev/null",
+    "/dev/shm/.org.chromium.Chromium.abc123",
+    "/tmp/.X11-unix/X0",
+    "/tmp/app-1000/lock",
+    "/run/user/1000/bus",
+    "/home/user/.config/app/Preferences",
+    "/home/user/.config/app/Local State",
+    "/home/user/.cache/app/Cache/Cache_Data/index",
+    "/home/user/.cache/mesa_shader_cache/index",
+    "/home/user/.local/share/recently-used.xbel",
+    "/home/user/Documents/report.odt",
+    "/home/user/.config/app/GPUCache/data_0",
+    "/home/user/.config/app/GPUCache/data_1",
+    "/home/user/.config/app/GPUCache/index",
+    "/opt/app/resources/app.asar",
+    "/opt/app/locales/en-US.pak",
+    "/opt/app/libffmpeg.so",
+    "/dev/nvidia0",
+    "/usr/lib/x86_64-linux-gnu/libnvidia-glcore.so.550.54",
+};
+#define NPATHS (sizeof(g_paths)/sizeof(g_paths[0]))
+
+static double now_ns(void) {
+    struct timespec ts;
+    clock_gettime(CLOCK_MONOTONIC, &ts);
+    return ts.tv_sec * 1e9 + ts.tv_nsec;
+}
+
+int main(int argc, char **argv) {
+    long rounds = argc > 1 ? atol(argv[1]) : 200000;
+    struct nh_vendor_sel sel;
+    vendor_sel_parse(&sel, NULL);
+    struct nh_targets t;
+    targets_init(&t, &sel);
+    targets_add_node(&t, "card1");
+    targets_add_node(&t, "renderD129");
+    targets_add_bdf(&t, "0000:01:00.0");
+
+    volatile int sink = 0;
+    double start = now_ns();
+    for (long r = 0; r < rounds; r++)
+        for (size_t i = 0; i < NPATHS; i++) sink += targets_match_path(&t, g_paths[i]);
+    double ns = (now_ns() - start) / ((double)rounds * NPATHS);
+
+    printf("%zu paths x %ld rounds: %.1f ns per targets_match_path() (%d hits per round)\n",
+           NPATHS, rounds, ns, sink / (int)rounds);
+    return 0;
+}
@@ -0,0 +1,73 @@
+// Times spawning /bin/true and waiting for it, the per-process cost the
+// library adds to build systems that start thousands of programs: without
+// the library, with it walking /sys/class/drm in every child, and with
+// LIBNVIDIAHIDE_CACHE=1 reading the topology cache instead. Not part of
+// `make check`; `make bench` runs it after bench-match.
+//
+//   tests/bench-spawn /path/to/libnvidia-hide.so
+#define _GNU_SOURCE
+#include <spawn.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/wait.h>
+#include <time.h>
+#include <unistd.h>
+
+extern char **environ;
+
+#define ROUNDS 300
+#define MAX_ENV 512
+
+// environ without the variables that would change the run, plus extra.
+static void build_env(char **env, char *const *extra) {
+    int n = 0;
+    for (char **e = environ; *e && n < MAX_ENV - 8; e++) {
+        if (!strncmp(*e, "LD_PRELOAD=", 11) || !strncmp(*e, "LIBNVIDIAHIDE_", 14)) continue;
+        env[n++] = *e;
+    }
+    for (; *extra; extra++) env[n++] = *extra;
+    env[n] = NULL;
+}
+
+static double spawn_us(char **env) {
+    char *argv[] = { "true", NULL };
+    struct timespec a, b;
+    clock_gettime(CLOCK_MONOTONIC, &a);
+    for (int i = 0; i < ROUNDS; i++) {
+        pid_t pid;
+        int status;
+        if (posix_spawn(&pid, "/bin/true", NULL, NULL, argv, env) != 0) { perror("posix_spawn"); exit(2); }
+        waitpid(pid, &status, 0);
+    }
+    clock_gettime(CLOCK_MONOTONIC, &b);
+    return ((b.tv_sec - a.tv_sec) * 1e9 + (b.tv_nsec - a.tv_nsec)) / 1e3 / ROUNDS;
+}
+
+int main(int argc, char **argv) {
+    if (argc != 2) { fprintf(stderr, "usage: %s /path/to/libnvidia-hide.so\n", argv[0]); return 2; }
+    char rt[] = "/tmp/nvidia-hide-bench.XXXXXX";
+    if (!mkdtemp(rt)) { perror("mkdtemp"); return 2; }
+    char preload[4096], runtime[64];
+    snprintf(preload, sizeof(preload), "LD_PRELOAD=%s", argv[1]);
+    snprintf(runtime, sizeof(runtime), "XDG_RUNTIME_DIR=%s", rt);
+
+    char *plain[] = { NULL };
+    char *walk[] = { preload, "LIBNVIDIAHIDE_ACTIVE=1", NULL };
+    char *cached[] = { preload, "LIBNVIDIAHIDE_ACTIVE=1", "LIBNVIDIAHIDE_CACHE=1", runtime, NULL };
+    static const char *const names[] = { "without the library", "library, sysfs walk", "library, LIBNVIDIAHIDE_CACHE=1" };
+    char *const *extras[] = { plain, walk, cached };
+
+    static char *env[MAX_ENV];
+    for (int i = 0; i < 3; i++) {
+        build_env(env, extras[i]);
+        spawn_us(env);     // warm up; writes the cache for the last row
+        printf("%-32s %8.1f us/spawn\n", names[i], spawn_us(env));
+    }
+
+    char cache[128];
+    snprintf(cache, sizeof(cache), "%s/nvidia-hide-topo", rt);
+    if (unlink(cache) != 0) printf("(no topology cache was written: no /sys/class/drm here?)\n");
+    rmdir(rt);
+    return 0;
+}
@@ -0,0 +1,56 @@
+// A second LD_PRELOAD library that wraps open the way tracing and
+// fd-accounting tools do, for `preload-probe chain`: it counts the calls it
+// sees for paths under /tmp/nvidia-hide-chain.* and forwards every call to
+// the next definition. Whichever side of libnvidia-hide.so it is loaded on,
+// the opens the library lets through must still reach it.
+#define _GNU_SOURCE
+#include <dlfcn.h>
+#include <fcntl.h>
+#include <stdarg.h>
+#include <string.h>
+#include <sys/types.h>
+
+static int g_calls = 0;
+
+int chain_open_calls(void) { return __atomic_load_n(&g_calls, __ATOMIC_ACQUIRE); }
+
+static void count(const char *path) {
+    if (path && strstr(path, "/nvidia-hide-chain.")) __atomic_add_fetch(&g_calls, 1, __ATOMIC_ACQ_REL);
+}
+
+typedef int (*open_f)(const char*, int, ...);
+typedef int (*openat_f)(int, const char*, int, ...);
+typedef int (*open_2_f)(const char*, int);
+typedef int (*openat_2_f)(int, const char*, int);
+
+static mode_t mode_arg(int flags, va_list ap) {
+    return (flags & O_CREAT) || (flags & O_TMPFILE) == O_TMPFILE ? va_arg(ap, mode_t) : 0;
+}
+
+int open(const char *path, int flags, ...) {
+    va_list ap;
+    va_start(ap, flags);
+    mode_t mode = mode_arg(flags, ap);
+    va_end(ap);
+    count(path);
+    return ((open_f)dlsym(RTLD_NEXT, "open"))(path, flags, mode);
+}
+
+int openat(int dirfd, const char *path, int flags, ...) {
+    va_list ap;
+    va_start(ap, flags);
+    mode_t mode = mode_arg(flags, ap);
+    va_end(ap);
+    count(path);
+    return ((openat_f)dlsym(RTLD_NEXT, "openat"))(dirfd, path, flags, mode);
+}
+
+int __open_2(const char *path, int flags) {
+    count(path);
+    return ((open_2_f)dlsym(RTLD_NEXT, "__open_2"))(path, flags);
+}
+
+int __openat_2(int dirfd, const char *path, int flags) {
+    count(path);
+    return ((openat_2_f)dlsym(RTLD_NEXT, "__openat_2"))(dirfd, path, flags);
+}
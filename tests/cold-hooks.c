@@ -0,0 +1,79 @@
+// A second LD_PRELOAD library for `preload-probe cold`. Listed after
+// libnvidia-hide.so, it is initialized first, so the listing calls its
+// constructor makes reach the library's hooks cold: before the library's own
+// constructor has run init, as calls from another library's constructor do.
+// The constructor records what it saw; cold_hooks_report() hands that back.
+#define _GNU_SOURCE
+#include <dirent.h>
+#include <fcntl.h>
+#include <glob.h>
+#include <limits.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/syscall.h>
+#include <unistd.h>
+
+static char g_report[1024];
+static size_t g_len = 0;
+
+const char *cold_hooks_report(void) { return g_report; }
+
+static void record(const char *what, int ok) {
+    int n = snprintf(g_report + g_len, sizeof(g_report) - g_len, "%s %s\n", ok ? "ok  " : "FAIL", what);
+    if (n > 0 && (size_t)n < sizeof(g_report) - g_len) g_len += (size_t)n;
+}
+
+// Fixtures through raw syscalls: nothing here may init the library early.
+static void touch_raw(const char *path) {
+    int fd = (int)syscall(SYS_openat, AT_FDCWD, path, O_WRONLY | O_CREAT | O_TRUNC | O_CLOEXEC, 0644);
+    if (fd >= 0) close(fd);
+}
+
+__attribute__((constructor)) static void cold_calls(void) {
+    char dir[64], nv[PATH_MAX], lib[PATH_MAX], plain[PATH_MAX], pattern[PATH_MAX];
+    snprintf(dir, sizeof(dir), "/tmp/nvidia-hide-cold.%d", (int)getpid());
+    if (syscall(SYS_mkdirat, AT_FDCWD, dir, 0755) != 0) { record("fixture dir", 0); return; }
+    snprintf(nv, sizeof(nv), "%s/nvidia0", dir);
+    snprintf(lib, sizeof(lib), "%s/libGLX_nvidia.so.0", dir);
+    snprintf(plain, sizeof(plain), "%s/plain", dir);
+    touch_raw(nv);
+    touch_raw(lib);
+    touch_raw(plain);
+
+    int seen_nv = 0, seen_plain = 0;
+    DIR *d = opendir(dir);
+    for (struct dirent *e; d && (e = readdir(d)); ) {
+        if (!strcmp(e->d_name, "nvidia0")) seen_nv = 1;
+        if (!strcmp(e->d_name, "plain")) seen_plain = 1;
+    }
+    if (d) closedir(d);
+    record("cold readdir hides nvidia0", !seen_nv);
+    record("cold readdir lists plain", seen_plain);
+
+    seen_nv = 0;
+    int fd = (int)syscall(SYS_openat, AT_FDCWD, dir, O_RDONLY | O_DIRECTORY | O_CLOEXEC, 0);
+    char buf[4096];
+    for (ssize_t n; fd >= 0 && (n = getdents64(fd, buf, sizeof(buf))) > 0; ) {
+        for (ssize_t off = 0; off < n; ) {
+            struct dirent64 *e = (struct dirent64 *)(buf + off);
+            if (!strcmp(e->d_name, "nvidia0")) seen_nv = 1;
+            off += e->d_reclen;
+        }
+    }
+    if (fd >= 0) close(fd);
+    record("cold getdents64 hides nvidia0", !seen_nv);
+
+    glob_t g;
+    snprintf(pattern, sizeof(pattern), "%s/*", dir);
+    seen_nv = 0;
+    if (glob(pattern, 0, NULL, &g) == 0) {
+        for (size_t i = 0; i < g.gl_pathc; i++) if (!strcmp(g.gl_pathv[i], lib)) seen_nv = 1;
+        globfree(&g);
+    }
+    record("cold glob hides libGLX_nvidia.so.0", !seen_nv);
+
+    syscall(SYS_unlinkat, AT_FDCWD, nv, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, lib, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, plain, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, dir, AT_REMOVEDIR);
+}
@@ -0,0 +1,202 @@
+// Directory listing check that builds against glibc or musl, run under
+// LD_PRELOAD with LIBNVIDIAHIDE_ACTIVE=1 by `make check` and, built with
+// musl-gcc against a musl build of the library, by `make check-musl`. It only
+// uses calls both libcs export under the same names: readdir, scandir, stat,
+// open, and the getdents wrapper (getdents64 on glibc, getdents on musl,
+// which fill the same kernel records). Preload-probe can't do this: it
+// leans on glibc's __open_2 and LFS64 symbols.
+//
+// `make check` also sets LIBNVIDIAHIDE_KEEP to the fixture dir's nvidia-kept,
+// with one '?' per mkdtemp character: only the canonical spelling of the
+// directory matches, so scandir of a relative or slash-ended path has to be
+// resolved before the entry is judged.
+//
+// The fixtures are created with raw syscalls so the library never sees them.
+#define _GNU_SOURCE
+#include <dirent.h>
+#include <fcntl.h>
+#include <limits.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/stat.h>
+#include <sys/syscall.h>
+#include <unistd.h>
+
+static int g_failed = 0;
+
+static void check(const char *what, int cond) {
+    printf("%s %s\n", cond ? "ok  " : "FAIL", what);
+    if (!cond) g_failed = 1;
+}
+
+static void touch_raw(const char *path) {
+    int fd = (int)syscall(SYS_openat, AT_FDCWD, path, O_WRONLY | O_CREAT | O_TRUNC, 0644);
+    if (fd < 0) { perror(path); exit(2); }
+    close(fd);
+}
+
+// Kernel dirent64 layout, what both wrappers fill.
+struct raw_dirent64 {
+    uint64_t d_ino;
+    int64_t  d_off;
+    unsigned short d_reclen;
+    unsigned char  d_type;
+    char d_name[];
+};
+
+// Entries among the ones the getdents wrapper hands back for fd, read into a
+// buffer of len bytes, whose name starts with prefix.
+static int getdents_count(int fd, const char *prefix, size_t len) {
+    char buf[4096];
+    int seen = 0;
+    for (;;) {
+#ifdef __GLIBC__
+        ssize_t n = getdents64(fd, buf, len);
+#else
+        ssize_t n = getdents(fd, (struct dirent *)buf, len);
+#endif
+        if (n <= 0) return seen;
+        for (ssize_t bpos = 0; bpos < n; ) {
+            struct raw_dirent64 *d = (struct raw_dirent64 *)(buf + bpos);
+            if (!strncmp(d->d_name, prefix, strlen(prefix))) seen++;
+            bpos += d->d_reclen;
+        }
+    }
+}
+
+// 1 if scandir(dir) lists name.
+static int scandir_lists(const char *dir, const char *name) {
+    struct dirent **list = NULL;
+    int n = scandir(dir, &list, NULL, alphasort), seen = 0;
+    for (int i = 0; i < n; i++) {
+        if (!strcmp(list[i]->d_name, name)) seen = 1;
+        free(list[i]);
+    }
+    free(list);
+    return seen;
+}
+
+int main(void) {
+    char dir[] = "/tmp/nvidia-hide-dirent.XXXXXX";
+    if (!mkdtemp(dir)) { perror("mkdtemp"); return 2; }
+    // Listings drop nvidia* names wherever they are; opens and stat refuse
+    // NVIDIA library paths.
+    char entry[PATH_MAX], nv[PATH_MAX], plain[PATH_MAX], kept[PATH_MAX];
+    snprintf(entry, sizeof(entry), "%s/nvidia0", dir);
+    snprintf(kept, sizeof(kept), "%s/nvidia-kept", dir);
+    snprintf(nv, sizeof(nv), "%s/libGLX_nvidia.so.0", dir);
+    snprintf(plain, sizeof(plain), "%s/libplain.so.0", dir);
+    touch_raw(entry);
+    touch_raw(nv);
+    touch_raw(plain);
+    touch_raw(kept);
+
+    int seen_nv = 0, seen_plain = 0;
+    DIR *d = opendir(dir);
+    for (struct dirent *e; d && (e = readdir(d)) != NULL; ) {
+        if (!strcmp(e->d_name, "nvidia0")) seen_nv = 1;
+        if (!strcmp(e->d_name, "libplain.so.0")) seen_plain = 1;
+    }
+    if (d) closedir(d);
+    check("readdir lists libplain.so.0", seen_plain);
+    check("readdir hides nvidia0", !seen_nv);
+
+    struct dirent **list = NULL;
+    int n = scandir(dir, &list, NULL, alphasort);
+    seen_nv = seen_plain = 0;
+    for (int i = 0; i < n; i++) {
+        if (!strcmp(list[i]->d_name, "nvidia0")) seen_nv = 1;
+        if (!strcmp(list[i]->d_name, "libplain.so.0")) seen_plain = 1;
+        free(list[i]);
+    }
+    free(list);
+    check("scandir lists libplain.so.0", seen_plain);
+    check("scandir hides nvidia0", !seen_nv);
+
+    // The keep rule is keyed by the directory, so it shows whether scandir
+    // judged the entry under the canonical path.
+    char slashed[PATH_MAX];
+    snprintf(slashed, sizeof(slashed), "%s/", dir);
+    check("scandir lists the kept entry", scandir_lists(dir, "nvidia-kept"));
+    check("scandir with a trailing slash lists it", scandir_lists(slashed, "nvidia-kept"));
+    check("scandir with a trailing slash hides nvidia0", !scandir_lists(slashed, "nvidia0"));
+    if (chdir("/tmp") != 0) { perror("/tmp"); return 2; }
+    check("scandir of a relative path lists it", scandir_lists(dir + 5, "nvidia-kept"));
+    check("scandir of a relative path hides nvidia0", !scandir_lists(dir + 5, "nvidia0"));
+
+    int fd = open(dir, O_RDONLY | O_DIRECTORY);
+    check("getdents lists libplain.so.0", fd >= 0 && getdents_count(fd, "libplain.so.0", 4096));
+    if (fd >= 0) close(fd);
+    fd = open(dir, O_RDONLY | O_DIRECTORY);
+    check("getdents hides nvidia0", fd >= 0 && !getdents_count(fd, "nvidia0", 4096));
+    if (fd >= 0) close(fd);
+
+    // A read that only returns hidden entries mustn't look like the end of the
+    // directory: with 64-byte reads (two records) and 200 nvidia* entries,
+    // whole batches are hidden wherever the filesystem puts the plain ones.
+    // They're created first, so tmpfs, which lists newest first, puts them last.
+    enum { HIDDEN = 200, PLAIN = 3 };
+    char batch[PATH_MAX - 64], path[PATH_MAX];
+    snprintf(batch, sizeof(batch), "%s/batch", dir);
+    if (syscall(SYS_mkdirat, AT_FDCWD, batch, 0755) != 0) { perror(batch); return 2; }
+    for (int i = 0; i < PLAIN + HIDDEN; i++) {
+        snprintf(path, sizeof(path), i < PLAIN ? "%s/plain%d" : "%s/nvidia%d", batch, i);
+        touch_raw(path);
+    }
+    fd = open(batch, O_RDONLY | O_DIRECTORY);
+    int plain_seen = fd >= 0 ? getdents_count(fd, "plain", 64) : -1;
+    if (fd >= 0) close(fd);
+    printf("%s getdents past all-hidden batches lists the %d plain entries (%d)\n",
+           plain_seen == PLAIN ? "ok  " : "FAIL", PLAIN, plain_seen);
+    if (plain_seen != PLAIN) g_failed = 1;
+    for (int i = 0; i < PLAIN + HIDDEN; i++) {
+        snprintf(path, sizeof(path), i < PLAIN ? "%s/plain%d" : "%s/nvidia%d", batch, i);
+        syscall(SYS_unlinkat, AT_FDCWD, path, 0);
+    }
+    syscall(SYS_unlinkat, AT_FDCWD, batch, AT_REMOVEDIR);
+
+    // Names aren't UTF-8: entries are judged on their bytes and the ones kept
+    // come back byte for byte.
+    static const char *const bytes_hidden[] = { "nvidia\xff\xfe", "nvidia0\x80" };
+    static const char *const bytes_shown[] = { "\xff\xfeplain", "plain\xc3\x28", "\x80nvidia" };
+    char bytes[PATH_MAX - 64];
+    snprintf(bytes, sizeof(bytes), "%s/bytes", dir);
+    if (syscall(SYS_mkdirat, AT_FDCWD, bytes, 0755) != 0) { perror(bytes); return 2; }
+    for (int i = 0; i < 5; i++) {
+        snprintf(path, sizeof(path), "%s/%s", bytes, i < 2 ? bytes_hidden[i] : bytes_shown[i - 2]);
+        touch_raw(path);
+    }
+    int shown = 0, leaked = 0;
+    d = opendir(bytes);
+    for (struct dirent *e; d && (e = readdir(d)) != NULL; ) {
+        for (int i = 0; i < 2; i++) if (!strcmp(e->d_name, bytes_hidden[i])) leaked++;
+        for (int i = 0; i < 3; i++) if (!strcmp(e->d_name, bytes_shown[i])) shown++;
+    }
+    if (d) closedir(d);
+    check("readdir hides nvidia* names with non-UTF-8 bytes", !leaked);
+    check("readdir lists other non-UTF-8 names byte for byte", shown == 3);
+    fd = open(bytes, O_RDONLY | O_DIRECTORY);
+    check("getdents hides them too", fd >= 0 && !getdents_count(fd, "nvidia", 4096));
+    if (fd >= 0) close(fd);
+    for (int i = 0; i < 5; i++) {
+        snprintf(path, sizeof(path), "%s/%s", bytes, i < 2 ? bytes_hidden[i] : bytes_shown[i - 2]);
+        syscall(SYS_unlinkat, AT_FDCWD, path, 0);
+    }
+    syscall(SYS_unlinkat, AT_FDCWD, bytes, AT_REMOVEDIR);
+
+    struct stat st;
+    check("stat libplain.so.0", stat(plain, &st) == 0);
+    check("stat libGLX_nvidia.so.0 fails", stat(nv, &st) != 0);
+    fd = open(nv, O_RDONLY);
+    check("open libGLX_nvidia.so.0 fails", fd < 0);
+    if (fd >= 0) close(fd);
+
+    syscall(SYS_unlinkat, AT_FDCWD, entry, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, nv, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, plain, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, kept, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, dir, AT_REMOVEDIR);
+    return g_failed;
+}
@@ -0,0 +1,116 @@
+// Table-driven check of nvidia-hide-elf.h's elf_inspect(), which `nvidia-hide
+// doctor` uses to tell a dynamically linked program (one with a PT_INTERP
+// segment, so ld.so runs and reads LD_PRELOAD) from a static one. The ELF
+// files are minimal headers written to a temporary directory, for both
+// classes, so the check doesn't depend on what the system has installed.
+// exec_drops_preload(), behind the setuid warnings of `run` and `doctor`, is
+// checked against one of them with the setuid and setgid bits set.
+#define _GNU_SOURCE
+#include <limits.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <unistd.h>
+
+#include "../nvidia-hide-elf.h"
+
+static int g_failed = 0;
+
+static void check(const char *what, int cond) {
+    printf("%s %s\n", cond ? "ok  " : "FAIL", what);
+    if (!cond) g_failed = 1;
+}
+
+static void write_file(const char *path, const void *data, size_t len) {
+    FILE *f = fopen(path, "wb");
+    if (!f || fwrite(data, 1, len, f) != len) { perror(path); exit(2); }
+    fclose(f);
+}
+
+static void fill_ident(unsigned char *ident, int cls) {
+    memcpy(ident, ELFMAG, SELFMAG);
+    ident[EI_CLASS] = (unsigned char)cls;
+#if __BYTE_ORDER__ == __ORDER_LITTLE_ENDIAN__
+    ident[EI_DATA] = ELFDATA2LSB;
+#else
+    ident[EI_DATA] = ELFDATA2MSB;
+#endif
+    ident[EI_VERSION] = EV_CURRENT;
+}
+
+// An executable with a PT_LOAD and, when interp, a PT_INTERP after it.
+static void write_elf64(const char *path, int interp) {
+    struct { Elf64_Ehdr h; Elf64_Phdr ph[2]; } f;
+    memset(&f, 0, sizeof(f));
+    fill_ident(f.h.e_ident, ELFCLASS64);
+    f.h.e_type = ET_DYN;
+    f.h.e_machine = EM_X86_64;
+    f.h.e_phoff = sizeof(f.h);
+    f.h.e_phentsize = sizeof(Elf64_Phdr);
+    f.h.e_phnum = interp ? 2 : 1;
+    f.ph[0].p_type = PT_LOAD;
+    f.ph[1].p_type = PT_INTERP;
+    write_file(path, &f, sizeof(f.h) + f.h.e_phnum * sizeof(Elf64_Phdr));
+}
+
+static void write_elf32(const char *path, int interp) {
+    struct { Elf32_Ehdr h; Elf32_Phdr ph[2]; } f;
+    memset(&f, 0, sizeof(f));
+    fill_ident(f.h.e_ident, ELFCLASS32);
+    f.h.e_type = ET_EXEC;
+    f.h.e_machine = EM_386;
+    f.h.e_phoff = sizeof(f.h);
+    f.h.e_phentsize = sizeof(Elf32_Phdr);
+    f.h.e_phnum = interp ? 2 : 1;
+    f.ph[0].p_type = PT_LOAD;
+    f.ph[1].p_type = PT_INTERP;
+    write_file(path, &f, sizeof(f.h) + f.h.e_phnum * sizeof(Elf32_Phdr));
+}
+
+int main(void) {
+    char dir[] = "/tmp/nvidia-hide-elf.XXXXXX";
+    if (!mkdtemp(dir)) { perror("mkdtemp"); return 2; }
+    char p[6][PATH_MAX];
+    for (int i = 0; i < 6; i++) snprintf(p[i], sizeof(p[i]), "%s/f%d", dir, i);
+    write_elf64(p[0], 1);
+    write_elf64(p[1], 0);
+    write_elf32(p[2], 1);
+    write_elf32(p[3], 0);
+    write_file(p[4], "#!/bin/sh\nexit 0\n", 17);
+    write_elf64(p[5], 1);
+    if (truncate(p[5], sizeof(Elf64_Ehdr) + 4) != 0) { perror(p[5]); return 2; }   // program headers cut short
+
+    struct nh_elf e;
+    check("64-bit with PT_INTERP: dynamic",
+          elf_inspect(p[0], &e) == 0 && e.elf_class == ELFCLASS64 && e.machine == EM_X86_64 && e.interp);
+    check("64-bit without PT_INTERP: static", elf_inspect(p[1], &e) == 0 && e.elf_class == ELFCLASS64 && !e.interp);
+    check("32-bit with PT_INTERP: dynamic",
+          elf_inspect(p[2], &e) == 0 && e.elf_class == ELFCLASS32 && e.machine == EM_386 && e.interp);
+    check("32-bit without PT_INTERP: static", elf_inspect(p[3], &e) == 0 && e.elf_class == ELFCLASS32 && !e.interp);
+    check("script: not ELF", elf_inspect(p[4], &e) == -1);
+    check("truncated program headers: rejected", elf_inspect(p[5], &e) == -1);
+    check("missing file: rejected", elf_inspect("/nonexistent/nvidia-hide-elf", &e) == -1);
+    check("elf_class agrees", elf_class(p[0]) == ELFCLASS64 && elf_class(p[2]) == ELFCLASS32 && elf_class(p[4]) == -1);
+    check("this program: dynamic", elf_inspect("/proc/self/exe", &e) == 0 && e.interp);
+
+    // Passing someone else's uid/gid stands in for running as a normal user,
+    // so this also holds when the tests run as root. On a nosuid mount the
+    // bits don't count at all.
+    struct statvfs vfs;
+    int nosuid = statvfs(dir, &vfs) == 0 && (vfs.f_flag & ST_NOSUID);
+    uid_t me = getuid(), other = me + 1;
+    gid_t mygid = getgid(), othergid = mygid + 1;
+    chmod(p[0], 0755);
+    check("plain 0755: preload kept", exec_drops_preload(p[0], other, othergid) == 0);
+    chmod(p[0], 04755);
+    check("setuid, other user: preload dropped", exec_drops_preload(p[0], other, mygid) == !nosuid);
+    check("setuid, owner: preload kept", exec_drops_preload(p[0], me, mygid) == 0);
+    chmod(p[0], 02755);
+    check("setgid, other group: preload dropped", exec_drops_preload(p[0], me, othergid) == !nosuid);
+    check("setgid, own group: preload kept", exec_drops_preload(p[0], me, mygid) == 0);
+    check("missing file: -1", exec_drops_preload("/nonexistent/nvidia-hide-elf", me, mygid) == -1);
+
+    for (int i = 0; i < 6; i++) unlink(p[i]);
+    rmdir(dir);
+    return g_failed;
+}
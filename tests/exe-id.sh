@@ -0,0 +1,39 @@
+#!/bin/sh
+# Checks `sha256:` and `inode:` allowlist entries against a renamed copy of
+# /bin/true, both through `nvidia-hide check` and under the preloaded library.
+# The hash comes from sha256sum, so this also vets the built-in SHA-256.
+#
+#   tests/exe-id.sh ./nvidia-hide /path/to/libnvidia-hide.so
+set -u
+nh=$1
+so=$2
+failed=0
+dir=$(mktemp -d /tmp/nvidia-hide-exeid.XXXXXX)
+trap 'rm -rf "$dir"' EXIT
+export XDG_CONFIG_HOME="$dir/cfg"
+unset LIBNVIDIAHIDE_DENYLIST LIBNVIDIAHIDE_ACTIVE
+
+ok() { echo "ok   $1"; }
+fail() { echo "FAIL $1"; failed=1; }
+
+cp /bin/true "$dir/renamed-app"
+hash=$(sha256sum "$dir/renamed-app" | cut -d' ' -f1)
+ino=$(stat -c %d:%i "$dir/renamed-app")
+
+# expect <what> <allowlist> <active>
+expect() {
+    out=$(LIBNVIDIAHIDE_ALLOWLIST=$2 "$nh" check "$dir/renamed-app" 2>/dev/null)
+    printf '%s\n' "$out" | grep -q "^active: *$3" && ok "check: $1" || fail "check: $1"
+    log=$(LD_PRELOAD=$so LIBNVIDIAHIDE_ALLOWLIST=$2 LIBNVIDIAHIDE_LOG_LEVEL=info "$dir/renamed-app" 2>&1)
+    printf '%s\n' "$log" | grep -q "active=$3" && ok "preload: $1" || fail "preload: $1"
+}
+
+expect "sha256 match" "sha256:$hash" 1
+expect "inode match" "inode:$ino" 1
+expect "sha256 mismatch" "sha256:0000000000000000000000000000000000000000000000000000000000000000" 0
+expect "excepted by hash" "renamed-app:!sha256:$hash" 0
+
+out=$(LIBNVIDIAHIDE_ALLOWLIST="sha256:$hash" "$nh" check "$dir/renamed-app" 2>/dev/null)
+printf '%s\n' "$out" | grep -q "^sha256: *$hash" && ok "check prints the hash" || fail "check prints the hash"
+
+exit $failed
@@ -0,0 +1,85 @@
+#!/bin/sh
+# Probe modes that need a dGPU next to an iGPU: a private mount namespace gets
+# fake ones in /sys/class and /dev/dri, so discovery finds card1/renderD129
+# without real hardware. A tmpfs /dev then holds NVIDIA device nodes that
+# exist, for the probes that must not find them or map a kept one, tmpfs /sys/module and
+# /proc/driver the driver's entries, and tmpfs GLVND and EGL config dirs
+# the vendor JSONs. Then the opposite, no
+# /sys/class/drm at all, as in a minimal container. tests/topo-cache.sh runs against the fake too. Needs
+# root; skipped otherwise.
+#
+#   tests/fake-gpu.sh ./tests/preload-probe /path/to/libnvidia-hide.so
+set -u
+probe=$1
+so=$2
+if [ "$(id -u)" != 0 ] || [ ! -d /dev/dri ] || ! unshare -m true 2>/dev/null; then
+    echo "skip fake-gpu (needs root, unshare and /dev/dri)"
+    exit 0
+fi
+unset LIBNVIDIAHIDE_TOPO LIBNVIDIAHIDE_CACHE
+unshare -m sh -c '
+set -e
+mount -t tmpfs none /sys/class
+mkdir -p /sys/class/drm /sys/class/pcidev/0000:01:00.0 /sys/class/pcidev/0000:00:02.0
+echo 0x10de > /sys/class/pcidev/0000:01:00.0/vendor
+echo 0x8086 > /sys/class/pcidev/0000:00:02.0/vendor
+for n in card1 renderD129; do mkdir /sys/class/drm/$n; ln -s /sys/class/pcidev/0000:01:00.0 /sys/class/drm/$n/device; done
+for n in card0 renderD128; do mkdir /sys/class/drm/$n; ln -s /sys/class/pcidev/0000:00:02.0 /sys/class/drm/$n/device; done
+mkdir /sys/class/drm/card1-DP-1 /sys/class/drm/card0-eDP-1
+echo 1.1.0 > /sys/class/drm/version
+
+mount -t tmpfs none /dev/dri
+touch /dev/dri/card0 /dev/dri/card1 /dev/dri/renderD128 /dev/dri/renderD129
+mkdir /dev/dri/by-id
+ln -s ../renderD129 /dev/dri/by-id/dgpu-render
+ln -s ../renderD128 /dev/dri/by-id/igpu-render
+mkdir /dev/dri/by-path
+ln -s ../card1 /dev/dri/by-path/platform-gpu0-card
+ln -s ../card0 /dev/dri/by-path/platform-gpu1-card
+
+export LD_PRELOAD=$2 LIBNVIDIAHIDE_ACTIVE=1
+"$1" atflags
+"$1" sysdrm
+unset LD_PRELOAD LIBNVIDIAHIDE_ACTIVE
+sh "$3" "$2" "$1"
+' sh "$probe" "$so" "$(dirname "$0")/topo-cache.sh" || exit 1
+
+unshare -m sh -c '
+set -e
+mount -t tmpfs none /dev
+mknod -m 666 /dev/null c 1 3
+mknod -m 666 /dev/zero c 1 5
+touch /dev/nvidia0 /dev/nvidiactl
+mount -t tmpfs none /sys/module
+mount -t tmpfs none /proc/driver
+for m in nvidia nvidia_uvm nvidia_drm nvidia_modeset snd i915; do mkdir /sys/module/$m; echo 1 > /sys/module/$m/version; done
+for m in nvidia rtc; do mkdir /proc/driver/$m; echo 1 > /proc/driver/$m/version; done
+for d in /usr/share/glvnd/egl_vendor.d /etc/glvnd/egl_vendor.d \
+         /usr/share/egl/egl_external_platform.d /etc/egl/egl_external_platform.d; do
+    [ -d "${d%/*}" ] || continue
+    mount -t tmpfs none "${d%/*}"
+    mkdir "$d"
+    for f in 10_nvidia.json 10_nvidia_wayland.json 50_mesa.json; do echo "{}" > "$d/$f"; done
+done
+export LD_PRELOAD=$2 LIBNVIDIAHIDE_ACTIVE=1
+"$1" devnodes
+LIBNVIDIAHIDE_KEEP=/dev/nvidia0 "$1" mmapdev
+LIBNVIDIAHIDE_KEEP=/dev/nvidia0 LIBNVIDIAHIDE_BLOCK_MMAP=1 "$1" mmapdev
+LIBNVIDIAHIDE_KEEP=/dev/nvidia0 LIBNVIDIAHIDE_BLOCK_IOCTL=1 "$1" mmapdev
+"$1" drivers
+"$1" eglvendor
+LIBNVIDIAHIDE_DRYRUN=1 "$1" dryrun
+' sh "$probe" "$so" || exit 1
+
+if [ -e /dev/nvidiactl ]; then
+    echo "skip nosysfs (a real /dev/nvidiactl counts as a DRM subsystem)"
+    exit 0
+fi
+exec unshare -m sh -c '
+set -e
+mount -t tmpfs none /sys/class
+export LD_PRELOAD=$2 XDG_CONFIG_HOME=/nonexistent
+unset LIBNVIDIAHIDE_ACTIVE LIBNVIDIAHIDE_ALLOWLIST LIBNVIDIAHIDE_DENYLIST LIBNVIDIAHIDE_REQUIRE_ENV
+"$1" nosysfs
+LIBNVIDIAHIDE_REQUIRE_SYSFS=1 "$1" nosysfs
+' sh "$probe" "$so"
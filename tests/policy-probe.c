@@ -0,0 +1,425 @@
+// Table-driven check of nvidia-hide-policy.h, the allow/deny matching the
+// library and `nvidia-hide check` share: glob and "re:" lines side by side in
+// one list, a regex that fails to compile matching nothing, '!' exceptions in
+// order and a bare '!' that is no entry, nested and
+// cyclic includes, "cmdline:" lines, "ancestor:" lines over a fake /proc
+// parent chain, compiled globs
+// against fnmatch() itself, the decide_active() verdict for empty,
+// unmatched and overriding lists, and /etc's lists merged with the user's.
+#define _GNU_SOURCE
+#include <fnmatch.h>
+#include <stdarg.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/stat.h>
+#include <unistd.h>
+
+static void dbg(const char *fmt, ...);
+
+#include "../nvidia-hide-policy.h"
+
+static void dbg(const char *fmt, ...) {
+    va_list ap; va_start(ap, fmt);
+    vfprintf(stderr, fmt, ap);
+    va_end(ap);
+    fputc('\n', stderr);
+}
+
+static int g_failed = 0;
+
+static void check(const char *what, int cond) {
+    printf("%s %s\n", cond ? "ok  " : "FAIL", what);
+    if (!cond) g_failed = 1;
+}
+
+static struct nh_subject subject(const char *exe) {
+    struct nh_subject s = { exe, base_name(exe), NULL, NULL, NULL };
+    return s;
+}
+
+static int env_list_match(const char *list, const struct nh_subject *s) {
+    struct nh_matchers m = {0};
+    matchers_from_env(&m, list);
+    int r = matchers_match(&m, s);
+    matchers_free(&m);
+    return r;
+}
+
+static void expect_list(const char *list, const char *exe, int want) {
+    struct nh_subject s = subject(exe);
+    int got = env_list_match(list, &s);
+    printf("%s \"%s\" against %s -> %d\n", got == want ? "ok  " : "FAIL", list, exe, got);
+    if (got != want) g_failed = 1;
+}
+
+// One denylist, a glob line next to "re:" lines with and without a '/'.
+static void probe_regex(void) {
+    static const char *const deny = "steam*:re:^/opt/games/[a-z]+/bin/run$:re:^(mpv|vlc)$";
+    expect_list(deny, "/usr/bin/steamwebhelper", 1);     // the glob
+    expect_list(deny, "/opt/games/doom/bin/run", 1);     // the full-path regex
+    expect_list(deny, "/opt/games/Doom2/bin/run", 0);
+    expect_list(deny, "/usr/bin/mpv", 1);                // the base-name regex
+    expect_list(deny, "/usr/bin/mpv2", 0);
+    expect_list(deny, "/usr/bin/firefox", 0);
+
+    // The same lines from a file.
+    char path[] = "/tmp/nvidia-hide-policy.XXXXXX";
+    int fd = mkstemp(path);
+    static const char text[] = "# games\nsteam*\nre:^/opt/games/[a-z]+/bin/run$\nre:^(mpv|vlc)$\n";
+    if (fd < 0 || write(fd, text, sizeof(text) - 1) != (ssize_t)(sizeof(text) - 1)) { perror("policy file"); exit(2); }
+    close(fd);
+    static const char *const exes[] = { "/usr/bin/steamwebhelper", "/opt/games/doom/bin/run",
+                                        "/opt/games/Doom2/bin/run", "/usr/bin/mpv", "/usr/bin/mpv2" };
+    struct nh_matchers f = {0};
+    matchers_from_file(&f, path);
+    unlink(path);
+    for (size_t i = 0; i < sizeof(exes) / sizeof(*exes); i++) {
+        struct nh_subject s = subject(exes[i]);
+        char what[128];
+        snprintf(what, sizeof(what), "file list agrees with env list for %s", exes[i]);
+        check(what, matchers_match(&f, &s) == env_list_match(deny, &s) && f.had_entries);
+    }
+    matchers_free(&f);
+
+    // A bad regex is dropped and counted; the lines around it still work.
+    struct nh_matchers m = {0};
+    matchers_from_env(&m, "mpv:re:([unclosed:vlc");
+    check("bad regex dropped, counted as bad", m.n == 2 && m.bad == 1 && m.had_entries);
+    struct nh_subject s = subject("/usr/bin/vlc");
+    check("lines around a bad regex still match", matchers_match(&m, &s));
+    matchers_free(&m);
+}
+
+// "cmdline:" lines against the joined command line; bare lines still the exe.
+static void probe_cmdline(void) {
+    static const struct { const char *list, *cmdline; int want; } cases[] = {
+        { "cmdline:*--app=discord*", "/usr/lib/electron/electron --app=discord --ozone", 1 },
+        { "cmdline:*--app=discord*", "/usr/lib/electron/electron --app=slack", 0 },
+        { "cmdline:*--app=discord*", NULL, 0 },                 // unreadable: never matches
+        { "cmdline:electron", "electron", 1 },                  // literal: the whole line
+        { "cmdline:electron", "electron --app=discord", 0 },
+        { "cmdline:*discord*", "/usr/lib/electron/electron", 0 }, // not the exe
+        { "electron", "python3 app.py", 1 },                    // bare line: the exe
+        { "*:cmdline:*--app=discord*:!cmdline:*--app=discord*", "electron --app=discord", 0 },
+    };
+    for (size_t i = 0; i < sizeof(cases) / sizeof(*cases); i++) {
+        struct nh_subject s = subject("/usr/lib/electron/electron");
+        s.cmdline = cases[i].cmdline;
+        int got = env_list_match(cases[i].list, &s);
+        printf("%s \"%s\" against cmdline %s -> %d\n", got == cases[i].want ? "ok  " : "FAIL",
+               cases[i].list, cases[i].cmdline ? cases[i].cmdline : "(unreadable)", got);
+        if (got != cases[i].want) g_failed = 1;
+    }
+}
+
+// A fake /proc for ancestors_walk. pid 100 runs under a shell under Steam's
+// reaper, whose exe can't be read, under steam itself, under init; pid 200's
+// parent has no readable stat; pids from 1000 up form a chain deeper than
+// NH_MAX_ANCESTORS.
+static const struct { int pid, ppid; const char *exe, *comm; } g_procs[] = {
+    { 100, 50, "/usr/bin/python3.12", "python3" },
+    { 50, 40, "/usr/bin/bash", "bash" },
+    { 40, 30, NULL, "reaper" },
+    { 30, 1, "/home/u/.steam/ubuntu12_32/steam", "steam" },
+    { 1, 0, "/usr/lib/systemd/systemd", "systemd" },
+    { 200, 60, "/usr/bin/sh", "sh" },
+    { 60, 30, "/usr/bin/bwrap", NULL },
+};
+
+static ssize_t fake_proc(int pid, const char *what, char *buf, size_t sz) {
+    const char *text = NULL;
+    char stat[64];
+    if (pid >= 1000) {
+        snprintf(stat, sizeof(stat), "%d (deep) S %d", pid, pid + 1);
+        if (!strcmp(what, "stat")) text = stat;
+        else if (!strcmp(what, "exe")) text = "/usr/bin/deep";
+    }
+    for (size_t i = 0; !text && i < sizeof(g_procs) / sizeof(*g_procs); i++) {
+        if (g_procs[i].pid != pid) continue;
+        if (!strcmp(what, "stat")) {
+            if (!g_procs[i].comm) return -1;
+            // a ") " inside comm must not throw off the ppid
+            snprintf(stat, sizeof(stat), "%d (%s) x) S %d 1 1", pid, g_procs[i].comm, g_procs[i].ppid);
+            text = stat;
+        } else if (!strcmp(what, "exe")) {
+            text = g_procs[i].exe;
+        } else if (!strcmp(what, "comm") && g_procs[i].comm) {
+            snprintf(stat, sizeof(stat), "%s\n", g_procs[i].comm);
+            text = stat;
+        }
+    }
+    if (!text) return -1;
+    size_t n = strlen(text) < sz ? strlen(text) : sz;
+    memcpy(buf, text, n);
+    return (ssize_t)n;
+}
+
+// "ancestor:" lines over the fake chain: exe paths, comm where the exe can't
+// be read, never init or the process itself, and a walk that stops at the
+// first unreadable entry or NH_MAX_ANCESTORS.
+static void probe_ancestors(void) {
+    struct nh_ancestors anc = { fake_proc, 100, 0, 0, {0} };
+    ancestors_walk(&anc);
+    check("chain is bash, reaper, steam", anc.n == 3 && !strcmp(anc.names[0], "/usr/bin/bash") &&
+          !strcmp(anc.names[1], "reaper") && !strcmp(anc.names[2], "/home/u/.steam/ubuntu12_32/steam"));
+
+    static const struct { const char *list; int pid, want; } cases[] = {
+        { "ancestor:steam", 100, 1 },
+        { "ancestor:reaper", 100, 1 },                  // comm: exe unreadable
+        { "ancestor:/home/*/.steam/*", 100, 1 },        // '/': the full path
+        { "ancestor:/usr/bin/bash", 100, 1 },
+        { "ancestor:python3.12", 100, 0 },              // the process itself
+        { "ancestor:systemd", 100, 0 },                 // init
+        { "ancestor:ste*", 100, 1 },
+        { "ancestor:*:!ancestor:steam", 100, 0 },
+        { "bash", 100, 0 },                             // bare line: the exe
+        { "ancestor:steam", 200, 0 },                   // stat unreadable: fail open
+        { "ancestor:deep", 1000, 1 },
+    };
+    for (size_t i = 0; i < sizeof(cases) / sizeof(*cases); i++) {
+        struct nh_ancestors a = { fake_proc, cases[i].pid, 0, 0, {0} };
+        struct nh_subject s = subject("/usr/bin/python3.12");
+        s.ancestors = &a;
+        int got = env_list_match(cases[i].list, &s);
+        printf("%s \"%s\" for pid %d -> %d\n", got == cases[i].want ? "ok  " : "FAIL", cases[i].list, cases[i].pid, got);
+        if (got != cases[i].want) g_failed = 1;
+        ancestors_free(&a);
+    }
+
+    struct nh_ancestors unread = { fake_proc, 200, 0, 0, {0} };
+    ancestors_walk(&unread);
+    check("walk stops at an unreadable stat", unread.n == 1 && !strcmp(unread.names[0], "/usr/bin/bwrap"));
+    struct nh_ancestors deep = { fake_proc, 1000, 0, 0, {0} };
+    ancestors_walk(&deep);
+    check("walk stops at NH_MAX_ANCESTORS", deep.n == NH_MAX_ANCESTORS);
+    struct nh_subject s = subject("/usr/bin/python3.12");
+    check("no ancestor list: ancestor lines never match", !env_list_match("ancestor:*", &s));
+    ancestors_free(&anc);
+    ancestors_free(&unread);
+    ancestors_free(&deep);
+}
+
+// '!' lines: the last matching line wins, so order decides; a bare '!' is
+// skipped and isn't an entry, so it can't make an allowlist of nothing.
+static void probe_negate(void) {
+    expect_list("*:!mpv", "/usr/bin/mpv", 0);
+    expect_list("*:!mpv", "/usr/bin/vlc", 1);
+    expect_list("!mpv:*", "/usr/bin/mpv", 1);               // '*' comes later
+    expect_list("*:!mpv:mpv", "/usr/bin/mpv", 1);
+    expect_list("*:!/usr/bin/mpv", "/usr/local/bin/mpv", 1);
+    expect_list("!mpv", "/usr/bin/mpv", 0);                 // only exceptions: nothing matches
+    expect_list("\\!odd", "/usr/bin/!odd", 1);              // escaped: a name starting with '!'
+    expect_list("mpv:!", "/usr/bin/mpv", 1);
+    expect_list("*:!", "/usr/bin/vlc", 1);
+
+    struct nh_matchers m = {0};
+    matchers_from_env(&m, "!:!");
+    check("bare '!' entries: no lines, no entries", m.n == 0 && !m.had_entries && !m.bad);
+    matchers_free(&m);
+    matchers_from_env(&m, "mpv:!");
+    check("bare '!' next to a line: one line", m.n == 1 && m.had_entries);
+    matchers_free(&m);
+
+    // An allowlist file of nothing but '!' doesn't make the program allow-only.
+    char allow[] = "/tmp/nvidia-hide-policy.XXXXXX";
+    int fd = mkstemp(allow);
+    static const char text[] = "!\n";
+    if (fd < 0 || write(fd, text, sizeof(text) - 1) != (ssize_t)(sizeof(text) - 1)) { perror("allow file"); exit(2); }
+    close(fd);
+    const char *const files[] = { allow, NULL };
+    static const char *const none[] = { "/nonexistent/nvidia-hide/deny", NULL };
+    struct nh_policy_sets sets;
+    policy_sets_load(&sets, NULL, NULL, files, none);
+    struct nh_subject s = subject("/usr/bin/mpv");
+    struct nh_decision d = decide_active(&s, &sets);
+    policy_sets_free(&sets);
+    unlink(allow);
+    check("allowlist file of a bare '!': no allowlist, hides", !d.has_allow && d.active);
+}
+
+static void write_file(const char *dir, const char *name, const char *text) {
+    char path[PATH_MAX];
+    snprintf(path, sizeof(path), "%s/%s", dir, name);
+    FILE *f = fopen(path, "w");
+    if (!f || fputs(text, f) < 0 || fclose(f) != 0) { perror(path); exit(2); }
+}
+
+// What a list file read with its includes gives: matches for exe, entries, bad lines.
+static void expect_file(const char *dir, const char *name, const char *exe, int match, int had, int bad) {
+    char path[PATH_MAX];
+    snprintf(path, sizeof(path), "%s/%s", dir, name);
+    struct nh_matchers m = {0};
+    matchers_from_file(&m, path);
+    struct nh_subject s = subject(exe);
+    int got = matchers_match(&m, &s);
+    int ok = got == match && m.had_entries == had && m.bad == bad;
+    printf("%s %s against %s -> match=%d had_entries=%d bad=%d\n", ok ? "ok  " : "FAIL", name, exe, got, m.had_entries, m.bad);
+    if (!ok) g_failed = 1;
+    matchers_free(&m);
+}
+
+// Nested includes, relative to the including file, and cycles that stop
+// without taking the lines around them along.
+static void probe_include(void) {
+    char dir[] = "/tmp/nvidia-hide-policy.XXXXXX";
+    if (!mkdtemp(dir)) { perror("mkdtemp"); exit(2); }
+    char sub[PATH_MAX];
+    snprintf(sub, sizeof(sub), "%s/sub", dir);
+    if (mkdir(sub, 0755) != 0) { perror(sub); exit(2); }
+    write_file(dir, "top", "vim\ninclude sub/games\n");
+    write_file(dir, "sub/games", "steam*\ninclude ../more   # back up a level\n");
+    write_file(dir, "more", "mpv\n");
+    write_file(dir, "self", "include self\nfoo\n");
+    write_file(dir, "ping", "include pong\nping\n");
+    write_file(dir, "pong", "include ping\npong\n");
+    write_file(dir, "only-include", "include more\n");
+    write_file(dir, "missing", "include nowhere\n");
+    write_file(dir, "bang", "# a stray exception mark\n!\n");
+
+    expect_file(dir, "top", "/usr/bin/vim", 1, 1, 0);
+    expect_file(dir, "top", "/usr/bin/steamwebhelper", 1, 1, 0);   // one level down
+    expect_file(dir, "top", "/usr/bin/mpv", 1, 1, 0);               // two levels down
+    expect_file(dir, "top", "/usr/bin/emacs", 0, 1, 0);
+    expect_file(dir, "bang", "/usr/bin/foo", 0, 0, 0);              // bare '!': no entries
+    expect_file(dir, "self", "/usr/bin/foo", 1, 1, 1);              // cycle counted, line kept
+    expect_file(dir, "ping", "/usr/bin/ping", 1, 1, 1);
+    expect_file(dir, "ping", "/usr/bin/pong", 1, 1, 1);
+    expect_file(dir, "only-include", "/usr/bin/mpv", 1, 1, 0);      // entries from the include count
+    expect_file(dir, "missing", "/usr/bin/mpv", 0, 0, 1);           // no entries: no allowlist
+
+    static const char *const names[] = { "top", "sub/games", "more", "self", "ping", "pong", "only-include", "missing", "bang" };
+    for (size_t i = 0; i < sizeof(names) / sizeof(*names); i++) {
+        char path[PATH_MAX];
+        snprintf(path, sizeof(path), "%s/%s", dir, names[i]);
+        unlink(path);
+    }
+    rmdir(sub);
+    rmdir(dir);
+}
+
+// Each pattern compiled once, then matched against every name: a glob line
+// must answer exactly as fnmatch(pat, name, 0), a literal one as strcmp.
+static void probe_fnmatch(void) {
+    static const char *const pats[] = {
+        "mpv", "mpv*", "*mpv", "m?v", "m[a-p]v", "m[!p]v", "m[]]v", "m\\*v", "m\\pv",
+        "*", "?*", "[[:digit:]]*", "a*b*c", "*.AppImage", "/usr/bin/*", "/usr/*/mpv",
+        "/opt/*game*", "[", "a[", "\\",
+    };
+    static const char *const names[] = {
+        "mpv", "mpv2", "xmpv", "mov", "mxv", "m]v", "m*v", "mv", "", "7zip", "abc", "aXbYc",
+        "Steam.AppImage", "/usr/bin/mpv", "/usr/local/bin/mpv", "/opt/games/doom", "[", "a[", "\\",
+    };
+    int mismatches = 0;
+    for (size_t i = 0; i < sizeof(pats) / sizeof(*pats); i++) {
+        struct nh_matchers m = {0};
+        matchers_add(&m, pats[i]);
+        if (m.n != 1) { printf("FAIL %s did not compile\n", pats[i]); g_failed = 1; continue; }
+        for (size_t j = 0; j < sizeof(names) / sizeof(*names); j++) {
+            int got = matcher_hit(&m.v[0], names[j]);
+            int want = fnmatch(pats[i], names[j], 0) == 0;
+            if (got != want) {
+                printf("FAIL compiled %s on \"%s\": %d, fnmatch says %d\n", pats[i], names[j], got, want);
+                mismatches++;
+            }
+        }
+        matchers_free(&m);
+    }
+    check("compiled globs agree with fnmatch", mismatches == 0);
+}
+
+// decide_active() over env lists only; files that don't exist are empty.
+static void probe_decide(void) {
+    static const char *const none[] = { "/nonexistent/nvidia-hide/allow", NULL };
+    static const struct {
+        const char *exe, *allow, *deny;
+        int has_allow, allow_match, deny_match, active;
+    } cases[] = {
+        { "/usr/bin/mpv",     NULL,    NULL,      0, 0, 0, 1 },    // empty lists: hide
+        { "/usr/bin/mpv",     "",      "",        0, 0, 0, 1 },
+        { "/usr/bin/mpv",     "steam", NULL,      1, 0, 0, 0 },    // allow, no match: off
+        { "/usr/bin/steam",   "steam", NULL,      1, 1, 0, 1 },
+        { "/usr/bin/steam",   "steam", "steam",   1, 1, 1, 0 },    // deny overrides allow
+        { "/usr/bin/steam",   "st*",   "mpv",     1, 1, 0, 1 },
+        { "/usr/bin/mpv",     NULL,    "mpv",     0, 0, 1, 0 },
+        { "/usr/bin/firefox", NULL,    "mpv",     0, 0, 0, 1 },
+    };
+    for (size_t i = 0; i < sizeof(cases) / sizeof(*cases); i++) {
+        struct nh_subject s = subject(cases[i].exe);
+        struct nh_policy_sets sets;
+        policy_sets_load(&sets, cases[i].allow, cases[i].deny, none, none);
+        struct nh_decision d = decide_active(&s, &sets);
+        policy_sets_free(&sets);
+        int ok = d.has_allow == cases[i].has_allow && d.allow_match == cases[i].allow_match &&
+                 d.deny_match == cases[i].deny_match && d.active == cases[i].active;
+        printf("%s decide %s allow=%s deny=%s -> has_allow=%d allow=%d deny=%d active=%d\n",
+               ok ? "ok  " : "FAIL", cases[i].exe, cases[i].allow ? cases[i].allow : "(unset)",
+               cases[i].deny ? cases[i].deny : "(unset)", d.has_allow, d.allow_match, d.deny_match, d.active);
+        if (!ok) g_failed = 1;
+    }
+}
+
+// /etc/nvidia-hide's lists merged with the user's: a system deny holds even
+// when the user's files allow the program or '!' it out of their denylist,
+// and a system allowlist makes every process allow-only.
+static void probe_system_lists(void) {
+    char dir[] = "/tmp/nvidia-hide-policy.XXXXXX";
+    if (!mkdtemp(dir)) { perror("mkdtemp"); exit(2); }
+    static const struct {
+        const char *exe, *sys_allow, *sys_deny, *user_allow, *user_deny;
+        int active;
+    } cases[] = {
+        { "/usr/bin/mpv",     "",        "mpv\n", "",          "",          0 },    // system deny
+        { "/usr/bin/mpv",     "",        "mpv\n", "mpv\n",     "",          0 },    // user allow can't lift it
+        { "/usr/bin/mpv",     "",        "mpv\n", "",          "!mpv\n",    0 },    // nor can a user '!'
+        { "/usr/bin/mpv",     "",        "mpv\n", "mpv\n",     "*\n!mpv\n", 0 },
+        { "/usr/bin/firefox", "",        "mpv\n", "",          "!mpv\n",    1 },
+        { "/usr/bin/firefox", "steam\n", "",      "",          "",          0 },    // system allowlist: allow-only
+        { "/usr/bin/firefox", "steam\n", "",      "firefox\n", "",          1 },    // a user match satisfies it
+        { "/usr/bin/steam",   "steam\n", "",      "",          "steam\n",   0 },
+    };
+    char sys_allow[PATH_MAX], sys_deny[PATH_MAX], user_allow[PATH_MAX], user_deny[PATH_MAX];
+    snprintf(sys_allow, sizeof(sys_allow), "%s/sys-allow", dir);
+    snprintf(sys_deny, sizeof(sys_deny), "%s/sys-deny", dir);
+    snprintf(user_allow, sizeof(user_allow), "%s/user-allow", dir);
+    snprintf(user_deny, sizeof(user_deny), "%s/user-deny", dir);
+    const char *const allow[] = { sys_allow, user_allow, NULL }, *const deny[] = { sys_deny, user_deny, NULL };
+    for (size_t i = 0; i < sizeof(cases) / sizeof(*cases); i++) {
+        write_file(dir, "sys-allow", cases[i].sys_allow);
+        write_file(dir, "sys-deny", cases[i].sys_deny);
+        write_file(dir, "user-allow", cases[i].user_allow);
+        write_file(dir, "user-deny", cases[i].user_deny);
+        struct nh_subject s = subject(cases[i].exe);
+        struct nh_policy_sets sets;
+        policy_sets_load(&sets, NULL, NULL, allow, deny);
+        struct nh_decision d = decide_active(&s, &sets);
+        policy_sets_free(&sets);
+        char what[256];
+        snprintf(what, sizeof(what), "system allow=%.16s deny=%.16s, user allow=%.16s deny=%.16s: %s active=%d",
+                 cases[i].sys_allow, cases[i].sys_deny, cases[i].user_allow, cases[i].user_deny,
+                 cases[i].exe, cases[i].active);
+        for (char *c = what; *c; c++) if (*c == '\n') *c = ' ';
+        check(what, d.active == cases[i].active);
+    }
+    struct nh_policy_files files;
+    policy_files(&files, "denylist");
+    check("the system file comes first", !strcmp(files.list[0], NH_SYSTEM_CONFIG_DIR "/denylist") &&
+                                         files.list[1] == files.user && !files.list[2]);
+    unlink(sys_allow);
+    unlink(sys_deny);
+    unlink(user_allow);
+    unlink(user_deny);
+    rmdir(dir);
+}
+
+int main(void) {
+    probe_regex();
+    probe_fnmatch();
+    probe_include();
+    probe_cmdline();
+    probe_negate();
+    probe_ancestors();
+    probe_decide();
+    probe_system_lists();
+    return g_failed;
+}
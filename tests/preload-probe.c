@@ -0,0 +1,2298 @@
+// End-to-end check of the interposer ABI: built with _FORTIFY_SOURCE=2 and run
+// under LD_PRELOAD by `make check`, so the calls below reach the library through
+// the same symbols a distro-built program uses (__open_2/__openat_2 for
+// non-constant flags, the varargs open for O_CREAT, the __open* aliases other
+// libraries bind to, fopen, fopen64, freopen, stat, statfs and statvfs).
+//
+//   preload-probe hidden    library active: NVIDIA-looking files must vanish
+//   preload-probe visible   library inactive: everything must open
+//   preload-probe graphics  only the graphics category hidden (HIDE_COMPUTE=0)
+//   preload-probe compute   only the compute category hidden (HIDE_GRAPHICS=0)
+//   preload-probe reload    a SIGHUP policy reload (LIBNVIDIAHIDE_WATCH=1) must
+//                           read the allowlist even though a hide rule covers
+//                           the config dir: the library's own opens bypass it
+//   preload-probe preready  init must read a denylist whose path matches a
+//                           library rule: before init finishes only device
+//                           nodes are refused
+//   preload-probe hidedev   LIBNVIDIAHIDE_HIDE_DEV hides /dev/kfd and /dev/full
+//                           from open, stat and the /dev listing
+//   preload-probe errnos DEV FILE
+//                           with LIBNVIDIAHIDE_HIDE_DEV=/dev/full: blocked
+//                           /dev paths fail with errno DEV, other blocked
+//                           paths with FILE, through open, openat, fopen,
+//                           stat, lstat and access
+//   preload-probe unrelated with every optional block on, path-less calls
+//                           (sockets, pipes, dup*, fcntl, memfd, eventfd,
+//                           epoll, ioctl, mmap) behave as without the library
+//   preload-probe audit     LIBNVIDIAHIDE_AUDIT: a blocked open and a hidden
+//                           listing entry must each leave one JSON line
+//   preload-probe dedup     a thousand identical blocked opens log a line and
+//                           a summary, or each its own with LOG_DEDUP=0
+//   preload-probe loglevel  LIBNVIDIAHIDE_LOG_LEVEL=info logs the policy but
+//                           not a blocked open; debug logs both, error neither
+//   preload-probe dryrun    under `nvidia-hide run --dry-run`: hidden files
+//                           open and list, and the log has a would-block
+//                           line for each
+//   preload-probe dump      LIBNVIDIAHIDE_DUMP_POLICY=1 writes one line of
+//                           valid JSON with the verdict and its reasons
+//   preload-probe maps      LIBNVIDIAHIDE_FILTER_MAPS=1 leaves a mapped
+//                           NVIDIA-looking file out of /proc/<pid>/maps; =0
+//                           keeps it
+//   preload-probe metrics   libnvidiahide_metrics() and the SIGUSR1 snapshot
+//                           count blocked and allowed opens, a hidden entry
+//                           and a refused dlopen
+//   preload-probe killswitch
+//                           creating the kill-switch file mid-run makes a
+//                           hidden file open, removing it hides it again
+//   preload-probe icdstub   LIBNVIDIAHIDE_ICD_STUB=1: open, openat and fopen of
+//                           the NVIDIA Vulkan ICD manifest read back the stub
+//   preload-probe tools     fake nvidia-smi and nvidia-settings on $PATH, and a
+//                           symlink to one: execvp, execlp, posix_spawnp and
+//                           execv fail with LIBNVIDIAHIDE_BLOCK_NVIDIA_TOOLS=1
+//                           and run without it; an unrelated tool always runs
+//   preload-probe session   LIBNVIDIAHIDE_ONLY_SESSION against combinations
+//                           of XDG_SESSION_TYPE, WAYLAND_DISPLAY and DISPLAY
+//   preload-probe race      threads make their first opendir/readdir/closedir
+//                           calls at once, racing the hooks' symbol lookups
+//   preload-probe dirsignal a signal handler reads and closes directories
+//                           while a large, half-hidden one is listed
+//   preload-probe chain first|last
+//                           with tests/chain-open.so preloaded after (first)
+//                           or before (last) the library: allowed opens must
+//                           still reach that other open wrapper
+//   preload-probe cold      with tests/cold-hooks.so preloaded after the
+//                           library: readdir, getdents64 and glob from its
+//                           constructor, before the library's init, hide
+//   preload-probe atflags   run by tests/fake-gpu.sh against a fake dGPU in
+//                           /dev/dri: AT_EMPTY_PATH on a node's fd,
+//                           AT_SYMLINK_NOFOLLOW, open and the listing of a
+//                           by-id link, readlink of it into a short buffer
+//                           or not, openat
+//                           of node names relative to a /dev/dri dirfd,
+//                           openat2 RESOLVE_* flags on by-id links, and
+//                           open of a by-path link with no BDF in its name
+//   preload-probe sysdrm    same fake: /sys/class/drm loses the dGPU's nodes
+//                           and connectors, keeps the iGPU's, and getdents64
+//                           on /dev/dri the dGPU's nodes
+//   preload-probe topodlopen SO
+//                           run by tests/topo-cache.sh in the same fake, not
+//                           preloaded: dlopen()s SO and prints its own
+//                           LIBNVIDIAHIDE_TOPO and what children spawned
+//                           through the library's posix_spawn get, from
+//                           environ and from an env with a stale value
+//   preload-probe devnodes  same script, a tmpfs /dev with /dev/nvidia0 and
+//                           /dev/nvidiactl: the stat, statfs and access
+//                           families must not see them
+//   preload-probe mmapdev   same namespace, with /dev/nvidia0 kept open:
+//                           mmap and mmap64 of its fd, and of a dup, fail
+//                           with ENODEV under LIBNVIDIAHIDE_BLOCK_MMAP or
+//                           BLOCK_IOCTL and map without; /dev/zero and
+//                           anonymous maps, and a reused fd number, map
+//   preload-probe drivers   same namespace, tmpfs /sys/module and /proc/driver:
+//                           the nvidia* modules and /proc/driver/nvidia leave
+//                           the listings and don't open, snd and the rest stay
+//   preload-probe eglvendor same namespace, tmpfs over the GLVND and EGL
+//                           config dirs: 10_nvidia.json and
+//                           10_nvidia_wayland.json leave the egl_vendor.d and
+//                           egl_external_platform.d listings and don't open
+//   preload-probe nosysfs   same script, no /sys/class/drm: the string rules
+//                           still hide, unless LIBNVIDIAHIDE_REQUIRE_SYSFS=1
+//   preload-probe keep      LIBNVIDIAHIDE_KEEP names one of two NVIDIA-looking
+//                           libraries and one of two nvidia* entries: the kept
+//                           ones must open or list, their siblings must not
+//
+// The four category modes also glob the fixtures: hidden files must not match.
+// Every mode also posix_spawns, execves, execveats and vforks itself as "spawn-child",
+// which exits 0 when its own LD_PRELOAD still names the library, and asks
+// libnvidiahide_probe() whether the library is active.
+//
+// The fixtures are created with raw syscalls so the library never sees them.
+#define _GNU_SOURCE
+#include <dirent.h>
+#include <dlfcn.h>
+#include <errno.h>
+#include <fcntl.h>
+#include <glob.h>
+#include <limits.h>
+#include <pthread.h>
+#include <signal.h>
+#include <spawn.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <stdint.h>
+#include <string.h>
+#include <linux/fs.h>
+#include <linux/openat2.h>
+#include <sys/epoll.h>
+#include <sys/eventfd.h>
+#include <sys/ioctl.h>
+#include <sys/mman.h>
+#include <sys/socket.h>
+#include <sys/stat.h>
+#include <sys/statfs.h>
+#include <sys/statvfs.h>
+#include <sys/syscall.h>
+#include <sys/time.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+// Which rule category a fixture falls under; 0 for paths never hidden.
+// CORE (device and driver names) is hidden while either category is.
+enum { GRAPHICS = 1, COMPUTE = 2, CORE = GRAPHICS | COMPUTE };
+
+static int g_failed = 0;
+static int g_hidden = 0;    // categories the mode hides
+
+// cat: the category of the path. ok: what the call reported.
+static void expect(const char *what, int cat, int ok, int err) {
+    int want_ok = !(cat & g_hidden);
+    if (ok == want_ok && (ok || err == ENOENT)) {
+        printf("ok   %s\n", what);
+        return;
+    }
+    printf("FAIL %s: %s\n", what, ok ? "succeeded" : strerror(err));
+    g_failed = 1;
+}
+
+static void expect_fd(const char *what, int cat, int fd) {
+    int err = errno;
+    expect(what, cat, fd >= 0, err);
+    if (fd >= 0) close(fd);
+}
+
+static void check(const char *what, int cond) {
+    printf("%s %s\n", cond ? "ok  " : "FAIL", what);
+    if (!cond) g_failed = 1;
+}
+
+static void touch_raw(const char *path) {
+    int fd = (int)syscall(SYS_openat, AT_FDCWD, path, O_WRONLY | O_CREAT | O_TRUNC, 0644);
+    if (fd < 0) { perror(path); exit(2); }
+    close(fd);
+}
+
+static void write_raw(const char *path, const char *text) {
+    int fd = (int)syscall(SYS_openat, AT_FDCWD, path, O_WRONLY | O_CREAT | O_TRUNC, 0644);
+    if (fd < 0 || write(fd, text, strlen(text)) != (ssize_t)strlen(text)) { perror(path); exit(2); }
+    close(fd);
+}
+
+// Re-execs itself as "reload-child <dir>" with XDG_CONFIG_HOME=<dir>, whose
+// nvidia-hide/ holds an allowlist naming this program and a hide-targets rule
+// covering nvidia-hide/ itself.
+static int probe_reload(void) {
+    char dir[] = "/tmp/nvidia-hide-probe.XXXXXX";
+    if (!mkdtemp(dir)) { perror("mkdtemp"); return 2; }
+    char cfg[PATH_MAX - 32], path[PATH_MAX], rule[PATH_MAX];
+    snprintf(cfg, sizeof(cfg), "%s/nvidia-hide", dir);
+    if (syscall(SYS_mkdirat, AT_FDCWD, cfg, 0755) != 0) { perror(cfg); return 2; }
+    snprintf(path, sizeof(path), "%s/allowlist", cfg);
+    write_raw(path, "preload-probe\n");
+    snprintf(path, sizeof(path), "%s/hide-targets", cfg);
+    snprintf(rule, sizeof(rule), "prefix:%s/\n", cfg);
+    write_raw(path, rule);
+    snprintf(path, sizeof(path), "%s/libGLX_nvidia.so.0", dir);
+    touch_raw(path);
+
+    setenv("XDG_CONFIG_HOME", dir, 1);
+    setenv("LIBNVIDIAHIDE_WATCH", "1", 1);
+    unsetenv("LIBNVIDIAHIDE_ACTIVE");
+    unsetenv("LIBNVIDIAHIDE_ALLOWLIST");
+    unsetenv("LIBNVIDIAHIDE_DENYLIST");
+    unsetenv("LIBNVIDIAHIDE_REQUIRE_ENV");
+    char *args[] = { "preload-probe", "reload-child", dir, NULL };
+    execv("/proc/self/exe", args);
+    perror("execv");
+    return 2;
+}
+
+static int probe_reload_child(const char *dir) {
+    char nv[PATH_MAX], allow[PATH_MAX], rules[PATH_MAX], cfg[PATH_MAX - 32];
+    snprintf(cfg, sizeof(cfg), "%s/nvidia-hide", dir);
+    snprintf(nv, sizeof(nv), "%s/libGLX_nvidia.so.0", dir);
+    snprintf(allow, sizeof(allow), "%s/allowlist", cfg);
+    snprintf(rules, sizeof(rules), "%s/hide-targets", cfg);
+
+    g_hidden = GRAPHICS;
+    expect_fd("open while allowlisted", GRAPHICS, open(nv, O_RDONLY));
+
+    // Dropped from the allowlist: after the reload nothing may be hidden.
+    write_raw(allow, "someone-else\n");
+    raise(SIGHUP);
+    g_hidden = 0;
+    expect_fd("open after reload, allowlist under a hide rule", GRAPHICS, open(nv, O_RDONLY));
+
+    syscall(SYS_unlinkat, AT_FDCWD, nv, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, allow, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, rules, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, cfg, AT_REMOVEDIR);
+    syscall(SYS_unlinkat, AT_FDCWD, dir, AT_REMOVEDIR);
+    return g_failed;
+}
+
+static int probe_keep(void) {
+    char dir[] = "/tmp/nvidia-hide-keep.XXXXXX";
+    if (!mkdtemp(dir)) { perror("mkdtemp"); return 2; }
+    char kept[PATH_MAX], sibling[PATH_MAX], kept_ent[PATH_MAX], sibling_ent[PATH_MAX];
+    snprintf(kept, sizeof(kept), "%s/libGLX_nvidia.so.0", dir);
+    snprintf(sibling, sizeof(sibling), "%s/libGLX_nvidia.so.1", dir);
+    // Listings hide nvidia* names in any directory, as they would in /dev.
+    snprintf(kept_ent, sizeof(kept_ent), "%s/nvidia-caps", dir);
+    snprintf(sibling_ent, sizeof(sibling_ent), "%s/nvidia0", dir);
+    touch_raw(kept);
+    touch_raw(sibling);
+    touch_raw(kept_ent);
+    touch_raw(sibling_ent);
+
+    g_hidden = GRAPHICS;
+    expect_fd("open kept file", 0, open(kept, O_RDONLY));
+    expect_fd("open its sibling", GRAPHICS, open(sibling, O_RDONLY));
+    struct stat st;
+    expect("stat kept file", 0, stat(kept, &st) == 0, errno);
+
+    int saw_kept = 0, saw_sibling = 0;
+    DIR *d = opendir(dir);
+    for (struct dirent *e; d && (e = readdir(d)); ) {
+        if (!strcmp(e->d_name, "nvidia-caps")) saw_kept = 1;
+        if (!strcmp(e->d_name, "nvidia0")) saw_sibling = 1;
+    }
+    if (d) closedir(d);
+    check("readdir lists the kept entry", saw_kept);
+    check("readdir hides its sibling", !saw_sibling);
+
+    syscall(SYS_unlinkat, AT_FDCWD, kept, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, sibling, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, kept_ent, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, sibling_ent, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, dir, AT_REMOVEDIR);
+    return g_failed;
+}
+
+static int probe_spawn_child(void) {
+    const char *p = getenv("LD_PRELOAD");
+    return p && strstr(p, "libnvidia-hide.so") ? 0 : 1;
+}
+
+enum launch { LAUNCH_SPAWN, LAUNCH_EXECVE, LAUNCH_EXECVEAT, LAUNCH_VFORK };
+
+// 1 when the child saw the library in its LD_PRELOAD. The exec launches fork
+// (or vfork) first and exec in the child, so the hooks run with the parent's
+// settings.
+static int spawned_child_has_preload(enum launch how, char *const argv[], char *const envp[]) {
+    pid_t pid;
+    int status;
+    if (how == LAUNCH_SPAWN) {
+        if (posix_spawn(&pid, "/proc/self/exe", NULL, NULL, argv, envp) != 0) return -1;
+    } else if (how == LAUNCH_VFORK) {
+        pid = vfork();
+        if (pid < 0) return -1;
+        if (pid == 0) {
+            execve("/proc/self/exe", argv, envp);
+            _exit(127);
+        }
+    } else {
+        pid = fork();
+        if (pid < 0) return -1;
+        if (pid == 0) {
+            if (how == LAUNCH_EXECVE) execve("/proc/self/exe", argv, envp);
+            else execveat(AT_FDCWD, "/proc/self/exe", argv, envp, 0);
+            _exit(127);
+        }
+    }
+    if (waitpid(pid, &status, 0) != pid || !WIFEXITED(status) || WEXITSTATUS(status) > 1) return -1;
+    return WEXITSTATUS(status) == 0;
+}
+
+// spawn-stop <launch>: started with LIBNVIDIAHIDE_NO_PROPAGATE in its
+// environment, which the library reads at init. Exit bit 0: the child on the
+// list kept the preload; bit 1: the child off it lost it.
+static int probe_spawn_stop(const char *how) {
+    extern char **environ;
+    char *argv[] = { "preload-probe", "spawn-child", NULL };
+    char *argv_stop[] = { "preload-probe", "spawn-child", "--no-propagate", NULL };
+    enum launch l = (enum launch)atoi(how);
+    return (spawned_child_has_preload(l, argv_stop, environ) != 0) |
+           (spawned_child_has_preload(l, argv, environ) != 1) << 1;
+}
+
+// An environment built from scratch loses LD_PRELOAD; while active the
+// library puts itself back, unless LIBNVIDIAHIDE_NO_PROPAGATE names the child.
+// The same goes for execve, execveat and vfork+execve, with a NULL envp as
+// well.
+static void probe_spawn(void) {
+    extern char **environ;
+    static const struct { enum launch how; const char *name; } launches[] = {
+        { LAUNCH_SPAWN, "posix_spawn" }, { LAUNCH_EXECVE, "execve" }, { LAUNCH_EXECVEAT, "execveat" },
+        { LAUNCH_VFORK, "vfork+execve" },
+    };
+    char *argv[] = { "preload-probe", "spawn-child", NULL };
+    char *scratch[] = { "PATH=/usr/bin:/bin", NULL };
+    int active = g_hidden != 0;
+    char what[128];
+
+    for (size_t i = 0; i < sizeof(launches) / sizeof(launches[0]); i++) {
+        const char *name = launches[i].name;
+        snprintf(what, sizeof(what), "%s with a scratch env: child preload follows the mode", name);
+        check(what, spawned_child_has_preload(launches[i].how, argv, scratch) == active);
+        if (launches[i].how == LAUNCH_EXECVE) {
+            check("execve with a NULL env: child preload follows the mode",
+                  spawned_child_has_preload(LAUNCH_EXECVE, argv, NULL) == active);
+        }
+        // More entries than the library rewrites without allocating.
+        static char *big[2048];
+        for (int j = 0; j < 2047; j++) big[j] = "NH_FILLER=1";
+        snprintf(what, sizeof(what), "%s with a huge env: passed as it is", name);
+        check(what, spawned_child_has_preload(launches[i].how, argv, big) == 0);
+        if (!active) continue;
+        char how[16];
+        snprintf(how, sizeof(how), "%d", (int)launches[i].how);
+        char *argv_stop[] = { "preload-probe", "spawn-stop", how, NULL };
+        pid_t pid;
+        int status = -1;
+        setenv("LIBNVIDIAHIDE_NO_PROPAGATE", "cmdline:*--no-propagate*", 1);
+        if (posix_spawn(&pid, "/proc/self/exe", NULL, NULL, argv_stop, environ) != 0 ||
+            waitpid(pid, &status, 0) != pid || !WIFEXITED(status))
+            status = 0xff << 8;
+        unsetenv("LIBNVIDIAHIDE_NO_PROPAGATE");
+        snprintf(what, sizeof(what), "%s, child on NO_PROPAGATE: preload stripped", name);
+        check(what, !(WEXITSTATUS(status) & 1));
+        snprintf(what, sizeof(what), "%s, child not on NO_PROPAGATE: preload kept", name);
+        check(what, !(WEXITSTATUS(status) & 2));
+    }
+}
+
+// libnvidiahide_probe(): bit 0 says whether the library is active here, and
+// the library must be found at all for these modes to mean anything.
+static void probe_symbol(void) {
+    int (*probe)(void) = (int (*)(void))dlsym(RTLD_DEFAULT, "libnvidiahide_probe");
+    check("libnvidiahide_probe is exported", probe != NULL);
+    if (probe) check("libnvidiahide_probe: active bit follows the mode", (probe() & 1) == (g_hidden != 0));
+}
+
+// Threads released together into their first opendir/readdir/closedir, so
+// the hooks' lookups of the real functions race. Each must see an empty
+// directory; a torn or half-resolved pointer would crash or misreport.
+#define RACE_THREADS 16
+static pthread_barrier_t g_race_start;
+
+static void *race_thread(void *dir) {
+    pthread_barrier_wait(&g_race_start);
+    DIR *d = opendir(dir);
+    if (!d) return (void*)1;
+    int others = 0;
+    struct dirent *e;
+    while ((e = readdir(d)) != NULL) if (e->d_name[0] != '.') others++;
+    closedir(d);
+    return (void*)(intptr_t)(others != 0);
+}
+
+static int probe_race(void) {
+    char dir[] = "/tmp/nvidia-hide-probe.XXXXXX";
+    if (!mkdtemp(dir)) { perror("mkdtemp"); return 2; }
+    pthread_t threads[RACE_THREADS];
+    pthread_barrier_init(&g_race_start, NULL, RACE_THREADS);
+    int started = 0, bad = 0;
+    for (int i = 0; i < RACE_THREADS; i++)
+        if (pthread_create(&threads[i], NULL, race_thread, dir) == 0) started++;
+    if (started != RACE_THREADS) { fprintf(stderr, "pthread_create failed\n"); return 2; }
+    for (int i = 0; i < RACE_THREADS; i++) {
+        void *r;
+        pthread_join(threads[i], &r);
+        if (r) bad++;
+    }
+    pthread_barrier_destroy(&g_race_start);
+    check("concurrent first calls to opendir/readdir/closedir", bad == 0);
+    syscall(SYS_unlinkat, AT_FDCWD, dir, AT_REMOVEDIR);
+    return g_failed;
+}
+
+// A listing of a large directory, half of it hidden, read over and over while
+// a fast interval timer's handler reads and closes directories of its own. The
+// handler lands inside the library's readdir and closedir, so neither may wait
+// for anything the interrupted call could be holding; a hang is caught by the
+// watchdog thread. What the handler itself lists isn't checked: landing in
+// the middle of a verdict, its calls pass unjudged like the library's own.
+#define DIRSIGNAL_ENTRIES 2000
+#define DIRSIGNAL_DIRS 512
+static DIR *g_sig_dirs[DIRSIGNAL_DIRS];
+static volatile int g_sig_next = 0;
+
+static void dirsignal_handler(int sig) {
+    (void)sig;
+    int saved = errno;
+    if (g_sig_next < DIRSIGNAL_DIRS) {
+        DIR *d = g_sig_dirs[g_sig_next++];
+        while (readdir(d)) {}
+        closedir(d);    // free(): the main loop below never allocates
+    }
+    errno = saved;
+}
+
+static void *dirsignal_watchdog(void *arg) {
+    (void)arg;
+    sleep(30);
+    static const char msg[] = "FAIL readdir/closedir from a signal handler: hung\n";
+    ssize_t w = write(STDOUT_FILENO, msg, sizeof(msg) - 1);
+    (void)w;
+    _exit(1);
+}
+
+static int probe_dirsignal(void) {
+    char dir[] = "/tmp/nvidia-hide-probe.XXXXXX", small[PATH_MAX];
+    if (!mkdtemp(dir)) { perror("mkdtemp"); return 2; }
+    for (int i = 0; i < DIRSIGNAL_ENTRIES; i++) {
+        char path[PATH_MAX];
+        snprintf(path, sizeof(path), "%s/%s%d", dir, i % 2 ? "nvidia" : "plain", i);
+        touch_raw(path);
+    }
+    snprintf(small, sizeof(small), "%s/plain0", dir);
+    syscall(SYS_unlinkat, AT_FDCWD, small, 0);
+    syscall(SYS_mkdirat, AT_FDCWD, small, 0755);
+    for (int i = 0; i < DIRSIGNAL_DIRS; i++)
+        if (!(g_sig_dirs[i] = opendir(small))) { perror("opendir"); return 2; }
+    DIR *big = opendir(dir);
+    if (!big) { perror("opendir"); return 2; }
+
+    // The watchdog starts with the timer's signal blocked, so only this thread takes it.
+    sigset_t alrm, old;
+    sigemptyset(&alrm);
+    sigaddset(&alrm, SIGALRM);
+    pthread_sigmask(SIG_BLOCK, &alrm, &old);
+    pthread_t dog;
+    if (pthread_create(&dog, NULL, dirsignal_watchdog, NULL) != 0) { fprintf(stderr, "pthread_create failed\n"); return 2; }
+    pthread_sigmask(SIG_SETMASK, &old, NULL);
+    struct sigaction sa;
+    memset(&sa, 0, sizeof(sa));
+    sa.sa_handler = dirsignal_handler;
+    sa.sa_flags = SA_RESTART;
+    sigaction(SIGALRM, &sa, NULL);
+    struct itimerval it = { { 0, 50 }, { 0, 50 } };
+    setitimer(ITIMER_REAL, &it, NULL);
+
+    int passes = 0, wrong = 0;
+    while (g_sig_next < DIRSIGNAL_DIRS && passes < 100000) {
+        int plain = 0, hidden = 0;
+        rewinddir(big);
+        for (struct dirent *e; (e = readdir(big)); ) {
+            if (!strncmp(e->d_name, "plain", 5)) plain++;
+            else if (!strncmp(e->d_name, "nvidia", 6)) hidden++;
+        }
+        // plain0 is the small directory; it lists like any other entry
+        if (plain != DIRSIGNAL_ENTRIES / 2 || hidden) wrong++;
+        passes++;
+    }
+    struct itimerval off = { { 0, 0 }, { 0, 0 } };
+    setitimer(ITIMER_REAL, &off, NULL);
+    signal(SIGALRM, SIG_DFL);
+    closedir(big);
+
+    printf("     %d passes, %d directories read and closed from the handler\n", passes, g_sig_next);
+    check("listing with readdir/closedir re-entered from a signal handler", !wrong);
+    check("the handler got to every directory", g_sig_next == DIRSIGNAL_DIRS);
+    for (int i = g_sig_next; i < DIRSIGNAL_DIRS; i++) closedir(g_sig_dirs[i]);
+    syscall(SYS_unlinkat, AT_FDCWD, small, AT_REMOVEDIR);
+    for (int i = 1; i < DIRSIGNAL_ENTRIES; i++) {
+        char path[PATH_MAX];
+        snprintf(path, sizeof(path), "%s/%s%d", dir, i % 2 ? "nvidia" : "plain", i);
+        syscall(SYS_unlinkat, AT_FDCWD, path, 0);
+    }
+    syscall(SYS_unlinkat, AT_FDCWD, dir, AT_REMOVEDIR);
+    return g_failed;
+}
+
+// Run with tests/cold-hooks.so preloaded after the library: prints what its
+// constructor's listing calls saw before the library's init had run.
+static int probe_cold(void) {
+    const char *(*report)(void) = (const char *(*)(void))dlsym(RTLD_DEFAULT, "cold_hooks_report");
+    if (!report) { fprintf(stderr, "tests/cold-hooks.so is not preloaded\n"); return 2; }
+    const char *r = report();
+    fputs(r, stdout);
+    check("calls made before init was run", *r && !strstr(r, "FAIL"));
+    return g_failed;
+}
+
+// Run with tests/chain-open.so, a second preload counting the opens it sees,
+// listed after the library ("first") or before it ("last"). Allowed opens
+// must reach it either way; a hidden one only when it comes first, since
+// the library stops the call instead of forwarding it.
+static int probe_chain(const char *order) {
+    int (*seen)(void) = (int (*)(void))dlsym(RTLD_DEFAULT, "chain_open_calls");
+    if (!seen) { fprintf(stderr, "tests/chain-open.so is not preloaded\n"); return 2; }
+    int lib_first = !strcmp(order, "first");
+    g_hidden = GRAPHICS;
+
+    char dir[] = "/tmp/nvidia-hide-chain.XXXXXX";
+    if (!mkdtemp(dir)) { perror("mkdtemp"); return 2; }
+    char nv[PATH_MAX], mesa[PATH_MAX];
+    snprintf(nv, sizeof(nv), "%s/libGLX_nvidia.so.0", dir);
+    snprintf(mesa, sizeof(mesa), "%s/libGLX_mesa.so.0", dir);
+    touch_raw(nv);
+    touch_raw(mesa);
+    int dfd = (int)syscall(SYS_openat, AT_FDCWD, dir, O_RDONLY | O_DIRECTORY | O_CLOEXEC, 0);
+    if (dfd < 0) { perror(dir); return 2; }
+    volatile int rdonly = O_RDONLY;
+
+    int before = seen();
+    expect_fd("open, allowed", 0, open(mesa, O_RDONLY));
+    expect_fd("open variable flags (__open_2), allowed", 0, open(mesa, rdonly));
+    expect_fd("openat, allowed", 0, openat(dfd, mesa, O_RDONLY));
+    expect_fd("openat variable flags (__openat_2), allowed", 0, openat(dfd, mesa, rdonly));
+    check("the other preload saw every allowed open", seen() - before == 4);
+
+    before = seen();
+    expect_fd("open, hidden", GRAPHICS, open(nv, O_RDONLY));
+    check(lib_first ? "the other preload never saw the hidden open" : "the other preload saw the hidden open first",
+          seen() - before == (lib_first ? 0 : 1));
+
+    close(dfd);
+    syscall(SYS_unlinkat, AT_FDCWD, nv, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, mesa, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, dir, AT_REMOVEDIR);
+    return g_failed;
+}
+
+// Spawns "session-child <lib> <hidden>" under session variables built from
+// scratch, with LIBNVIDIAHIDE_ONLY_SESSION set; the child checks whether the
+// NVIDIA-looking library opens.
+static int probe_session(void) {
+    static const struct { const char *what, *only, *vars[3]; int hidden; } cases[] = {
+        { "XDG_SESSION_TYPE=wayland", "wayland", { "XDG_SESSION_TYPE=wayland" }, 1 },
+        { "XDG_SESSION_TYPE=x11", "wayland", { "XDG_SESSION_TYPE=x11" }, 0 },
+        { "XDG_SESSION_TYPE wins over WAYLAND_DISPLAY", "wayland",
+          { "XDG_SESSION_TYPE=x11", "WAYLAND_DISPLAY=wayland-0" }, 0 },
+        { "WAYLAND_DISPLAY and DISPLAY (Xwayland)", "wayland", { "WAYLAND_DISPLAY=wayland-0", "DISPLAY=:0" }, 1 },
+        { "DISPLAY only", "wayland", { "DISPLAY=:0" }, 0 },
+        { "no display, listed as tty", "X11,tty", { NULL }, 1 },
+        { "DISPLAY only, listed as X11", "X11,tty", { "DISPLAY=:0" }, 1 },
+        { "XDG_SESSION_TYPE=wayland, denylisted", "wayland",
+          { "XDG_SESSION_TYPE=wayland", "LIBNVIDIAHIDE_DENYLIST=preload-probe" }, 0 },
+    };
+    char dir[] = "/tmp/nvidia-hide-probe.XXXXXX";
+    if (!mkdtemp(dir)) { perror("mkdtemp"); return 2; }
+    char nv[PATH_MAX];
+    snprintf(nv, sizeof(nv), "%s/libGLX_nvidia.so.0", dir);
+    touch_raw(nv);
+
+    const char *preload_env = getenv("LD_PRELOAD");
+    char preload[PATH_MAX + 16], home[PATH_MAX + 32], only[64];
+    snprintf(preload, sizeof(preload), "LD_PRELOAD=%s", preload_env ? preload_env : "");
+    snprintf(home, sizeof(home), "XDG_CONFIG_HOME=%s", dir);
+    for (size_t i = 0; i < sizeof(cases)/sizeof(cases[0]); i++) {
+        snprintf(only, sizeof(only), "LIBNVIDIAHIDE_ONLY_SESSION=%s", cases[i].only);
+        char *envp[8] = { preload, home, only, "LIBNVIDIAHIDE_FILE_ERRNO=ENOENT" };
+        int n = 4;
+        for (int v = 0; v < 3 && cases[i].vars[v]; v++) envp[n++] = (char*)cases[i].vars[v];
+        envp[n] = NULL;
+        char *argv[] = { "preload-probe", "session-child", nv, cases[i].hidden ? "1" : "0", NULL };
+
+        pid_t pid;
+        int status;
+        int ok = posix_spawn(&pid, "/proc/self/exe", NULL, NULL, argv, envp) == 0 &&
+                 waitpid(pid, &status, 0) == pid && WIFEXITED(status) && WEXITSTATUS(status) == 0;
+        char what[128];
+        snprintf(what, sizeof(what), "ONLY_SESSION=%s, %s: %s", cases[i].only, cases[i].what,
+                 cases[i].hidden ? "hidden" : "visible");
+        check(what, ok);
+    }
+
+    syscall(SYS_unlinkat, AT_FDCWD, nv, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, dir, AT_REMOVEDIR);
+    return g_failed;
+}
+
+static int probe_session_child(const char *nv, const char *hidden) {
+    g_hidden = !strcmp(hidden, "1") ? GRAPHICS : 0;
+    int fd = open(nv, O_RDONLY);
+    int err = errno;
+    if (fd >= 0) close(fd);
+    int want_ok = !g_hidden;
+    return (fd >= 0) == want_ok && (fd >= 0 || err == ENOENT) ? 0 : 1;
+}
+
+// Re-execs itself as "preready-child <dir>" with its denylist under a
+// config home named like an NVIDIA library.
+static int probe_preready(void) {
+    char dir[] = "/tmp/nvidia-hide-probe.XXXXXX";
+    if (!mkdtemp(dir)) { perror("mkdtemp"); return 2; }
+    char home[PATH_MAX - 64], cfg[PATH_MAX - 32], path[PATH_MAX];
+    snprintf(home, sizeof(home), "%s/libGLX_nvidia.so.d", dir);
+    snprintf(cfg, sizeof(cfg), "%s/nvidia-hide", home);
+    if (syscall(SYS_mkdirat, AT_FDCWD, home, 0755) != 0 || syscall(SYS_mkdirat, AT_FDCWD, cfg, 0755) != 0) {
+        perror(cfg);
+        return 2;
+    }
+    snprintf(path, sizeof(path), "%s/denylist", cfg);
+    write_raw(path, "preload-probe\n");
+    snprintf(path, sizeof(path), "%s/libGLX_nvidia.so.0", dir);
+    touch_raw(path);
+
+    setenv("XDG_CONFIG_HOME", home, 1);
+    unsetenv("LIBNVIDIAHIDE_ACTIVE");
+    unsetenv("LIBNVIDIAHIDE_ALLOWLIST");
+    unsetenv("LIBNVIDIAHIDE_DENYLIST");
+    unsetenv("LIBNVIDIAHIDE_REQUIRE_ENV");
+    char *args[] = { "preload-probe", "preready-child", dir, NULL };
+    execv("/proc/self/exe", args);
+    perror("execv");
+    return 2;
+}
+
+static int probe_preready_child(const char *dir) {
+    char home[PATH_MAX - 64], cfg[PATH_MAX - 32], deny[PATH_MAX], nv[PATH_MAX];
+    snprintf(home, sizeof(home), "%s/libGLX_nvidia.so.d", dir);
+    snprintf(cfg, sizeof(cfg), "%s/nvidia-hide", home);
+    snprintf(deny, sizeof(deny), "%s/denylist", cfg);
+    snprintf(nv, sizeof(nv), "%s/libGLX_nvidia.so.0", dir);
+
+    // Denied, so inactive: had init's read of the denylist been refused, the
+    // library would be active and hide this.
+    g_hidden = 0;
+    expect_fd("open, denylist read during init", GRAPHICS, open(nv, O_RDONLY));
+
+    syscall(SYS_unlinkat, AT_FDCWD, nv, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, deny, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, cfg, AT_REMOVEDIR);
+    syscall(SYS_unlinkat, AT_FDCWD, home, AT_REMOVEDIR);
+    syscall(SYS_unlinkat, AT_FDCWD, dir, AT_REMOVEDIR);
+    return g_failed;
+}
+
+// Re-execs itself as "audit-child <dir>" with LIBNVIDIAHIDE_AUDIT=<dir>/audit.jsonl.
+static int probe_audit(void) {
+    char dir[] = "/tmp/nvidia-hide-probe.XXXXXX";
+    if (!mkdtemp(dir)) { perror("mkdtemp"); return 2; }
+    char audit[PATH_MAX];
+    snprintf(audit, sizeof(audit), "%s/audit.jsonl", dir);
+    setenv("LIBNVIDIAHIDE_AUDIT", audit, 1);
+    setenv("LIBNVIDIAHIDE_ACTIVE", "1", 1);
+    char *args[] = { "preload-probe", "audit-child", dir, NULL };
+    execv("/proc/self/exe", args);
+    perror("execv");
+    return 2;
+}
+
+// The line of the audit file containing needle, or NULL.
+static const char *audit_line(const char *text, const char *needle, char *out, size_t sz) {
+    const char *hit = strstr(text, needle);
+    if (!hit) return NULL;
+    const char *start = hit;
+    while (start > text && start[-1] != '\n') start--;
+    const char *end = strchr(hit, '\n');
+    if (!end || (size_t)(end - start) >= sz) return NULL;
+    memcpy(out, start, (size_t)(end - start));
+    out[end - start] = 0;
+    return out;
+}
+
+static int probe_audit_child(const char *dir) {
+    char nv[PATH_MAX], quoted[PATH_MAX], node[PATH_MAX], audit[PATH_MAX];
+    snprintf(nv, sizeof(nv), "%s/libGLX_nvidia.so.0", dir);
+    snprintf(quoted, sizeof(quoted), "%s/libGLX_nvidia.so.\"q", dir);
+    snprintf(node, sizeof(node), "%s/nvidia0", dir);
+    snprintf(audit, sizeof(audit), "%s/audit.jsonl", dir);
+    touch_raw(nv);
+    touch_raw(node);
+
+    g_hidden = GRAPHICS | COMPUTE;
+    expect_fd("open, audited", GRAPHICS, open(nv, O_RDONLY));
+    expect_fd("open of a name with a quote, audited", GRAPHICS, open(quoted, O_RDONLY));
+    DIR *d = opendir(dir);
+    while (d && readdir(d)) {}
+    if (d) closedir(d);
+
+    char text[8192] = "";
+    int fd = (int)syscall(SYS_openat, AT_FDCWD, audit, O_RDONLY, 0);
+    ssize_t n = fd >= 0 ? read(fd, text, sizeof(text) - 1) : -1;
+    if (fd >= 0) close(fd);
+    if (n > 0) text[n] = 0;
+    check("audit file ends with a newline", n > 0 && text[n - 1] == '\n');
+
+    char line[PATH_MAX + 256], want[PATH_MAX + 64];
+    long long sec;
+    long usec;
+    int pid = 0, used = 0;
+    snprintf(want, sizeof(want), "\"path\":\"%s\"", nv);
+    const char *l = audit_line(text, want, line, sizeof(line));
+    check("blocked open has a line", l != NULL);
+    if (l) {
+        check("line is {\"ts\":<s.us>,\"pid\":<pid>,...}",
+              sscanf(l, "{\"ts\":%lld.%6ld,\"pid\":%d,%n", &sec, &usec, &pid, &used) == 3 &&
+              used > 0 && pid == (int)getpid() && l[strlen(l) - 1] == '}');
+        check("line names the hook", strstr(l, ",\"event\":\"open\",") != NULL);
+        check("line has the errno", strstr(l, ",\"action\":\"ENOENT\"}") != NULL);
+    }
+    snprintf(want, sizeof(want), "\"path\":\"%s/libGLX_nvidia.so.\\\"q\"", dir);
+    check("quote in the path is escaped", strstr(text, want) != NULL);
+    snprintf(want, sizeof(want), "\"event\":\"readdir\",\"path\":\"%s\",\"action\":\"skip\"", node);
+    check("hidden listing entry has a line", strstr(text, want) != NULL);
+
+    syscall(SYS_unlinkat, AT_FDCWD, nv, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, node, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, audit, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, dir, AT_REMOVEDIR);
+    return g_failed;
+}
+
+// Started by `nvidia-hide run --dry-run`, so LIBNVIDIAHIDE_DRYRUN=1; reruns
+// itself with the log in a fresh dir.
+static int probe_dryrun(void) {
+    char dir[] = "/tmp/nvidia-hide-probe.XXXXXX";
+    if (!mkdtemp(dir)) { perror("mkdtemp"); return 2; }
+    char log[PATH_MAX];
+    snprintf(log, sizeof(log), "%s/log", dir);
+    setenv("LIBNVIDIAHIDE_LOG", log, 1);
+    char *args[] = { "preload-probe", "dryrun-child", dir, NULL };
+    execv("/proc/self/exe", args);
+    perror("execv");
+    return 2;
+}
+
+// Everything opens and lists as without the library, and each of those
+// calls leaves a would-block line. /dev/nvidia0 only where
+// tests/fake-gpu.sh's tmpfs /dev provides one.
+static int probe_dryrun_child(const char *dir) {
+    char nv[PATH_MAX], node[PATH_MAX], log[PATH_MAX], want[PATH_MAX + 64];
+    snprintf(nv, sizeof(nv), "%s/libGLX_nvidia.so.0", dir);
+    snprintf(node, sizeof(node), "%s/nvidia0", dir);
+    snprintf(log, sizeof(log), "%s/log", dir);
+    touch_raw(nv);
+    touch_raw(node);
+
+    g_hidden = 0;
+    struct stat st;
+    expect_fd("dry run: open still opens", GRAPHICS, open(nv, O_RDONLY));
+    expect("dry run: stat still succeeds", GRAPHICS, stat(nv, &st) == 0, errno);
+    int listed = 0;
+    DIR *d = opendir(dir);
+    for (struct dirent *e; d && (e = readdir(d)); ) if (!strcmp(e->d_name, "nvidia0")) listed = 1;
+    if (d) closedir(d);
+    expect("dry run: readdir still lists nvidia0", CORE, listed, ENOENT);
+    int dev = syscall(SYS_newfstatat, AT_FDCWD, "/dev/nvidia0", &st, 0) == 0;
+    if (dev) expect_fd("dry run: /dev/nvidia0 still opens", CORE, open("/dev/nvidia0", O_RDONLY));
+
+    char text[16384] = "";
+    int fd = (int)syscall(SYS_openat, AT_FDCWD, log, O_RDONLY, 0);
+    ssize_t n = fd >= 0 ? read(fd, text, sizeof(text) - 1) : -1;
+    if (fd >= 0) close(fd);
+    if (n > 0) text[n] = 0;
+    snprintf(want, sizeof(want), "[dry-run would-block] path: %s\n", nv);
+    check("log has a would-block line for the open", strstr(text, want) != NULL);
+    check("log has a would-block line for the entry", strstr(text, "[dry-run would-block] dirent: nvidia0\n") != NULL);
+    if (dev) check("log has a would-block line for /dev/nvidia0",
+                   strstr(text, "[dry-run would-block] path: /dev/nvidia0\n") != NULL);
+
+    syscall(SYS_unlinkat, AT_FDCWD, nv, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, node, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, log, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, dir, AT_REMOVEDIR);
+    return g_failed;
+}
+
+// Runs "dedup-child <file> <opens>", which opens the hidden file <opens>
+// times, with the log at level in <dir>/log; returns the log's text in out.
+static int dedup_run(const char *dir, const char *file, const char *level, const char *dedup, const char *opens,
+                     char *out, size_t sz) {
+    char log[PATH_MAX];
+    snprintf(log, sizeof(log), "%s/log", dir);
+    syscall(SYS_unlinkat, AT_FDCWD, log, 0);
+    pid_t pid = fork();
+    if (pid < 0) return -1;
+    if (pid == 0) {
+        setenv("LIBNVIDIAHIDE_LOG", log, 1);
+        setenv("LIBNVIDIAHIDE_LOG_LEVEL", level, 1);
+        setenv("LIBNVIDIAHIDE_LOG_DEDUP", dedup, 1);
+        setenv("LIBNVIDIAHIDE_ACTIVE", "1", 1);
+        char *args[] = { "preload-probe", "dedup-child", (char *)file, (char *)opens, NULL };
+        execv("/proc/self/exe", args);
+        _exit(127);
+    }
+    int status;
+    if (waitpid(pid, &status, 0) != pid || !WIFEXITED(status) || WEXITSTATUS(status) != 0) return -1;
+    int fd = (int)syscall(SYS_openat, AT_FDCWD, log, O_RDONLY, 0);
+    ssize_t n = fd >= 0 ? read(fd, out, sz - 1) : -1;
+    if (fd >= 0) close(fd);
+    out[n > 0 ? n : 0] = 0;
+    syscall(SYS_unlinkat, AT_FDCWD, log, 0);
+    return 0;
+}
+
+static int count_lines_with(const char *text, const char *needle) {
+    int n = 0;
+    for (const char *l = text; *l; ) {
+        const char *end = strchr(l, '\n');
+        size_t len = end ? (size_t)(end - l) : strlen(l);
+        const char *hit = strstr(l, needle);
+        if (hit && hit < l + len) n++;
+        l += len + (end != NULL);
+    }
+    return n;
+}
+
+// A thousand identical blocked opens must log a couple of lines, the last
+// one with the count; LIBNVIDIAHIDE_LOG_DEDUP=0 logs each of them.
+static int probe_dedup(void) {
+    char dir[] = "/tmp/nvidia-hide-probe.XXXXXX";
+    if (!mkdtemp(dir)) { perror("mkdtemp"); return 2; }
+    char nv[PATH_MAX], want[PATH_MAX + 64];
+    snprintf(nv, sizeof(nv), "%s/libGLX_nvidia.so.0", dir);
+    touch_raw(nv);
+    snprintf(want, sizeof(want), "blocked open: %s", nv);
+
+    static char text[1 << 20];
+    int lines = dedup_run(dir, nv, "debug", "1", "1000", text, sizeof(text)) == 0 ? count_lines_with(text, want) : -1;
+    check("1000 blocked opens, deduplicated: at most 2 lines", lines >= 1 && lines <= 2);
+    check("summary line counts the rest", count_lines_with(text, "(x999 in last ") == 1);
+    lines = dedup_run(dir, nv, "debug", "0", "1000", text, sizeof(text)) == 0 ? count_lines_with(text, want) : -1;
+    check("LIBNVIDIAHIDE_LOG_DEDUP=0: one line per block", lines == 1000);
+
+    syscall(SYS_unlinkat, AT_FDCWD, nv, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, dir, AT_REMOVEDIR);
+    return g_failed;
+}
+
+// LIBNVIDIAHIDE_LOG_LEVEL=info keeps the policy lines but not the blocked
+// opens, which start at debug; error keeps neither.
+static int probe_loglevel(void) {
+    char dir[] = "/tmp/nvidia-hide-probe.XXXXXX";
+    if (!mkdtemp(dir)) { perror("mkdtemp"); return 2; }
+    char nv[PATH_MAX], want[PATH_MAX + 64];
+    snprintf(nv, sizeof(nv), "%s/libGLX_nvidia.so.0", dir);
+    touch_raw(nv);
+    snprintf(want, sizeof(want), "blocked open: %s", nv);
+
+    static char text[1 << 16];
+    int ran = dedup_run(dir, nv, "info", "1", "1", text, sizeof(text)) == 0;
+    check("info: a policy line", ran && count_lines_with(text, "policy: ") > 0);
+    check("info: no blocked-open line", ran && count_lines_with(text, want) == 0);
+    ran = dedup_run(dir, nv, "debug", "1", "1", text, sizeof(text)) == 0;
+    check("debug: a policy line", ran && count_lines_with(text, "policy: ") > 0);
+    check("debug: the blocked-open line", ran && count_lines_with(text, want) == 1);
+    ran = dedup_run(dir, nv, "error", "1", "1", text, sizeof(text)) == 0;
+    check("error: neither", ran && count_lines_with(text, "policy: ") == 0 && count_lines_with(text, want) == 0);
+
+    syscall(SYS_unlinkat, AT_FDCWD, nv, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, dir, AT_REMOVEDIR);
+    return g_failed;
+}
+
+static int probe_dedup_child(const char *file, int n) {
+    for (int i = 0; i < n; i++) {
+        int fd = open(file, O_RDONLY);
+        if (fd >= 0) { close(fd); return 1; }
+    }
+    return 0;
+}
+
+// Just enough of a JSON parser to reject a malformed dump: each returns the
+// text past one value, or NULL.
+static const char *json_value(const char *p);
+
+static const char *json_ws(const char *p) {
+    while (*p == ' ' || *p == '\t' || *p == '\n' || *p == '\r') p++;
+    return p;
+}
+
+static const char *json_string(const char *p) {
+    if (*p++ != '"') return NULL;
+    for (; *p != '"'; p++) {
+        if (!*p || (unsigned char)*p < 0x20) return NULL;
+        if (*p == '\\' && !*++p) return NULL;
+    }
+    return p + 1;
+}
+
+static const char *json_list(const char *p, char close, int keyed) {
+    p = json_ws(p + 1);
+    if (*p == close) return p + 1;
+    for (;;) {
+        if (keyed) {
+            if (!(p = json_string(json_ws(p)))) return NULL;
+            p = json_ws(p);
+            if (*p++ != ':') return NULL;
+        }
+        if (!(p = json_value(p))) return NULL;
+        p = json_ws(p);
+        if (*p == close) return p + 1;
+        if (*p++ != ',') return NULL;
+    }
+}
+
+static const char *json_value(const char *p) {
+    p = json_ws(p);
+    if (*p == '{') return json_list(p, '}', 1);
+    if (*p == '[') return json_list(p, ']', 0);
+    if (*p == '"') return json_string(p);
+    static const char *const words[] = { "true", "false", "null" };
+    for (size_t i = 0; i < sizeof(words)/sizeof(words[0]); i++)
+        if (!strncmp(p, words[i], strlen(words[i]))) return p + strlen(words[i]);
+    const char *q = p + (*p == '-');
+    if (*q < '0' || *q > '9') return NULL;
+    while ((*q >= '0' && *q <= '9') || *q == '.') q++;
+    return q;
+}
+
+// Runs "dump-child" with LIBNVIDIAHIDE_DUMP_POLICY=1 and the log in <dir>/log,
+// adding `extra` (NAME=value) to its environment; out gets the log.
+static int dump_run(const char *dir, const char *extra, char *out, size_t sz) {
+    char log[PATH_MAX];
+    snprintf(log, sizeof(log), "%s/log", dir);
+    syscall(SYS_unlinkat, AT_FDCWD, log, 0);
+    pid_t pid = fork();
+    if (pid < 0) return -1;
+    if (pid == 0) {
+        setenv("LIBNVIDIAHIDE_LOG", log, 1);
+        setenv("LIBNVIDIAHIDE_LOG_LEVEL", "error", 1);
+        setenv("LIBNVIDIAHIDE_DUMP_POLICY", "1", 1);
+        setenv("XDG_CONFIG_HOME", dir, 1);
+        unsetenv("LIBNVIDIAHIDE_ACTIVE");
+        putenv((char *)extra);
+        char *args[] = { "preload-probe", "dump-child", NULL };
+        execv("/proc/self/exe", args);
+        _exit(127);
+    }
+    int status;
+    if (waitpid(pid, &status, 0) != pid || !WIFEXITED(status) || WEXITSTATUS(status) != 0) return -1;
+    int fd = (int)syscall(SYS_openat, AT_FDCWD, log, O_RDONLY, 0);
+    ssize_t n = fd >= 0 ? read(fd, out, sz - 1) : -1;
+    if (fd >= 0) close(fd);
+    out[n > 0 ? n : 0] = 0;
+    syscall(SYS_unlinkat, AT_FDCWD, log, 0);
+    return 0;
+}
+
+// LIBNVIDIAHIDE_DUMP_POLICY=1 must write exactly one line, valid JSON, that
+// reports the verdict and what decided it, at the default log level.
+static int probe_dump(void) {
+    char dir[] = "/tmp/nvidia-hide-probe.XXXXXX";
+    if (!mkdtemp(dir)) { perror("mkdtemp"); return 2; }
+    char cfg[64], keep[PATH_MAX], want[PATH_MAX + 64];
+    snprintf(cfg, sizeof(cfg), "%s/nvidia-hide", dir);
+    snprintf(keep, sizeof(keep), "%s/hide-exceptions", cfg);
+    if (syscall(SYS_mkdirat, AT_FDCWD, cfg, 0755) != 0) { perror(cfg); return 2; }
+    touch_raw(keep);
+
+    static char text[1 << 16];
+    int ok = dump_run(dir, "LIBNVIDIAHIDE_DENYLIST=preload-probe", text, sizeof(text)) == 0;
+    const char *end = ok ? json_value(text) : NULL;
+    check("dump: one JSON object", end && *end == '\n' && end[1] == 0 && text[0] == '{');
+    check("dump: basename", strstr(text, "\"basename\":\"preload-probe\"") != NULL);
+    check("dump: decided by the lists", strstr(text, "\"decided_by\":\"lists\"") != NULL);
+    check("dump: env denylist matched", strstr(text, "\"deny_match_env\":true") != NULL);
+    check("dump: inactive", strstr(text, "\"active\":false") != NULL);
+    snprintf(want, sizeof(want), "\"config_files\":[\"%s\"]", keep);
+    check("dump: lists the config file that exists", strstr(text, want) != NULL);
+
+    ok = dump_run(dir, "LIBNVIDIAHIDE_ACTIVE=1", text, sizeof(text)) == 0;
+    end = ok ? json_value(text) : NULL;
+    check("dump with ACTIVE=1: one JSON object", end && *end == '\n' && end[1] == 0 && text[0] == '{');
+    check("dump with ACTIVE=1: decided by the override", strstr(text, "\"decided_by\":\"override\"") != NULL);
+    check("dump with ACTIVE=1: lists not read", strstr(text, "\"allow_match_env\":null") != NULL);
+    check("dump with ACTIVE=1: active", strstr(text, "\"active\":true") != NULL);
+
+    syscall(SYS_unlinkat, AT_FDCWD, keep, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, cfg, AT_REMOVEDIR);
+    syscall(SYS_unlinkat, AT_FDCWD, dir, AT_REMOVEDIR);
+    return g_failed;
+}
+
+// Maps <dir>/libGLX_nvidia.so.0 and <dir>/libplain.so.0 into a child run
+// as "maps-child <dir> <filtered>" with LIBNVIDIAHIDE_FILTER_MAPS set to it.
+static int probe_maps(void) {
+    char dir[] = "/tmp/nvidia-hide-probe.XXXXXX";
+    if (!mkdtemp(dir)) { perror("mkdtemp"); return 2; }
+    char nv[PATH_MAX], plain[PATH_MAX];
+    snprintf(nv, sizeof(nv), "%s/libGLX_nvidia.so.0", dir);
+    snprintf(plain, sizeof(plain), "%s/libplain.so.0", dir);
+    write_raw(nv, "x");
+    write_raw(plain, "x");
+
+    for (int filtered = 0; filtered < 2; filtered++) {
+        pid_t pid = fork();
+        if (pid < 0) { perror("fork"); return 2; }
+        if (pid == 0) {
+            setenv("LIBNVIDIAHIDE_FILTER_MAPS", filtered ? "1" : "0", 1);
+            setenv("LIBNVIDIAHIDE_ACTIVE", "1", 1);
+            char *args[] = { "preload-probe", "maps-child", dir, filtered ? "1" : "0", NULL };
+            execv("/proc/self/exe", args);
+            _exit(127);
+        }
+        int status;
+        check(filtered ? "maps child, FILTER_MAPS=1" : "maps child, FILTER_MAPS=0",
+              waitpid(pid, &status, 0) == pid && WIFEXITED(status) && WEXITSTATUS(status) == 0);
+    }
+    syscall(SYS_unlinkat, AT_FDCWD, nv, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, plain, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, dir, AT_REMOVEDIR);
+    return g_failed;
+}
+
+static void map_raw(const char *path) {
+    int fd = (int)syscall(SYS_openat, AT_FDCWD, path, O_RDONLY | O_CLOEXEC, 0);
+    if (fd < 0 || mmap(NULL, 1, PROT_READ, MAP_PRIVATE, fd, 0) == MAP_FAILED) { perror(path); exit(2); }
+    close(fd);
+}
+
+static int probe_maps_child(const char *dir, int filtered) {
+    char nv[PATH_MAX], plain[PATH_MAX];
+    snprintf(nv, sizeof(nv), "%s/libGLX_nvidia.so.0", dir);
+    snprintf(plain, sizeof(plain), "%s/libplain.so.0", dir);
+    map_raw(nv);
+    map_raw(plain);
+
+    char self_pid[64], task[96];
+    snprintf(self_pid, sizeof(self_pid), "/proc/%d/maps", (int)getpid());
+    snprintf(task, sizeof(task), "/proc/%d/task/%d/maps", (int)getpid(), (int)getpid());
+    const char *paths[] = { "/proc/self/maps", "/proc/thread-self/maps", self_pid, task };
+    static char text[1 << 20];
+    for (size_t i = 0; i < sizeof(paths)/sizeof(paths[0]); i++) {
+        int fd = open(paths[i], O_RDONLY);
+        size_t have = 0;
+        for (ssize_t n; fd >= 0 && (n = read(fd, text + have, sizeof(text) - 1 - have)) > 0; ) have += (size_t)n;
+        if (fd >= 0) close(fd);
+        text[have] = 0;
+        char what[160];
+        snprintf(what, sizeof(what), "%s lists libplain.so.0", paths[i]);
+        check(what, strstr(text, plain) != NULL);
+        snprintf(what, sizeof(what), "%s %s libGLX_nvidia.so.0", paths[i], filtered ? "hides" : "lists");
+        check(what, (strstr(text, nv) == NULL) == filtered);
+    }
+
+    // stdio readers get the same snapshot
+    FILE *f = fopen("/proc/self/maps", "r");
+    int seen = 0;
+    char line[PATH_MAX + 128];
+    while (f && fgets(line, sizeof(line), f)) if (strstr(line, nv)) seen = 1;
+    if (f) fclose(f);
+    check(filtered ? "fopen /proc/self/maps hides libGLX_nvidia.so.0" : "fopen /proc/self/maps lists libGLX_nvidia.so.0",
+          f && seen != filtered);
+    return g_failed;
+}
+
+// Runs "metrics-child <dir> <file>" with LIBNVIDIAHIDE_METRICS naming <file>,
+// then with LIBNVIDIAHIDE_METRICS=1, which writes under $XDG_RUNTIME_DIR.
+static int probe_metrics(void) {
+    char dir[] = "/tmp/nvidia-hide-probe.XXXXXX";
+    if (!mkdtemp(dir)) { perror("mkdtemp"); return 2; }
+    char custom[PATH_MAX], dflt[PATH_MAX];
+    snprintf(custom, sizeof(custom), "%s/snapshots", dir);
+    snprintf(dflt, sizeof(dflt), "%s/nvidia-hide-metrics", dir);
+
+    for (int custom_path = 1; custom_path >= 0; custom_path--) {
+        pid_t pid = fork();
+        if (pid < 0) { perror("fork"); return 2; }
+        if (pid == 0) {
+            setenv("LIBNVIDIAHIDE_ACTIVE", "1", 1);
+            setenv("XDG_RUNTIME_DIR", dir, 1);
+            setenv("LIBNVIDIAHIDE_METRICS", custom_path ? custom : "1", 1);
+            char *args[] = { "preload-probe", "metrics-child", dir, custom_path ? custom : dflt, NULL };
+            execv("/proc/self/exe", args);
+            _exit(127);
+        }
+        int status;
+        if (waitpid(pid, &status, 0) != pid || !WIFEXITED(status) || WEXITSTATUS(status) != 0) g_failed = 1;
+    }
+
+    syscall(SYS_unlinkat, AT_FDCWD, custom, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, dflt, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, dir, AT_REMOVEDIR);
+    return g_failed;
+}
+
+// The value of "name": in a metrics line, or -1.
+static long metric(const char *line, const char *name) {
+    char key[64];
+    snprintf(key, sizeof(key), "\"%s\":", name);
+    const char *at = strstr(line, key);
+    return at ? strtol(at + strlen(key), NULL, 10) : -1;
+}
+
+static int probe_metrics_child(const char *dir, const char *file) {
+    enum { BLOCKED = 7, ALLOWED = 5 };
+    char nv[PATH_MAX], plain[PATH_MAX], sub[PATH_MAX - 32], entry[PATH_MAX];
+    snprintf(nv, sizeof(nv), "%s/libGLX_nvidia.so.0", dir);
+    snprintf(plain, sizeof(plain), "%s/libplain.so.0", dir);
+    snprintf(sub, sizeof(sub), "%s/listing", dir);
+    snprintf(entry, sizeof(entry), "%s/nvidia0", sub);
+    touch_raw(nv);
+    touch_raw(plain);
+    if (syscall(SYS_mkdirat, AT_FDCWD, sub, 0755) != 0) { perror(sub); return 2; }
+    touch_raw(entry);
+
+    int (*metrics)(char *, size_t) = (int (*)(char *, size_t))dlsym(RTLD_DEFAULT, "libnvidiahide_metrics");
+    check("libnvidiahide_metrics is exported", metrics != NULL);
+    if (!metrics) return g_failed;
+    char before[512], after[512];
+    metrics(before, sizeof(before));
+
+    for (int i = 0; i < BLOCKED; i++) {
+        int fd = open(nv, O_RDONLY);
+        if (fd >= 0) close(fd);
+    }
+    for (int i = 0; i < ALLOWED; i++) {
+        int fd = open(plain, O_RDONLY);
+        if (fd >= 0) close(fd);
+    }
+    DIR *d = opendir(sub);
+    while (d && readdir(d)) {}
+    if (d) closedir(d);
+    void *h = dlopen("libcuda.so.1", RTLD_NOW);
+    if (h) dlclose(h);
+    int len = metrics(after, sizeof(after));
+
+    check("metrics: a JSON object", len > 0 && (size_t)len < sizeof(after) && after[0] == '{' && after[len - 1] == '}');
+    // opendir checks one more path, the listed directory
+    check("metrics: every open checked", metric(after, "paths_checked") - metric(before, "paths_checked") == BLOCKED + ALLOWED + 1);
+    check("metrics: blocked opens", metric(after, "paths_blocked") - metric(before, "paths_blocked") == BLOCKED);
+    check("metrics: hidden listing entry", metric(after, "entries_hidden") - metric(before, "entries_hidden") == 1);
+    check("metrics: blocked dlopen", metric(after, "dlopens_blocked") - metric(before, "dlopens_blocked") == 1);
+    check("metrics: each path misses the cache once",
+          metric(after, "cache_misses") - metric(before, "cache_misses") == 3 &&
+          metric(after, "cache_hits") - metric(before, "cache_hits") == BLOCKED + ALLOWED - 2);
+    char small[8];
+    check("metrics: returns the full length when cut short", metrics(small, sizeof(small)) == len && strlen(small) == 7);
+
+    raise(SIGUSR1);
+    char text[1024];
+    int fd = (int)syscall(SYS_openat, AT_FDCWD, file, O_RDONLY, 0);
+    ssize_t n = fd >= 0 ? read(fd, text, sizeof(text) - 1) : -1;
+    if (fd >= 0) close(fd);
+    text[n > 0 ? n : 0] = 0;
+    check("SIGUSR1: one line in the metrics file", n > 0 && strchr(text, '\n') == text + n - 1);
+    check("SIGUSR1: this process's counters", metric(text, "pid") == (long)getpid() &&
+          metric(text, "paths_blocked") == metric(after, "paths_blocked"));
+
+    syscall(SYS_unlinkat, AT_FDCWD, entry, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, sub, AT_REMOVEDIR);
+    syscall(SYS_unlinkat, AT_FDCWD, nv, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, plain, 0);
+    return g_failed;
+}
+
+// Runs "killswitch-child <dir> <switch>" twice: once with the switch named
+// by LIBNVIDIAHIDE_KILLSWITCH, toggled by the child, and once at the default
+// $XDG_RUNTIME_DIR/nvidia-hide-disable, created before the child starts.
+static int probe_killswitch(void) {
+    char dir[] = "/tmp/nvidia-hide-probe.XXXXXX";
+    if (!mkdtemp(dir)) { perror("mkdtemp"); return 2; }
+    char nv[PATH_MAX], custom[PATH_MAX], dflt[PATH_MAX];
+    snprintf(nv, sizeof(nv), "%s/libGLX_nvidia.so.0", dir);
+    snprintf(custom, sizeof(custom), "%s/panic", dir);
+    snprintf(dflt, sizeof(dflt), "%s/nvidia-hide-disable", dir);
+    touch_raw(nv);
+    touch_raw(dflt);
+
+    for (int custom_path = 1; custom_path >= 0; custom_path--) {
+        pid_t pid = fork();
+        if (pid < 0) { perror("fork"); return 2; }
+        if (pid == 0) {
+            setenv("LIBNVIDIAHIDE_ACTIVE", "1", 1);
+            setenv("XDG_RUNTIME_DIR", dir, 1);
+            if (custom_path) setenv("LIBNVIDIAHIDE_KILLSWITCH", custom, 1);
+            else unsetenv("LIBNVIDIAHIDE_KILLSWITCH");
+            char *args[] = { "preload-probe", "killswitch-child", dir, custom_path ? custom : dflt, NULL };
+            execv("/proc/self/exe", args);
+            _exit(127);
+        }
+        int status;
+        if (waitpid(pid, &status, 0) != pid || !WIFEXITED(status) || WEXITSTATUS(status) != 0) g_failed = 1;
+    }
+
+    syscall(SYS_unlinkat, AT_FDCWD, nv, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, dflt, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, dir, AT_REMOVEDIR);
+    return g_failed;
+}
+
+// Polls open(path) for up to two seconds until it succeeds (or fails).
+static int opens_within(const char *path, int want_open) {
+    for (int i = 0; i < 200; i++) {
+        int fd = open(path, O_RDONLY);
+        if (fd >= 0) close(fd);
+        if ((fd >= 0) == want_open) return 1;
+        usleep(10000);
+    }
+    return 0;
+}
+
+static int probe_killswitch_child(const char *dir, const char *sw) {
+    char nv[PATH_MAX];
+    snprintf(nv, sizeof(nv), "%s/libGLX_nvidia.so.0", dir);
+    struct stat st;
+    if (syscall(SYS_faccessat, AT_FDCWD, sw, F_OK) == 0) {
+        check("kill switch present at start: libGLX_nvidia.so.0 opens", opens_within(nv, 1));
+        return g_failed;
+    }
+    check("no kill switch: libGLX_nvidia.so.0 hidden", opens_within(nv, 0) && stat(nv, &st) != 0);
+    touch_raw(sw);
+    check("kill switch created mid-run: libGLX_nvidia.so.0 opens", opens_within(nv, 1) && stat(nv, &st) == 0);
+    syscall(SYS_unlinkat, AT_FDCWD, sw, 0);
+    check("kill switch removed: libGLX_nvidia.so.0 hidden again", opens_within(nv, 0) && stat(nv, &st) != 0);
+    return g_failed;
+}
+
+// tests/fake-gpu.sh sets up renderD129 (the dGPU), renderD128 and
+// by-id/{dgpu,igpu}-render links to them, in a private mount namespace.
+// libc has no openat2 wrapper; the library exports one.
+static int call_openat2(int dirfd, const char *path, const struct open_how *how, size_t size) {
+    int (*fn)(int, const char*, const struct open_how*, size_t) =
+        (int (*)(int, const char*, const struct open_how*, size_t))dlsym(RTLD_DEFAULT, "openat2");
+    if (!fn) { errno = ENOSYS; return -1; }
+    return fn(dirfd, path, how, size);
+}
+
+// openat2 under dfd (dir): a hidden name is refused with ENOENT before the
+// RESOLVE_* flags come into it; an allowed one still gets them, and a how
+// the kernel rejects gets the kernel's error.
+static void probe_openat2_resolve(int dfd, const char *dir) {
+    struct open_how how = { .flags = O_RDONLY, .resolve = RESOLVE_BENEATH };
+    expect_fd("openat2 RESOLVE_BENEATH", GRAPHICS, call_openat2(dfd, "libGLX_nvidia.so.0", &how, sizeof(how)));
+    expect_fd("openat2 RESOLVE_BENEATH, allowed", 0, call_openat2(dfd, "libGLX_mesa.so.0", &how, sizeof(how)));
+    char up[PATH_MAX];
+    snprintf(up, sizeof(up), "../%s/libGLX_mesa.so.0", strrchr(dir, '/') + 1);
+    int fd = call_openat2(dfd, up, &how, sizeof(how));
+    check("openat2 RESOLVE_BENEATH, allowed but escaping: EXDEV", fd < 0 && errno == EXDEV);
+    if (fd >= 0) close(fd);
+    how.resolve = RESOLVE_IN_ROOT;
+    expect_fd("openat2 RESOLVE_IN_ROOT, allowed, .. clamped to the dirfd", 0,
+              call_openat2(dfd, "../libGLX_mesa.so.0", &how, sizeof(how)));
+    fd = call_openat2(dfd, "libGLX_mesa.so.0", NULL, sizeof(how));
+    check("openat2 with a NULL how: EFAULT", fd < 0 && errno == EFAULT);
+    if (fd >= 0) close(fd);
+    fd = call_openat2(dfd, "libGLX_mesa.so.0", &how, sizeof(how.flags));
+    check("openat2 with a short how: EINVAL", fd < 0 && errno == EINVAL);
+    if (fd >= 0) close(fd);
+}
+
+// readlink and readlinkat of /dev/dri/by-id/<name>, plain and through the
+// _FORTIFY_SOURCE __readlink*_chk entry points, into a buffer of bufsiz. A
+// hidden target fails and leaves the buffer as it was, even when bufsiz cuts
+// it short; a visible one comes back cut to bufsiz.
+static void expect_readlinks(const char *name, int cat, size_t bufsiz, const char *target) {
+    ssize_t (*volatile plain_readlink)(const char *, char *, size_t) = readlink;
+    ssize_t (*volatile plain_readlinkat)(int, const char *, char *, size_t) = readlinkat;
+    static const char *const hows[] = { "readlink", "__readlink_chk", "readlinkat", "__readlinkat_chk" };
+    char path[64], buf[64], what[128];
+    snprintf(path, sizeof(path), "/dev/dri/by-id/%s", name);
+    int byid = (int)syscall(SYS_openat, AT_FDCWD, "/dev/dri/by-id", O_RDONLY | O_DIRECTORY | O_CLOEXEC, 0);
+    if (byid < 0) { perror("/dev/dri/by-id"); exit(2); }
+    for (int how = 0; how < 4; how++) {
+        memset(buf, 'x', sizeof(buf));
+        ssize_t n = how == 0 ? plain_readlink(path, buf, bufsiz)
+                  : how == 1 ? readlink(path, buf, bufsiz)
+                  : how == 2 ? plain_readlinkat(byid, name, buf, bufsiz)
+                  : readlinkat(byid, name, buf, bufsiz);
+        int err = errno;
+        snprintf(what, sizeof(what), "%s %s, bufsiz %zu", hows[how], path, bufsiz);
+        expect(what, cat, n >= 0, err);
+        size_t want = strlen(target) < bufsiz ? strlen(target) : bufsiz;
+        int untouched = 1;
+        for (size_t i = 0; i < sizeof(buf); i++) if (buf[i] != 'x') untouched = 0;
+        snprintf(what, sizeof(what), "%s %s, bufsiz %zu: buffer", hows[how], path, bufsiz);
+        if (n < 0) check(what, untouched);
+        else check(what, (size_t)n == want && !memcmp(buf, target, want) && buf[want] == 'x');
+    }
+    close(byid);
+}
+
+static int probe_atflags(void) {
+    g_hidden = CORE;
+    struct stat st;
+    struct statx stx;
+    expect("fstatat AT_SYMLINK_NOFOLLOW, link to the dGPU", CORE,
+           fstatat(AT_FDCWD, "/dev/dri/by-id/dgpu-render", &st, AT_SYMLINK_NOFOLLOW) == 0, errno);
+    expect("fstatat AT_SYMLINK_NOFOLLOW, link to the iGPU", 0,
+           fstatat(AT_FDCWD, "/dev/dri/by-id/igpu-render", &st, AT_SYMLINK_NOFOLLOW) == 0, errno);
+    expect("lstat, link to the dGPU", CORE, lstat("/dev/dri/by-id/dgpu-render", &st) == 0, errno);
+    expect_fd("open by-id link to the dGPU's render node", CORE, open("/dev/dri/by-id/dgpu-render", O_RDONLY));
+    expect_fd("open by-id link to the iGPU's render node", 0, open("/dev/dri/by-id/igpu-render", O_RDONLY));
+    int dgpu_listed = 0, igpu_listed = 0;
+    DIR *byid = opendir("/dev/dri/by-id");
+    if (!byid) { perror("/dev/dri/by-id"); return 2; }
+    for (struct dirent *e; (e = readdir(byid)); ) {
+        if (!strcmp(e->d_name, "dgpu-render")) dgpu_listed = 1;
+        if (!strcmp(e->d_name, "igpu-render")) igpu_listed = 1;
+    }
+    closedir(byid);
+    expect("/dev/dri/by-id listing, dgpu-render", CORE, dgpu_listed, ENOENT);
+    expect("/dev/dri/by-id listing, igpu-render", 0, igpu_listed, ENOENT);
+    // "../" alone would pass: the whole target is judged
+    expect_readlinks("dgpu-render", CORE, 64, "../renderD129");
+    expect_readlinks("dgpu-render", CORE, 3, "../renderD129");
+    expect_readlinks("igpu-render", 0, 64, "../renderD128");
+    expect_readlinks("igpu-render", 0, 5, "../renderD128");
+
+    int dgpu = (int)syscall(SYS_openat, AT_FDCWD, "/dev/dri/renderD129", O_PATH | O_CLOEXEC, 0);
+    int igpu = (int)syscall(SYS_openat, AT_FDCWD, "/dev/dri/renderD128", O_PATH | O_CLOEXEC, 0);
+    if (dgpu < 0 || igpu < 0) { perror("/dev/dri"); return 2; }
+    expect("fstatat AT_EMPTY_PATH, dGPU node fd", CORE, fstatat(dgpu, "", &st, AT_EMPTY_PATH) == 0, errno);
+    expect("statx AT_EMPTY_PATH, dGPU node fd", CORE, statx(dgpu, "", AT_EMPTY_PATH, STATX_BASIC_STATS, &stx) == 0, errno);
+    expect("fstatat AT_EMPTY_PATH, iGPU node fd", 0, fstatat(igpu, "", &st, AT_EMPTY_PATH) == 0, errno);
+    close(dgpu);
+    close(igpu);
+
+    // Relative names under a /dev/dri dirfd are judged as the full path
+    volatile int rdonly = O_RDONLY;
+    int dri = (int)syscall(SYS_openat, AT_FDCWD, "/dev/dri", O_RDONLY | O_DIRECTORY | O_CLOEXEC, 0);
+    if (dri < 0) { perror("/dev/dri"); return 2; }
+    expect_fd("openat /dev/dri + renderD129", CORE, openat(dri, "renderD129", O_RDONLY));
+    expect_fd("openat /dev/dri + renderD129 (__openat_2)", CORE, openat(dri, "renderD129", rdonly));
+    expect_fd("openat /dev/dri + renderD128", 0, openat(dri, "renderD128", O_RDONLY));
+    struct open_how how = { .flags = O_RDONLY };
+    expect_fd("openat2 /dev/dri + card1", CORE, call_openat2(dri, "card1", &how, sizeof(how)));
+    expect_fd("openat2 /dev/dri + card0", 0, call_openat2(dri, "card0", &how, sizeof(how)));
+    // by-id links lead out of by-id: RESOLVE_BENEATH refuses the allowed one
+    // with EXDEV, the hidden one with ENOENT first
+    int links = (int)syscall(SYS_openat, AT_FDCWD, "/dev/dri/by-id", O_RDONLY | O_DIRECTORY | O_CLOEXEC, 0);
+    if (links < 0) { perror("/dev/dri/by-id"); return 2; }
+    how.resolve = RESOLVE_BENEATH;
+    expect_fd("openat2 RESOLVE_BENEATH by-id + dgpu-render", CORE, call_openat2(links, "dgpu-render", &how, sizeof(how)));
+    int fd = call_openat2(links, "igpu-render", &how, sizeof(how));
+    check("openat2 RESOLVE_BENEATH by-id + igpu-render: EXDEV", fd < 0 && errno == EXDEV);
+    if (fd >= 0) close(fd);
+    how.resolve = RESOLVE_NO_SYMLINKS;
+    fd = call_openat2(links, "igpu-render", &how, sizeof(how));
+    check("openat2 RESOLVE_NO_SYMLINKS by-id + igpu-render: ELOOP", fd < 0 && errno == ELOOP);
+    if (fd >= 0) close(fd);
+    close(links);
+    close(dri);
+
+    // A by-path link whose name has no BDF (a platform bus) is judged by
+    // where it leads
+    expect_fd("open by-path link to the dGPU's card, no BDF in the name", CORE,
+              open("/dev/dri/by-path/platform-gpu0-card", O_RDONLY));
+    expect_fd("open by-path link to the iGPU's card", 0, open("/dev/dri/by-path/platform-gpu1-card", O_RDONLY));
+    expect("stat by-path link to the dGPU's card", CORE, stat("/dev/dri/by-path/platform-gpu0-card", &st) == 0, errno);
+
+    // "" with AT_FDCWD is the cwd
+    if (chdir("/dev/dri") != 0) { perror("chdir"); return 2; }
+    expect("fstatat AT_EMPTY_PATH, cwd", 0, fstatat(AT_FDCWD, "", &st, AT_EMPTY_PATH) == 0, errno);
+    return g_failed;
+}
+
+// tests/fake-gpu.sh also gives /sys/class/drm card1 and renderD129 (the
+// dGPU), card0 and renderD128, connectors card1-DP-1 and card0-eDP-1.
+static int probe_sysdrm(void) {
+    static const struct { const char *name; int cat; } entries[] = {
+        { "card1", CORE }, { "renderD129", CORE }, { "card1-DP-1", CORE },
+        { "card0", 0 }, { "renderD128", 0 }, { "card0-eDP-1", 0 }, { "version", 0 },
+    };
+    g_hidden = CORE;
+    int seen[sizeof(entries)/sizeof(entries[0])] = {0};
+    DIR *d = opendir("/sys/class/drm");
+    if (!d) { perror("/sys/class/drm"); return 2; }
+    for (struct dirent *e; (e = readdir(d)); )
+        for (size_t i = 0; i < sizeof(entries)/sizeof(entries[0]); i++)
+            if (!strcmp(e->d_name, entries[i].name)) seen[i] = 1;
+    closedir(d);
+
+    for (size_t i = 0; i < sizeof(entries)/sizeof(entries[0]); i++) {
+        char what[96], path[PATH_MAX];
+        struct stat st;
+        snprintf(what, sizeof(what), "/sys/class/drm listing, %s", entries[i].name);
+        expect(what, entries[i].cat, seen[i], ENOENT);
+        snprintf(what, sizeof(what), "stat /sys/class/drm/%s", entries[i].name);
+        snprintf(path, sizeof(path), "/sys/class/drm/%s", entries[i].name);
+        expect(what, entries[i].cat, stat(path, &st) == 0, errno);
+    }
+
+    // getdents64 as static and Go-style readers call it, past readdir
+    static const struct { const char *name; int cat; } nodes[] = {
+        { "card1", CORE }, { "renderD129", CORE }, { "card0", 0 }, { "renderD128", 0 },
+    };
+    int seen_node[sizeof(nodes)/sizeof(nodes[0])] = {0};
+    int fd = (int)syscall(SYS_openat, AT_FDCWD, "/dev/dri", O_RDONLY | O_DIRECTORY | O_CLOEXEC, 0);
+    if (fd < 0) { perror("/dev/dri"); return 2; }
+    char buf[4096];
+    for (ssize_t n; (n = getdents64(fd, buf, sizeof(buf))) > 0; ) {
+        for (ssize_t off = 0; off < n; ) {
+            struct dirent64 *e = (struct dirent64 *)(buf + off);
+            for (size_t i = 0; i < sizeof(nodes)/sizeof(nodes[0]); i++)
+                if (!strcmp(e->d_name, nodes[i].name)) seen_node[i] = 1;
+            off += e->d_reclen;
+        }
+    }
+    close(fd);
+    for (size_t i = 0; i < sizeof(nodes)/sizeof(nodes[0]); i++) {
+        char what[64];
+        snprintf(what, sizeof(what), "getdents64 /dev/dri, %s", nodes[i].name);
+        expect(what, nodes[i].cat, seen_node[i], ENOENT);
+    }
+    return g_failed;
+}
+
+// tests/fake-gpu.sh's second namespace: a tmpfs /dev holding /dev/nvidia0
+// and /dev/nvidiactl as plain files next to a real /dev/null, so a call that
+// reaches the kernel finds them.
+// statfs and statvfs, which glibc implements apart, in both widths.
+static void probe_statfs(const char *path, int cat) {
+    struct statfs sfs;
+    struct statfs64 sfs64;
+    struct statvfs svfs;
+    struct statvfs64 svfs64;
+    char what[PATH_MAX + 32];
+    snprintf(what, sizeof(what), "statfs %s", path);
+    expect(what, cat, statfs(path, &sfs) == 0, errno);
+    snprintf(what, sizeof(what), "statfs64 %s", path);
+    expect(what, cat, statfs64(path, &sfs64) == 0, errno);
+    snprintf(what, sizeof(what), "statvfs %s", path);
+    expect(what, cat, statvfs(path, &svfs) == 0, errno);
+    snprintf(what, sizeof(what), "statvfs64 %s", path);
+    expect(what, cat, statvfs64(path, &svfs64) == 0, errno);
+}
+
+static int probe_devnodes(void) {
+    g_hidden = CORE;
+    struct stat st;
+    check("/dev/nvidia0 exists behind the library",
+          syscall(SYS_newfstatat, AT_FDCWD, "/dev/nvidia0", &st, 0) == 0);
+    expect("stat /dev/nvidia0", CORE, stat("/dev/nvidia0", &st) == 0, errno);
+    expect("lstat /dev/nvidia0", CORE, lstat("/dev/nvidia0", &st) == 0, errno);
+    expect("fstatat /dev/nvidia0", CORE, fstatat(AT_FDCWD, "/dev/nvidia0", &st, 0) == 0, errno);
+    int dev = open("/dev", O_RDONLY | O_DIRECTORY);
+    expect("fstatat /dev + nvidiactl", CORE, fstatat(dev, "nvidiactl", &st, 0) == 0, errno);
+    close(dev);
+    struct statx stx;
+    expect("statx /dev/nvidia0", CORE, statx(AT_FDCWD, "/dev/nvidia0", 0, STATX_BASIC_STATS, &stx) == 0, errno);
+    struct stat64 st64;
+    expect("stat64 /dev/nvidia0", CORE, stat64("/dev/nvidia0", &st64) == 0, errno);
+    expect("lstat64 /dev/nvidia0", CORE, lstat64("/dev/nvidia0", &st64) == 0, errno);
+    // The pre-2.33 glibc ABI; only older libcs export these by default.
+    int (*xstat)(int, const char*, struct stat*) = (int (*)(int, const char*, struct stat*))dlsym(RTLD_DEFAULT, "__xstat");
+    if (xstat) expect("__xstat /dev/nvidia0", CORE, xstat(1, "/dev/nvidia0", &st) == 0, errno);
+    else printf("skip __xstat (not exported)\n");
+    expect("stat /dev/null", 0, stat("/dev/null", &st) == 0, errno);
+    probe_statfs("/dev/nvidia0", CORE);
+    probe_statfs("/dev/null", 0);
+
+    expect("access /dev/nvidia0", CORE, access("/dev/nvidia0", F_OK) == 0, errno);
+    expect("access /dev/null", 0, access("/dev/null", F_OK) == 0, errno);
+    expect("faccessat AT_EACCESS /dev/nvidiactl", CORE,
+           faccessat(AT_FDCWD, "/dev/nvidiactl", R_OK | W_OK, AT_EACCESS) == 0, errno);
+    expect("faccessat AT_EACCESS /dev/null", 0, faccessat(AT_FDCWD, "/dev/null", W_OK, AT_EACCESS) == 0, errno);
+    dev = open("/dev", O_RDONLY | O_DIRECTORY);
+    expect("faccessat /dev + nvidia0", CORE, faccessat(dev, "nvidia0", F_OK, 0) == 0, errno);
+    close(dev);
+    int (*faccessat2_fn)(int, const char*, int, int) =
+        (int (*)(int, const char*, int, int))dlsym(RTLD_DEFAULT, "faccessat2");
+    int nv = (int)syscall(SYS_openat, AT_FDCWD, "/dev/nvidia0", O_PATH | O_CLOEXEC, 0);
+    int null = (int)syscall(SYS_openat, AT_FDCWD, "/dev/null", O_PATH | O_CLOEXEC, 0);
+    if (faccessat2_fn && nv >= 0 && null >= 0) {
+        expect("faccessat2 /dev/nvidia0", CORE, faccessat2_fn(AT_FDCWD, "/dev/nvidia0", F_OK, 0) == 0, errno);
+        expect("faccessat2 AT_EMPTY_PATH, /dev/nvidia0 fd", CORE, faccessat2_fn(nv, "", F_OK, AT_EMPTY_PATH) == 0, errno);
+        // Forwarded with its flags: the same answer as the raw call
+        int raw = (int)syscall(SYS_faccessat2, null, "", W_OK, AT_EMPTY_PATH | AT_EACCESS);
+        int got = faccessat2_fn(null, "", W_OK, AT_EMPTY_PATH | AT_EACCESS);
+        check("faccessat2 AT_EMPTY_PATH|AT_EACCESS, /dev/null fd: as without the library", got == raw);
+    } else {
+        printf("skip faccessat2 (no symbol or fds)\n");
+    }
+    if (nv >= 0) close(nv);
+    if (null >= 0) close(null);
+    return g_failed;
+}
+
+// Whether mmap (or mmap64) of fd maps; *err is why not.
+static int maps_fd(int fd, int wide, int *err) {
+    void *m = wide ? mmap64(NULL, 4096, PROT_READ, MAP_SHARED, fd, 0) : mmap(NULL, 4096, PROT_READ, MAP_SHARED, fd, 0);
+    *err = errno;
+    if (m == MAP_FAILED) return 0;
+    munmap(m, 4096);
+    return 1;
+}
+
+static void expect_maps(const char *what, int want, int fd, int wide) {
+    int err;
+    int got = maps_fd(fd, wide, &err);
+    int ok = got == want && (got || err == ENODEV);
+    printf("%s %s (%s)\n", ok ? "ok  " : "FAIL", what, got ? "mapped" : strerror(err));
+    if (!ok) g_failed = 1;
+}
+
+// Same namespace, run with LIBNVIDIAHIDE_KEEP=/dev/nvidia0 so the node opens
+// while its fd is still tracked as an NVIDIA device.
+static int probe_mmapdev(void) {
+    const char *mm = getenv("LIBNVIDIAHIDE_BLOCK_MMAP"), *io = getenv("LIBNVIDIAHIDE_BLOCK_IOCTL");
+    int blocked = (mm && strcmp(mm, "0")) || (io && strcmp(io, "0"));
+    int nv = open("/dev/nvidia0", O_RDONLY | O_CLOEXEC);
+    int zero = open("/dev/zero", O_RDONLY | O_CLOEXEC);
+    if (nv < 0 || zero < 0) { perror("open /dev/nvidia0 or /dev/zero"); return 2; }
+    expect_maps("mmap the /dev/nvidia0 fd", !blocked, nv, 0);
+    expect_maps("mmap64 the /dev/nvidia0 fd", !blocked, nv, 1);
+    int copy = dup(nv);
+    expect_maps("mmap a dup of it", !blocked, copy, 0);
+    expect_maps("mmap /dev/zero", 1, zero, 0);
+    expect_maps("mmap64 /dev/zero", 1, zero, 1);
+    void *anon = mmap(NULL, 4096, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+    check("mmap anonymous", anon != MAP_FAILED);
+    if (anon != MAP_FAILED) munmap(anon, 4096);
+
+    // Its number, reused by /dev/zero once closed, is no longer tracked.
+    close(nv);
+    close(copy);
+    int again = dup2(zero, nv);
+    check("dup2 /dev/zero onto the closed fd number", again == nv);
+    expect_maps("mmap /dev/zero under the old number", 1, nv, 0);
+    close(again);
+    close(zero);
+    return g_failed;
+}
+
+typedef int (*posix_spawn_f)(pid_t *, const char *, const posix_spawn_file_actions_t *,
+                             const posix_spawnattr_t *, char *const [], char *const []);
+
+// What LIBNVIDIAHIDE_TOPO a child started through spawn with envp sees.
+static void print_child_topo(const char *what, posix_spawn_f spawn, char *const envp[]) {
+    char *argv[] = { "sh", "-c", "printf %s \"${LIBNVIDIAHIDE_TOPO-(unset)}\"", NULL };
+    char out[4096] = "";
+    int p[2];
+    if (pipe(p) != 0) { perror("pipe"); exit(2); }
+    posix_spawn_file_actions_t fa;
+    posix_spawn_file_actions_init(&fa);
+    posix_spawn_file_actions_adddup2(&fa, p[1], 1);
+    pid_t pid;
+    int status;
+    if (spawn(&pid, "/bin/sh", &fa, NULL, argv, envp) != 0) { perror("posix_spawn"); exit(2); }
+    posix_spawn_file_actions_destroy(&fa);
+    close(p[1]);
+    ssize_t n, len = 0;
+    while ((n = read(p[0], out + len, sizeof(out) - 1 - (size_t)len)) > 0) len += n;
+    out[len] = 0;
+    close(p[0]);
+    waitpid(pid, &status, 0);
+    printf("%s %s\n", what, out);
+}
+
+// Loaded with dlopen() into a program that may already run threads, the
+// library must leave environ alone and hand its topology to children only
+// through the environment it builds for them.
+static int probe_topodlopen(const char *so) {
+    extern char **environ;
+    void *h = dlopen(so, RTLD_NOW | RTLD_LOCAL);
+    posix_spawn_f spawn = h ? (posix_spawn_f)dlsym(h, "posix_spawn") : NULL;
+    if (!spawn) { fprintf(stderr, "dlopen %s: %s\n", so, dlerror()); return 2; }
+    const char *own = getenv("LIBNVIDIAHIDE_TOPO");
+    printf("environ %s\n", own ? own : "(unset)");
+    print_child_topo("child", spawn, environ);
+
+    static char *stale[512];
+    int n = 0;
+    for (char **e = environ; *e && n < 510; e++) stale[n++] = *e;
+    stale[n++] = "LIBNVIDIAHIDE_TOPO=v1 key=00000000 nodes=card7 bdfs=0000:07:00.0";
+    stale[n] = NULL;
+    print_child_topo("stale-child", spawn, stale);
+    return 0;
+}
+
+// The same namespace mounts tmpfs over /sys/module and /proc/driver and fills
+// them with the driver's entries next to ones that must stay.
+static int probe_drivers(void) {
+    static const struct { const char *dir, *name; int cat; } entries[] = {
+        { "/sys/module", "nvidia", CORE }, { "/sys/module", "nvidia_uvm", CORE },
+        { "/sys/module", "nvidia_drm", CORE }, { "/sys/module", "nvidia_modeset", CORE },
+        { "/sys/module", "snd", 0 }, { "/sys/module", "i915", 0 },
+        { "/proc/driver", "nvidia", CORE }, { "/proc/driver", "rtc", 0 },
+    };
+    enum { N = sizeof(entries)/sizeof(entries[0]) };
+    g_hidden = CORE;
+    int seen[N] = {0};
+    static const char *const dirs[] = { "/sys/module", "/proc/driver" };
+    for (size_t k = 0; k < 2; k++) {
+        DIR *d = opendir(dirs[k]);
+        if (!d) { perror(dirs[k]); return 2; }
+        for (struct dirent *e; (e = readdir(d)); )
+            for (size_t i = 0; i < N; i++)
+                if (!strcmp(entries[i].dir, dirs[k]) && !strcmp(e->d_name, entries[i].name)) seen[i] = 1;
+        closedir(d);
+    }
+    for (size_t i = 0; i < N; i++) {
+        char what[96], path[64];
+        snprintf(what, sizeof(what), "%s listing, %s", entries[i].dir, entries[i].name);
+        expect(what, entries[i].cat, seen[i], ENOENT);
+        snprintf(path, sizeof(path), "%s/%s/version", entries[i].dir, entries[i].name);
+        snprintf(what, sizeof(what), "open %s", path);
+        expect_fd(what, entries[i].cat, open(path, O_RDONLY));
+    }
+    return g_failed;
+}
+
+// Run by tests/fake-gpu.sh, which puts 10_nvidia.json, 10_nvidia_wayland.json
+// and 50_mesa.json in each loader selection dir whose parent exists (tmpfs
+// over it): the NVIDIA ones neither open nor list, Mesa's does both.
+static int probe_eglvendor(void) {
+    static const char *const dirs[] = {
+        "/usr/share/glvnd/egl_vendor.d", "/etc/glvnd/egl_vendor.d",
+        "/usr/share/egl/egl_external_platform.d", "/etc/egl/egl_external_platform.d",
+    };
+    static const struct { const char *name; int cat; } files[] = {
+        { "10_nvidia.json", GRAPHICS }, { "10_nvidia_wayland.json", GRAPHICS }, { "50_mesa.json", 0 },
+    };
+    enum { N = sizeof(files)/sizeof(files[0]) };
+    g_hidden = GRAPHICS;
+    int tested = 0;
+    for (size_t k = 0; k < sizeof(dirs)/sizeof(dirs[0]); k++) {
+        struct stat st;
+        if (syscall(SYS_newfstatat, AT_FDCWD, dirs[k], &st, 0) != 0) continue;
+        tested++;
+        int seen[N] = {0};
+        DIR *d = opendir(dirs[k]);
+        if (!d) { perror(dirs[k]); return 2; }
+        for (struct dirent *e; (e = readdir(d)); )
+            for (size_t i = 0; i < N; i++) if (!strcmp(e->d_name, files[i].name)) seen[i] = 1;
+        closedir(d);
+        for (size_t i = 0; i < N; i++) {
+            char what[128], path[96];
+            snprintf(what, sizeof(what), "%s listing, %s", dirs[k], files[i].name);
+            expect(what, files[i].cat, seen[i], ENOENT);
+            snprintf(path, sizeof(path), "%s/%s", dirs[k], files[i].name);
+            snprintf(what, sizeof(what), "open %s", path);
+            expect_fd(what, files[i].cat, open(path, O_RDONLY));
+            FILE *f = fopen(path, "re");
+            snprintf(what, sizeof(what), "fopen %s", path);
+            expect(what, files[i].cat, f != NULL, errno);
+            if (f) fclose(f);
+        }
+    }
+    check("at least one loader selection dir to test", tested > 0);
+    return g_failed;
+}
+
+// Runs name, found on $PATH, with exec variant how (0 execvp, 1 execlp, 2
+// posix_spawnp, 3 execv of path). Whether it ran; *err is why not.
+static int tool_ran(int how, const char *name, const char *path, int *err) {
+    extern char **environ;
+    char *argv[] = { (char *)name, "--query-gpu=name", NULL };
+    pid_t pid;
+    int status;
+    *err = 0;
+    if (how == 2) {
+        int rc = posix_spawnp(&pid, name, NULL, NULL, argv, environ);
+        if (rc != 0) { *err = rc; return 0; }
+    } else if ((pid = fork()) == 0) {
+        if (how == 0) execvp(name, argv);
+        else if (how == 1) execlp(name, name, "--query-gpu=name", (char *)NULL);
+        else execv(path, argv);
+        _exit(errno == ENOENT ? 100 : 101);
+    }
+    if (pid < 0 || waitpid(pid, &status, 0) != pid || !WIFEXITED(status)) { *err = EIO; return 0; }
+    if (WEXITSTATUS(status) == 0) return 1;
+    *err = WEXITSTATUS(status) == 100 ? ENOENT : EIO;
+    return 0;
+}
+
+// Fake nvidia-smi and nvidia-settings scripts, a symlink to one under an
+// innocent name, and an unrelated tool, all on $PATH: with
+// LIBNVIDIAHIDE_BLOCK_NVIDIA_TOOLS=1 the vendor's never run, however they
+// are exec'd; without it, all of them do.
+static int probe_tools(void) {
+    const char *block = getenv("LIBNVIDIAHIDE_BLOCK_NVIDIA_TOOLS");
+    g_hidden = block && !strcmp(block, "1") ? CORE : 0;
+    char dir[] = "/tmp/nvidia-hide-probe.XXXXXX";
+    if (!mkdtemp(dir)) { perror("mkdtemp"); return 2; }
+    static const struct { const char *name; int cat; } tools[] = {
+        { "nvidia-smi", CORE }, { "nvidia-settings", CORE }, { "gpu-info", CORE }, { "plain-tool", 0 },
+    };
+    static const char *const hows[] = { "execvp", "execlp", "posix_spawnp", "execv" };
+    char path[PATH_MAX], what[PATH_MAX + 64];
+    for (size_t i = 0; i < sizeof(tools)/sizeof(tools[0]); i++) {
+        snprintf(path, sizeof(path), "%s/%s", dir, tools[i].name);
+        if (!strcmp(tools[i].name, "gpu-info")) {
+            if (symlink("nvidia-smi", path) != 0) { perror(path); return 2; }
+            continue;
+        }
+        write_raw(path, "#!/bin/sh\nexit 0\n");
+        if (chmod(path, 0755) != 0) { perror(path); return 2; }
+    }
+    char search[PATH_MAX + 16];
+    const char *old = getenv("PATH");
+    snprintf(search, sizeof(search), "%s:%s", dir, old ? old : "/usr/bin:/bin");
+    setenv("PATH", search, 1);
+
+    for (size_t i = 0; i < sizeof(tools)/sizeof(tools[0]); i++) {
+        snprintf(path, sizeof(path), "%s/%s", dir, tools[i].name);
+        for (int how = 0; how < 4; how++) {
+            int err;
+            int ran = tool_ran(how, tools[i].name, path, &err);
+            snprintf(what, sizeof(what), "%s %s", hows[how], how == 3 ? path : tools[i].name);
+            expect(what, tools[i].cat, ran, err);
+        }
+    }
+    for (size_t i = 0; i < sizeof(tools)/sizeof(tools[0]); i++) {
+        snprintf(path, sizeof(path), "%s/%s", dir, tools[i].name);
+        syscall(SYS_unlinkat, AT_FDCWD, path, 0);
+    }
+    syscall(SYS_unlinkat, AT_FDCWD, dir, AT_REMOVEDIR);
+    return g_failed;
+}
+
+// The manifest need not exist: the stub stands in for it either way.
+static int probe_icdstub(void) {
+    static const char icd[] = "/usr/share/vulkan/icd.d/nvidia_icd.json";
+    static const char want[] =
+        "{\"file_format_version\":\"1.0.0\",\"ICD\":{\"library_path\":\"/nonexistent\",\"api_version\":\"1.0.0\"}}\n";
+    char buf[256];
+    for (int how = 0; how < 3; how++) {
+        static const char *const names[] = { "open", "openat", "fopen" };
+        char what[64];
+        ssize_t n = -1;
+        if (how < 2) {
+            int fd = how ? openat(AT_FDCWD, icd, O_RDONLY | O_CLOEXEC) : open(icd, O_RDONLY);
+            if (fd >= 0) { n = read(fd, buf, sizeof(buf)); close(fd); }
+        } else {
+            FILE *f = fopen(icd, "re");
+            if (f) { n = (ssize_t)fread(buf, 1, sizeof(buf), f); fclose(f); }
+        }
+        snprintf(what, sizeof(what), "%s of the NVIDIA ICD reads the stub", names[how]);
+        check(what, n == (ssize_t)(sizeof(want) - 1) && !memcmp(buf, want, sizeof(want) - 1));
+    }
+    return g_failed;
+}
+
+static int probe_nosysfs(void) {
+    const char *req = getenv("LIBNVIDIAHIDE_REQUIRE_SYSFS");
+    g_hidden = req && strcmp(req, "0") != 0 ? 0 : GRAPHICS;
+
+    char dir[] = "/tmp/nvidia-hide-probe.XXXXXX";
+    if (!mkdtemp(dir)) { perror("mkdtemp"); return 2; }
+    char nv[PATH_MAX];
+    snprintf(nv, sizeof(nv), "%s/libGLX_nvidia.so.0", dir);
+    touch_raw(nv);
+    expect_fd(g_hidden ? "no DRM subsystem, string rules apply" : "no DRM subsystem, REQUIRE_SYSFS: inactive",
+              GRAPHICS, open(nv, O_RDONLY));
+    syscall(SYS_unlinkat, AT_FDCWD, nv, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, dir, AT_REMOVEDIR);
+    return g_failed;
+}
+
+// Run with LIBNVIDIAHIDE_HIDE_DEV=/dev/kfd:/dev/full and both categories off.
+// /dev/full exists everywhere, so refusing it shows the rule at work; /dev/kfd
+// usually doesn't, so its refusal has to show up in the blocked count.
+static int probe_hidedev(void) {
+    int (*metrics)(char *, size_t) = (int (*)(char *, size_t))dlsym(RTLD_DEFAULT, "libnvidiahide_metrics");
+    char before[512], after[512];
+    if (metrics) metrics(before, sizeof(before));
+    int fd = open("/dev/kfd", O_RDWR);
+    int err = errno;
+    if (fd >= 0) close(fd);
+    if (metrics) metrics(after, sizeof(after));
+    check("open /dev/kfd refused by the library",
+          fd < 0 && err == ENOENT && metrics &&
+          metric(after, "paths_blocked") == metric(before, "paths_blocked") + 1);
+
+    fd = open("/dev/full", O_WRONLY);
+    err = errno;
+    if (fd >= 0) close(fd);
+    check("open /dev/full fails with ENOENT", fd < 0 && err == ENOENT);
+    struct stat st;
+    check("stat /dev/full fails", stat("/dev/full", &st) != 0);
+    fd = open("/dev/zero", O_RDONLY);
+    check("open /dev/zero", fd >= 0);
+    if (fd >= 0) close(fd);
+
+    int seen_full = 0, seen_kfd = 0, seen_zero = 0;
+    DIR *d = opendir("/dev");
+    for (struct dirent *e; d && (e = readdir(d)); ) {
+        if (!strcmp(e->d_name, "full")) seen_full = 1;
+        if (!strcmp(e->d_name, "kfd")) seen_kfd = 1;
+        if (!strcmp(e->d_name, "zero")) seen_zero = 1;
+    }
+    if (d) closedir(d);
+    check("/dev listing hides full and kfd", !seen_full && !seen_kfd);
+    check("/dev listing keeps zero", seen_zero);
+    return g_failed;
+}
+
+// An errno by name, or 0.
+static int errno_named(const char *name) {
+    static const struct { const char *name; int err; } names[] = {
+        { "ENOENT", ENOENT }, { "EACCES", EACCES }, { "EPERM", EPERM },
+        { "ENODEV", ENODEV }, { "ENXIO", ENXIO }, { "EIO", EIO },
+    };
+    for (size_t i = 0; i < sizeof(names)/sizeof(names[0]); i++)
+        if (!strcmp(name, names[i].name)) return names[i].err;
+    return 0;
+}
+
+// Every hook refusing path fails with want.
+static void expect_errno(const char *path, int want) {
+    char what[PATH_MAX + 64];
+    struct stat st;
+    int fd = open(path, O_RDONLY);
+    int err = errno;
+    if (fd >= 0) close(fd);
+    snprintf(what, sizeof(what), "open %s: %s", path, strerror(want));
+    check(what, fd < 0 && err == want);
+    fd = openat(AT_FDCWD, path, O_RDONLY);
+    err = errno;
+    if (fd >= 0) close(fd);
+    snprintf(what, sizeof(what), "openat %s: %s", path, strerror(want));
+    check(what, fd < 0 && err == want);
+    FILE *f = fopen(path, "r");
+    err = errno;
+    if (f) fclose(f);
+    snprintf(what, sizeof(what), "fopen %s: %s", path, strerror(want));
+    check(what, !f && err == want);
+    int rc = stat(path, &st);
+    snprintf(what, sizeof(what), "stat %s: %s", path, strerror(want));
+    check(what, rc != 0 && errno == want);
+    rc = lstat(path, &st);
+    snprintf(what, sizeof(what), "lstat %s: %s", path, strerror(want));
+    check(what, rc != 0 && errno == want);
+    rc = access(path, F_OK);
+    snprintf(what, sizeof(what), "access %s: %s", path, strerror(want));
+    check(what, rc != 0 && errno == want);
+}
+
+// Run with LIBNVIDIAHIDE_DEVICE_ERRNO and LIBNVIDIAHIDE_FILE_ERRNO set (or
+// not, or to something unparsable), and HIDE_DEV=/dev/full for a device node
+// that exists. dev and file are the errnos each class must fail with; a
+// missing path nothing hides still gets the kernel's ENOENT.
+static int probe_errnos(const char *dev, const char *file) {
+    int dev_err = errno_named(dev), file_err = errno_named(file);
+    if (!dev_err || !file_err) { fprintf(stderr, "errnos: unknown errno name\n"); return 2; }
+    char dir[] = "/tmp/nvidia-hide-probe.XXXXXX";
+    if (!mkdtemp(dir)) { perror("mkdtemp"); return 2; }
+    char glx[PATH_MAX], cuda[PATH_MAX], missing[PATH_MAX];
+    snprintf(glx, sizeof(glx), "%s/libGLX_nvidia.so.0", dir);
+    snprintf(cuda, sizeof(cuda), "%s/libcuda.so.1", dir);
+    snprintf(missing, sizeof(missing), "%s/plain", dir);
+    touch_raw(glx);
+    touch_raw(cuda);
+
+    expect_errno("/dev/nvidiactl", dev_err);
+    expect_errno("/dev/nvidia0", dev_err);
+    expect_errno("/dev/full", dev_err);
+    expect_errno(glx, file_err);
+    expect_errno(cuda, file_err);
+    expect_errno(missing, ENOENT);
+    int fd = open("/dev/null", O_RDONLY);
+    check("open /dev/null", fd >= 0);
+    if (fd >= 0) close(fd);
+
+    syscall(SYS_unlinkat, AT_FDCWD, glx, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, cuda, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, dir, AT_REMOVEDIR);
+    return g_failed;
+}
+
+static long call_ok(const char *what, long rc) {
+    if (rc >= 0) printf("ok   %s\n", what);
+    else { printf("FAIL %s: %s\n", what, strerror(errno)); g_failed = 1; }
+    return rc;
+}
+
+// Calls that name no hidden path, on fds the library never tracked, run with
+// every optional block switched on (see the Makefile). Each must succeed, or
+// fail just as the raw syscall does, and none may count as a block. A new
+// hook adds its own unrelated call here.
+static int probe_unrelated(void) {
+    int (*metrics)(char *, size_t) = (int (*)(char *, size_t))dlsym(RTLD_DEFAULT, "libnvidiahide_metrics");
+    check("libnvidiahide_metrics is exported", metrics != NULL);
+    if (!metrics) return g_failed;
+    char before[512], after[512];
+    metrics(before, sizeof(before));
+
+    int sv[2], p[2];
+    call_ok("socketpair", socketpair(AF_UNIX, SOCK_STREAM | SOCK_CLOEXEC, 0, sv));
+    int sock = (int)call_ok("socket", socket(AF_UNIX, SOCK_DGRAM | SOCK_CLOEXEC, 0));
+    call_ok("pipe2", pipe2(p, O_CLOEXEC));
+    int d1 = (int)call_ok("dup", dup(p[0]));
+    int d2 = (int)call_ok("dup2", dup2(p[0], 200));
+    int d3 = (int)call_ok("dup3", dup3(p[1], 201, O_CLOEXEC));
+    call_ok("fcntl F_GETFL", fcntl(p[0], F_GETFL));
+    call_ok("fcntl F_SETFD", fcntl(p[0], F_SETFD, FD_CLOEXEC));
+    int d4 = (int)call_ok("fcntl F_DUPFD_CLOEXEC", fcntl(p[0], F_DUPFD_CLOEXEC, 100));
+
+    call_ok("write to the pipe", write(p[1], "abc", 3));
+    int avail = -1;
+    call_ok("ioctl FIONREAD on a pipe", ioctl(p[0], FIONREAD, &avail));
+    check("FIONREAD counts 3 bytes", avail == 3);
+    call_ok("ioctl FIONBIO on a socket", ioctl(sv[0], FIONBIO, &(int){1}));
+
+    int mfd = (int)call_ok("memfd_create", memfd_create("probe", MFD_CLOEXEC));
+    call_ok("ftruncate the memfd", ftruncate(mfd, 4096));
+    void *m = mmap(NULL, 4096, PROT_READ | PROT_WRITE, MAP_SHARED, mfd, 0);
+    call_ok("mmap the memfd", m == MAP_FAILED ? -1 : 0);
+    if (m != MAP_FAILED) munmap(m, 4096);
+    m = mmap(NULL, 4096, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+    call_ok("mmap anonymous", m == MAP_FAILED ? -1 : 0);
+    if (m != MAP_FAILED) munmap(m, 4096);
+    struct stat st;
+    call_ok("fstatat AT_EMPTY_PATH on the memfd", fstatat(mfd, "", &st, AT_EMPTY_PATH));
+    char link[64], target[PATH_MAX];
+    snprintf(link, sizeof(link), "/proc/self/fd/%d", mfd);
+    call_ok("readlink /proc/self/fd/<memfd>", readlink(link, target, sizeof(target)));
+
+    int efd = (int)call_ok("eventfd", eventfd(0, EFD_CLOEXEC));
+    uint64_t one = 1;
+    call_ok("write the eventfd", write(efd, &one, sizeof(one)));
+    int ep = (int)call_ok("epoll_create1", epoll_create1(EPOLL_CLOEXEC));
+    struct epoll_event ev = { .events = EPOLLIN };
+    call_ok("epoll_ctl ADD the eventfd", epoll_ctl(ep, EPOLL_CTL_ADD, efd, &ev));
+    call_ok("epoll_wait sees it", epoll_wait(ep, &ev, 1, 0) == 1 ? 0 : -1);
+
+    // FICLONE fails on most filesystems; it has to fail the same way hooked.
+    char dir[] = "/tmp/nvidia-hide-probe.XXXXXX";
+    if (!mkdtemp(dir)) { perror("mkdtemp"); return 2; }
+    char src[PATH_MAX - 32], dst[PATH_MAX - 32];
+    snprintf(src, sizeof(src), "%s/src", dir);
+    snprintf(dst, sizeof(dst), "%s/dst", dir);
+    write_raw(src, "data\n");
+    touch_raw(dst);
+    int sfd = (int)syscall(SYS_openat, AT_FDCWD, src, O_RDONLY | O_CLOEXEC, 0);
+    int dfd = (int)syscall(SYS_openat, AT_FDCWD, dst, O_WRONLY | O_CLOEXEC, 0);
+    long raw = syscall(SYS_ioctl, dfd, FICLONE, sfd);
+    int raw_err = raw < 0 ? errno : 0;
+    int rc = ioctl(dfd, FICLONE, sfd);
+    int err = rc < 0 ? errno : 0;
+    printf("%s ioctl FICLONE as the raw syscall (%d %s, raw %ld %s)\n", rc == raw && err == raw_err ? "ok  " : "FAIL",
+           rc, strerror(err), raw, strerror(raw_err));
+    if (rc != raw || err != raw_err) g_failed = 1;
+
+    int null = (int)call_ok("open /dev/null", open("/dev/null", O_RDWR | O_CLOEXEC));
+    char byte;
+    int zero = (int)call_ok("open /dev/zero", open("/dev/zero", O_RDONLY | O_CLOEXEC));
+    call_ok("read /dev/zero", read(zero, &byte, 1) == 1 ? 0 : -1);
+    FILE *f = fdopen(dup(p[1]), "w");
+    call_ok("fdopen a pipe", f ? 0 : -1);
+    if (f) call_ok("fclose it", fclose(f));
+
+    int fds[] = { sv[0], sv[1], sock, p[0], p[1], d1, d2, d3, d4, mfd, efd, ep, sfd, dfd, null, zero };
+    for (size_t i = 0; i < sizeof(fds)/sizeof(fds[0]) - 2; i++) if (fds[i] >= 0) close(fds[i]);
+#if __GLIBC_PREREQ(2, 34)
+    call_ok("close_range", close_range((unsigned)(null < zero ? null : zero), (unsigned)(null < zero ? zero : null), 0));
+#else
+    close(null);
+    close(zero);
+#endif
+
+    metrics(after, sizeof(after));
+    check("nothing counted as blocked",
+          metric(after, "paths_blocked") == metric(before, "paths_blocked") &&
+          metric(after, "dlopens_blocked") == metric(before, "dlopens_blocked"));
+
+    syscall(SYS_unlinkat, AT_FDCWD, src, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, dst, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, dir, AT_REMOVEDIR);
+    return g_failed;
+}
+
+struct raw_dirent64 {
+    uint64_t d_ino;
+    int64_t  d_off;
+    unsigned short d_reclen;
+    unsigned char  d_type;
+    char d_name[];
+};
+
+// A listing with hidden entries between visible ones. readdir skips the
+// hidden ones, so telldir/seekdir must still round-trip and move forward, and
+// a seekdir to the offset right before a hidden entry (read here with a raw
+// getdents64, which the library never filters) must land on a visible one.
+static void probe_seekdir(const char *base) {
+    static const char *const names[] = { "a", "nvidia0", "b", "nvidia1", "nvidia2", "c", NULL };
+    char sub[PATH_MAX - 32], path[PATH_MAX];
+    snprintf(sub, sizeof(sub), "%s/listing", base);
+    if (syscall(SYS_mkdirat, AT_FDCWD, sub, 0755) != 0) { perror(sub); exit(2); }
+    for (int i = 0; names[i]; i++) {
+        snprintf(path, sizeof(path), "%s/%s", sub, names[i]);
+        touch_raw(path);
+    }
+
+    DIR *d = opendir(sub);
+    if (!d) { perror(sub); exit(2); }
+    int visible = 0, hidden = 0, round_trips_ok = 1, steps = 0;
+    struct dirent *e;
+    while ((e = readdir(d)) != NULL && steps++ < 64) {
+        if (e->d_name[0] != '.') {
+            if (strncmp(e->d_name, "nvidia", 6)) visible++;
+            else hidden++;
+        }
+        long pos = telldir(d);
+        e = readdir(d);
+        char want[NAME_MAX + 1];
+        snprintf(want, sizeof(want), "%s", e ? e->d_name : "");
+        seekdir(d, pos);
+        e = readdir(d);
+        if (strcmp(want, e ? e->d_name : "") != 0) round_trips_ok = 0;
+        seekdir(d, pos);
+    }
+    check("readdir lists every visible entry once", visible == 3 && steps < 64);
+    check(g_hidden ? "readdir skips hidden entries" : "readdir lists every entry", hidden == (g_hidden ? 0 : 3));
+    check("telldir/seekdir round trips", round_trips_ok);
+
+    int fd = (int)syscall(SYS_openat, AT_FDCWD, sub, O_RDONLY | O_DIRECTORY | O_CLOEXEC, 0);
+    char buf[4096];
+    int n = fd >= 0 ? (int)syscall(SYS_getdents64, fd, buf, sizeof(buf)) : -1;
+    if (fd >= 0) close(fd);
+    int seeks_ok = n > 0;
+    long before = 0;    // offset of the entry preceding the current one
+    for (int bpos = 0; bpos < n; ) {
+        struct raw_dirent64 *r = (struct raw_dirent64*)(buf + bpos);
+        bpos += r->d_reclen;
+        if (!strncmp(r->d_name, "nvidia", 6)) {
+            seekdir(d, before);
+            e = readdir(d);
+            if (g_hidden ? e && !strncmp(e->d_name, "nvidia", 6) : !e || strcmp(e->d_name, r->d_name)) seeks_ok = 0;
+        }
+        before = (long)r->d_off;
+    }
+    check("seekdir to a skipped entry lands on a visible one", seeks_ok);
+    closedir(d);
+
+    for (int i = 0; names[i]; i++) {
+        snprintf(path, sizeof(path), "%s/%s", sub, names[i]);
+        syscall(SYS_unlinkat, AT_FDCWD, path, 0);
+    }
+    syscall(SYS_unlinkat, AT_FDCWD, sub, AT_REMOVEDIR);
+}
+
+static int glob_has(const glob_t *g, const char *path) {
+    for (size_t i = 0; i < g->gl_pathc; i++)
+        if (g->gl_pathv[g->gl_offs + i] && !strcmp(g->gl_pathv[g->gl_offs + i], path)) return 1;
+    return 0;
+}
+
+// glibc's glob lists directories through its internal readdir, so matches are
+// filtered after the fact. GLOB_DOOFFS shifts the results; GLOB_NOCHECK hands
+// back the pattern when every match is hidden, as when none exists.
+static void probe_glob(const char *dir, const char *nv, const char *mesa, const char *cuda) {
+    char pat[PATH_MAX];
+    glob_t g = { .gl_offs = 2 };
+    snprintf(pat, sizeof(pat), "%s/lib*.so.*", dir);
+    int rc = glob(pat, GLOB_DOOFFS, NULL, &g);
+    check("glob finds the allowed library", rc == 0 && glob_has(&g, mesa) && !g.gl_pathv[0] && !g.gl_pathv[1]);
+    expect("glob match", GRAPHICS, rc == 0 && glob_has(&g, nv), ENOENT);
+    expect("glob match, compute library", COMPUTE, rc == 0 && glob_has(&g, cuda), ENOENT);
+    check("glob result stays NULL-terminated", rc == 0 && !g.gl_pathv[g.gl_offs + g.gl_pathc]);
+    globfree(&g);
+
+    snprintf(pat, sizeof(pat), "%s/libcuda*", dir);
+    rc = glob(pat, 0, NULL, &g);
+    expect("glob with only hidden matches", COMPUTE, rc == 0, rc == GLOB_NOMATCH ? ENOENT : EIO);
+    if (rc == 0) globfree(&g);
+
+    snprintf(pat, sizeof(pat), "%s/libGLX_nv*", dir);
+    rc = glob(pat, GLOB_NOCHECK, NULL, &g);
+    expect("glob GLOB_NOCHECK match", GRAPHICS, rc == 0 && glob_has(&g, nv), ENOENT);
+    if (g_hidden & GRAPHICS)
+        check("glob GLOB_NOCHECK falls back to the pattern", rc == 0 && g.gl_pathc == 1 && glob_has(&g, pat));
+    globfree(&g);
+
+    rc = glob(nv, GLOB_NOCHECK, NULL, &g);
+    check("glob GLOB_NOCHECK keeps a literal pattern", rc == 0 && glob_has(&g, nv));
+    globfree(&g);
+}
+
+// The mode is a vararg: a hook that drops or misreads it shows up here, on
+// whichever ABI the probe was built for. Each file is checked by fstat on
+// the fd the hook returned.
+static void expect_mode(const char *what, int fd, mode_t want) {
+    struct stat st;
+    int ok = fd >= 0 && fstat(fd, &st) == 0 && (st.st_mode & 07777) == want;
+    if (ok) printf("ok   %s mode %o\n", what, (unsigned)want);
+    else if (fd < 0) printf("FAIL %s: %s\n", what, strerror(errno));
+    else printf("FAIL %s mode: got %o, want %o\n", what, (unsigned)(st.st_mode & 07777), (unsigned)want);
+    if (!ok) g_failed = 1;
+    if (fd >= 0) close(fd);
+}
+
+// O_TMPFILE needs filesystem support; without it the mode can't be checked.
+static void expect_tmpfile_mode(const char *what, int fd, mode_t want) {
+    if (fd < 0 && (errno == EOPNOTSUPP || errno == EISDIR)) printf("ok   %s (not supported here, skipped)\n", what);
+    else expect_mode(what, fd, want);
+}
+
+// O_CREAT|O_TMPFILE goes to the kernel as is (which refuses it with EINVAL):
+// the same outcome as the raw call, and the mode forwarded if it ever opens.
+static void expect_like_raw(const char *what, int fd, int raw, int raw_err, mode_t want) {
+    if (raw >= 0) { close(raw); expect_mode(what, fd, want); return; }
+    int err = errno;
+    check(what, fd < 0 && err == raw_err);
+    if (fd >= 0) close(fd);
+}
+
+// Every combination of O_CREAT and O_TMPFILE through open and openat: the
+// mode argument is read exactly when one of them asks for it.
+static void probe_create_modes(const char *dir, const char *created) {
+    mode_t old = umask(0);
+    int dfd = open(dir, O_RDONLY | O_DIRECTORY);
+    char existing[PATH_MAX];
+    snprintf(existing, sizeof(existing), "%s/existing", dir);
+    int fd = (int)syscall(SYS_openat, AT_FDCWD, existing, O_WRONLY | O_CREAT | O_EXCL | O_CLOEXEC, 0600);
+    if (fd >= 0) close(fd);
+
+    // neither: no mode is read, and an existing file keeps its own
+    expect_mode("open, neither O_CREAT nor O_TMPFILE", open(existing, O_WRONLY, 0777), 0600);
+    expect_mode("openat, neither O_CREAT nor O_TMPFILE", openat(dfd, "existing", O_WRONLY, 0777), 0600);
+    expect_mode("open O_CREAT", open(created, O_WRONLY | O_CREAT | O_EXCL, 0640), 0640);
+    expect_mode("openat O_CREAT", openat(dfd, "created-at", O_WRONLY | O_CREAT | O_EXCL, 0604), 0604);
+    expect_tmpfile_mode("open O_TMPFILE", open(dir, O_TMPFILE | O_WRONLY, 0620), 0620);
+    expect_tmpfile_mode("openat O_TMPFILE", openat(dfd, ".", O_TMPFILE | O_WRONLY, 0640), 0640);
+    int raw = (int)syscall(SYS_openat, AT_FDCWD, dir, O_CREAT | O_TMPFILE | O_WRONLY, 0602);
+    int raw_err = errno;
+    expect_like_raw("open O_CREAT|O_TMPFILE: as without the library",
+                    open(dir, O_CREAT | O_TMPFILE | O_WRONLY, 0602), raw, raw_err, 0602);
+    raw = (int)syscall(SYS_openat, dfd, ".", O_CREAT | O_TMPFILE | O_WRONLY, 0602);
+    raw_err = errno;
+    expect_like_raw("openat O_CREAT|O_TMPFILE: as without the library",
+                    openat(dfd, ".", O_CREAT | O_TMPFILE | O_WRONLY, 0602), raw, raw_err, 0602);
+
+    syscall(SYS_unlinkat, dfd, "created-at", 0);
+    syscall(SYS_unlinkat, dfd, "existing", 0);
+    close(dfd);
+    umask(old);
+}
+
+int main(int argc, char **argv) {
+    if (argc == 2 && !strcmp(argv[1], "reload")) return probe_reload();
+    if (argc == 3 && !strcmp(argv[1], "reload-child")) return probe_reload_child(argv[2]);
+    if (argc >= 2 && !strcmp(argv[1], "spawn-child")) return probe_spawn_child();
+    if (argc == 3 && !strcmp(argv[1], "spawn-stop")) return probe_spawn_stop(argv[2]);
+    if (argc == 2 && !strcmp(argv[1], "preready")) return probe_preready();
+    if (argc == 3 && !strcmp(argv[1], "preready-child")) return probe_preready_child(argv[2]);
+    if (argc == 2 && !strcmp(argv[1], "keep")) return probe_keep();
+    if (argc == 2 && !strcmp(argv[1], "atflags")) return probe_atflags();
+    if (argc == 2 && !strcmp(argv[1], "sysdrm")) return probe_sysdrm();
+    if (argc == 2 && !strcmp(argv[1], "nosysfs")) return probe_nosysfs();
+    if (argc == 2 && !strcmp(argv[1], "devnodes")) return probe_devnodes();
+    if (argc == 3 && !strcmp(argv[1], "topodlopen")) return probe_topodlopen(argv[2]);
+    if (argc == 2 && !strcmp(argv[1], "mmapdev")) return probe_mmapdev();
+    if (argc == 2 && !strcmp(argv[1], "drivers")) return probe_drivers();
+    if (argc == 2 && !strcmp(argv[1], "eglvendor")) return probe_eglvendor();
+    if (argc == 2 && !strcmp(argv[1], "hidedev")) return probe_hidedev();
+    if (argc == 4 && !strcmp(argv[1], "errnos")) return probe_errnos(argv[2], argv[3]);
+    if (argc == 2 && !strcmp(argv[1], "unrelated")) return probe_unrelated();
+    if (argc == 2 && !strcmp(argv[1], "race")) return probe_race();
+    if (argc == 2 && !strcmp(argv[1], "loglevel")) return probe_loglevel();
+    if (argc == 2 && !strcmp(argv[1], "dryrun")) return probe_dryrun();
+    if (argc == 3 && !strcmp(argv[1], "dryrun-child")) return probe_dryrun_child(argv[2]);
+    if (argc == 2 && !strcmp(argv[1], "dirsignal")) return probe_dirsignal();
+    if (argc == 3 && !strcmp(argv[1], "chain")) return probe_chain(argv[2]);
+    if (argc == 2 && !strcmp(argv[1], "cold")) return probe_cold();
+    if (argc == 2 && !strcmp(argv[1], "session")) return probe_session();
+    if (argc == 2 && !strcmp(argv[1], "icdstub")) return probe_icdstub();
+    if (argc == 2 && !strcmp(argv[1], "tools")) return probe_tools();
+    if (argc == 4 && !strcmp(argv[1], "session-child")) return probe_session_child(argv[2], argv[3]);
+    if (argc == 2 && !strcmp(argv[1], "audit")) return probe_audit();
+    if (argc == 3 && !strcmp(argv[1], "audit-child")) return probe_audit_child(argv[2]);
+    if (argc == 2 && !strcmp(argv[1], "dedup")) return probe_dedup();
+    if (argc == 2 && !strcmp(argv[1], "dump")) return probe_dump();
+    if (argc == 2 && !strcmp(argv[1], "dump-child")) return 0;
+    if (argc == 2 && !strcmp(argv[1], "metrics")) return probe_metrics();
+    if (argc == 4 && !strcmp(argv[1], "metrics-child")) return probe_metrics_child(argv[2], argv[3]);
+    if (argc == 2 && !strcmp(argv[1], "killswitch")) return probe_killswitch();
+    if (argc == 4 && !strcmp(argv[1], "killswitch-child")) return probe_killswitch_child(argv[2], argv[3]);
+    if (argc == 2 && !strcmp(argv[1], "maps")) return probe_maps();
+    if (argc == 4 && !strcmp(argv[1], "maps-child")) return probe_maps_child(argv[2], atoi(argv[3]));
+    if (argc == 4 && !strcmp(argv[1], "dedup-child")) return probe_dedup_child(argv[2], atoi(argv[3]));
+
+    static const struct { const char *name; int hidden; } modes[] = {
+        { "hidden", GRAPHICS | COMPUTE }, { "visible", 0 }, { "graphics", GRAPHICS }, { "compute", COMPUTE },
+    };
+    int mode = -1;
+    for (int i = 0; argc == 2 && i < (int)(sizeof(modes)/sizeof(modes[0])); i++)
+        if (!strcmp(argv[1], modes[i].name)) mode = i;
+    if (mode < 0) {
+        fprintf(stderr, "usage: %s hidden|visible|graphics|compute|reload|preready|keep|hidedev|errnos DEV FILE|unrelated|audit|dedup|loglevel|dryrun|dump|maps|killswitch|metrics|icdstub|tools|session|race|dirsignal|chain first/last|cold|atflags|sysdrm|topodlopen SO|devnodes|mmapdev|drivers|eglvendor|nosysfs\n", argv[0]);
+        return 2;
+    }
+    g_hidden = modes[mode].hidden;
+
+    char dir[] = "/tmp/nvidia-hide-probe.XXXXXX";
+    if (!mkdtemp(dir)) { perror("mkdtemp"); return 2; }
+    char nv[PATH_MAX], mesa[PATH_MAX], cuda[PATH_MAX], created[PATH_MAX], nv_created[PATH_MAX];
+    snprintf(nv, sizeof(nv), "%s/libGLX_nvidia.so.0", dir);
+    snprintf(mesa, sizeof(mesa), "%s/libGLX_mesa.so.0", dir);
+    snprintf(cuda, sizeof(cuda), "%s/libcuda.so.1", dir);
+    snprintf(created, sizeof(created), "%s/created", dir);
+    snprintf(nv_created, sizeof(nv_created), "%s/libGLX_nvidia.so.1", dir);
+    touch_raw(nv);
+    touch_raw(mesa);
+    touch_raw(cuda);
+
+    // volatile keeps the flags out of reach of the fortify wrappers' constant folding
+    volatile int rdonly = O_RDONLY;
+
+    expect_fd("open constant flags (open)", GRAPHICS, open(nv, O_RDONLY));
+    expect_fd("open constant flags, allowed", 0, open(mesa, O_RDONLY));
+    expect_fd("open variable flags (__open_2)", GRAPHICS, open(nv, rdonly));
+    expect_fd("open variable flags, allowed", 0, open(mesa, rdonly));
+    expect_fd("open64 (__open64_2 or open64)", GRAPHICS, open64(nv, rdonly));
+
+    // Looked up at run time: whether libc exports these varies (glibc keeps
+    // __open_nocancel GLIBC_PRIVATE, musl has neither), so absent ones are skipped.
+    static const char *const aliases[] = { "__open", "__open64", "__open_nocancel", "__open64_nocancel" };
+    for (size_t i = 0; i < sizeof(aliases)/sizeof(aliases[0]); i++) {
+        int (*alias)(const char*, int, ...) = (int (*)(const char*, int, ...))dlsym(RTLD_DEFAULT, aliases[i]);
+        if (!alias) { printf("skip %s (not exported)\n", aliases[i]); continue; }
+        char what[64];
+        snprintf(what, sizeof(what), "%s", aliases[i]);
+        expect_fd(what, GRAPHICS, alias(nv, O_RDONLY));
+        snprintf(what, sizeof(what), "%s, allowed", aliases[i]);
+        expect_fd(what, 0, alias(mesa, O_RDONLY));
+    }
+
+    int dfd = open(dir, O_RDONLY | O_DIRECTORY);
+    if (dfd < 0) { perror(dir); return 2; }
+    expect_fd("openat relative (openat)", GRAPHICS, openat(dfd, "libGLX_nvidia.so.0", O_RDONLY));
+    expect_fd("openat variable flags (__openat_2)", GRAPHICS, openat(dfd, "libGLX_nvidia.so.0", rdonly));
+    expect_fd("openat relative, allowed", 0, openat(dfd, "libGLX_mesa.so.0", rdonly));
+    probe_openat2_resolve(dfd, dir);
+    close(dfd);
+
+    probe_create_modes(dir, created);
+    expect_fd("open O_CREAT", GRAPHICS, open(nv_created, O_WRONLY | O_CREAT, 0600));
+    struct stat st;
+
+    FILE *f = fopen(nv, "r");
+    expect("fopen", GRAPHICS, f != NULL, errno);
+    if (f) fclose(f);
+    f = fopen(mesa, "r");
+    expect("fopen, allowed", 0, f != NULL, errno);
+    if (f) fclose(f);
+    f = fopen64(nv, "r");
+    expect("fopen64", GRAPHICS, f != NULL, errno);
+    if (f) fclose(f);
+    f = fopen64(mesa, "r");
+    expect("fopen64, allowed", 0, f != NULL, errno);
+    if (f) fclose(f);
+    // After a failed freopen the stream is not to be used again, refused or not
+    f = fopen(mesa, "r");
+    if (f) expect("freopen", GRAPHICS, freopen(nv, "r", f) != NULL, errno);
+    f = fopen(mesa, "r");
+    FILE *re = f ? freopen(mesa, "r", f) : NULL;
+    expect("freopen, allowed", 0, re != NULL, errno);
+    if (re) fclose(re);
+
+    expect("stat", GRAPHICS, stat(nv, &st) == 0, errno);
+    expect("stat, allowed", 0, stat(mesa, &st) == 0, errno);
+    expect("access", GRAPHICS, access(nv, F_OK) == 0, errno);
+    probe_statfs(nv, GRAPHICS);
+    probe_statfs(mesa, 0);
+
+    // AT_EMPTY_PATH: the call is about the fd itself, opened behind the library's back
+    int raw_nv = (int)syscall(SYS_openat, AT_FDCWD, nv, O_PATH | O_CLOEXEC, 0);
+    int raw_mesa = (int)syscall(SYS_openat, AT_FDCWD, mesa, O_PATH | O_CLOEXEC, 0);
+    expect("fstatat AT_EMPTY_PATH", GRAPHICS, fstatat(raw_nv, "", &st, AT_EMPTY_PATH) == 0, errno);
+    expect("fstatat AT_EMPTY_PATH, allowed", 0, fstatat(raw_mesa, "", &st, AT_EMPTY_PATH) == 0, errno);
+    close(raw_nv);
+    close(raw_mesa);
+
+    probe_seekdir(dir);
+    probe_glob(dir, nv, mesa, cuda);
+    probe_spawn();
+    probe_symbol();
+
+    // The compute stack is its own category: LIBNVIDIAHIDE_HIDE_COMPUTE=0
+    // keeps it while the graphics files above stay hidden, and vice versa.
+    expect_fd("open compute library", COMPUTE, open(cuda, rdonly));
+    expect("stat compute library", COMPUTE, stat(cuda, &st) == 0, errno);
+
+    syscall(SYS_unlinkat, AT_FDCWD, nv, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, nv_created, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, mesa, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, cuda, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, created, 0);
+    syscall(SYS_unlinkat, AT_FDCWD, dir, AT_REMOVEDIR);
+    return g_failed;
+}
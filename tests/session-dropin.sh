@@ -0,0 +1,37 @@
+#!/bin/sh
+# Checks what `nvidia-hide install-session --print` renders, in a scratch
+# XDG_CONFIG_HOME: refusal without an allowlist, the LD_PRELOAD and
+# LIBNVIDIAHIDE_ALLOWLIST directives, and that uninstall-session only removes
+# its own file.
+#
+#   tests/session-dropin.sh ./nvidia-hide /path/to/libnvidia-hide.so
+set -u
+nh=$1
+so=$2
+failed=0
+cfg=$(mktemp -d /tmp/nvidia-hide-session.XXXXXX)
+trap 'rm -rf "$cfg"' EXIT
+export XDG_CONFIG_HOME="$cfg" LIBNVIDIAHIDE_SO="$so"
+unset LIBNVIDIAHIDE_ALLOWLIST
+
+ok() { echo "ok   $1"; }
+fail() { echo "FAIL $1"; failed=1; }
+
+if "$nh" install-session --print >/dev/null 2>&1; then fail "refuses without an allowlist"; else ok "refuses without an allowlist"; fi
+
+mkdir -p "$cfg/nvidia-hide"
+printf '# comment\nobs\n!obs-helper\ncmdline:*--gpu*\n' > "$cfg/nvidia-hide/allowlist"
+out=$("$nh" install-session --print 2>/dev/null)
+printf '%s\n' "$out" | grep -qx "LD_PRELOAD=\${LD_PRELOAD:+\$LD_PRELOAD:}$so" && ok "LD_PRELOAD directive" || fail "LD_PRELOAD directive"
+printf '%s\n' "$out" | grep -qx 'LIBNVIDIAHIDE_ALLOWLIST=obs:!obs-helper:cmdline:\*--gpu\*' && ok "allowlist directive" || fail "allowlist directive"
+printf '%s\n' "$out" | head -n1 | grep -q "^# Generated by 'nvidia-hide install-session'" && ok "marker line" || fail "marker line"
+
+dropin="$cfg/environment.d/60-nvidia-hide.conf"
+"$nh" install-session >/dev/null 2>&1 && [ -f "$dropin" ] && ok "install" || fail "install"
+"$nh" install-session >/dev/null 2>&1 && fail "install twice without --force" || ok "install twice without --force"
+"$nh" uninstall-session >/dev/null 2>&1 && [ ! -e "$dropin" ] && ok "uninstall" || fail "uninstall"
+echo 'FOO=1' > "$dropin"
+"$nh" uninstall-session >/dev/null 2>&1
+[ -e "$dropin" ] && ok "uninstall leaves foreign files" || fail "uninstall leaves foreign files"
+
+exit $failed
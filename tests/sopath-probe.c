@@ -0,0 +1,128 @@
+// Check of nvidia-hide-sopath.h, the fixed places the CLI looks for
+// libnvidia-hide.so: the candidate list for a lib64 and a multiarch libc,
+// with $XDG_DATA_DIRS unset, set, and holding relative or empty entries, and
+// which one so_find_candidate() picks from a fake tree in a temporary
+// directory as copies of the library appear in it.
+#define _GNU_SOURCE
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/stat.h>
+#include <unistd.h>
+
+#include "../nvidia-hide-sopath.h"
+
+static int g_failed = 0;
+
+static void check(const char *what, int cond) {
+    printf("%s %s\n", cond ? "ok  " : "FAIL", what);
+    if (!cond) g_failed = 1;
+}
+
+static char g_cands[MAX_SO_CANDIDATES][PATH_MAX];
+
+// so_candidates() as one ' '-joined line.
+static const char *joined(const char *own_dir, const char *xdg) {
+    static char line[8192];
+    int n = so_candidates(g_cands, MAX_SO_CANDIDATES, own_dir, xdg);
+    line[0] = 0;
+    for (int i = 0; i < n; i++) {
+        if (i) strcat(line, " ");
+        strcat(line, g_cands[i]);
+    }
+    return line;
+}
+
+static void expect_cands(const char *own_dir, const char *xdg, const char *want) {
+    const char *got = joined(own_dir, xdg);
+    int ok = !strcmp(got, want);
+    printf("%s candidates for %s, XDG_DATA_DIRS=%s\n", ok ? "ok  " : "FAIL", own_dir ? own_dir : "(unknown)", xdg ? xdg : "(unset)");
+    if (!ok) {
+        printf("     got  %s\n     want %s\n", got, want);
+        g_failed = 1;
+    }
+}
+
+#define LIBS "/usr/lib/libnvidia-hide.so"
+#define REST "/usr/lib64/libnvidia-hide.so /usr/local/lib/libnvidia-hide.so /usr/local/lib64/libnvidia-hide.so " \
+             "/lib/libnvidia-hide.so /lib64/libnvidia-hide.so"
+#define XDG_DEFAULT "/usr/local/share/nvidia-hide/libnvidia-hide.so /usr/share/nvidia-hide/libnvidia-hide.so"
+
+static void probe_candidates(void) {
+    expect_cands(NULL, NULL, LIBS " " REST " " XDG_DEFAULT);
+    expect_cands("lib64", "", LIBS " " REST " " XDG_DEFAULT);
+    expect_cands("lib/x86_64-linux-gnu", NULL,
+                 LIBS " /usr/lib/x86_64-linux-gnu/libnvidia-hide.so " REST " " XDG_DEFAULT);
+    expect_cands("lib", "/nix/profile/share:relative/share::/gnu/share",
+                 LIBS " " REST " /nix/profile/share/nvidia-hide/libnvidia-hide.so /gnu/share/nvidia-hide/libnvidia-hide.so");
+
+    // A long $XDG_DATA_DIRS stops at MAX_SO_CANDIDATES.
+    char many[MAX_SO_CANDIDATES * 8] = "";
+    for (int i = 0; i < MAX_SO_CANDIDATES; i++) strcat(many, "/d:");
+    check("candidates stop at MAX_SO_CANDIDATES", so_candidates(g_cands, MAX_SO_CANDIDATES, NULL, many) == MAX_SO_CANDIDATES);
+}
+
+// What put() created, removed last to first.
+static char g_made[64][PATH_MAX];
+static int g_nmade = 0;
+
+static void put(const char *root, const char *rel, int dir) {
+    char path[PATH_MAX];
+    snprintf(path, sizeof(path), "%s%s", root, rel);
+    for (char *s = strchr(path + strlen(root) + 1, '/'); s; s = strchr(s + 1, '/')) {
+        *s = 0;
+        if (mkdir(path, 0755) == 0) snprintf(g_made[g_nmade++], PATH_MAX, "%s", path);
+        *s = '/';
+    }
+    if (dir) {
+        if (mkdir(path, 0755) != 0) { perror(path); exit(2); }
+    } else {
+        FILE *f = fopen(path, "w");
+        if (!f) { perror(path); exit(2); }
+        fclose(f);
+    }
+    snprintf(g_made[g_nmade++], PATH_MAX, "%s", path);
+}
+
+static void expect_found(const char *root, const char *own_dir, const char *xdg, const char *want) {
+    char got[PATH_MAX], full[PATH_MAX];
+    int rc = so_find_candidate(got, sizeof(got), root, own_dir, xdg);
+    snprintf(full, sizeof(full), "%s%s", root, want ? want : "");
+    int ok = want ? rc == 0 && !strcmp(got, full) : rc == -1;
+    printf("%s found %s\n", ok ? "ok  " : "FAIL", want ? want : "nothing");
+    if (!ok) {
+        printf("     got %s\n", rc == 0 ? got : "nothing");
+        g_failed = 1;
+    }
+}
+
+// Library copies added to a fake tree one at a time, each ahead of the last
+// in the search order, with a directory by the library's name in the way.
+static void probe_fake_tree(void) {
+    char root[] = "/tmp/nvidia-hide-sopath.XXXXXX";
+    if (!mkdtemp(root)) { perror("mkdtemp"); exit(2); }
+    static const char *const xdg = "/nix/profile/share:/usr/share";
+    expect_found(root, "lib/x86_64-linux-gnu", xdg, NULL);
+    put(root, "/lib64/libnvidia-hide.so", 1);
+    expect_found(root, "lib/x86_64-linux-gnu", xdg, NULL);
+    put(root, "/usr/share/nvidia-hide/libnvidia-hide.so", 0);
+    expect_found(root, "lib/x86_64-linux-gnu", xdg, "/usr/share/nvidia-hide/libnvidia-hide.so");
+    put(root, "/nix/profile/share/nvidia-hide/libnvidia-hide.so", 0);
+    expect_found(root, "lib/x86_64-linux-gnu", xdg, "/nix/profile/share/nvidia-hide/libnvidia-hide.so");
+    put(root, "/usr/lib64/libnvidia-hide.so", 0);
+    expect_found(root, "lib/x86_64-linux-gnu", xdg, "/usr/lib64/libnvidia-hide.so");
+    put(root, "/usr/lib/x86_64-linux-gnu/libnvidia-hide.so", 0);
+    expect_found(root, "lib/x86_64-linux-gnu", xdg, "/usr/lib/x86_64-linux-gnu/libnvidia-hide.so");
+    expect_found(root, "lib64", xdg, "/usr/lib64/libnvidia-hide.so");
+    put(root, "/usr/lib/libnvidia-hide.so", 0);
+    expect_found(root, "lib/x86_64-linux-gnu", xdg, "/usr/lib/libnvidia-hide.so");
+
+    while (g_nmade > 0) remove(g_made[--g_nmade]);
+    rmdir(root);
+}
+
+int main(void) {
+    probe_candidates();
+    probe_fake_tree();
+    return g_failed;
+}
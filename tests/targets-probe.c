@@ -0,0 +1,513 @@
+// Table-driven check of nvidia-hide-targets.h's path predicate, with the
+// targets filled in by hand instead of discovered, so it runs without a GPU:
+// BDF matching in its full and domain-less forms, for the default PCI domain
+// and a non-default one (10000:, as behind Intel VMD); /sys/class/drm entries
+// following the hidden nodes; LIBNVIDIAHIDE_HIDE_ALL_DRI taking every DRM
+// node with it; keep rules un-hiding single paths while their siblings
+// stay hidden; LIBNVIDIAHIDE_HIDE_DEV prefixes hiding paths and listing
+// entries; and discovery from a fake sys/class/drm tree under an injected
+// root, as LIBNVIDIAHIDE_SYSROOT gives it, nodes without a vendor file
+// included, and ONLY_BDF/EXCEPT_BDF picking one of two NVIDIA GPUs there; allow/deny entries parsing the
+// same from an env var and from a file. Also that every
+// prefix-anchored built-in sits under a root the fast path in
+// targets_match_path() still inspects. And the GLVND/EGL vendor JSONs
+// hidden from their selection dirs.
+#define _GNU_SOURCE
+#include <stdarg.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <sys/stat.h>
+#include <unistd.h>
+
+static void dbg(const char *fmt, ...);
+
+#include "../nvidia-hide-targets.h"
+
+static void dbg(const char *fmt, ...) {
+    va_list ap; va_start(ap, fmt);
+    vfprintf(stderr, fmt, ap);
+    va_end(ap);
+    fputc('\n', stderr);
+}
+
+static int g_failed = 0;
+
+static void expect(const struct nh_targets *t, const char *p, int want) {
+    int got = targets_match_path(t, p);
+    printf("%s %s -> %d\n", got == want ? "ok  " : "FAIL", p, got);
+    if (got != want) g_failed = 1;
+}
+
+static void targets_with_bdf(struct nh_targets *t, const char *bdf) {
+    struct nh_vendor_sel sel;
+    vendor_sel_parse(&sel, NULL);
+    targets_init(t, &sel);
+    t->hide_graphics = t->hide_compute = 1;
+    targets_add_bdf(t, bdf);
+}
+
+static void keep(struct nh_targets *t, const char *pat) {
+    targets_add_keep(t, pat, strlen(pat));
+}
+
+static void expect_rooted(const char *const *list) {
+    for (int i = 0; i < MAX_PROFILE_RULES && list[i]; i++) {
+        int rooted = targets_anchored_root(list[i]);
+        printf("%s %s under an anchored root\n", rooted ? "ok  " : "FAIL", list[i]);
+        if (!rooted) g_failed = 1;
+    }
+}
+
+static void probe_keep(void) {
+    struct nh_targets t;
+    targets_with_bdf(&t, "0000:01:00.0");
+    targets_add_rule(&t, "prefix:/opt/hidden/");
+    keep(&t, "/dev/nvidia-caps");
+    keep(&t, "/dev/nvidia-caps/*");
+    keep(&t, "/opt/hidden/keep-*.so");
+    expect(&t, "/dev/nvidia-caps", 0);
+    expect(&t, "/dev/nvidia-caps/nvidia-cap1", 0);
+    expect(&t, "/dev/nvidia0", 1);
+    expect(&t, "/dev/nvidiactl", 1);
+    expect(&t, "/dev/nvidia-uvm", 1);
+    expect(&t, "/opt/hidden/keep-me.so", 0);
+    expect(&t, "/opt/hidden/other.so", 1);
+}
+
+// A fake <root>/sys/class/drm: an NVIDIA card1/renderD129 pair on
+// 0000:01:00.0 with a connector, and an Intel card0 on 0000:00:02.0. Two more
+// NVIDIA GPUs have one node each whose vendor can't be read: renderD130 links
+// to a copy of 0000:02:00.0 without a vendor file, and card3 has no device
+// link at all, only an entry under renderD131's device/drm/.
+static void fake_file(const char *root, const char *rel, const char *text) {
+    char path[PATH_MAX];
+    snprintf(path, sizeof(path), "%s/%s", root, rel);
+    FILE *f = fopen(path, "w");
+    if (!f) { perror(path); exit(2); }
+    fputs(text, f);
+    fclose(f);
+}
+
+static void fake_dir(const char *root, const char *rel) {
+    char path[PATH_MAX];
+    snprintf(path, sizeof(path), "%s/%s", root, rel);
+    if (mkdir(path, 0755) != 0) { perror(path); exit(2); }
+}
+
+static void fake_node(const char *root, const char *node, const char *bdf) {
+    char rel[PATH_MAX], path[PATH_MAX], target[PATH_MAX];
+    snprintf(rel, sizeof(rel), "sys/class/drm/%s", node);
+    fake_dir(root, rel);
+    snprintf(path, sizeof(path), "%s/sys/class/drm/%s/device", root, node);
+    snprintf(target, sizeof(target), "../../../devices/pci0000:00/%s", bdf);
+    if (symlink(target, path) != 0) { perror(path); exit(2); }
+}
+
+static void probe_sysroot(void) {
+    char root[] = "/tmp/nvidia-hide-sysroot.XXXXXX";
+    if (!mkdtemp(root)) { perror("mkdtemp"); exit(2); }
+    fake_dir(root, "sys");
+    fake_dir(root, "sys/class");
+    fake_dir(root, "sys/class/drm");
+    fake_dir(root, "sys/devices");
+    fake_dir(root, "sys/devices/pci0000:00");
+    fake_dir(root, "sys/devices/pci0000:00/0000:01:00.0");
+    fake_file(root, "sys/devices/pci0000:00/0000:01:00.0/vendor", "0x10de\n");
+    fake_file(root, "sys/devices/pci0000:00/0000:01:00.0/device", "0x2820\n");
+    fake_dir(root, "sys/devices/pci0000:00/0000:00:02.0");
+    fake_file(root, "sys/devices/pci0000:00/0000:00:02.0/vendor", "0x8086\n");
+    fake_node(root, "card1", "0000:01:00.0");
+    fake_node(root, "renderD129", "0000:01:00.0");
+    fake_node(root, "card0", "0000:00:02.0");
+    fake_dir(root, "sys/devices/pci0000:00/0000:02:00.0");
+    fake_file(root, "sys/devices/pci0000:00/0000:02:00.0/vendor", "0x10de\n");
+    fake_node(root, "card2", "0000:02:00.0");
+    fake_dir(root, "sys/devices/stale");
+    fake_dir(root, "sys/devices/stale/0000:02:00.0");
+    fake_dir(root, "sys/class/drm/renderD130");
+    char link[PATH_MAX];
+    snprintf(link, sizeof(link), "%s/sys/class/drm/renderD130/device", root);
+    if (symlink("../../../devices/stale/0000:02:00.0", link) != 0) { perror(link); exit(2); }
+    fake_dir(root, "sys/devices/pci0000:00/0000:03:00.0");
+    fake_file(root, "sys/devices/pci0000:00/0000:03:00.0/vendor", "0x10de\n");
+    fake_dir(root, "sys/devices/pci0000:00/0000:03:00.0/drm");
+    fake_dir(root, "sys/devices/pci0000:00/0000:03:00.0/drm/card3");
+    fake_dir(root, "sys/devices/pci0000:00/0000:03:00.0/drm/renderD131");
+    fake_node(root, "renderD131", "0000:03:00.0");
+    fake_dir(root, "sys/class/drm/card3");
+    fake_dir(root, "sys/class/drm/card1-DP-1");
+    fake_file(root, "sys/class/drm/version", "drm 1.1.0 20060810\n");
+    setenv("XDG_CONFIG_HOME", root, 1);     // no hide-targets or hide-exceptions
+
+    struct nh_drm_node found[8];
+    int n = nh_scan_drm_at(root, found, 8);
+    int nvidia = 0;
+    for (int i = 0; i < n; i++)
+        if (found[i].vendor == 0x10de && !strcmp(found[i].bdf, "0000:01:00.0")) nvidia++;
+    int ok = n == 7 && nvidia == 2;
+    printf("%s scan of %s finds 7 nodes, 2 on the first dGPU (%d, %d)\n", ok ? "ok  " : "FAIL", root, n, nvidia);
+    if (!ok) g_failed = 1;
+
+    ok = nh_drm_present_at(root) && !nh_drm_present_at("/nonexistent");
+    printf("%s DRM present under %s, not under /nonexistent\n", ok ? "ok  " : "FAIL", root);
+    if (!ok) g_failed = 1;
+
+    struct nh_targets t;
+    struct nh_vendor_sel sel;
+    vendor_sel_parse(&sel, NULL);
+    targets_init(&t, &sel);
+    t.hide_graphics = t.hide_compute = 1;
+    n = targets_discover_at(&t, &sel, root);
+    printf("%s discovery under %s adds 6 nodes (%d)\n", n == 6 ? "ok  " : "FAIL", root, n);
+    if (n != 6) g_failed = 1;
+    // the predicate judges the paths a program sees, not the fake tree's
+    expect(&t, "/dev/dri/renderD129", 1);
+    expect(&t, "/dev/dri/card1", 1);
+    expect(&t, "/dev/dri/card0", 0);
+    expect(&t, "/dev/dri/renderD130", 1);   // paired with card2 by BDF
+    expect(&t, "/dev/dri/card3", 1);        // paired with renderD131 by device/drm/
+    expect(&t, "/dev/dri/by-path/pci-0000:01:00.0-render", 1);
+    expect(&t, "/sys/class/drm/card1-DP-1/status", 1);
+    expect(&t, "/sys/class/drm/card0/device/vendor", 0);
+
+    char cmd[PATH_MAX + 16];
+    snprintf(cmd, sizeof(cmd), "rm -rf '%s'", root);
+    if (system(cmd) != 0) fprintf(stderr, "could not remove %s\n", root);
+}
+
+// Nodes whose device has no vendor file, as while an eGPU is hotplugged or
+// for a virtual device: device/uevent's PCI_ID, then its DRIVER, names the
+// vendor, and PCI_SLOT_NAME the BDF when the device is no PCI link.
+static void probe_uevent(void) {
+    char root[] = "/tmp/nvidia-hide-sysroot.XXXXXX";
+    if (!mkdtemp(root)) { perror("mkdtemp"); exit(2); }
+    fake_dir(root, "sys");
+    fake_dir(root, "sys/class");
+    fake_dir(root, "sys/class/drm");
+    fake_dir(root, "sys/devices");
+    fake_dir(root, "sys/devices/pci0000:00");
+    fake_dir(root, "sys/devices/pci0000:00/0000:04:00.0");
+    fake_file(root, "sys/devices/pci0000:00/0000:04:00.0/uevent",
+              "DRIVER=nvidia\nPCI_CLASS=30000\nPCI_ID=10DE:2820\nPCI_SLOT_NAME=0000:04:00.0\n");
+    fake_node(root, "card4", "0000:04:00.0");
+    fake_dir(root, "sys/class/drm/card5");
+    fake_dir(root, "sys/class/drm/card5/device");
+    fake_file(root, "sys/class/drm/card5/device/uevent", "DRIVER=nvidia\nPCI_SLOT_NAME=0000:05:00.0\n");
+    fake_dir(root, "sys/class/drm/card6");
+    fake_dir(root, "sys/class/drm/card6/device");
+    fake_file(root, "sys/class/drm/card6/device/uevent", "DRIVER=i915\nPCI_ID=8086:46A6\nPCI_SLOT_NAME=0000:00:02.0\n");
+    fake_dir(root, "sys/class/drm/card7");
+    fake_dir(root, "sys/class/drm/card7/device");   // vkms: nothing to read
+    static const struct { const char *name, *bdf, *from; unsigned vendor, device; } want[] = {
+        { "card4", "0000:04:00.0", "uevent PCI_ID", 0x10de, 0x2820 },
+        { "card5", "0000:05:00.0", "uevent DRIVER", 0x10de, 0 },
+        { "card6", "0000:00:02.0", "uevent PCI_ID", 0x8086, 0x46a6 },
+        { "card7", "", NULL, 0, 0 },
+    };
+
+    struct nh_drm_node found[8];
+    int n = nh_scan_drm_at(root, found, 8);
+    for (size_t w = 0; w < sizeof(want)/sizeof(want[0]); w++) {
+        const struct nh_drm_node *node = NULL;
+        for (int i = 0; i < n; i++) if (!strcmp(found[i].name, want[w].name)) node = &found[i];
+        int ok = node && node->vendor == want[w].vendor && node->device == want[w].device &&
+                 !strcmp(node->bdf, want[w].bdf) &&
+                 (want[w].from ? node->vendor_from && !strcmp(node->vendor_from, want[w].from) : !node->vendor_from);
+        printf("%s %s without a vendor file: %04x:%04x %s from %s\n", ok ? "ok  " : "FAIL", want[w].name,
+               node ? node->vendor : 0, node ? node->device : 0, node ? node->bdf : "-",
+               node && node->vendor_from ? node->vendor_from : "nothing");
+        if (!ok) g_failed = 1;
+    }
+
+    struct nh_targets t;
+    struct nh_vendor_sel sel;
+    vendor_sel_parse(&sel, NULL);
+    targets_init(&t, &sel);
+    t.hide_graphics = t.hide_compute = 1;
+    n = targets_discover_at(&t, &sel, root);
+    printf("%s discovery under %s adds the 2 NVIDIA nodes (%d)\n", n == 2 ? "ok  " : "FAIL", root, n);
+    if (n != 2) g_failed = 1;
+    expect(&t, "/dev/dri/card4", 1);
+    expect(&t, "/dev/dri/card5", 1);
+    expect(&t, "/dev/dri/card6", 0);
+    expect(&t, "/dev/dri/card7", 0);
+    expect(&t, "/dev/dri/by-path/pci-0000:05:00.0-card", 1);
+
+    char cmd[PATH_MAX + 16];
+    snprintf(cmd, sizeof(cmd), "rm -rf '%s'", root);
+    if (system(cmd) != 0) fprintf(stderr, "could not remove %s\n", root);
+}
+
+// Two NVIDIA GPUs and an iGPU: LIBNVIDIAHIDE_ONLY_BDF and EXCEPT_BDF pick which
+// of the NVIDIA ones discovery hides, nodes, links and PCI entries alike;
+// /dev/nvidia* and the libraries belong to the driver and stay hidden.
+static void probe_only_bdf(void) {
+    char root[] = "/tmp/nvidia-hide-sysroot.XXXXXX";
+    if (!mkdtemp(root)) { perror("mkdtemp"); exit(2); }
+    fake_dir(root, "sys");
+    fake_dir(root, "sys/class");
+    fake_dir(root, "sys/class/drm");
+    fake_dir(root, "sys/devices");
+    fake_dir(root, "sys/devices/pci0000:00");
+    fake_dir(root, "sys/devices/pci0000:00/0000:01:00.0");
+    fake_file(root, "sys/devices/pci0000:00/0000:01:00.0/vendor", "0x10de\n");
+    fake_dir(root, "sys/devices/pci0000:00/0000:02:00.0");
+    fake_file(root, "sys/devices/pci0000:00/0000:02:00.0/vendor", "0x10de\n");
+    fake_dir(root, "sys/devices/pci0000:00/0000:00:02.0");
+    fake_file(root, "sys/devices/pci0000:00/0000:00:02.0/vendor", "0x8086\n");
+    fake_node(root, "card1", "0000:01:00.0");
+    fake_node(root, "renderD129", "0000:01:00.0");
+    fake_node(root, "card2", "0000:02:00.0");
+    fake_node(root, "renderD130", "0000:02:00.0");
+    fake_node(root, "card0", "0000:00:02.0");
+    fake_node(root, "renderD128", "0000:00:02.0");
+    setenv("XDG_CONFIG_HOME", root, 1);
+
+    static const struct { const char *only, *except; int added, first, second; } cases[] = {
+        { NULL,                        NULL,           4, 1, 1 },
+        { "0000:02:00.0",              NULL,           2, 0, 1 },
+        { "0000:01:00.0",              NULL,           2, 1, 0 },
+        { "0000:01:00.0,0000:02:00.0", NULL,           4, 1, 1 },
+        { NULL,                        "0000:02:00.0", 2, 1, 0 },
+        { "0000:05:00.0",              NULL,           0, 0, 0 },
+    };
+    for (size_t i = 0; i < sizeof(cases)/sizeof(cases[0]); i++) {
+        if (cases[i].only) setenv("LIBNVIDIAHIDE_ONLY_BDF", cases[i].only, 1);
+        else unsetenv("LIBNVIDIAHIDE_ONLY_BDF");
+        if (cases[i].except) setenv("LIBNVIDIAHIDE_EXCEPT_BDF", cases[i].except, 1);
+        else unsetenv("LIBNVIDIAHIDE_EXCEPT_BDF");
+        struct nh_targets t;
+        struct nh_vendor_sel sel;
+        vendor_sel_parse(&sel, NULL);
+        targets_init(&t, &sel);
+        int n = targets_discover_at(&t, &sel, root);
+        printf("%s ONLY_BDF=%s EXCEPT_BDF=%s: discovery adds %d nodes (%d)\n", n == cases[i].added ? "ok  " : "FAIL",
+               cases[i].only ? cases[i].only : "", cases[i].except ? cases[i].except : "", cases[i].added, n);
+        if (n != cases[i].added) g_failed = 1;
+        expect(&t, "/dev/dri/card1", cases[i].first);
+        expect(&t, "/dev/dri/renderD129", cases[i].first);
+        expect(&t, "/dev/dri/by-path/pci-0000:01:00.0-render", cases[i].first);
+        expect(&t, "/sys/bus/pci/devices/0000:01:00.0/config", cases[i].first);
+        expect(&t, "/dev/dri/card2", cases[i].second);
+        expect(&t, "/dev/dri/renderD130", cases[i].second);
+        expect(&t, "/dev/dri/by-path/pci-0000:02:00.0-card", cases[i].second);
+        expect(&t, "/sys/class/drm/card2/device/vendor", cases[i].second);
+        expect(&t, "/dev/dri/card0", 0);
+        expect(&t, "/dev/nvidia0", 1);
+        expect(&t, "/usr/lib/x86_64-linux-gnu/libGLX_nvidia.so.0", 1);
+    }
+    unsetenv("LIBNVIDIAHIDE_ONLY_BDF");
+    unsetenv("LIBNVIDIAHIDE_EXCEPT_BDF");
+
+    char cmd[PATH_MAX + 16];
+    snprintf(cmd, sizeof(cmd), "rm -rf '%s'", root);
+    if (system(cmd) != 0) fprintf(stderr, "could not remove %s\n", root);
+}
+
+static void expect_library(const struct nh_targets *t, const char *name, int want) {
+    int got = targets_library_hidden(t, name);
+    printf("%s dlopen %s -> %d\n", got == want ? "ok  " : "FAIL", name, got);
+    if (got != want) g_failed = 1;
+}
+
+// dlopen names: the vendor's libraries by base-name prefix, never this
+// library, and nothing that merely contains a vendor name.
+static void probe_libraries(void) {
+    struct nh_targets t;
+    struct nh_vendor_sel sel;
+    vendor_sel_parse(&sel, NULL);
+    targets_init(&t, &sel);
+    t.hide_graphics = t.hide_compute = 1;
+    expect_library(&t, "libnvidia-glcore.so.550.54", 1);
+    expect_library(&t, "/usr/lib/x86_64-linux-gnu/libnvidia-glcore.so.550.54", 1);
+    expect_library(&t, "libGLX_nvidia.so.0", 1);
+    expect_library(&t, "libEGL_nvidia.so.0", 1);
+    expect_library(&t, "/usr/lib/nvidia-drm_gbm.so", 1);
+    expect_library(&t, "libcuda.so.1", 1);
+    expect_library(&t, "libnvidia-hide.so", 0);
+    expect_library(&t, "/usr/lib/libnvidia-hide.so", 0);
+    expect_library(&t, "/opt/foo/notlibnvidia-thing.so", 0);
+    expect_library(&t, "mylibnvidia-tool.so", 0);
+    expect_library(&t, "/home/u/nvidia/libfoo.so", 0);
+    expect_library(&t, "libGLX_mesa.so.0", 0);
+    t.hide_compute = 0;
+    expect_library(&t, "libcuda.so.1", 0);
+    expect_library(&t, "libnvidia-glcore.so.550.54", 1);
+
+    setenv("LIBNVIDIAHIDE_DLOPEN_KEEP", "glcore:  :libvdpau_", 1);
+    targets_init(&t, &sel);
+    t.hide_graphics = t.hide_compute = 1;
+    expect_library(&t, "libnvidia-glcore.so.550.54", 0);
+    expect_library(&t, "libvdpau_nvidia.so", 0);
+    expect_library(&t, "libnvidia-eglcore.so.550.54", 0);
+    expect_library(&t, "libnvidia-tls.so.550.54", 1);
+    unsetenv("LIBNVIDIAHIDE_DLOPEN_KEEP");
+}
+
+static void expect_entry(const struct nh_targets *t, const char *dir, const char *name, int want) {
+    int got = targets_hide_dev_entry(t, dir, name);
+    printf("%s listing %s: %s -> %d\n", got == want ? "ok  " : "FAIL", dir, name, got);
+    if (got != want) g_failed = 1;
+}
+
+static void expect_config(const struct nh_targets *t, const char *dir, const char *name, int want) {
+    char p[PATH_MAX];
+    snprintf(p, sizeof(p), "%s/%s", dir, name);
+    expect(t, p, want);
+    int got = targets_config_entry(t, dir, strlen(dir), name);
+    printf("%s listing %s: %s -> %d\n", got == want ? "ok  " : "FAIL", dir, name, got);
+    if (got != want) g_failed = 1;
+}
+
+// GLVND and EGL external platform selection dirs: every NVIDIA JSON, Wayland
+// and GBM ones included, neither opens nor lists; other vendors' do both, as
+// do NVIDIA-named files in other dirs.
+static void probe_config_dirs(void) {
+    struct nh_targets t;
+    targets_with_bdf(&t, "0000:01:00.0");
+    static const char *const dirs[] = {
+        "/usr/share/glvnd/egl_vendor.d", "/etc/glvnd/egl_vendor.d",
+        "/usr/share/egl/egl_external_platform.d", "/etc/egl/egl_external_platform.d",
+    };
+    for (size_t i = 0; i < sizeof(dirs)/sizeof(dirs[0]); i++) {
+        expect_config(&t, dirs[i], "10_nvidia.json", 1);
+        expect_config(&t, dirs[i], "10_nvidia_wayland.json", 1);
+        expect_config(&t, dirs[i], "15_nvidia_gbm.json", 1);
+        expect_config(&t, dirs[i], "50_mesa.json", 0);
+        expect_config(&t, dirs[i], "10_wayland_eglstream.json", 0);
+    }
+    expect_config(&t, "/usr/share/glvnd/egl_vendor.d/sub", "10_nvidia.json", 0);
+    expect_config(&t, "/usr/share/doc", "10_nvidia.json", 0);
+    t.hide_graphics = 0;
+    expect(&t, "/usr/share/glvnd/egl_vendor.d/10_nvidia.json", 0);
+}
+
+// LIBNVIDIAHIDE_HIDE_DEV: extra /dev prefixes, hidden with both categories
+// off, and from the listing of their parent; entries outside /dev ignored.
+static void probe_hide_dev(void) {
+    struct nh_targets t;
+    struct nh_vendor_sel sel;
+    vendor_sel_parse(&sel, NULL);
+    setenv("LIBNVIDIAHIDE_HIDE_DEV", "/dev/kfd::/opt/kfd:/dev/:/dev/dri/renderD13", 1);
+    targets_init(&t, &sel);
+    unsetenv("LIBNVIDIAHIDE_HIDE_DEV");
+    int ok = t.hide_dev_n == 2;
+    printf("%s HIDE_DEV keeps the 2 /dev prefixes (%d)\n", ok ? "ok  " : "FAIL", t.hide_dev_n);
+    if (!ok) g_failed = 1;
+    t.hide_graphics = t.hide_compute = 0;
+    expect(&t, "/dev/kfd", 1);
+    expect(&t, "/dev/dri/renderD130", 1);
+    expect(&t, "/dev/dri/renderD128", 0);
+    expect(&t, "/opt/kfd", 0);
+    expect(&t, "/dev/nvidia0", 0);
+    expect_entry(&t, "/dev", "kfd", 1);
+    expect_entry(&t, "/dev", "null", 0);
+    expect_entry(&t, "/dev/dri", "renderD131", 1);
+    expect_entry(&t, "/dev/dri", "card0", 0);
+    expect_entry(&t, "/tmp", "kfd", 0);
+    keep(&t, "/dev/kfd");
+    expect(&t, "/dev/kfd", 0);
+}
+
+// The same entries as an env list and as a file must compile to the same
+// matchers: comments, blank entries and stray whitespace handled alike.
+static void probe_list_tokens(void) {
+    static const struct { const char *env, *file; } cases[] = {
+        { "mpv:firefox", "mpv\nfirefox\n" },
+        { "  mpv \t: firefox  ", "  mpv \t\n\tfirefox  \n" },
+        { "mpv # video player:# only a comment::firefox", "mpv # video player\n# only a comment\n\nfirefox\n" },
+        { " cmdline:*--no-gpu* # flag: !re:^code$ ", "cmdline:*--no-gpu* # flag\n!re:^code$\n" },
+        { "/opt/a#b/app:#", "/opt/a#b/app\n#\n" },
+    };
+    char dir[] = "/tmp/nvidia-hide-lists.XXXXXX";
+    if (!mkdtemp(dir)) { perror("mkdtemp"); exit(2); }
+    for (size_t i = 0; i < sizeof(cases)/sizeof(cases[0]); i++) {
+        fake_file(dir, "list", cases[i].file);
+        char path[PATH_MAX];
+        snprintf(path, sizeof(path), "%s/list", dir);
+        struct nh_matchers env = {0}, file = {0};
+        matchers_from_env(&env, cases[i].env);
+        matchers_from_file(&file, path);
+        int ok = env.n == file.n && env.n > 0;
+        for (int k = 0; ok && k < env.n; k++) {
+            const struct nh_matcher *a = &env.v[k], *b = &file.v[k];
+            ok = a->kind == b->kind && a->negate == b->negate && a->on_cmdline == b->on_cmdline &&
+                 a->on_ancestor == b->on_ancestor && !strcmp(a->pat, b->pat);
+        }
+        printf("%s env list \"%s\" parses like its file form (%d, %d entries)\n",
+               ok ? "ok  " : "FAIL", cases[i].env, env.n, file.n);
+        if (!ok) g_failed = 1;
+        matchers_free(&env);
+        matchers_free(&file);
+        unlink(path);
+    }
+    rmdir(dir);
+}
+
+int main(void) {
+    struct nh_targets t;
+
+    for (size_t i = 0; i < sizeof(g_profiles)/sizeof(g_profiles[0]); i++) {
+        expect_rooted(g_profiles[i].dev_prefixes);
+        expect_rooted(g_profiles[i].compute_dev_prefixes);
+        expect_rooted(g_profiles[i].config_dirs);
+    }
+
+    targets_with_bdf(&t, "0000:01:00.0");
+    expect(&t, "/dev/dri/by-path/pci-0000:01:00.0-card", 1);
+    expect(&t, "/dev/dri/by-path/pci-01:00.0-render", 1);
+    expect(&t, "/dev/dri/by-path/pci-10000:01:00.0-card", 0);
+    expect(&t, "/dev/dri/by-path/pci-0000:01:00.1-card", 0);
+    expect(&t, "/sys/bus/pci/devices/0000:01:00.0/config", 1);
+    expect(&t, "/sys/bus/pci/devices/10000:01:00.0/config", 0);
+    expect(&t, "/proc/bus/pci/01/00.0", 1);
+    expect(&t, "/tmp/01:00.0/config", 0);
+    // Outside the anchored roots only substring rules apply, and still do
+    expect(&t, "/opt/nvidia/lib/libGLX_nvidia.so.0", 1);
+    expect(&t, "/nix/store/abc-nvidia-x11/lib/libcuda.so.1", 1);
+    expect(&t, "/home/u/.cache/mesa_shader_cache/index", 0);
+
+    targets_with_bdf(&t, "10000:01:00.0");
+    expect(&t, "/dev/dri/by-path/pci-10000:01:00.0-card", 1);
+    expect(&t, "/dev/dri/by-path/pci-0000:01:00.0-card", 0);
+    expect(&t, "/dev/dri/by-path/pci-01:00.0-card", 0);
+    expect(&t, "/sys/devices/pci10000:00/10000:00:02.0/10000:01:00.0/config", 1);
+    expect(&t, "/sys/bus/pci/devices/0000:01:00.0/config", 0);
+    expect(&t, "/proc/bus/pci/10000:01/00.0", 1);
+    expect(&t, "/proc/bus/pci/01/00.0", 0);
+
+    // Only the dGPU's nodes, unless every DRM node goes
+    targets_with_bdf(&t, "0000:01:00.0");
+    targets_add_node(&t, "card1");
+    targets_add_node(&t, "renderD129");
+    expect(&t, "/dev/dri/renderD129", 1);
+    expect(&t, "/dev/dri/renderD128", 0);
+    expect(&t, "/dev/dri/by-id/platform-igpu-render", 0);
+    // and their /sys/class/drm entries, connectors included
+    expect(&t, "/sys/class/drm/card1/device/vendor", 1);
+    expect(&t, "/sys/class/drm/renderD129", 1);
+    expect(&t, "/sys/class/drm/card1-DP-1/status", 1);
+    expect(&t, "/sys/class/drm/card10-DP-1/status", 0);
+    expect(&t, "/sys/class/drm/card0-eDP-1/status", 0);
+    expect(&t, "/sys/class/drm/version", 0);
+    expect(&t, "/sys/class/drm", 0);
+    t.hide_all_dri = 1;
+    expect(&t, "/dev/dri/renderD128", 1);
+    expect(&t, "/dev/dri/card0", 1);
+    expect(&t, "/dev/dri/by-path/pci-0000:00:02.0-render", 1);
+    expect(&t, "/dev/dri/by-id/platform-igpu-render", 1);
+    expect(&t, "/dev/dri", 0);
+    expect(&t, "/dev/dri/by-path", 0);
+    expect(&t, "/sys/class/drm/card0-eDP-1/status", 1);
+    expect(&t, "/sys/class/drm/version", 0);
+
+    probe_keep();
+    probe_sysroot();
+    probe_uevent();
+    probe_only_bdf();
+    probe_list_tokens();
+    probe_libraries();
+    probe_hide_dev();
+    probe_config_dirs();
+    return g_failed;
+}
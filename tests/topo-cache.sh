@@ -0,0 +1,88 @@
+#!/bin/sh
+# Run by tests/fake-gpu.sh inside its mount namespace, where /sys/class/drm
+# holds a fake dGPU (card1/renderD129 on 0000:01:00.0) next to an iGPU.
+# LIBNVIDIAHIDE_CACHE=1: the first process walks sysfs and writes the cache,
+# the next loads the same nodes and BDFs from it; a new mtime alone keeps it,
+# while a node appearing or going, or another vendor/BDF selection, walks
+# sysfs again. Then the
+# LIBNVIDIAHIDE_TOPO a process exports: handed back, it skips the walk; a
+# malformed or stale one falls back to it. dlopen()ed instead of preloaded,
+# the library leaves its own environment alone and hands the value to the
+# children it spawns instead.
+#
+#   tests/topo-cache.sh /path/to/libnvidia-hide.so ./tests/preload-probe
+set -u
+so=$1
+probe=$2
+failed=0
+rt=$(mktemp -d /tmp/nvidia-hide-topo.XXXXXX)
+trap 'rm -rf "$rt"' EXIT
+unset LIBNVIDIAHIDE_TOPO LIBNVIDIAHIDE_VENDOR LIBNVIDIAHIDE_ONLY_BDF LIBNVIDIAHIDE_EXCEPT_BDF
+
+ok() { echo "ok   $1"; }
+fail() { echo "FAIL $1"; failed=1; }
+
+# from [VAR=value...]: runs /bin/true under the library and prints where its
+# topology came from (sysfs, cache or LIBNVIDIAHIDE_TOPO).
+from() {
+    : > "$rt/log"
+    env LD_PRELOAD="$so" LIBNVIDIAHIDE_ACTIVE=1 LIBNVIDIAHIDE_CACHE=1 XDG_RUNTIME_DIR="$rt" \
+        LIBNVIDIAHIDE_LOG="$rt/log" LIBNVIDIAHIDE_LOG_LEVEL=info "$@" /bin/true
+    sed -n 's/.*init: topology from //p' "$rt/log"
+}
+
+# The node and bdf lines of the last run's log.
+found() { sed -n 's/.*pid=[0-9]* *\(node\|bdf\): */\1 /p' "$rt/log"; }
+
+expect() {
+    [ "$2" = "$3" ] && ok "$1" || fail "$1 (got '$2', want '$3')"
+}
+
+expect "first run walks sysfs" "$(from)" sysfs
+walked=$(found)
+expect "walk found the dGPU" "$(echo "$walked" | grep -c '^node ')" 2
+cache=$rt/nvidia-hide-topo
+for line in "node card1" "node renderD129" "bdf 0000:01:00.0"; do
+    grep -qx "$line" "$cache" 2>/dev/null && ok "cache has '$line'" || fail "cache has '$line'"
+done
+expect "second run loads the cache" "$(from)" cache
+expect "same nodes and bdfs from the cache" "$(found)" "$walked"
+
+touch /sys/class/drm
+expect "new /sys/class/drm mtime alone: cache" "$(from)" cache
+# A hotplugged node, with the mtime put back as sysfs would leave it
+mkdir /sys/class/drm/renderD130
+ln -s /sys/class/pcidev/0000:01:00.0 /sys/class/drm/renderD130/device
+touch -d @0 /sys/class/drm
+expect "node appeared after the cache was written: walks again" "$(from)" sysfs
+expect "walk found the new node" "$(found | grep -c '^node renderD130$')" 1
+expect "then loads the rewritten cache" "$(from)" cache
+rm -r /sys/class/drm/renderD130
+touch -d @0 /sys/class/drm
+expect "node gone: walks again" "$(from)" sysfs
+expect "same nodes and bdfs as before it came" "$(found)" "$walked"
+expect "another vendor selection: walks again" "$(from LIBNVIDIAHIDE_VENDOR=10de:2820)" sysfs
+expect "another ONLY_BDF: walks again" "$(from LIBNVIDIAHIDE_ONLY_BDF=0000:01:00.0)" sysfs
+expect "same ONLY_BDF again: cache" "$(from LIBNVIDIAHIDE_ONLY_BDF=0000:01:00.0)" cache
+
+topo=$(env LD_PRELOAD="$so" LIBNVIDIAHIDE_ACTIVE=1 printenv LIBNVIDIAHIDE_TOPO)
+case $topo in
+    "v1 key="????????" nodes=card1,renderD129 bdfs=0000:01:00.0" | \
+    "v1 key="????????" nodes=renderD129,card1 bdfs=0000:01:00.0") ok "exported LIBNVIDIAHIDE_TOPO" ;;
+    *) fail "exported LIBNVIDIAHIDE_TOPO (got '$topo')" ;;
+esac
+expect "valid LIBNVIDIAHIDE_TOPO: no walk" "$(from LIBNVIDIAHIDE_CACHE=0 LIBNVIDIAHIDE_TOPO="$topo")" LIBNVIDIAHIDE_TOPO
+expect "same nodes and bdfs from LIBNVIDIAHIDE_TOPO" "$(found)" "$walked"
+bad_node=$(echo "$topo" | sed 's/card1/foo1/')
+expect "bad node name: walks" "$(from LIBNVIDIAHIDE_CACHE=0 LIBNVIDIAHIDE_TOPO="$bad_node")" sysfs
+bad_bdf=$(echo "$topo" | sed 's/bdfs=0000:01:00.0/bdfs=0000.01.00.0/')
+expect "bdf without a colon: walks" "$(from LIBNVIDIAHIDE_CACHE=0 LIBNVIDIAHIDE_TOPO="$bad_bdf")" sysfs
+stale=$(echo "$topo" | sed 's/key=......../key=00000000/')
+expect "stale key: walks" "$(from LIBNVIDIAHIDE_CACHE=0 LIBNVIDIAHIDE_TOPO="$stale")" sysfs
+
+dl=$(env LIBNVIDIAHIDE_ACTIVE=1 "$probe" topodlopen "$so")
+expect "dlopen()ed: own environment left alone" "$(echo "$dl" | sed -n 's/^environ //p')" "(unset)"
+expect "dlopen()ed: spawned child gets it" "$(echo "$dl" | sed -n 's/^child //p')" "$topo"
+expect "dlopen()ed: a stale value in the child's env replaced" "$(echo "$dl" | sed -n 's/^stale-child //p')" "$topo"
+
+exit $failed
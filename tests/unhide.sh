@@ -0,0 +1,53 @@
+#!/bin/sh
+# Checks that `nvidia-hide unhide` drops the library from LD_PRELOAD, however
+# it is spelled, while other preloads stay in order, and that the command then
+# sees what the library hides even when the launcher itself runs preloaded.
+#
+#   tests/unhide.sh ./nvidia-hide /path/to/libnvidia-hide.so /path/to/other.so
+set -u
+nh=$1
+so=$2
+other=$3
+failed=0
+dir=$(mktemp -d /tmp/nvidia-hide-unhide.XXXXXX)
+trap 'rm -rf "$dir"' EXIT
+unset LIBNVIDIAHIDE_ACTIVE LIBNVIDIAHIDE_NO_PROPAGATE
+export LIBNVIDIAHIDE_SO=$so
+
+ok() { echo "ok   $1"; }
+fail() { echo "FAIL $1"; failed=1; }
+
+ln -s "$so" "$dir/renamed.so"
+: > "$dir/libGLX_nvidia.so.0"
+
+# expect <what> <LD_PRELOAD before> <LD_PRELOAD after, or - for unset>
+expect() {
+    out=$(LD_PRELOAD=$2 "$nh" unhide -- env 2>/dev/null)
+    got=$(printf '%s\n' "$out" | sed -n 's/^LD_PRELOAD=//p')
+    if [ "$3" = - ]; then
+        printf '%s\n' "$out" | grep -q '^LD_PRELOAD=' && fail "$1 (got '$got')" || ok "$1"
+    else
+        [ "$got" = "$3" ] && ok "$1" || fail "$1 (got '$got')"
+    fi
+}
+
+expect "space-separated, other kept" "$other $so" "$other"
+expect "colon-separated, other kept" "$so:$other" "$other"
+expect "mixed separators, order kept" "$other:$so $other" "$other $other"
+expect "only ours: LD_PRELOAD unset" "$so" -
+expect "other path to the same file" "$dir/renamed.so:$other" "$other"
+expect "\$LIB template" "/nonexistent/\$LIB/libnvidia-hide.so $other" "$other"
+
+out=$(LD_PRELOAD=$so "$nh" unhide -- env 2>/dev/null)
+printf '%s\n' "$out" | grep -qx 'LIBNVIDIAHIDE_ACTIVE=0' && ok "sets LIBNVIDIAHIDE_ACTIVE=0" || fail "sets LIBNVIDIAHIDE_ACTIVE=0"
+
+# The launcher runs with the library active; its exec hook must not put the
+# library back, so the child opens what the library hides.
+LD_PRELOAD=$so LIBNVIDIAHIDE_ACTIVE=1 cat "$dir/libGLX_nvidia.so.0" 2>/dev/null \
+    && fail "preloaded cat opens the hidden file" || ok "preloaded cat can't open the hidden file"
+LD_PRELOAD=$so LIBNVIDIAHIDE_ACTIVE=1 "$nh" unhide -- cat "$dir/libGLX_nvidia.so.0" \
+    && ok "unhide -- cat opens it" || fail "unhide -- cat opens it"
+
+"$nh" unhide 2>/dev/null; [ $? -eq 2 ] && ok "no command: usage error" || fail "no command: usage error"
+
+exit $failed
@@ -0,0 +1,35 @@
+#!/bin/sh
+# Checks the script `nvidia-hide wrap` writes: its directory created, the
+# script executable, marked as generated and preloading the library, with the
+# arguments forwarded; an existing file kept unless --force is given.
+#
+#   tests/wrap.sh ./nvidia-hide /path/to/libnvidia-hide.so
+set -u
+nh=$1
+so=$2
+failed=0
+dir=$(mktemp -d /tmp/nvidia-hide-wrap.XXXXXX)
+trap 'rm -rf "$dir"' EXIT
+export LIBNVIDIAHIDE_SO="$so"
+unset LD_PRELOAD
+
+ok() { echo "ok   $1"; }
+fail() { echo "FAIL $1"; failed=1; }
+
+w="$dir/bin/sub/printenv"
+"$nh" wrap printenv "$w" >/dev/null 2>&1 && ok "wrap into a missing directory" || fail "wrap into a missing directory"
+[ -x "$w" ] && ok "wrapper is executable" || fail "wrapper is executable"
+sed -n 2p "$w" 2>/dev/null | grep -q "^# Generated by 'nvidia-hide wrap'" && ok "marker line" || fail "marker line"
+grep -qx "so='$so'" "$w" 2>/dev/null && ok "preload line" || fail "preload line"
+[ "$("$w" LD_PRELOAD 2>/dev/null)" = "$so" ] && ok "runs the program preloaded, arguments forwarded" ||
+    fail "runs the program preloaded, arguments forwarded"
+[ "$(LD_PRELOAD="$so" "$w" LD_PRELOAD 2>/dev/null)" = "$so" ] && ok "preloaded already: not added twice" ||
+    fail "preloaded already: not added twice"
+
+echo 'mine' > "$dir/taken"
+"$nh" wrap printenv "$dir/taken" >/dev/null 2>&1 && fail "refuses to overwrite" || ok "refuses to overwrite"
+[ "$(cat "$dir/taken")" = mine ] && ok "existing file untouched" || fail "existing file untouched"
+"$nh" wrap --force printenv "$dir/taken" >/dev/null 2>&1 && grep -qx "so='$so'" "$dir/taken" &&
+    ok "--force overwrites" || fail "--force overwrites"
+
+exit $failed